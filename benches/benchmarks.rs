@@ -9,6 +9,7 @@ use nu_protocol::report_parse_error;
 use tango_bench::{benchmark_fn, tango_benchmarks, tango_main, Benchmark, IntoBenchmarks};
 
 use new_nu_parser::compiler::Compiler;
+use new_nu_parser::ir_generator::IrGenerator;
 use new_nu_parser::parser::Parser;
 use new_nu_parser::resolver::Resolver;
 use new_nu_parser::typechecker::Typechecker;
@@ -24,6 +25,16 @@ const BENCHMARKS: &[&str] = &[
     "int100",
 ];
 
+/// Depth of the generated deeply nested inputs
+const NESTING_DEPTH: usize = 100;
+
+/// Length of the generated long inputs
+const LENGTH: usize = 10000;
+
+/// Number of operands of the generated long expression; the old parser overflows the stack on
+/// much longer ones
+const SUM_LENGTH: usize = 1000;
+
 enum Stage {
     Lex,
     Parse,
@@ -32,6 +43,7 @@ enum Stage {
     ResolveMerge,
     TypecheckMerge,
     Compile,
+    Ir,
     Nu,
 }
 
@@ -47,9 +59,87 @@ const STAGES: &[Stage] = &[
     Stage::Nu,
 ];
 
+/// Stages for inputs supported by the IR generator
+const IR_STAGES: &[Stage] = &[
+    Stage::Lex,
+    Stage::Parse,
+    Stage::Resolve,
+    Stage::Typecheck,
+    Stage::Compile,
+    Stage::Ir,
+    Stage::Nu,
+];
+
+/// Stages for inputs that can only be lexed so far
+const LEX_STAGES: &[Stage] = &[Stage::Lex, Stage::Nu];
+
+/// Input to benchmark with the stages it supports
+struct Input {
+    name: String,
+    contents: Vec<u8>,
+    stages: &'static [Stage],
+}
+
+/// Pathological inputs generated on the fly, too big to keep in benches/nu/
+fn generated_inputs() -> Vec<Input> {
+    let nested_parens = format!(
+        "{}1{}",
+        "(".repeat(NESTING_DEPTH),
+        " + 2)".repeat(NESTING_DEPTH)
+    );
+
+    let nested_blocks = format!(
+        "{}1{}",
+        "if true { ".repeat(NESTING_DEPTH),
+        " }".repeat(NESTING_DEPTH)
+    );
+
+    let long_sum = vec!["1"; SUM_LENGTH].join(" + ");
+
+    let long_pipeline = format!("def foo [] {{ 1 }}\n{}", vec!["foo"; LENGTH].join(" | "));
+
+    let huge_list = format!(
+        "[{}]",
+        (0..LENGTH)
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    vec![
+        Input {
+            name: format!("nested_parens{NESTING_DEPTH}"),
+            contents: nested_parens.into_bytes(),
+            stages: IR_STAGES,
+        },
+        Input {
+            name: format!("nested_blocks{NESTING_DEPTH}"),
+            contents: nested_blocks.into_bytes(),
+            stages: STAGES,
+        },
+        Input {
+            name: format!("long_sum{SUM_LENGTH}"),
+            contents: long_sum.into_bytes(),
+            stages: IR_STAGES,
+        },
+        // The parser does not support pipelines yet
+        Input {
+            name: format!("long_pipeline{LENGTH}"),
+            contents: long_pipeline.into_bytes(),
+            stages: LEX_STAGES,
+        },
+        Input {
+            name: format!("huge_list{LENGTH}"),
+            contents: huge_list.into_bytes(),
+            stages: STAGES,
+        },
+    ]
+}
+
 /// Set up compiler with selected stages pre-run
 fn setup_compiler(
     fname: &str,
+    contents: &[u8],
     do_parse: bool,
     do_resolve: bool,
     do_typecheck: bool,
@@ -57,10 +147,9 @@ fn setup_compiler(
     let mut compiler = Compiler::new();
     let span_offset = compiler.span_offset();
 
-    let contents = std::fs::read(fname).map_err(|_| format!("Cannot find file {fname}"))?;
-    compiler.add_file(&fname, &contents);
+    compiler.add_file(fname, contents);
 
-    let (tokens, err) = lex(&contents, span_offset);
+    let (tokens, err) = lex(contents, span_offset);
     if let Err(e) = err {
         tokens.eprint(&compiler.source);
         eprintln!("Lexing error. Error: {:?}", e);
@@ -180,6 +269,17 @@ pub fn compile(mut compiler: Compiler, span_offset: usize) {
     compiler.merge_types(typechecker.to_types());
 }
 
+/// Generate IR only
+pub fn generate_ir(compiler: &Compiler) {
+    let mut ir_generator = IrGenerator::new(compiler);
+    ir_generator.generate();
+
+    if !ir_generator.errors().is_empty() {
+        eprintln!("Error generating IR.");
+        exit(1);
+    }
+}
+
 fn make_engine_state() -> Box<EngineState> {
     let mut engine_state = Box::new(EngineState::new());
 
@@ -221,11 +321,22 @@ fn parse_nu_old(engine_state: &EngineState, contents: &[u8]) {
 fn compiler_benchmarks() -> impl IntoBenchmarks {
     let mut benchmarks: Vec<Benchmark> = vec![];
 
-    for bench_name in BENCHMARKS {
-        for stage in STAGES {
-            let bench_file = format!("benches/nu/{bench_name}.nu");
-            let bench_contents =
-                std::fs::read(&bench_file).expect(&format!("Cannot find file {bench_file}"));
+    let file_inputs = BENCHMARKS.iter().map(|bench_name| {
+        let bench_file = format!("benches/nu/{bench_name}.nu");
+        let contents =
+            std::fs::read(&bench_file).unwrap_or_else(|_| panic!("Cannot find file {bench_file}"));
+        Input {
+            name: bench_name.to_string(),
+            contents,
+            stages: STAGES,
+        }
+    });
+
+    for input in file_inputs.chain(generated_inputs()) {
+        for stage in input.stages {
+            let bench_name = &input.name;
+            let bench_file = format!("{bench_name}.nu");
+            let bench_contents = input.contents.clone();
 
             let bench = match stage {
                 Stage::Lex => {
@@ -246,7 +357,7 @@ fn compiler_benchmarks() -> impl IntoBenchmarks {
                     let name = format!("{bench_name}_parse");
                     benchmark_fn(name, move |b| {
                         let (compiler_def_init, span_offset) =
-                            setup_compiler(&bench_file, false, false, false)
+                            setup_compiler(&bench_file, &bench_contents, false, false, false)
                                 .expect("Error setting up compiler");
                         let contents = bench_contents.clone();
                         let (tokens, err) = lex(&contents, span_offset);
@@ -262,7 +373,7 @@ fn compiler_benchmarks() -> impl IntoBenchmarks {
                     let name = format!("{bench_name}_resolve");
                     benchmark_fn(name, move |b| {
                         let (compiler_def_parsed, _) =
-                            setup_compiler(&bench_file.clone(), true, false, false)
+                            setup_compiler(&bench_file, &bench_contents, true, false, false)
                                 .expect("Error setting up compiler");
                         b.iter(move || resolve(compiler_def_parsed.clone(), false))
                     })
@@ -271,7 +382,7 @@ fn compiler_benchmarks() -> impl IntoBenchmarks {
                     let name = format!("{bench_name}_resolve_merge");
                     benchmark_fn(name, move |b| {
                         let (compiler_def_parsed, _) =
-                            setup_compiler(&bench_file.clone(), true, false, false)
+                            setup_compiler(&bench_file, &bench_contents, true, false, false)
                                 .expect("Error setting up compiler");
                         b.iter(move || resolve(compiler_def_parsed.clone(), true))
                     })
@@ -280,7 +391,7 @@ fn compiler_benchmarks() -> impl IntoBenchmarks {
                     let name = format!("{bench_name}_typecheck");
                     benchmark_fn(name, move |b| {
                         let (compiler_def_parsed, _) =
-                            setup_compiler(&bench_file.clone(), true, true, false)
+                            setup_compiler(&bench_file, &bench_contents, true, true, false)
                                 .expect("Error setting up compiler");
                         b.iter(move || typecheck(compiler_def_parsed.clone(), false))
                     })
@@ -289,7 +400,7 @@ fn compiler_benchmarks() -> impl IntoBenchmarks {
                     let name = format!("{bench_name}_typecheck_merge");
                     benchmark_fn(name, move |b| {
                         let (compiler_def_parsed, _) =
-                            setup_compiler(&bench_file.clone(), true, true, false)
+                            setup_compiler(&bench_file, &bench_contents, true, true, false)
                                 .expect("Error setting up compiler");
                         b.iter(move || typecheck(compiler_def_parsed.clone(), true))
                     })
@@ -298,11 +409,20 @@ fn compiler_benchmarks() -> impl IntoBenchmarks {
                     let name = format!("{bench_name}_compile");
                     benchmark_fn(name, move |b| {
                         let (compiler_def_init, span_offset) =
-                            setup_compiler(&bench_file.clone(), false, false, false)
+                            setup_compiler(&bench_file, &bench_contents, false, false, false)
                                 .expect("Error setting up compiler");
                         b.iter(move || compile(compiler_def_init.clone(), span_offset))
                     })
                 }
+                Stage::Ir => {
+                    let name = format!("{bench_name}_ir");
+                    benchmark_fn(name, move |b| {
+                        let (compiler_def_typechecked, _) =
+                            setup_compiler(&bench_file, &bench_contents, true, true, true)
+                                .expect("Error setting up compiler");
+                        b.iter(move || generate_ir(&compiler_def_typechecked))
+                    })
+                }
                 Stage::Nu => {
                     let name = format!("{bench_name}_nu_old");
                     benchmark_fn(name, move |b| {
@@ -317,7 +437,7 @@ fn compiler_benchmarks() -> impl IntoBenchmarks {
         }
     }
 
-    benchmarks.push(benchmark_fn(format!("nu_old_empty"), move |b| {
+    benchmarks.push(benchmark_fn("nu_old_empty".to_string(), move |b| {
         let engine_state = make_engine_state();
         b.iter(move || parse_nu_old(&engine_state, &[]))
     }));
@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use new_nu_parser_fuzz::Program;
+
+fuzz_target!(|program: Program| {
+    let source = program.render();
+    new_nu_parser_fuzz::compile(source.as_bytes());
+});
@@ -0,0 +1,351 @@
+//! Shared code for the fuzz targets
+//!
+//! The fuzz targets only check that the compiler does not panic (and does not hang, which is
+//! caught by libFuzzer's `-timeout`). Errors in the source are fine, they are expected most of
+//! the time.
+
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use new_nu_parser::compiler::Compiler;
+use new_nu_parser::ir_generator::IrGenerator;
+use new_nu_parser::lexer::lex;
+use new_nu_parser::parser::Parser;
+use new_nu_parser::resolver::Resolver;
+use new_nu_parser::typechecker::Typechecker;
+
+/// Run all compiler stages on the source, stopping at the first stage that reports errors
+pub fn compile(source: &[u8]) {
+    let mut compiler = Compiler::new();
+    let span_offset = compiler.span_offset();
+    compiler.add_file("fuzz.nu", source);
+
+    let (tokens, err) = lex(source, span_offset);
+    if err.is_err() {
+        return;
+    }
+
+    let parser = Parser::new(compiler, tokens);
+    compiler = parser.parse();
+
+    // Every node must have a span pointing inside the source, even when the parser recovered
+    // from an error
+    assert_eq!(compiler.ast_nodes.len(), compiler.spans.len());
+    for span in &compiler.spans {
+        assert!(span.start <= span.end && span.end <= compiler.source.len());
+    }
+
+    if !compiler.errors.is_empty() {
+        return;
+    }
+
+    let mut resolver = Resolver::new(&compiler);
+    resolver.resolve();
+    compiler.merge_name_bindings(resolver.to_name_bindings());
+
+    if !compiler.errors.is_empty() {
+        return;
+    }
+
+    let mut typechecker = Typechecker::new(&compiler);
+    typechecker.typecheck();
+    compiler.merge_types(typechecker.to_types());
+
+    if !compiler.errors.is_empty() {
+        return;
+    }
+
+    let mut ir_generator = IrGenerator::new(&compiler);
+    ir_generator.generate();
+}
+
+const NAMES: &[&str] = &["x", "y", "foo", "bar", "it", "in", "spam", "def"];
+const TYPES: &[&str] = &["int", "float", "string", "bool", "any", "list<int>", "closure"];
+const OPERATORS: &[&str] = &[
+    "+", "-", "*", "/", "//", "mod", "**", "==", "!=", "<", "<=", ">", ">=", "=~", "!~", "in",
+    "++", "and", "or", "xor", "=", "+=",
+];
+/// Snippets used for mutating otherwise valid programs to exercise error recovery
+const JUNK: &[&str] = &[
+    "{", "}", "[", "]", "(", ")", "|", ",", ":", ";", "\n", "$", "=", "..", ".", "\"", "'", "#",
+    "->", "=>", "...", "let", "def", "if", "else",
+];
+
+#[derive(Arbitrary, Debug)]
+pub struct Name(u8);
+
+impl Name {
+    fn render(&self) -> &'static str {
+        NAMES[self.0 as usize % NAMES.len()]
+    }
+}
+
+#[derive(Arbitrary, Debug)]
+pub struct TypeName(u8);
+
+impl TypeName {
+    fn render(&self) -> &'static str {
+        TYPES[self.0 as usize % TYPES.len()]
+    }
+}
+
+#[derive(Arbitrary, Debug)]
+pub enum Expr {
+    Int(i64),
+    Float(u16, u16),
+    String(Name),
+    True,
+    False,
+    Null,
+    Variable(Name),
+    BinaryOp(Box<Expr>, u8, Box<Expr>),
+    Paren(Box<Expr>),
+    Range(Box<Expr>, Box<Expr>),
+    List(Vec<Expr>),
+    Record(Vec<(Name, Expr)>),
+    Closure(Vec<Name>, Vec<Stmt>),
+    If(Box<Expr>, Vec<Stmt>, Option<Vec<Stmt>>),
+    Match(Box<Expr>, Vec<(Expr, Expr)>),
+    Call(Name, Vec<Expr>),
+}
+
+#[derive(Arbitrary, Debug)]
+pub enum Stmt {
+    Let(Name, Option<TypeName>, Expr),
+    Mut(Name, Expr),
+    Def(Name, Vec<(Name, Option<TypeName>)>, Vec<Stmt>),
+    Alias(Name, Name),
+    While(Expr, Vec<Stmt>),
+    For(Name, Expr, Vec<Stmt>),
+    Loop(Vec<Stmt>),
+    Break,
+    Continue,
+    Return(Option<Expr>),
+    Expr(Expr),
+}
+
+/// Byte-level edit applied to the rendered program
+#[derive(Arbitrary, Debug)]
+pub enum Mutation {
+    Insert(u16, u8),
+    Delete(u16),
+    Truncate(u16),
+}
+
+/// Grammar-aware fuzzing input: a structurally valid program with a few random mutations applied
+#[derive(Arbitrary, Debug)]
+pub struct Program {
+    pub stmts: Vec<Stmt>,
+    pub mutations: Vec<Mutation>,
+}
+
+impl Program {
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        render_stmts(&self.stmts, &mut out);
+
+        for mutation in &self.mutations {
+            let len = out.len().max(1);
+            match mutation {
+                Mutation::Insert(pos, junk) => {
+                    let pos = floor_char_boundary(&out, *pos as usize % len);
+                    out.insert_str(pos, JUNK[*junk as usize % JUNK.len()]);
+                }
+                Mutation::Delete(pos) => {
+                    let pos = floor_char_boundary(&out, *pos as usize % len);
+                    if pos < out.len() {
+                        out.remove(pos);
+                    }
+                }
+                Mutation::Truncate(pos) => {
+                    let pos = floor_char_boundary(&out, *pos as usize % len);
+                    out.truncate(pos);
+                }
+            }
+        }
+
+        out
+    }
+}
+
+fn floor_char_boundary(s: &str, mut pos: usize) -> usize {
+    pos = pos.min(s.len());
+    while !s.is_char_boundary(pos) {
+        pos -= 1;
+    }
+    pos
+}
+
+fn render_stmts(stmts: &[Stmt], out: &mut String) {
+    for stmt in stmts {
+        render_stmt(stmt, out);
+        out.push('\n');
+    }
+}
+
+fn render_block(stmts: &[Stmt], out: &mut String) {
+    out.push_str("{\n");
+    render_stmts(stmts, out);
+    out.push('}');
+}
+
+fn render_stmt(stmt: &Stmt, out: &mut String) {
+    match stmt {
+        Stmt::Let(name, ty, init) => {
+            out.push_str("let ");
+            out.push_str(name.render());
+            if let Some(ty) = ty {
+                out.push_str(": ");
+                out.push_str(ty.render());
+            }
+            out.push_str(" = ");
+            render_expr(init, out);
+        }
+        Stmt::Mut(name, init) => {
+            out.push_str("mut ");
+            out.push_str(name.render());
+            out.push_str(" = ");
+            render_expr(init, out);
+        }
+        Stmt::Def(name, params, body) => {
+            out.push_str("def ");
+            out.push_str(name.render());
+            out.push_str(" [");
+            for (param, ty) in params {
+                out.push_str(param.render());
+                if let Some(ty) = ty {
+                    out.push_str(": ");
+                    out.push_str(ty.render());
+                }
+                out.push_str(", ");
+            }
+            out.push_str("] ");
+            render_block(body, out);
+        }
+        Stmt::Alias(new_name, old_name) => {
+            out.push_str("alias ");
+            out.push_str(new_name.render());
+            out.push_str(" = ");
+            out.push_str(old_name.render());
+        }
+        Stmt::While(cond, body) => {
+            out.push_str("while ");
+            render_expr(cond, out);
+            out.push(' ');
+            render_block(body, out);
+        }
+        Stmt::For(var, range, body) => {
+            out.push_str("for ");
+            out.push_str(var.render());
+            out.push_str(" in ");
+            render_expr(range, out);
+            out.push(' ');
+            render_block(body, out);
+        }
+        Stmt::Loop(body) => {
+            out.push_str("loop ");
+            render_block(body, out);
+        }
+        Stmt::Break => out.push_str("break"),
+        Stmt::Continue => out.push_str("continue"),
+        Stmt::Return(val) => {
+            out.push_str("return");
+            if let Some(val) = val {
+                out.push(' ');
+                render_expr(val, out);
+            }
+        }
+        Stmt::Expr(expr) => render_expr(expr, out),
+    }
+}
+
+fn render_expr(expr: &Expr, out: &mut String) {
+    match expr {
+        Expr::Int(val) => out.push_str(&val.to_string()),
+        Expr::Float(int, frac) => out.push_str(&format!("{int}.{frac}")),
+        Expr::String(val) => {
+            out.push('"');
+            out.push_str(val.render());
+            out.push('"');
+        }
+        Expr::True => out.push_str("true"),
+        Expr::False => out.push_str("false"),
+        Expr::Null => out.push_str("null"),
+        Expr::Variable(name) => {
+            out.push('$');
+            out.push_str(name.render());
+        }
+        Expr::BinaryOp(lhs, op, rhs) => {
+            render_expr(lhs, out);
+            out.push(' ');
+            out.push_str(OPERATORS[*op as usize % OPERATORS.len()]);
+            out.push(' ');
+            render_expr(rhs, out);
+        }
+        Expr::Paren(inner) => {
+            out.push('(');
+            render_expr(inner, out);
+            out.push(')');
+        }
+        Expr::Range(from, to) => {
+            render_expr(from, out);
+            out.push_str("..");
+            render_expr(to, out);
+        }
+        Expr::List(items) => {
+            out.push('[');
+            for item in items {
+                render_expr(item, out);
+                out.push(' ');
+            }
+            out.push(']');
+        }
+        Expr::Record(pairs) => {
+            out.push('{');
+            for (key, val) in pairs {
+                out.push_str(key.render());
+                out.push_str(": ");
+                render_expr(val, out);
+                out.push_str(", ");
+            }
+            out.push('}');
+        }
+        Expr::Closure(params, body) => {
+            out.push_str("{|");
+            for param in params {
+                out.push_str(param.render());
+                out.push_str(", ");
+            }
+            out.push_str("| ");
+            render_stmts(body, out);
+            out.push('}');
+        }
+        Expr::If(cond, then_block, else_block) => {
+            out.push_str("if ");
+            render_expr(cond, out);
+            out.push(' ');
+            render_block(then_block, out);
+            if let Some(else_block) = else_block {
+                out.push_str(" else ");
+                render_block(else_block, out);
+            }
+        }
+        Expr::Match(target, arms) => {
+            out.push_str("match ");
+            render_expr(target, out);
+            out.push_str(" {\n");
+            for (pattern, result) in arms {
+                render_expr(pattern, out);
+                out.push_str(" => ");
+                render_expr(result, out);
+                out.push('\n');
+            }
+            out.push('}');
+        }
+        Expr::Call(name, args) => {
+            out.push_str(name.render());
+            for arg in args {
+                out.push(' ');
+                render_expr(arg, out);
+            }
+        }
+    }
+}
@@ -0,0 +1,50 @@
+/*
+ * C interface of new-nu-parser, built with `cargo build --release --features ffi`
+ *
+ * See src/ffi.rs for the documentation of each function.
+ */
+#ifndef NEW_NU_PARSER_H
+#define NEW_NU_PARSER_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct NnpCompilation NnpCompilation;
+
+typedef enum NnpSeverity {
+    NNP_SEVERITY_ERROR = 0,
+    NNP_SEVERITY_NOTE = 1,
+    NNP_SEVERITY_WARNING = 2,
+} NnpSeverity;
+
+typedef struct NnpDiagnostic {
+    NnpSeverity severity;
+    /* Byte offsets into the source buffer, end is exclusive */
+    size_t start;
+    size_t end;
+    /* Owned by the compilation */
+    const char *message;
+    /* Owned by the compilation, NULL if missing */
+    const char *help;
+    const char *note;
+} NnpDiagnostic;
+
+NnpCompilation *nnp_compile(const uint8_t *source, size_t len);
+void nnp_compilation_free(NnpCompilation *compilation);
+
+size_t nnp_diagnostic_count(const NnpCompilation *compilation);
+bool nnp_diagnostic_get(const NnpCompilation *compilation, size_t idx, NnpDiagnostic *out);
+
+/* Owned by the compilation */
+const char *nnp_ast_json(const NnpCompilation *compilation);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* NEW_NU_PARSER_H */
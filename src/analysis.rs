@@ -0,0 +1,329 @@
+//! Results of compiling a document, shared between threads
+//!
+//! A compiled `Analysis` is immutable and `Send + Sync`, so any number of threads can query its
+//! types, symbols and diagnostics while a newer version of the document is compiled on another
+//! thread. `LatestAnalysis` holds the newest compiled version and swaps it atomically, readers
+//! keep the version they started with for as long as they need it.
+use std::sync::{Arc, PoisonError, RwLock};
+
+use crate::compiler::{Compiler, Span, Spanned, StageObserver};
+use crate::errors::{SourceError, TextEdit};
+use crate::incremental::NodeIdMap;
+use crate::lexer::{lex, relex, LexError, Tokens};
+use crate::typechecker::Types;
+
+/// Output of the stages that ran on a document
+pub struct Analysis {
+    /// Compiler with the document added under its file name
+    pub compiler: Compiler,
+    /// Present only if the names were resolved, i.e., there were no syntax or name errors
+    pub types: Option<Types>,
+    /// Span of the document in the compiler's source and its tokens, for relexing the next
+    /// version. None if lexing failed.
+    tokens: Option<(Span, Tokens)>,
+    /// Unchanged nodes of the analysis this one was updated from, for carrying over data keyed by
+    /// NodeId
+    pub previous_nodes: Option<NodeIdMap>,
+}
+
+impl Analysis {
+    /// Run the stages up to typechecking, stopping after the first stage reporting fatal errors
+    ///
+    /// The compiler carries the options and the file provider to compile with.
+    pub fn new(compiler: Compiler, fname: &str, contents: &[u8]) -> Self {
+        let lexed = lex(contents, compiler.span_offset());
+        Self::run_stages(compiler, fname, contents, lexed)
+    }
+
+    /// Same as `new()` for the next version of the document, relexing only the lines that changed
+    /// since this version
+    ///
+    /// The change is found by comparing the contents of both versions, this analysis does not
+    /// have to be of the version right before.
+    pub fn update(&self, compiler: Compiler, fname: &str, contents: &[u8]) -> Self {
+        let span_offset = compiler.span_offset();
+        let relexed = self.tokens.as_ref().and_then(|(span, tokens)| {
+            if span.start != span_offset {
+                return None;
+            }
+            let old_contents = &self.compiler.source[span.start..span.end];
+            let edit = edit_between(old_contents, contents, span_offset)?;
+            let relexed = relex(tokens, contents, span_offset, &edit);
+            Some((relexed.tokens, relexed.result))
+        });
+        let lexed = relexed.unwrap_or_else(|| lex(contents, span_offset));
+
+        let mut analysis = Self::run_stages(compiler, fname, contents, lexed);
+        analysis.previous_nodes = Some(NodeIdMap::new(&self.compiler, &analysis.compiler));
+        analysis
+    }
+
+    fn run_stages(
+        mut compiler: Compiler,
+        fname: &str,
+        contents: &[u8],
+        lexed: (Tokens, Result<(), Spanned<LexError>>),
+    ) -> Self {
+        let span = Span::new(
+            compiler.span_offset(),
+            compiler.span_offset() + contents.len(),
+        );
+        let mut observer = KeepTokens(None);
+        let types = compiler.run_stages_on_tokens(fname, contents, lexed, &mut observer);
+
+        Analysis {
+            compiler,
+            types,
+            tokens: observer.0.map(|tokens| (span, tokens)),
+            previous_nodes: None,
+        }
+    }
+
+    /// Errors of all the stages that ran, the type errors are merged into the compiler as well
+    pub fn errors(&self) -> impl Iterator<Item = &SourceError> {
+        self.compiler.errors.iter()
+    }
+}
+
+/// Keeps the tokens before the parser consumes them
+struct KeepTokens(Option<Tokens>);
+
+impl StageObserver for KeepTokens {
+    fn lexed(&mut self, _compiler: &Compiler, tokens: &Tokens, error: Option<&Spanned<LexError>>) {
+        if error.is_none() {
+            self.0 = Some(tokens.clone());
+        }
+    }
+}
+
+/// Edit replacing the range between the common prefix and suffix of the old and new contents
+///
+/// None if the replacement is not valid UTF-8.
+fn edit_between(old: &[u8], new: &[u8], span_offset: usize) -> Option<TextEdit> {
+    // the replacement starts and ends on whole characters
+    let is_char_start = |idx: usize| new.get(idx).map_or(true, |c| (*c as i8) >= -0x40);
+
+    let mut prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    while !is_char_start(prefix) {
+        prefix -= 1;
+    }
+
+    let max_suffix = old.len().min(new.len()) - prefix;
+    let mut suffix = old
+        .iter()
+        .rev()
+        .zip(new.iter().rev())
+        .take(max_suffix)
+        .take_while(|(a, b)| a == b)
+        .count();
+    while !is_char_start(new.len() - suffix) {
+        suffix -= 1;
+    }
+
+    let replacement = String::from_utf8(new[prefix..new.len() - suffix].to_vec()).ok()?;
+    let span = Span::new(span_offset + prefix, span_offset + old.len() - suffix);
+
+    Some(TextEdit::replace(span, replacement))
+}
+
+#[derive(Default)]
+struct Versions {
+    version: i64,
+    current: Option<Arc<Analysis>>,
+    resolved: Option<Arc<Analysis>>,
+}
+
+/// Newest analysis of a document, updated as its versions are compiled
+#[derive(Default)]
+pub struct LatestAnalysis {
+    versions: RwLock<Versions>,
+}
+
+impl LatestAnalysis {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Analysis of the newest version compiled so far
+    pub fn current(&self) -> Option<Arc<Analysis>> {
+        self.read().current.clone()
+    }
+
+    /// Analysis of the newest version whose names were resolved
+    ///
+    /// While typing, the document has syntax errors most of the time, this is the last analysis
+    /// usable for completion then.
+    pub fn resolved(&self) -> Option<Arc<Analysis>> {
+        self.read().resolved.clone()
+    }
+
+    /// Store the analysis of the given version of the document
+    ///
+    /// Compilations can finish out of order, the analysis is dropped if a newer version was stored
+    /// already. Returns whether it was stored.
+    pub fn publish(&self, version: i64, analysis: Analysis) -> bool {
+        let mut versions = self
+            .versions
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        if versions.current.is_some() && version <= versions.version {
+            return false;
+        }
+
+        let analysis = Arc::new(analysis);
+        if analysis.types.is_some() {
+            versions.resolved = Some(analysis.clone());
+        }
+        versions.version = version;
+        versions.current = Some(analysis);
+        true
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Versions> {
+        // the versions are replaced only once they are complete, so they are valid even if a
+        // writer panicked
+        self.versions.read().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{edit_between, Analysis, LatestAnalysis};
+    use crate::compiler::{Compiler, Span};
+    use crate::errors::TextEdit;
+    use crate::parser::NodeId;
+    use std::sync::Arc;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn results_are_send_and_sync() {
+        assert_send_sync::<Compiler>();
+        assert_send_sync::<Analysis>();
+        assert_send_sync::<LatestAnalysis>();
+    }
+
+    #[test]
+    fn newest_version_wins() {
+        let latest = LatestAnalysis::new();
+        assert!(latest.current().is_none());
+
+        let analysis = |src: &str| Analysis::new(Compiler::new(), "test.nu", src.as_bytes());
+        assert!(latest.publish(2, analysis("let x = 2\n$x")));
+        assert!(!latest.publish(1, analysis("let x = 1")));
+        assert!(latest.publish(3, analysis("let x = (")));
+
+        let current = latest.current().expect("version 3 is stored");
+        assert!(current.types.is_none());
+        assert_eq!(current.compiler.source, b"let x = (");
+
+        let resolved = latest.resolved().expect("version 2 is resolved");
+        assert_eq!(resolved.compiler.source, b"let x = 2\n$x");
+        assert_eq!(resolved.errors().count(), 0);
+    }
+
+    #[test]
+    fn updates_same_as_new() {
+        let versions = [
+            "let x = 1\n$x",
+            "let long_name = 1\n$long_name",
+            "let long_name = \"(\n$long_name",
+            "let long_name = 'ö'\n$long_name",
+            "let long_name = 'ü'\n$long_name",
+            "let x = 'ü'\n$y",
+        ];
+
+        let mut previous = Analysis::new(Compiler::new(), "test.nu", versions[0].as_bytes());
+        for src in &versions[1..] {
+            let updated = previous.update(Compiler::new(), "test.nu", src.as_bytes());
+            let new = Analysis::new(Compiler::new(), "test.nu", src.as_bytes());
+            assert_eq!(
+                updated.compiler.display_state(),
+                new.compiler.display_state(),
+                "{src}"
+            );
+            assert_eq!(updated.types.is_some(), new.types.is_some());
+            assert!(updated.previous_nodes.is_some());
+            previous = updated;
+        }
+    }
+
+    #[test]
+    fn updates_map_unchanged_nodes() {
+        let previous = Analysis::new(Compiler::new(), "test.nu", b"let x = 1 + 2\n$x");
+        let updated = previous.update(Compiler::new(), "test.nu", b"let y = 0\nlet x = 1 + 2\n$x");
+        let nodes = updated.previous_nodes.expect("updated from an analysis");
+
+        let find = |compiler: &Compiler, text: &[u8]| {
+            (0..compiler.ast_nodes.len())
+                .map(NodeId)
+                .find(|node_id| compiler.get_span_contents(*node_id) == text)
+                .expect("node not found")
+        };
+        assert_eq!(
+            nodes.get(find(&previous.compiler, b"1 + 2")),
+            Some(find(&updated.compiler, b"1 + 2"))
+        );
+        assert_eq!(nodes.get_old(find(&updated.compiler, b"0")), None);
+    }
+
+    #[test]
+    fn edits_between_versions() {
+        let edit = |old: &str, new: &str| {
+            edit_between(old.as_bytes(), new.as_bytes(), 2).expect("valid UTF-8")
+        };
+
+        assert_eq!(
+            edit("let x = 1", "let xy = 1"),
+            TextEdit::replace(Span::new(7, 7), "y")
+        );
+        assert_eq!(edit("aaa", "aa"), TextEdit::replace(Span::new(4, 5), ""));
+        assert_eq!(edit("same", "same"), TextEdit::replace(Span::new(6, 6), ""));
+        // 'ö' and 'ü' share their first byte
+        assert_eq!(edit("'ö'", "'ü'"), TextEdit::replace(Span::new(3, 5), "ü"));
+        assert_eq!(edit_between(b"a", b"\xff", 0), None);
+    }
+
+    #[test]
+    fn unknown_variables_skip_typechecking() {
+        for src in ["$y", "def f [a: string] { $b }"] {
+            let analysis = Analysis::new(Compiler::new(), "test.nu", src.as_bytes());
+            assert!(analysis.types.is_none());
+            assert_eq!(analysis.errors().count(), 1, "{src}");
+        }
+    }
+
+    #[test]
+    fn queries_run_while_compiling() {
+        let latest = Arc::new(LatestAnalysis::new());
+        latest.publish(
+            0,
+            Analysis::new(Compiler::new(), "test.nu", b"let x = 0\n$x"),
+        );
+
+        std::thread::scope(|scope| {
+            let writer = latest.clone();
+            scope.spawn(move || {
+                for version in 1..20 {
+                    let src = format!("let x = {version}\n$x + 1");
+                    let analysis = Analysis::new(Compiler::new(), "test.nu", src.as_bytes());
+                    assert!(writer.publish(version, analysis));
+                }
+            });
+
+            for _ in 0..4 {
+                let reader = latest.clone();
+                scope.spawn(move || {
+                    for _ in 0..20 {
+                        let analysis = reader.current().expect("version 0 is stored");
+                        assert!(analysis.compiler.source.starts_with(b"let x = "));
+                        assert_eq!(analysis.errors().count(), 0);
+                    }
+                });
+            }
+        });
+
+        let analysis = latest.current().expect("the last version is stored");
+        assert_eq!(analysis.compiler.source, b"let x = 19\n$x + 1");
+    }
+}
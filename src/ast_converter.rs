@@ -0,0 +1,649 @@
+use crate::compiler::Compiler;
+use crate::errors::{ErrorCode, Message, Severity, SourceError};
+use crate::parser::{
+    binary_literal_value, datetime_literal_value, float_literal_value, int_literal_value,
+    interpolation_chunk_value, raw_string_value, unit_literal_value, AstNode, CellPathMember,
+    NodeId, RecordEntry, DURATION_UNITS, FILESIZE_UNITS,
+};
+use crate::typechecker::Type;
+use nu_protocol::ast::{
+    Assignment, Bits, Block, Boolean, Comparison, Expr, Expression, FullCellPath, ListItem, Math,
+    Operator, PathMember, Pipeline, Range, RangeInclusion, RangeOperator, RecordItem, Unit,
+    ValueWithUnit,
+};
+use nu_protocol::engine::StateWorkingSet;
+use nu_protocol::{FilesizeUnit, PositionalArg, Signature, Span, Spanned, SyntaxShape, VarId};
+use std::sync::Arc;
+
+/// Converts the AST into the nu_protocol AST consumed by the nushell engine.
+///
+/// Nested blocks and closures are added to the working set. Variables resolved from the engine
+/// refer to the engine's VarIds, other variables refer to the VarIds of this crate's resolver which
+/// do not correspond to the variables in the working set yet. Nodes that
+/// would be converted into calls of nushell's keyword commands (`let`, `def`, `if`, ...) are not
+/// supported yet.
+pub struct AstConverter<'a, 'b, 'c> {
+    // Immutable reference to a compiler after the typechecker pass
+    compiler: &'a Compiler,
+    working_set: &'b mut StateWorkingSet<'c>,
+    /// Offset of the compiler's source in the working set
+    span_offset: usize,
+    errors: Vec<SourceError>,
+    block: Option<Block>,
+}
+
+impl<'a, 'b, 'c> AstConverter<'a, 'b, 'c> {
+    /// Creates a new converter, adding all source code of the compiler to the working set.
+    pub fn new(compiler: &'a Compiler, working_set: &'b mut StateWorkingSet<'c>) -> Self {
+        let span_offset = working_set.next_span_start();
+        let fname = compiler
+            .file_offsets
+            .first()
+            .map(|(fname, _, _)| fname.clone())
+            .unwrap_or_default();
+        let _ = working_set.add_file(fname, &compiler.source);
+
+        Self {
+            compiler,
+            working_set,
+            span_offset,
+            errors: Default::default(),
+            block: None,
+        }
+    }
+
+    /// Returns the converted root block.
+    ///
+    /// Call `convert` before using this method and ensure there are no errors.
+    pub fn block(self) -> Option<Block> {
+        self.block
+    }
+
+    /// Returns errors encountered during the conversion.
+    ///
+    /// Call `convert` before using this method.
+    pub fn errors(&self) -> &Vec<SourceError> {
+        &self.errors
+    }
+
+    /// Prints the internal state to standard output.
+    pub fn print(&self) {
+        let output = self.display_state();
+        print!("{output}");
+    }
+
+    /// Displays the converted block, suitable for snapshot tests.
+    pub fn display_state(&self) -> String {
+        let mut result = String::new();
+        result.push_str("==== NU AST ====\n");
+
+        if let Some(block) = &self.block {
+            for (idx, pipeline) in block.pipelines.iter().enumerate() {
+                for element in &pipeline.elements {
+                    result.push_str(&format!(
+                        "{}: {} ({} to {}): {}\n",
+                        idx,
+                        self.expr_to_string(&element.expr),
+                        element.expr.span.start - self.span_offset,
+                        element.expr.span.end - self.span_offset,
+                        element.expr.ty
+                    ));
+                }
+            }
+        }
+
+        if !self.errors.is_empty() {
+            result.push_str("==== NU AST ERRORS ====\n");
+            for error in &self.errors {
+                result.push_str(&error.display(self.compiler));
+            }
+        }
+
+        result
+    }
+
+    /// Converts the AST, starting from the last node.
+    /// After this is called, use `block` and `errors` to get the result.
+    pub fn convert(&mut self) {
+        if self.compiler.ast_nodes.is_empty() {
+            return;
+        }
+
+        let node_id = NodeId(self.compiler.ast_nodes.len() - 1);
+        self.block = Some(self.convert_block(node_id, Signature::new("")));
+    }
+
+    fn convert_block(&mut self, node_id: NodeId, signature: Signature) -> Block {
+        let mut block = Block::new();
+        block.signature = Box::new(signature);
+        block.span = Some(self.span(node_id));
+
+        if let AstNode::Block(block_id) = self.compiler.get_node(node_id) {
+            for inner_node_id in &self.compiler.blocks[block_id.0].nodes {
+                let expr = self.convert_node(*inner_node_id);
+                block.pipelines.push(Pipeline::from_vec(vec![expr]));
+            }
+        } else {
+            let expr = self.convert_node(node_id);
+            block.pipelines.push(Pipeline::from_vec(vec![expr]));
+        }
+
+        block
+    }
+
+    fn convert_node(&mut self, node_id: NodeId) -> Expression {
+        let expr = match self.compiler.get_node(node_id) {
+            AstNode::Int => match int_literal_value(self.compiler.get_span_contents(node_id)) {
+                Ok(val) => Expr::Int(val),
+                Err(err) => {
+                    self.error(
+                        ErrorCode::ConvertInvalidLiteral,
+                        format!("failed to convert a node to i64: {err}"),
+                        node_id,
+                    );
+                    Expr::Garbage
+                }
+            },
+            AstNode::Float => match float_literal_value(self.compiler.get_span_contents(node_id)) {
+                Ok(val) => Expr::Float(val),
+                Err(err) => {
+                    self.error(
+                        ErrorCode::ConvertInvalidLiteral,
+                        format!("failed to convert a node to f64: {err}"),
+                        node_id,
+                    );
+                    Expr::Garbage
+                }
+            },
+            AstNode::Filesize => {
+                self.unit_value(node_id, FILESIZE_UNITS, Unit::Filesize(FilesizeUnit::B))
+            }
+            AstNode::Duration => self.unit_value(node_id, DURATION_UNITS, Unit::Nanosecond),
+            AstNode::Binary => match binary_literal_value(self.compiler.get_span_contents(node_id))
+            {
+                Ok(val) => Expr::Binary(val),
+                Err(err) => {
+                    self.error(
+                        ErrorCode::ConvertInvalidLiteral,
+                        format!("failed to convert a node to binary: {err}"),
+                        node_id,
+                    );
+                    Expr::Garbage
+                }
+            },
+            AstNode::Datetime => {
+                match datetime_literal_value(self.compiler.get_span_contents(node_id)) {
+                    Ok(val) => Expr::DateTime(val),
+                    Err(err) => {
+                        self.error(
+                            ErrorCode::ConvertInvalidLiteral,
+                            format!("failed to convert a node to datetime: {err}"),
+                            node_id,
+                        );
+                        Expr::Garbage
+                    }
+                }
+            }
+            AstNode::String | AstNode::Name => {
+                let val = trim_quotes(&self.span_to_string(node_id));
+                match self.node_type(node_id) {
+                    nu_protocol::Type::Glob => Expr::GlobPattern(val, false),
+                    _ => Expr::String(val),
+                }
+            }
+            AstNode::RawString => {
+                let val = raw_string_value(self.compiler.get_span_contents(node_id));
+                Expr::RawString(String::from_utf8_lossy(val).to_string())
+            }
+            AstNode::GlobPattern => {
+                let val = self.span_to_string(node_id);
+                match self.node_type(node_id) {
+                    nu_protocol::Type::String => Expr::String(val),
+                    _ => Expr::GlobPattern(val, false),
+                }
+            }
+            AstNode::True => Expr::Bool(true),
+            AstNode::False => Expr::Bool(false),
+            AstNode::Null => Expr::Nothing,
+            AstNode::Variable => match self.compiler.var_resolution.get(&node_id) {
+                Some(var_id) => Expr::Var(
+                    self.compiler
+                        .engine_var_id(*var_id)
+                        .unwrap_or(VarId::new(var_id.0)),
+                ),
+                None => {
+                    self.error(
+                        ErrorCode::ConvertUnresolvedVariable,
+                        "variable is not resolved",
+                        node_id,
+                    );
+                    Expr::Garbage
+                }
+            },
+            AstNode::UnaryOp { op, value } if *self.compiler.get_node(*op) == AstNode::Not => {
+                Expr::UnaryNot(Box::new(self.convert_node(*value)))
+            }
+            AstNode::BinaryOp { lhs, op, rhs } => {
+                let (lhs, op_node_id, rhs) = (*lhs, *op, *rhs);
+                let lhs = self.convert_node(lhs);
+                let op = match self.node_to_operator(op_node_id) {
+                    Some(op) => self.expression(Expr::Operator(op), op_node_id),
+                    None => self.expression(Expr::Garbage, op_node_id),
+                };
+                let rhs = self.convert_node(rhs);
+                Expr::BinaryOp(Box::new(lhs), Box::new(op), Box::new(rhs))
+            }
+            AstNode::Range {
+                from,
+                next,
+                to,
+                exclusive,
+            } => {
+                let (from, next, to, exclusive) = (*from, *next, *to, *exclusive);
+                let span = self.span(node_id);
+                let from = from.map(|from| self.convert_node(from));
+                let next = next.map(|next| self.convert_node(next));
+                let to = to.map(|to| self.convert_node(to));
+
+                // the operators are between the bounds
+                let from_end = from.as_ref().map_or(span.start, |from| from.span.end);
+                let op_start = next.as_ref().map_or(from_end, |next| next.span.end);
+                let op_span = Span::new(op_start, to.as_ref().map_or(span.end, |to| to.span.start));
+                let next_op_span = next
+                    .as_ref()
+                    .map_or(op_span, |next| Span::new(from_end, next.span.start));
+                let inclusion = if exclusive {
+                    RangeInclusion::RightExclusive
+                } else {
+                    RangeInclusion::Inclusive
+                };
+
+                Expr::Range(Box::new(Range {
+                    from,
+                    next,
+                    to,
+                    operator: RangeOperator {
+                        inclusion,
+                        span: op_span,
+                        next_op_span,
+                    },
+                }))
+            }
+            AstNode::CellPath { head, members } => {
+                let head = self.convert_node(*head);
+                let tail = members
+                    .iter()
+                    .map(|member| self.path_member(*member))
+                    .collect::<Option<_>>();
+                match tail {
+                    Some(tail) => Expr::FullCellPath(Box::new(FullCellPath { head, tail })),
+                    None => Expr::Garbage,
+                }
+            }
+            AstNode::List(items) => Expr::List(
+                items
+                    .iter()
+                    .map(|item| match self.compiler.get_node(*item) {
+                        AstNode::Spread(value) => {
+                            ListItem::Spread(self.span(*item), self.convert_node(*value))
+                        }
+                        _ => ListItem::Item(self.convert_node(*item)),
+                    })
+                    .collect(),
+            ),
+            AstNode::StringInterpolation(parts) => {
+                let double_quoted = self.compiler.get_span_contents(node_id).starts_with(b"$\"");
+                Expr::StringInterpolation(
+                    parts
+                        .iter()
+                        .map(|part| match self.compiler.get_node(*part) {
+                            AstNode::String => self.interpolation_chunk(*part, double_quoted),
+                            _ => self.convert_node(*part),
+                        })
+                        .collect(),
+                )
+            }
+            AstNode::Record { entries } => Expr::Record(
+                entries
+                    .iter()
+                    .map(|entry| match entry {
+                        RecordEntry::Pair(key, val) => {
+                            RecordItem::Pair(self.convert_node(*key), self.convert_node(*val))
+                        }
+                        RecordEntry::Spread(spread) => {
+                            let AstNode::Spread(value) = self.compiler.get_node(*spread) else {
+                                panic!("internal error: record spread is not a Spread node");
+                            };
+                            RecordItem::Spread(self.span(*spread), self.convert_node(*value))
+                        }
+                    })
+                    .collect(),
+            ),
+            AstNode::Block(_) => {
+                let block = self.convert_block(node_id, Signature::new("block"));
+                Expr::Block(self.working_set.add_block(Arc::new(block)))
+            }
+            AstNode::Subexpression(block) => {
+                let block = self.convert_block(*block, Signature::new("subexpression"));
+                Expr::Subexpression(self.working_set.add_block(Arc::new(block)))
+            }
+            AstNode::Closure { params, block } => {
+                let (params, block) = (*params, *block);
+                let signature = self.closure_signature(params);
+                let block = self.convert_block(block, signature);
+                Expr::Closure(self.working_set.add_block(Arc::new(block)))
+            }
+            AstNode::Statement(inner) => return self.convert_node(*inner),
+            // Already reported by the parser
+            AstNode::Garbage => Expr::Garbage,
+            ast_node => {
+                self.error(
+                    ErrorCode::ConvertUnsupportedNode,
+                    format!("node {:?} not supported yet", ast_node),
+                    node_id,
+                );
+                Expr::Garbage
+            }
+        };
+
+        self.expression(expr, node_id)
+    }
+
+    /// Value in bytes or nanoseconds, the smallest `unit`
+    fn unit_value(&mut self, node_id: NodeId, units: &[(&str, i64)], unit: Unit) -> Expr {
+        match unit_literal_value(self.compiler.get_span_contents(node_id), units) {
+            Ok(val) => {
+                let span = self.span(node_id);
+                let expr = Expression::new(
+                    self.working_set,
+                    Expr::Int(val),
+                    span,
+                    nu_protocol::Type::Int,
+                );
+                Expr::ValueWithUnit(Box::new(ValueWithUnit {
+                    expr,
+                    unit: Spanned { item: unit, span },
+                }))
+            }
+            Err(err) => {
+                self.error(
+                    ErrorCode::ConvertInvalidLiteral,
+                    format!("failed to convert a node to i64: {err}"),
+                    node_id,
+                );
+                Expr::Garbage
+            }
+        }
+    }
+
+    /// Literal part of a string interpolation, it is not quoted
+    fn interpolation_chunk(&mut self, node_id: NodeId, double_quoted: bool) -> Expression {
+        let contents = self.compiler.get_span_contents(node_id);
+        let expr = match interpolation_chunk_value(contents, double_quoted) {
+            Ok(value) => Expr::String(String::from_utf8_lossy(&value).into_owned()),
+            Err(err) => {
+                self.error(
+                    ErrorCode::ConvertInvalidLiteral,
+                    format!("failed to convert a node to string: {err}"),
+                    node_id,
+                );
+                Expr::Garbage
+            }
+        };
+        self.expression(expr, node_id)
+    }
+
+    fn path_member(&mut self, member: CellPathMember) -> Option<PathMember> {
+        let CellPathMember { member, optional } = member;
+        let span = self.span(member);
+
+        if *self.compiler.get_node(member) != AstNode::Int {
+            let val = trim_quotes(&self.span_to_string(member));
+            return Some(PathMember::String {
+                val,
+                span,
+                optional,
+            });
+        }
+
+        match self.span_to_string(member).replace('_', "").parse() {
+            Ok(val) => Some(PathMember::Int {
+                val,
+                span,
+                optional,
+            }),
+            Err(err) => {
+                self.error(
+                    ErrorCode::ConvertInvalidLiteral,
+                    format!("failed to convert a cell path index to usize: {err}"),
+                    member,
+                );
+                None
+            }
+        }
+    }
+
+    fn closure_signature(&mut self, params: Option<NodeId>) -> Signature {
+        let mut signature = Signature::new("closure");
+
+        let Some(AstNode::Params(params)) = params.map(|id| self.compiler.get_node(id)) else {
+            return signature;
+        };
+
+        for param in params {
+            if let AstNode::Param { name, .. } = self.compiler.get_node(*param) {
+                signature.required_positional.push(PositionalArg {
+                    name: trim_var_name(&self.span_to_string(*name)),
+                    desc: String::new(),
+                    shape: SyntaxShape::Any,
+                    var_id: self
+                        .compiler
+                        .var_resolution
+                        .get(name)
+                        .map(|var_id| VarId::new(var_id.0)),
+                    default_value: None,
+                });
+            }
+        }
+
+        signature
+    }
+
+    fn expression(&mut self, expr: Expr, node_id: NodeId) -> Expression {
+        let ty = self.node_type(node_id);
+        Expression::new(self.working_set, expr, self.span(node_id), ty)
+    }
+
+    fn span(&self, node_id: NodeId) -> Span {
+        let span = self.compiler.get_span(node_id);
+        Span::new(span.start + self.span_offset, span.end + self.span_offset)
+    }
+
+    fn node_type(&self, node_id: NodeId) -> nu_protocol::Type {
+        match self.compiler.node_types.get(node_id.0) {
+            Some(type_id) => self.convert_type(*type_id),
+            None => nu_protocol::Type::Any,
+        }
+    }
+
+    fn convert_type(&self, type_id: crate::typechecker::TypeId) -> nu_protocol::Type {
+        match self.compiler.types.get(type_id.0) {
+            Some(Type::Int) => nu_protocol::Type::Int,
+            Some(Type::Float) => nu_protocol::Type::Float,
+            Some(Type::Number) => nu_protocol::Type::Number,
+            Some(Type::Bool) => nu_protocol::Type::Bool,
+            Some(Type::String) => nu_protocol::Type::String,
+            Some(Type::Glob) => nu_protocol::Type::Glob,
+            Some(Type::Binary) | Some(Type::Stream(_)) => nu_protocol::Type::Binary,
+            Some(Type::Closure) => nu_protocol::Type::Closure,
+            Some(Type::Range) => nu_protocol::Type::Range,
+            Some(Type::Filesize) => nu_protocol::Type::Filesize,
+            Some(Type::Duration) => nu_protocol::Type::Duration,
+            Some(Type::Datetime) => nu_protocol::Type::Date,
+            Some(Type::Nothing) | Some(Type::None) => nu_protocol::Type::Nothing,
+            Some(Type::Error) => nu_protocol::Type::Error,
+            Some(Type::List(inner)) => nu_protocol::Type::list(self.convert_type(*inner)),
+            _ => nu_protocol::Type::Any,
+        }
+    }
+
+    fn node_to_operator(&mut self, node_id: NodeId) -> Option<Operator> {
+        let op = match self.compiler.get_node(node_id) {
+            AstNode::Pow => Operator::Math(Math::Pow),
+            AstNode::Multiply => Operator::Math(Math::Multiply),
+            AstNode::Divide => Operator::Math(Math::Divide),
+            AstNode::FloorDiv => Operator::Math(Math::FloorDivision),
+            AstNode::Modulo => Operator::Math(Math::Modulo),
+            AstNode::Plus => Operator::Math(Math::Plus),
+            AstNode::Minus => Operator::Math(Math::Minus),
+            AstNode::Append => Operator::Math(Math::Concat),
+            AstNode::Equal => Operator::Comparison(Comparison::Equal),
+            AstNode::NotEqual => Operator::Comparison(Comparison::NotEqual),
+            AstNode::LessThan => Operator::Comparison(Comparison::LessThan),
+            AstNode::GreaterThan => Operator::Comparison(Comparison::GreaterThan),
+            AstNode::LessThanOrEqual => Operator::Comparison(Comparison::LessThanOrEqual),
+            AstNode::GreaterThanOrEqual => Operator::Comparison(Comparison::GreaterThanOrEqual),
+            AstNode::RegexMatch => Operator::Comparison(Comparison::RegexMatch),
+            AstNode::NotRegexMatch => Operator::Comparison(Comparison::NotRegexMatch),
+            AstNode::In => Operator::Comparison(Comparison::In),
+            AstNode::NotIn => Operator::Comparison(Comparison::NotIn),
+            AstNode::StartsWith => Operator::Comparison(Comparison::StartsWith),
+            AstNode::EndsWith => Operator::Comparison(Comparison::EndsWith),
+            AstNode::BitOr => Operator::Bits(Bits::BitOr),
+            AstNode::BitXor => Operator::Bits(Bits::BitXor),
+            AstNode::BitAnd => Operator::Bits(Bits::BitAnd),
+            AstNode::ShiftLeft => Operator::Bits(Bits::ShiftLeft),
+            AstNode::ShiftRight => Operator::Bits(Bits::ShiftRight),
+            AstNode::And => Operator::Boolean(Boolean::And),
+            AstNode::Xor => Operator::Boolean(Boolean::Xor),
+            AstNode::Or => Operator::Boolean(Boolean::Or),
+            AstNode::Assignment => Operator::Assignment(Assignment::Assign),
+            AstNode::AddAssignment => Operator::Assignment(Assignment::PlusAssign),
+            AstNode::SubtractAssignment => Operator::Assignment(Assignment::MinusAssign),
+            AstNode::MultiplyAssignment => Operator::Assignment(Assignment::MultiplyAssign),
+            AstNode::DivideAssignment => Operator::Assignment(Assignment::DivideAssign),
+            AstNode::AppendAssignment => Operator::Assignment(Assignment::ConcatAssign),
+            node => {
+                self.error(
+                    ErrorCode::ConvertUnsupportedOperator,
+                    format!("unrecognized operator {:?}", node),
+                    node_id,
+                );
+                return None;
+            }
+        };
+
+        Some(op)
+    }
+
+    fn expr_to_string(&self, expr: &Expression) -> String {
+        match &expr.expr {
+            Expr::Int(val) => format!("Int({val})"),
+            Expr::Float(val) => format!("Float({val})"),
+            Expr::Bool(val) => format!("Bool({val})"),
+            Expr::String(val) => format!("String({val:?})"),
+            Expr::Var(var_id) => format!("Var({})", var_id.get()),
+            Expr::Operator(op) => format!("Operator({op})"),
+            Expr::BinaryOp(lhs, op, rhs) => format!(
+                "BinaryOp({}, {}, {})",
+                self.expr_to_string(lhs),
+                self.expr_to_string(op),
+                self.expr_to_string(rhs)
+            ),
+            Expr::Range(range) => format!(
+                "Range({}, {})",
+                range
+                    .from
+                    .as_ref()
+                    .map(|from| self.expr_to_string(from))
+                    .unwrap_or_default(),
+                range
+                    .to
+                    .as_ref()
+                    .map(|to| self.expr_to_string(to))
+                    .unwrap_or_default()
+            ),
+            Expr::List(items) => {
+                let items: Vec<_> = items
+                    .iter()
+                    .map(|item| match item {
+                        ListItem::Item(expr) => self.expr_to_string(expr),
+                        ListItem::Spread(_, expr) => format!("...{}", self.expr_to_string(expr)),
+                    })
+                    .collect();
+                format!("List[{}]", items.join(", "))
+            }
+            Expr::Record(items) => {
+                let items: Vec<_> = items
+                    .iter()
+                    .map(|item| match item {
+                        RecordItem::Pair(key, val) => {
+                            format!("{}: {}", self.expr_to_string(key), self.expr_to_string(val))
+                        }
+                        RecordItem::Spread(_, val) => format!("...{}", self.expr_to_string(val)),
+                    })
+                    .collect();
+                format!("Record{{{}}}", items.join(", "))
+            }
+            Expr::Block(block_id) | Expr::Closure(block_id) => {
+                let block = self.working_set.get_block(*block_id);
+                let params: Vec<_> = block
+                    .signature
+                    .required_positional
+                    .iter()
+                    .map(|param| param.name.clone())
+                    .collect();
+                let pipelines: Vec<_> = block
+                    .pipelines
+                    .iter()
+                    .flat_map(|pipeline| &pipeline.elements)
+                    .map(|element| self.expr_to_string(&element.expr))
+                    .collect();
+                let kind = if matches!(expr.expr, Expr::Block(_)) {
+                    "Block"
+                } else {
+                    "Closure"
+                };
+                format!(
+                    "{kind}|{}| {{ {} }}",
+                    params.join(", "),
+                    pipelines.join("; ")
+                )
+            }
+            Expr::Nothing => "Nothing".to_string(),
+            Expr::Garbage => "Garbage".to_string(),
+            other => format!("{other:?}"),
+        }
+    }
+
+    fn span_to_string(&self, node_id: NodeId) -> String {
+        String::from_utf8_lossy(self.compiler.get_span_contents(node_id)).to_string()
+    }
+
+    fn error(&mut self, code: ErrorCode, message: impl Into<Message>, node: NodeId) {
+        self.errors.push(SourceError {
+            code,
+            message: message.into(),
+            node_id: node,
+            severity: Severity::Error,
+            help: None,
+            note: None,
+            related: vec![],
+            fixes: vec![],
+        })
+    }
+}
+
+fn trim_quotes(s: &str) -> String {
+    for quote in ['"', '\'', '`'] {
+        if s.len() >= 2 && s.starts_with(quote) && s.ends_with(quote) {
+            return s[1..s.len() - 1].to_string();
+        }
+    }
+
+    s.to_string()
+}
+
+fn trim_var_name(s: &str) -> String {
+    s.strip_prefix('$').unwrap_or(s).to_string()
+}
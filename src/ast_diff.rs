@@ -0,0 +1,280 @@
+//! Structural comparison of two parses
+//!
+//! Unlike comparing the sources, the diff ignores trivia: whitespace, comments and the positions
+//! of the nodes do not matter, only the kinds of the nodes, their structure and the source text of
+//! the leaves (names, literals, operators, ...). This is useful for checking that a refactoring or
+//! formatting did not change the meaning of a file, and for testing incremental reparsing.
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use crate::compiler::Compiler;
+use crate::parser::{AstNode, NodeId};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AstChange {
+    /// Node of the new parse without a counterpart in the old one
+    Added(NodeId),
+    /// Node of the old parse without a counterpart in the new one
+    Removed(NodeId),
+    /// Node replaced by a different one, e.g., a literal with another value or an expression of
+    /// another kind. The children of the nodes are not compared further.
+    Changed { old: NodeId, new: NodeId },
+}
+
+/// Changes between two parses, ordered by their position in the tree
+#[derive(Debug, Default)]
+pub struct AstDiff {
+    pub changes: Vec<AstChange>,
+}
+
+impl AstDiff {
+    /// Compare the ASTs of two compilations, starting from their last (root) nodes
+    pub fn new(old: &Compiler, new: &Compiler) -> Self {
+        let mut diff = AstDiff::default();
+
+        match (old.ast_nodes.len(), new.ast_nodes.len()) {
+            (0, 0) => (),
+            (0, len) => diff.changes.push(AstChange::Added(NodeId(len - 1))),
+            (len, 0) => diff.changes.push(AstChange::Removed(NodeId(len - 1))),
+            (old_len, new_len) => {
+                let mut differ = Differ {
+                    old: Hashes::new(old),
+                    new: Hashes::new(new),
+                    changes: &mut diff.changes,
+                };
+                differ.diff_nodes(NodeId(old_len - 1), NodeId(new_len - 1));
+            }
+        }
+
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Human-readable list of the changes, one per line
+    pub fn display(&self, old: &Compiler, new: &Compiler) -> String {
+        let node = |compiler: &Compiler, node_id: NodeId| {
+            let span = compiler.get_span(node_id);
+            format!(
+                "{} ({} to {}) {:?}",
+                node_kind(compiler.get_node(node_id)),
+                span.start,
+                span.end,
+                String::from_utf8_lossy(compiler.get_span_contents(node_id))
+            )
+        };
+
+        let mut result = String::new();
+        result.push_str("==== AST DIFF ====\n");
+
+        for change in &self.changes {
+            let line = match change {
+                AstChange::Added(node_id) => format!("+ {}", node(new, *node_id)),
+                AstChange::Removed(node_id) => format!("- {}", node(old, *node_id)),
+                AstChange::Changed {
+                    old: old_id,
+                    new: new_id,
+                } => {
+                    format!("~ {} => {}", node(old, *old_id), node(new, *new_id))
+                }
+            };
+            result.push_str(&line);
+            result.push('\n');
+        }
+
+        result
+    }
+}
+
+/// Name of the node's variant without its fields
+fn node_kind(node: &AstNode) -> String {
+    let debug = format!("{node:?}");
+    debug
+        .split([' ', '('])
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Hashes of the subtrees of all nodes of a compilation, ignoring trivia, indexed by NodeId
+struct Hashes<'a> {
+    compiler: &'a Compiler,
+    hashes: Vec<Option<u64>>,
+}
+
+impl<'a> Hashes<'a> {
+    fn new(compiler: &'a Compiler) -> Self {
+        Self {
+            compiler,
+            hashes: vec![None; compiler.ast_nodes.len()],
+        }
+    }
+
+    fn get(&mut self, node_id: NodeId) -> u64 {
+        if let Some(hash) = self.hashes[node_id.0] {
+            return hash;
+        }
+
+        let mut hasher = DefaultHasher::new();
+        self.label(node_id).hash(&mut hasher);
+        for child in self.compiler.node_children(node_id) {
+            self.get(child).hash(&mut hasher);
+        }
+
+        let hash = hasher.finish();
+        self.hashes[node_id.0] = Some(hash);
+        hash
+    }
+
+    /// What identifies the node itself, without its children: its kind, the fields that are not
+    /// child nodes and, for leaves, the source text
+    fn label(&self, node_id: NodeId) -> u64 {
+        let node = self.compiler.get_node(node_id);
+
+        let mut hasher = DefaultHasher::new();
+        std::mem::discriminant(node).hash(&mut hasher);
+        match node {
+            AstNode::Type { optional, .. } => optional.hash(&mut hasher),
+            AstNode::Let { is_mutable, .. } => is_mutable.hash(&mut hasher),
+            _ if self.compiler.node_children(node_id).is_empty() => {
+                self.compiler.get_span_contents(node_id).hash(&mut hasher)
+            }
+            _ => (),
+        }
+
+        hasher.finish()
+    }
+}
+
+struct Differ<'a, 'b> {
+    old: Hashes<'a>,
+    new: Hashes<'a>,
+    changes: &'b mut Vec<AstChange>,
+}
+
+impl Differ<'_, '_> {
+    fn diff_nodes(&mut self, old_node_id: NodeId, new_node_id: NodeId) {
+        if self.old.get(old_node_id) == self.new.get(new_node_id) {
+            return;
+        }
+
+        if self.old.label(old_node_id) != self.new.label(new_node_id) {
+            self.changes.push(AstChange::Changed {
+                old: old_node_id,
+                new: new_node_id,
+            });
+            return;
+        }
+
+        let old_children = self.old.compiler.node_children(old_node_id);
+        let new_children = self.new.compiler.node_children(new_node_id);
+
+        let old_hashes: Vec<_> = old_children.iter().map(|c| self.old.get(*c)).collect();
+        let new_hashes: Vec<_> = new_children.iter().map(|c| self.new.get(*c)).collect();
+
+        // The unchanged children split the rest into gaps, whose children are paired up in order
+        let mut anchors = longest_common_subsequence(&old_hashes, &new_hashes);
+        anchors.push((old_children.len(), new_children.len()));
+
+        let (mut old_start, mut new_start) = (0, 0);
+        for (old_end, new_end) in anchors {
+            let old_gap = &old_children[old_start..old_end];
+            let new_gap = &new_children[new_start..new_end];
+
+            for (old_child, new_child) in old_gap.iter().zip(new_gap) {
+                self.diff_nodes(*old_child, *new_child);
+            }
+            for old_child in old_gap.iter().skip(new_gap.len()) {
+                self.changes.push(AstChange::Removed(*old_child));
+            }
+            for new_child in new_gap.iter().skip(old_gap.len()) {
+                self.changes.push(AstChange::Added(*new_child));
+            }
+
+            (old_start, new_start) = (old_end + 1, new_end + 1);
+        }
+    }
+}
+
+/// Index pairs of the equal elements of the longest common subsequence of `a` and `b`
+fn longest_common_subsequence(a: &[u64], b: &[u64]) -> Vec<(usize, usize)> {
+    // lengths[i][j] is the length of the LCS of a[i..] and b[j..]
+    let mut lengths = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i][j] = if a[i] == b[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut pairs = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+
+    pairs
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AstChange, AstDiff};
+    use crate::compiler::Compiler;
+
+    fn parse(src: &[u8]) -> Compiler {
+        let mut compiler = Compiler::new();
+        assert!(compiler.check("test.nu", src));
+        compiler
+    }
+
+    fn diff(old: &[u8], new: &[u8]) -> Vec<String> {
+        let (old, new) = (parse(old), parse(new));
+        let text = |compiler: &Compiler, node_id| {
+            String::from_utf8_lossy(compiler.get_span_contents(node_id)).into_owned()
+        };
+
+        AstDiff::new(&old, &new)
+            .changes
+            .into_iter()
+            .map(|change| match change {
+                AstChange::Added(node_id) => format!("+{}", text(&new, node_id)),
+                AstChange::Removed(node_id) => format!("-{}", text(&old, node_id)),
+                AstChange::Changed {
+                    old: old_id,
+                    new: new_id,
+                } => {
+                    format!("{}=>{}", text(&old, old_id), text(&new, new_id))
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn trivia_is_ignored() {
+        let old = b"let x = 1 + 2 # sum\n\n$x";
+        let new = b"# comment\nlet   x = 1   +   2\n$x";
+        assert!(diff(old, new).is_empty());
+    }
+
+    #[test]
+    fn changes_are_reported() {
+        let old = b"let x = 1\nlet y = 3\n$x";
+        let new = b"let x = 2\nmut y = 3\n$y\n$x";
+
+        assert_eq!(diff(old, new), ["1=>2", "let y = 3=>mut y = 3", "+$y"]);
+        assert_eq!(diff(new, old), ["2=>1", "mut y = 3=>let y = 3", "-$y"]);
+    }
+}
@@ -0,0 +1,270 @@
+//! Example language server built on the `lsp` module, communicating over stdio
+//!
+//! Run with `cargo run --features lsp-server --bin lsp` from an editor's LSP client. Documents are
+//! synchronized in full and compiled on every change in a background thread, relexing only what
+//! changed since the last finished compilation, and their diagnostics are published when done. Go
+//! to definition, hover, completion and semantic tokens are served on their own threads from the
+//! last finished compilation.
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, PoisonError, RwLock};
+
+use lsp_server::{Connection, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+    Notification as LspNotification, PublishDiagnostics,
+};
+use lsp_types::request::{
+    Completion, GotoDefinition, HoverRequest, Request as LspRequest, SemanticTokensFullRequest,
+};
+use lsp_types::{
+    CompletionOptions, CompletionResponse, GotoDefinitionResponse, HoverProviderCapability, OneOf,
+    PublishDiagnosticsParams, SemanticTokensFullOptions, SemanticTokensOptions,
+    SemanticTokensResult, SemanticTokensServerCapabilities, ServerCapabilities,
+    TextDocumentPositionParams, TextDocumentSyncCapability, TextDocumentSyncKind, Uri,
+};
+use new_nu_parser::analysis::{Analysis, LatestAnalysis};
+use new_nu_parser::compiler::Compiler;
+use new_nu_parser::lsp::{
+    completions, goto_definition, hover, semantic_tokens_legend, to_diagnostic, to_offset,
+    to_semantic_tokens,
+};
+
+/// Byte offset of the position of a request
+fn offset(analysis: &Analysis, params: &TextDocumentPositionParams) -> Option<usize> {
+    to_offset(
+        &analysis.compiler,
+        params.text_document.uri.as_str(),
+        params.position,
+    )
+}
+
+/// Open documents, shared by the threads compiling them and answering the requests
+#[derive(Clone, Default)]
+struct Documents(Arc<RwLock<HashMap<Uri, Arc<LatestAnalysis>>>>);
+
+impl Documents {
+    fn get(&self, uri: &Uri) -> Option<Arc<LatestAnalysis>> {
+        let documents = self.0.read().unwrap_or_else(PoisonError::into_inner);
+        documents.get(uri).cloned()
+    }
+
+    fn open(&self, uri: Uri) -> Arc<LatestAnalysis> {
+        let mut documents = self.0.write().unwrap_or_else(PoisonError::into_inner);
+        documents.entry(uri).or_default().clone()
+    }
+
+    fn close(&self, uri: &Uri) {
+        let mut documents = self.0.write().unwrap_or_else(PoisonError::into_inner);
+        documents.remove(uri);
+    }
+
+    /// Whether the document is still open with the same analyses
+    fn is_open(&self, uri: &Uri, latest: &Arc<LatestAnalysis>) -> bool {
+        self.get(uri)
+            .is_some_and(|current| Arc::ptr_eq(&current, latest))
+    }
+}
+
+#[derive(Default)]
+struct Server {
+    documents: Documents,
+}
+
+impl Server {
+    /// Compile the new version of the document in the background, publishing its diagnostics when
+    /// done
+    ///
+    /// Requests are answered from the previous version until then.
+    fn update(&self, uri: Uri, version: i32, text: String, connection: &Connection) {
+        let documents = self.documents.clone();
+        let latest = documents.open(uri.clone());
+        let sender = connection.sender.clone();
+
+        std::thread::spawn(move || {
+            let analysis = match latest.current() {
+                Some(previous) => previous.update(Compiler::new(), uri.as_str(), text.as_bytes()),
+                None => Analysis::new(Compiler::new(), uri.as_str(), text.as_bytes()),
+            };
+            let diagnostics = diagnostics(uri.clone(), Some(&analysis));
+
+            if latest.publish(version.into(), analysis) && documents.is_open(&uri, &latest) {
+                let _ = sender.send(Message::Notification(diagnostics));
+            }
+        });
+    }
+
+    fn handle_notification(&self, notification: Notification, connection: &Connection) {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                if let Some(params) = params::<DidOpenTextDocument>(notification) {
+                    let document = params.text_document;
+                    self.update(document.uri, document.version, document.text, connection);
+                }
+            }
+            DidChangeTextDocument::METHOD => {
+                let Some(mut params) = params::<DidChangeTextDocument>(notification) else {
+                    return;
+                };
+                // the whole document is sent on each change
+                if let Some(change) = params.content_changes.pop() {
+                    let document = params.text_document;
+                    self.update(document.uri, document.version, change.text, connection);
+                }
+            }
+            DidCloseTextDocument::METHOD => {
+                if let Some(params) = params::<DidCloseTextDocument>(notification) {
+                    let uri = params.text_document.uri;
+                    self.documents.close(&uri);
+                    let diagnostics = diagnostics(uri, None);
+                    let _ = connection.sender.send(Message::Notification(diagnostics));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Answer the request on its own thread from the latest compiled version of the document
+    fn handle_request(&self, request: Request, connection: &Connection) {
+        let documents = self.documents.clone();
+        let sender = connection.sender.clone();
+
+        std::thread::spawn(move || {
+            let response = respond_to(&documents, request);
+            let _ = sender.send(Message::Response(response));
+        });
+    }
+}
+
+fn diagnostics(uri: Uri, analysis: Option<&Analysis>) -> Notification {
+    let diagnostics = analysis
+        .map(|analysis| {
+            analysis
+                .errors()
+                .map(|error| to_diagnostic(&analysis.compiler, error, &uri))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Notification::new(
+        PublishDiagnostics::METHOD.into(),
+        PublishDiagnosticsParams::new(uri, diagnostics, None),
+    )
+}
+
+fn respond_to(documents: &Documents, request: Request) -> Response {
+    let current = |uri: &Uri| documents.get(uri)?.current();
+
+    match request.method.as_str() {
+        GotoDefinition::METHOD => respond::<GotoDefinition>(request, |params| {
+            let params = params.text_document_position_params;
+            let uri = &params.text_document.uri;
+            let analysis = current(uri)?;
+            let location = goto_definition(&analysis.compiler, offset(&analysis, &params)?, uri)?;
+            Some(GotoDefinitionResponse::Scalar(location))
+        }),
+        HoverRequest::METHOD => respond::<HoverRequest>(request, |params| {
+            let params = params.text_document_position_params;
+            let analysis = current(&params.text_document.uri)?;
+            hover(
+                &analysis.compiler,
+                analysis.types.as_ref()?,
+                offset(&analysis, &params)?,
+            )
+        }),
+        Completion::METHOD => respond::<Completion>(request, |params| {
+            let params = params.text_document_position;
+            let latest = documents.get(&params.text_document.uri)?;
+            let analysis = match latest.current()? {
+                analysis if analysis.types.is_some() => analysis,
+                analysis => latest.resolved().unwrap_or(analysis),
+            };
+            let items = completions(&analysis.compiler, offset(&analysis, &params)?);
+            Some(CompletionResponse::Array(items))
+        }),
+        SemanticTokensFullRequest::METHOD => {
+            respond::<SemanticTokensFullRequest>(request, |params| {
+                let analysis = current(&params.text_document.uri)?;
+                Some(SemanticTokensResult::Tokens(to_semantic_tokens(
+                    &analysis.compiler,
+                )))
+            })
+        }
+        _ => Response::new_err(
+            request.id,
+            lsp_server::ErrorCode::MethodNotFound as i32,
+            format!("unsupported request {}", request.method),
+        ),
+    }
+}
+
+fn params<N: LspNotification>(notification: Notification) -> Option<N::Params> {
+    notification.extract(N::METHOD).ok()
+}
+
+/// Respond to the request with the result of the handler, or with an error if the request's
+/// parameters are invalid
+fn respond<R: LspRequest>(
+    request: Request,
+    handler: impl FnOnce(R::Params) -> R::Result,
+) -> Response {
+    match serde_json::from_value::<R::Params>(request.params) {
+        Ok(params) => Response::new_ok(request.id, handler(params)),
+        Err(err) => Response::new_err(
+            request.id,
+            lsp_server::ErrorCode::InvalidParams as i32,
+            err.to_string(),
+        ),
+    }
+}
+
+fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec!["$".into()]),
+            ..Default::default()
+        }),
+        semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(
+            SemanticTokensOptions {
+                legend: semantic_tokens_legend(),
+                full: Some(SemanticTokensFullOptions::Bool(true)),
+                ..Default::default()
+            },
+        )),
+        ..Default::default()
+    }
+}
+
+fn main_loop(connection: Connection) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let server = Server::default();
+
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    break;
+                }
+                server.handle_request(request, &connection);
+            }
+            Message::Notification(notification) => {
+                server.handle_notification(notification, &connection);
+            }
+            Message::Response(_) => (),
+        }
+    }
+
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (connection, io_threads) = Connection::stdio();
+    connection.initialize(serde_json::to_value(capabilities())?)?;
+
+    main_loop(connection)?;
+    io_threads.join()?;
+
+    Ok(())
+}
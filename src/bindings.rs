@@ -0,0 +1,125 @@
+//! Compilation pipeline shared by the bindings to other languages (`wasm`, `ffi` and `python`
+//! features)
+use crate::compiler::Compiler;
+#[cfg(feature = "ffi")]
+use crate::compiler::Span;
+#[cfg(any(feature = "wasm", feature = "python"))]
+use crate::compiler::StageObserver;
+#[cfg(any(feature = "wasm", feature = "python"))]
+use crate::errors::diagnostics_json;
+#[cfg(feature = "ffi")]
+use crate::errors::Severity;
+#[cfg(any(feature = "wasm", feature = "python"))]
+use crate::resolver::Resolver;
+#[cfg(any(feature = "wasm", feature = "python"))]
+use crate::typechecker::Typechecker;
+
+/// Output of all stages that ran successfully
+pub(crate) struct Compiled {
+    pub compiler: Compiler,
+    #[cfg(any(feature = "wasm", feature = "python"))]
+    output: JsonOutput,
+}
+
+/// Error of any stage with the span it refers to
+#[cfg(feature = "ffi")]
+pub(crate) struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub help: Option<String>,
+    pub note: Option<String>,
+    pub span: Span,
+}
+
+#[cfg(feature = "ffi")]
+impl Compiled {
+    /// Errors of all stages that ran, including the lexing error
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.compiler
+            .errors
+            .iter()
+            .map(|error| Diagnostic {
+                severity: error.severity,
+                message: error.message(&self.compiler).into_owned(),
+                help: error.help.clone(),
+                note: error.note.clone(),
+                span: self.compiler.get_span(error.node_id),
+            })
+            .collect()
+    }
+}
+
+/// Output of the bindings returning structured values instead of C structs
+#[cfg(any(feature = "wasm", feature = "python"))]
+impl Compiled {
+    /// Output of each stage that ran, the same as the JSON output of the CLI
+    pub fn stages_json(&self) -> serde_json::Value {
+        let mut output = serde_json::Map::new();
+        output.insert("ast".into(), self.compiler.to_json());
+        if let Some(scope) = &self.output.scope {
+            output.insert("scope".into(), scope.clone());
+        }
+        if let Some(types) = &self.output.types {
+            output.insert("types".into(), types.clone());
+        }
+
+        serde_json::Value::Object(output)
+    }
+
+    /// Errors of all stages that ran, the same as the diagnostics in the JSON output of the CLI
+    pub fn diagnostics_json(&self) -> serde_json::Value {
+        diagnostics_json(&self.compiler, &self.compiler.errors)
+    }
+
+    /// Semantic tokens of the source, with the kinds named after LSP semantic token types
+    pub fn semantic_tokens_json(&self) -> serde_json::Value {
+        crate::semantic_tokens::semantic_tokens(&self.compiler)
+            .into_iter()
+            .map(|token| {
+                serde_json::json!({
+                    "start": token.span.start,
+                    "end": token.span.end,
+                    "kind": token.kind.as_str(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// JSON output of the name resolution and the typechecking
+#[cfg(any(feature = "wasm", feature = "python"))]
+#[derive(Default)]
+struct JsonOutput {
+    scope: Option<serde_json::Value>,
+    types: Option<serde_json::Value>,
+}
+
+#[cfg(any(feature = "wasm", feature = "python"))]
+impl StageObserver for JsonOutput {
+    fn resolved(&mut self, resolver: &Resolver) {
+        self.scope = Some(resolver.to_json());
+    }
+
+    fn typechecked(&mut self, typechecker: &Typechecker) {
+        self.types = Some(typechecker.to_json());
+    }
+}
+
+/// Run the stages up to typechecking, stopping at the first stage that reports fatal errors
+///
+/// The JSON output of the stages is only collected for the bindings returning it.
+pub(crate) fn compile(fname: &str, source: &[u8]) -> Compiled {
+    let mut compiler = Compiler::new();
+
+    #[cfg(any(feature = "wasm", feature = "python"))]
+    {
+        let mut output = JsonOutput::default();
+        compiler.run_stages(fname, source, &mut output);
+        Compiled { compiler, output }
+    }
+    #[cfg(not(any(feature = "wasm", feature = "python")))]
+    {
+        compiler.run_stages(fname, source, &mut ());
+        Compiled { compiler }
+    }
+}
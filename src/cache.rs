@@ -0,0 +1,329 @@
+//! On-disk cache of compiled files
+//!
+//! Scripts compiled on every startup, such as the configuration files, rarely change. The cache
+//! stores the IR of a successfully compiled file together with the signatures of the commands it
+//! defines, keyed by a hash of the file's contents and the compiler options (and of this crate's
+//! version), so that an unchanged file is loaded without lexing, parsing, resolving and
+//! typechecking it again.
+use std::io;
+use std::path::{Path, PathBuf};
+
+use nu_protocol::ir::IrBlock;
+
+use crate::compiler::{Compiler, CompilerOptions};
+use crate::ir_generator::IrGenerator;
+use crate::lint::Lint;
+use crate::parser::AstNode;
+use crate::protocol::{Signature, Signatures};
+use crate::typechecker::Types;
+
+/// Result of compiling a file without fatal errors
+#[derive(Debug, Clone)]
+pub struct CompiledFile {
+    pub ir_block: IrBlock,
+    /// Signatures of the commands defined at the top level of the file
+    pub signatures: Signatures,
+}
+
+impl CompiledFile {
+    /// Run all the stages on the file
+    ///
+    /// Returns the compiler with the errors if any stage reported fatal errors.
+    pub fn compile(
+        fname: &str,
+        contents: &[u8],
+        options: CompilerOptions,
+    ) -> Result<Self, Box<Compiler>> {
+        let mut compiler = Compiler::new();
+        compiler.options = options;
+
+        let types = compiler.run_stages(fname, contents, &mut ());
+        let Some(types) = types.filter(|_| !compiler.has_fatal_errors(&compiler.errors)) else {
+            return Err(Box::new(compiler));
+        };
+        let signatures = defined_signatures(&compiler, &types);
+
+        let mut ir_generator = IrGenerator::new(&compiler);
+        ir_generator.generate();
+        if compiler.has_fatal_errors(ir_generator.errors()) {
+            let errors = ir_generator.errors().clone();
+            compiler.errors.extend(errors);
+            return Err(Box::new(compiler));
+        }
+
+        Ok(Self {
+            ir_block: ir_generator.block(),
+            signatures,
+        })
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "ir_block": self.ir_block,
+            "signatures": self.signatures.to_json(),
+        })
+    }
+
+    /// Read the JSON written by `to_json()`, None if it is not valid
+    pub fn from_json(json: &[u8]) -> Option<Self> {
+        let mut value: serde_json::Value = serde_json::from_slice(json).ok()?;
+
+        let ir_block = serde_json::from_value(value.get_mut("ir_block")?.take()).ok()?;
+        let mut signatures = Signatures::new();
+        signatures
+            .add_json(&value.get("signatures")?.to_string())
+            .ok()?;
+
+        Some(Self {
+            ir_block,
+            signatures,
+        })
+    }
+}
+
+/// Signatures of the commands defined by the `def`s at the top level of the compiled file
+fn defined_signatures(compiler: &Compiler, types: &Types) -> Signatures {
+    let mut signatures = Signatures::new();
+
+    let Some(AstNode::Block(block_id)) = compiler.ast_nodes.last() else {
+        return signatures;
+    };
+
+    for node_id in &compiler.blocks[block_id.0].nodes {
+        let AstNode::Def { name, .. } = compiler.ast_nodes[node_id.0] else {
+            continue;
+        };
+        let Some(decl_id) = compiler.decl_resolution.get(&name) else {
+            continue;
+        };
+
+        let mut signature = Signature::new(compiler.decls[decl_id.0].name());
+        for in_out_type in &types.decl_types[decl_id.0] {
+            signature = signature.input_output_type(
+                types.type_to_string(in_out_type.in_type),
+                types.type_to_string(in_out_type.out_type),
+            );
+        }
+        signatures.add(signature);
+    }
+
+    signatures
+}
+
+/// Directory with the compiled files, one JSON file per cached source
+#[derive(Debug, Clone)]
+pub struct CompilationCache {
+    dir: PathBuf,
+}
+
+impl CompilationCache {
+    /// The directory is created when the first file is stored
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn entry_path(&self, contents: &[u8], options: &CompilerOptions) -> PathBuf {
+        self.dir
+            .join(format!("{:016x}.json", content_hash(contents, options)))
+    }
+
+    /// The cached compilation of the contents with the options, None if there is none or it
+    /// cannot be read
+    pub fn get(&self, contents: &[u8], options: &CompilerOptions) -> Option<CompiledFile> {
+        let json = std::fs::read(self.entry_path(contents, options)).ok()?;
+        CompiledFile::from_json(&json)
+    }
+
+    pub fn put(
+        &self,
+        contents: &[u8],
+        options: &CompilerOptions,
+        compiled: &CompiledFile,
+    ) -> io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+
+        // Written to a temporary file first, so that a concurrent get() never sees a partial entry
+        let path = self.entry_path(contents, options);
+        let tmp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+        std::fs::write(&tmp_path, compiled.to_json().to_string())?;
+        std::fs::rename(tmp_path, path)
+    }
+
+    /// Take the compiled file from the cache, or compile it and store it in the cache
+    ///
+    /// Failing to store the file does not fail the compilation, the file is compiled again next
+    /// time.
+    pub fn compile(
+        &self,
+        fname: &str,
+        contents: &[u8],
+        options: CompilerOptions,
+    ) -> Result<CompiledFile, Box<Compiler>> {
+        if let Some(compiled) = self.get(contents, &options) {
+            return Ok(compiled);
+        }
+
+        let compiled = CompiledFile::compile(fname, contents, options.clone())?;
+        let _ = self.put(contents, &options, &compiled);
+        Ok(compiled)
+    }
+}
+
+/// 64-bit FNV-1a hash of the contents, the options and the crate version
+///
+/// Unlike `DefaultHasher`, the hash is the same across Rust versions and runs.
+fn content_hash(contents: &[u8], options: &CompilerOptions) -> u64 {
+    let version = env!("CARGO_PKG_VERSION").as_bytes();
+    let options = options_key(options);
+
+    [version, b"\0", options.as_bytes(), b"\0", contents]
+        .iter()
+        .flat_map(|bytes| bytes.iter())
+        .fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+/// The options in the same form regardless of the order of the lint levels
+///
+/// The lints are listed with their effective levels, so setting a lint to its default level does
+/// not change the key.
+fn options_key(options: &CompilerOptions) -> String {
+    let lint_levels: Vec<_> = Lint::ALL
+        .iter()
+        .map(|lint| format!("{lint}={:?}", options.lint_level(*lint)))
+        .collect();
+
+    format!(
+        "warnings_as_errors={} profile={:?} optimize_ir={} lints={}",
+        options.warnings_as_errors,
+        options.profile,
+        options.optimize_ir,
+        lint_levels.join(",")
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{defined_signatures, CompilationCache, CompiledFile};
+    use crate::compiler::{Compiler, CompilerOptions};
+    use crate::lint::{Lint, LintLevel};
+    use crate::protocol::Signature;
+    use crate::resolver::Resolver;
+    use crate::typechecker::Typechecker;
+
+    fn cache_dir(name: &str) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("new-nu-parser-cache-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn signatures_of_defs() {
+        let mut compiler = Compiler::new();
+        assert!(compiler.check("test.nu", b"def foo [] { 1 }\ndef bar [] { def baz [] {} }"));
+
+        let mut resolver = Resolver::new(&compiler);
+        resolver.resolve();
+        compiler.merge_name_bindings(resolver.to_name_bindings());
+
+        let mut typechecker = Typechecker::new(&compiler);
+        typechecker.typecheck();
+        let signatures = defined_signatures(&compiler, &typechecker.to_types());
+
+        assert_eq!(signatures.len(), 2);
+        assert_eq!(
+            signatures.find(b"foo"),
+            Some(&Signature::new("foo").input_output_type("any", "int"))
+        );
+        assert!(signatures.find(b"baz").is_none());
+    }
+
+    #[test]
+    fn compiled_file_roundtrip() {
+        let src = b"1 + 2";
+        let compiled = CompiledFile::compile("test.nu", src, CompilerOptions::default())
+            .unwrap_or_else(|compiler| panic!("failed to compile: {:?}", compiler.errors));
+
+        let json = compiled.to_json().to_string();
+        let read = CompiledFile::from_json(json.as_bytes()).expect("valid cache entry");
+        assert_eq!(read.to_json(), compiled.to_json());
+
+        assert!(CompiledFile::from_json(b"{}").is_none());
+    }
+
+    #[test]
+    fn unchanged_files_are_taken_from_cache() {
+        let dir = cache_dir("unchanged");
+        let cache = CompilationCache::new(&dir);
+        let src = b"1 + 2";
+
+        assert!(cache.get(src, &CompilerOptions::default()).is_none());
+        let compiled = cache
+            .compile("test.nu", src, CompilerOptions::default())
+            .unwrap_or_else(|compiler| panic!("failed to compile: {:?}", compiler.errors));
+
+        let cached = cache
+            .get(src, &CompilerOptions::default())
+            .expect("compiled file is cached");
+        assert_eq!(cached.to_json(), compiled.to_json());
+        assert!(cache.get(b"1 + 3", &CompilerOptions::default()).is_none());
+
+        std::fs::remove_dir_all(dir).expect("failed to remove the cache");
+    }
+
+    #[test]
+    fn failed_compilations_are_not_cached() {
+        let dir = cache_dir("failed");
+        let cache = CompilationCache::new(&dir);
+        let src = b"1 + \"a\"";
+
+        let compiler = cache
+            .compile("test.nu", src, CompilerOptions::default())
+            .expect_err("mismatched types");
+        assert!(!compiler.errors.is_empty());
+        assert!(cache.get(src, &CompilerOptions::default()).is_none());
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn options_are_part_of_the_key() {
+        let dir = cache_dir("options");
+        let cache = CompilationCache::new(&dir);
+        // the unused variable is a warning by default
+        let src = b"let x = 1";
+
+        cache
+            .compile("test.nu", src, CompilerOptions::default())
+            .unwrap_or_else(|compiler| panic!("failed to compile: {:?}", compiler.errors));
+
+        let warnings_as_errors = CompilerOptions {
+            warnings_as_errors: true,
+            ..Default::default()
+        };
+        assert!(cache.get(src, &warnings_as_errors).is_none());
+        cache
+            .compile("test.nu", src, warnings_as_errors)
+            .expect_err("the warning is an error");
+
+        let optimize_ir = CompilerOptions {
+            optimize_ir: true,
+            ..Default::default()
+        };
+        assert!(cache.get(src, &optimize_ir).is_none());
+
+        // the default level of a lint is the same key as no level
+        let mut default_level = CompilerOptions::default();
+        default_level
+            .lint_levels
+            .insert(Lint::UnusedVariable, LintLevel::Warn);
+        assert!(cache.get(src, &default_level).is_some());
+
+        std::fs::remove_dir_all(dir).expect("failed to remove the cache");
+    }
+}
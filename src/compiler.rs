@@ -1,9 +1,19 @@
-use crate::errors::SourceError;
-use crate::parser::{AstNode, Block, NodeId};
+use crate::engine::EngineIds;
+use crate::errors::{ErrorCode, ErrorCounts, Message, Severity, SourceError};
+use crate::files::FileProvider;
+use crate::ir_optimizer::Constant;
+use crate::lexer::{lex, LexError, Tokens};
+use crate::line_index::LineIndex;
+use crate::lint::{Lint, LintDirective, LintLevel};
+use crate::parser::{unquote, AstNode, Block, NodeId, Parser, RecordEntry};
 use crate::protocol::Command;
-use crate::resolver::{DeclId, Frame, NameBindings, ScopeId, VarId, Variable};
-use crate::typechecker::{TypeId, Types};
+use crate::resolver::{
+    DeclId, Frame, Module, ModuleId, NameBindings, Resolver, ScopeId, VarId, Variable,
+};
+use crate::typechecker::{Type, TypeId, Typechecker, Types};
 use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
 
 pub struct RollbackPoint {
     idx_span_start: usize,
@@ -13,7 +23,26 @@ pub struct RollbackPoint {
     token_pos: usize,
 }
 
+/// State of the compiler to return to with `Compiler::rollback()`
+pub struct Checkpoint {
+    idx_nodes: usize,
+    idx_node_types: usize,
+    idx_types: usize,
+    idx_blocks: usize,
+    idx_source: usize,
+    idx_file_offsets: usize,
+    idx_scope: usize,
+    idx_variables: usize,
+    idx_decls: usize,
+    idx_modules: usize,
+    idx_errors: usize,
+    scope_stack: Vec<ScopeId>,
+    /// Frames that were entered at the time of the checkpoint and can still get new definitions
+    entered_frames: Vec<(ScopeId, Frame)>,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Span {
     pub start: usize,
     pub end: usize,
@@ -26,6 +55,7 @@ impl Span {
 }
 
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Spanned<T> {
     pub item: T,
     pub span: Span,
@@ -37,16 +67,72 @@ impl<T> Spanned<T> {
     }
 }
 
+/// Callbacks of `Compiler::run_stages()` with the output of each stage before it is merged into
+/// the compiler, e.g., for printing it
+///
+/// The methods do nothing by default, `()` observes nothing.
+pub trait StageObserver {
+    /// The lexing error is reported after this, on a garbage node
+    fn lexed(
+        &mut self,
+        _compiler: &Compiler,
+        _tokens: &Tokens,
+        _error: Option<&Spanned<LexError>>,
+    ) {
+    }
+    fn parsed(&mut self, _compiler: &Compiler) {}
+    fn resolved(&mut self, _resolver: &Resolver) {}
+    fn typechecked(&mut self, _typechecker: &Typechecker) {}
+}
+
+impl StageObserver for () {}
+
+/// Options changing which diagnostics are reported and how, and how the IR is generated
+#[derive(Debug, Clone, Default)]
+pub struct CompilerOptions {
+    /// Whether warnings stop the compilation like errors do
+    pub warnings_as_errors: bool,
+    /// Levels of lints overriding their default levels
+    pub lint_levels: HashMap<Lint, LintLevel>,
+    /// What kind of file is compiled
+    pub profile: Profile,
+    /// Whether the IR generator folds the constant expressions, see the `ir_optimizer` module
+    pub optimize_ir: bool,
+}
+
+/// Kind of the compiled file, enabling the checks and definitions specific to it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Profile {
+    /// A script or a module
+    #[default]
+    Script,
+    /// Nushell's env.nu or config.nu, see the `config` module
+    Config,
+}
+
+impl CompilerOptions {
+    pub fn lint_level(&self, lint: Lint) -> LintLevel {
+        self.lint_levels
+            .get(&lint)
+            .copied()
+            .unwrap_or_else(|| lint.default_level())
+    }
+}
+
 #[derive(Clone)]
 pub struct Compiler {
     // Core information, indexed by NodeId:
     pub spans: Vec<Span>,
     pub ast_nodes: Vec<AstNode>,
     pub node_types: Vec<TypeId>,
+    /// Types referenced by the TypeIds in node_types
+    pub types: Vec<Type>,
     // node_lifetimes: Vec<AllocationLifetime>,
     pub blocks: Vec<Block>, // Blocks, indexed by BlockId
     pub source: Vec<u8>,
     pub file_offsets: Vec<(String, usize, usize)>, // fname, start, end
+    /// Lines of each file, indexed like file_offsets
+    pub line_indexes: Vec<LineIndex>,
 
     // name bindings:
     /// All scope frames ever entered, indexed by ScopeId
@@ -61,6 +147,14 @@ pub struct Compiler {
     pub decls: Vec<Box<dyn Command>>,
     /// Mapping of decl's name node -> Command
     pub decl_resolution: HashMap<NodeId, DeclId>,
+    /// Number of parts of the command name of resolved calls
+    pub call_name_parts: HashMap<NodeId, usize>,
+    /// Modules, indexed by ModuleId
+    pub modules: Vec<Module>,
+    /// Mapping of module's name node (in `module` or `use`) -> Module
+    pub module_resolution: HashMap<NodeId, ModuleId>,
+    /// Engine's ids of the declarations and variables resolved from the engine
+    pub engine_ids: EngineIds,
 
     // Definitions:
     // indexed by FunId
@@ -72,6 +166,43 @@ pub struct Compiler {
     // pub call_resolution: HashMap<NodeId, CallTarget>,
     // pub type_resolution: HashMap<NodeId, TypeId>,
     pub errors: Vec<SourceError>,
+    pub options: CompilerOptions,
+    /// Lint levels set by lint comments in the source
+    pub lint_directives: Vec<LintDirective>,
+    /// Mapping of `def`/`extern` node -> comment lines above it, without the `#`
+    pub doc_comments: HashMap<NodeId, String>,
+    /// Mapping of `def`/`extern` node -> its attributes, in the order they are written
+    pub attributes: HashMap<NodeId, Vec<NodeId>>,
+    /// Values known when parsing: of the variable name node of each `const` and of each variable
+    /// referring to a constant
+    pub constants: HashMap<NodeId, Constant>,
+
+    /// Provider of the files loaded by `source` and `use`, no files are loaded without one
+    pub file_provider: Option<Arc<dyn FileProvider>>,
+    /// Mapping of `source`/`use` call -> root block of the loaded file
+    pub sources: HashMap<NodeId, NodeId>,
+    /// Files containing the `source`/`use` calls whose files are being loaded, from the outermost
+    loading_files: Vec<String>,
+}
+
+/// Remove the `.` and `..` components of the path, without accessing the file system
+fn normalize_path(path: &Path) -> String {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized.to_string_lossy().into_owned()
 }
 
 impl Default for Compiler {
@@ -86,9 +217,11 @@ impl Compiler {
             spans: vec![],
             ast_nodes: vec![],
             node_types: vec![],
+            types: vec![],
             blocks: vec![],
             source: vec![],
             file_offsets: vec![],
+            line_indexes: vec![],
 
             scope: vec![],
             scope_stack: vec![],
@@ -96,6 +229,10 @@ impl Compiler {
             var_resolution: HashMap::new(),
             decls: vec![],
             decl_resolution: HashMap::new(),
+            call_name_parts: HashMap::new(),
+            modules: vec![],
+            module_resolution: HashMap::new(),
+            engine_ids: EngineIds::default(),
 
             // variables: vec![],
             // functions: vec![],
@@ -105,9 +242,78 @@ impl Compiler {
             // var_resolution: HashMap::new(),
             // type_resolution: HashMap::new(),
             errors: vec![],
+            options: CompilerOptions::default(),
+            lint_directives: vec![],
+            doc_comments: HashMap::new(),
+            attributes: HashMap::new(),
+            constants: HashMap::new(),
+
+            file_provider: None,
+            sources: HashMap::new(),
+            loading_files: vec![],
         }
     }
 
+    /// Load the files of `source` and `use` calls through the provider while parsing
+    pub fn with_file_provider(mut self, file_provider: impl FileProvider + 'static) -> Self {
+        self.file_provider = Some(Arc::new(file_provider));
+        self
+    }
+
+    /// Whether any of the errors should stop the compilation, for `self.errors` as well as for
+    /// errors of stages that keep their own, e.g., the IR generator
+    pub fn has_fatal_errors(&self, errors: &[SourceError]) -> bool {
+        errors
+            .iter()
+            .any(|error| error.is_fatal(self.options.warnings_as_errors))
+    }
+
+    /// Level of a lint at the given location, taking the lint comments into account
+    pub fn lint_level(&self, lint: Lint, span: Span) -> LintLevel {
+        // A later comment overrides an earlier one covering the same line
+        self.lint_directives
+            .iter()
+            .rev()
+            .find(|directive| {
+                directive.lint == lint
+                    && directive.span.start <= span.start
+                    && span.start < directive.span.end
+            })
+            .map_or_else(
+                || self.options.lint_level(lint),
+                |directive| directive.level,
+            )
+    }
+
+    /// Create the diagnostic of a lint reported on a node, or None if the lint is allowed there
+    pub fn lint(
+        &self,
+        lint: Lint,
+        message: impl Into<Message>,
+        node_id: NodeId,
+    ) -> Option<SourceError> {
+        let severity = match self.lint_level(lint, self.get_span(node_id)) {
+            LintLevel::Allow => return None,
+            LintLevel::Warn => Severity::Warning,
+            LintLevel::Deny => Severity::Error,
+        };
+
+        Some(SourceError {
+            code: lint.code(),
+            message: message.into(),
+            node_id,
+            severity,
+            help: None,
+            note: None,
+            related: vec![],
+            fixes: vec![],
+        })
+    }
+
+    pub fn error_counts(&self) -> ErrorCounts {
+        ErrorCounts::new(&self.errors)
+    }
+
     pub fn print(&self) {
         let output = self.display_state();
         print!("{output}");
@@ -126,7 +332,17 @@ impl Compiler {
 
             if matches!(
                 ast_node,
-                AstNode::Name | AstNode::Variable | AstNode::Int | AstNode::Float | AstNode::String
+                AstNode::Name
+                    | AstNode::Variable
+                    | AstNode::Int
+                    | AstNode::Float
+                    | AstNode::Filesize
+                    | AstNode::Duration
+                    | AstNode::Binary
+                    | AstNode::Datetime
+                    | AstNode::String
+                    | AstNode::RawString
+                    | AstNode::GlobPattern
             ) {
                 result.push_str(&format!(
                     " \"{}\"",
@@ -137,19 +353,72 @@ impl Compiler {
             result.push('\n');
         }
 
+        if !self.doc_comments.is_empty() {
+            result.push_str("==== DOC COMMENTS ====\n");
+            let mut doc_comments: Vec<_> = self.doc_comments.iter().collect();
+            doc_comments.sort_by_key(|(node_id, _)| node_id.0);
+            for (node_id, doc) in doc_comments {
+                result.push_str(&format!("NodeId {}: {doc:?}\n", node_id.0));
+            }
+        }
+
+        if !self.constants.is_empty() {
+            result.push_str("==== CONSTANTS ====\n");
+            let mut constants: Vec<_> = self.constants.iter().collect();
+            constants.sort_by_key(|(node_id, _)| node_id.0);
+            for (node_id, constant) in constants {
+                let constant = match constant {
+                    Constant::String(val) => format!("String({:?})", String::from_utf8_lossy(val)),
+                    _ => format!("{constant:?}"),
+                };
+                result.push_str(&format!("NodeId {}: {constant}\n", node_id.0));
+            }
+        }
+
+        if !self.attributes.is_empty() {
+            result.push_str("==== ATTRIBUTES ====\n");
+            let mut attributes: Vec<_> = self.attributes.iter().collect();
+            attributes.sort_by_key(|(node_id, _)| node_id.0);
+            for (node_id, attributes) in attributes {
+                result.push_str(&format!("NodeId {}: {attributes:?}\n", node_id.0));
+            }
+        }
+
         if !self.errors.is_empty() {
             result.push_str("==== COMPILER ERRORS ====\n");
             for error in &self.errors {
-                result.push_str(&format!(
-                    "{:?} (NodeId {}): {}\n",
-                    error.severity, error.node_id.0, error.message
-                ));
+                result.push_str(&error.display(self));
             }
         }
 
         result
     }
 
+    /// Format the AST nodes and errors as JSON for machine-readable output
+    pub fn to_json(&self) -> serde_json::Value {
+        let nodes: Vec<_> = self
+            .ast_nodes
+            .iter()
+            .enumerate()
+            .map(|(idx, ast_node)| {
+                serde_json::json!({
+                    "node_id": idx,
+                    "node": format!("{:?}", ast_node),
+                    "start": self.spans[idx].start,
+                    "end": self.spans[idx].end,
+                    "text": String::from_utf8_lossy(self.get_span_contents(NodeId(idx))),
+                })
+            })
+            .collect();
+        let errors: Vec<_> = self
+            .errors
+            .iter()
+            .map(|error| error.to_json(self))
+            .collect();
+
+        serde_json::json!({ "nodes": nodes, "errors": errors })
+    }
+
     pub fn merge_name_bindings(&mut self, name_bindings: NameBindings) {
         self.scope.extend(name_bindings.scope);
         self.scope_stack.extend(name_bindings.scope_stack);
@@ -157,11 +426,50 @@ impl Compiler {
         self.var_resolution.extend(name_bindings.var_resolution);
         self.decls.extend(name_bindings.decls);
         self.decl_resolution.extend(name_bindings.decl_resolution);
+        self.call_name_parts.extend(name_bindings.call_name_parts);
+        self.modules.extend(name_bindings.modules);
+        self.module_resolution
+            .extend(name_bindings.module_resolution);
+        self.engine_ids.extend(name_bindings.engine_ids);
         self.errors.extend(name_bindings.errors);
     }
 
+    /// Move the name bindings out of the compiler, e.g., to continue resolving with
+    /// `Resolver::with_name_bindings()`
+    pub fn take_name_bindings(&mut self) -> NameBindings {
+        NameBindings {
+            scope: std::mem::take(&mut self.scope),
+            scope_stack: std::mem::take(&mut self.scope_stack),
+            variables: std::mem::take(&mut self.variables),
+            var_resolution: std::mem::take(&mut self.var_resolution),
+            decls: std::mem::take(&mut self.decls),
+            decl_resolution: std::mem::take(&mut self.decl_resolution),
+            call_name_parts: std::mem::take(&mut self.call_name_parts),
+            modules: std::mem::take(&mut self.modules),
+            module_resolution: std::mem::take(&mut self.module_resolution),
+            engine_ids: std::mem::take(&mut self.engine_ids),
+            errors: vec![],
+        }
+    }
+
+    /// Engine's DeclId of a declaration that was resolved from the engine
+    pub fn engine_decl_id(&self, decl_id: DeclId) -> Option<nu_protocol::DeclId> {
+        self.engine_ids.decls.get(&decl_id).copied()
+    }
+
+    /// Engine's DeclId of `run-external`, if it was needed to call an external command
+    pub fn engine_run_external(&self) -> Option<nu_protocol::DeclId> {
+        self.engine_ids.run_external
+    }
+
+    /// Engine's VarId of a variable that was resolved from the engine
+    pub fn engine_var_id(&self, var_id: VarId) -> Option<nu_protocol::VarId> {
+        self.engine_ids.variables.get(&var_id).copied()
+    }
+
     pub fn merge_types(&mut self, types: Types) {
         self.node_types.extend(types.node_types);
+        self.types.extend(types.types);
         self.errors.extend(types.errors);
     }
 
@@ -170,10 +478,220 @@ impl Compiler {
 
         self.file_offsets
             .push((fname.to_string(), span_offset, span_offset + contents.len()));
+        self.line_indexes.push(LineIndex::new(contents));
 
         self.source.extend_from_slice(contents);
     }
 
+    /// Add the file and check only its syntax, without resolving names, typechecking or
+    /// generating IR
+    ///
+    /// This is much faster than running all stages, e.g., for validating scripts the way
+    /// `nu-check` does. The syntax errors are added to `errors`, a lexing error is reported as
+    /// well. Returns whether there are no fatal errors in the file.
+    /// Add the file and parse it, lexing one top-level statement at a time
+    ///
+    /// Returns whether there were no fatal errors.
+    pub fn check(&mut self, fname: &str, contents: &[u8]) -> bool {
+        let file = Span::new(self.span_offset(), self.span_offset() + contents.len());
+        self.add_file(fname, contents);
+        let errors_start = self.errors.len();
+
+        *self = Parser::with_statements(std::mem::take(self), file).parse();
+
+        !self.has_fatal_errors(&self.errors[errors_start..])
+    }
+
+    fn check_with_observer(
+        &mut self,
+        fname: &str,
+        contents: &[u8],
+        (tokens, err): (Tokens, Result<(), Spanned<LexError>>),
+        observer: &mut impl StageObserver,
+    ) -> bool {
+        self.add_file(fname, contents);
+        let errors_start = self.errors.len();
+
+        observer.lexed(self, &tokens, err.as_ref().err());
+
+        if let Err(e) = err {
+            self.push_lex_error(e);
+            return false;
+        }
+
+        *self = Parser::new(std::mem::take(self), tokens).parse();
+        observer.parsed(self);
+
+        !self.has_fatal_errors(&self.errors[errors_start..])
+    }
+
+    /// Add the file and run the stages up to typechecking on it, merging the output of each stage
+    /// into the compiler
+    ///
+    /// The stages stop after parsing or resolving names if they reported fatal errors. Returns the
+    /// types, which are merged as well, or None if the stages stopped before typechecking. Fatal
+    /// type errors are left to the caller to check, e.g., before generating IR.
+    pub fn run_stages(
+        &mut self,
+        fname: &str,
+        contents: &[u8],
+        observer: &mut impl StageObserver,
+    ) -> Option<Types> {
+        let lexed = lex(contents, self.span_offset());
+        self.run_stages_on_tokens(fname, contents, lexed, observer)
+    }
+
+    /// Same as `run_stages()` with the contents lexed already, e.g., by `relex()`
+    ///
+    /// The spans of the tokens must start at the compiler's `span_offset()`.
+    pub fn run_stages_on_tokens(
+        &mut self,
+        fname: &str,
+        contents: &[u8],
+        lexed: (Tokens, Result<(), Spanned<LexError>>),
+        observer: &mut impl StageObserver,
+    ) -> Option<Types> {
+        let errors_start = self.errors.len();
+        if !self.check_with_observer(fname, contents, lexed, observer) {
+            return None;
+        }
+
+        let mut resolver = Resolver::new(self);
+        resolver.resolve();
+        observer.resolved(&resolver);
+        let name_bindings = resolver.to_name_bindings();
+        self.merge_name_bindings(name_bindings);
+        if self.has_fatal_errors(&self.errors[errors_start..]) {
+            return None;
+        }
+
+        let mut typechecker = Typechecker::new(self);
+        typechecker.typecheck();
+        observer.typechecked(&typechecker);
+        let types = typechecker.to_types();
+        self.merge_types(types.clone());
+
+        Some(types)
+    }
+
+    /// Report an error returned by `lex()` on a garbage node of its span
+    ///
+    /// An unterminated string is reported at its opening quote, with the closing one expected at
+    /// the end of the file.
+    pub fn push_lex_error(&mut self, error: Spanned<LexError>) {
+        self.spans.push(error.span);
+        let node_id = self.push_node(AstNode::Garbage);
+
+        let opening = String::from_utf8_lossy(&self.source[error.span.start..error.span.end]);
+        let closing = match error.item {
+            LexError::UnterminatedString => opening.trim_start_matches('$').to_string(),
+            // `r##'` is closed by `'##`
+            LexError::UnterminatedRawString => format!("'{}", &opening[1..opening.len() - 1]),
+            _ => {
+                self.errors.push(SourceError {
+                    code: ErrorCode::LexError,
+                    message: format!("lexing error: {:?}", error.item).into(),
+                    node_id,
+                    severity: Severity::Error,
+                    help: None,
+                    note: None,
+                    related: vec![],
+                    fixes: vec![],
+                });
+                return;
+            }
+        };
+
+        // before the trailing newlines, so that they do not end up in the string
+        let file_end = self
+            .file_of(error.span)
+            .map_or(self.source.len(), |(_, _, end)| *end);
+        let trailing = self.source[error.span.end..file_end]
+            .iter()
+            .rev()
+            .take_while(|c| c.is_ascii_whitespace())
+            .count();
+
+        self.errors.push(SourceError::unclosed_delimiter(
+            node_id,
+            &opening,
+            &closing,
+            file_end - trailing,
+        ));
+    }
+
+    /// Load and parse the file of a `source` or `use` call, recording its root block in `sources`
+    ///
+    /// The path is relative to the directory of the file containing the call. Does nothing without
+    /// a file provider.
+    pub fn load_source(&mut self, call: NodeId, path: NodeId) {
+        let Some(file_provider) = self.file_provider.clone() else {
+            return;
+        };
+
+        let including_file = self
+            .file_of(self.get_span(path))
+            .map(|(fname, _, _)| fname.clone())
+            .unwrap_or_default();
+        let relative = self.parse_time_path(path).unwrap_or_default();
+        let relative = String::from_utf8_lossy(&relative).into_owned();
+        let including_file = normalize_path(Path::new(&including_file));
+        let fname = Path::new(&including_file)
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(&relative);
+        let fname = normalize_path(&fname);
+
+        if fname == including_file || self.loading_files.contains(&fname) {
+            self.errors.push(SourceError {
+                code: ErrorCode::RecursiveSource,
+                message: format!("`{relative}` is loaded recursively").into(),
+                node_id: path,
+                severity: Severity::Error,
+                help: None,
+                note: Some(format!("`{fname}` is already being loaded")),
+                related: vec![],
+                fixes: vec![],
+            });
+            return;
+        }
+
+        let contents = match file_provider.read_file(Path::new(&fname)) {
+            Ok(contents) => contents,
+            Err(err) => {
+                self.errors.push(SourceError {
+                    code: ErrorCode::FileNotFound,
+                    message: format!("cannot read `{relative}`: {err}").into(),
+                    node_id: path,
+                    severity: Severity::Error,
+                    help: None,
+                    note: None,
+                    related: vec![],
+                    fixes: vec![],
+                });
+                return;
+            }
+        };
+
+        self.loading_files.push(including_file);
+        self.check(&fname, &contents);
+        self.loading_files.pop();
+
+        let root = NodeId(self.ast_nodes.len() - 1);
+        if matches!(self.ast_nodes[root.0], AstNode::Block(_)) {
+            self.sources.insert(call, root);
+        }
+    }
+
+    /// Path given to `source` or `use`, if it is a string literal or a constant string
+    pub fn parse_time_path(&self, path: NodeId) -> Option<Vec<u8>> {
+        match (self.get_node(path), self.constants.get(&path)) {
+            (AstNode::String, _) => Some(unquote(self.get_span_contents(path)).to_vec()),
+            (_, Some(Constant::String(path))) => Some(path.clone()),
+            _ => None,
+        }
+    }
+
     pub fn span_offset(&self) -> usize {
         self.source.len()
     }
@@ -192,6 +710,164 @@ impl Compiler {
         NodeId(self.ast_nodes.len() - 1)
     }
 
+    /// Get the direct children of a node in source order
+    pub fn node_children(&self, node_id: NodeId) -> Vec<NodeId> {
+        match self.get_node(node_id) {
+            AstNode::Type { name, params, .. } => {
+                [Some(*name), *params].into_iter().flatten().collect()
+            }
+            AstNode::Let {
+                variable_name,
+                ty,
+                initializer,
+                ..
+            } => [Some(*variable_name), *ty, Some(*initializer)]
+                .into_iter()
+                .flatten()
+                .collect(),
+            AstNode::Const {
+                variable_name,
+                ty,
+                initializer,
+            } => [Some(*variable_name), *ty, Some(*initializer)]
+                .into_iter()
+                .flatten()
+                .collect(),
+            AstNode::While { condition, block } => vec![*condition, *block],
+            AstNode::For {
+                variable,
+                range,
+                block,
+            } => vec![*variable, *range, *block],
+            AstNode::Loop { block } => vec![*block],
+            AstNode::Return(value) => value.iter().copied().collect(),
+            AstNode::Def {
+                name,
+                params,
+                return_ty,
+                block,
+            } => [Some(*name), Some(*params), *return_ty, Some(*block)]
+                .into_iter()
+                .flatten()
+                .collect(),
+            AstNode::Params(nodes)
+            | AstNode::InOutTypes(nodes)
+            | AstNode::List(nodes)
+            | AstNode::StringInterpolation(nodes) => nodes.clone(),
+            AstNode::Param {
+                name,
+                ty,
+                short,
+                default,
+                ..
+            } => [Some(*name), *short, *ty, *default]
+                .into_iter()
+                .flatten()
+                .collect(),
+            AstNode::InOutType(in_ty, out_ty) => {
+                [*in_ty, Some(*out_ty)].into_iter().flatten().collect()
+            }
+            AstNode::Closure { params, block } => {
+                [*params, Some(*block)].into_iter().flatten().collect()
+            }
+            AstNode::Try { block, catch } => [Some(*block), *catch].into_iter().flatten().collect(),
+            AstNode::Alias {
+                new_name,
+                expansion,
+            } => vec![*new_name, *expansion],
+            AstNode::Extern { name, params } => vec![*name, *params],
+            AstNode::Module { name, block } => vec![*name, *block],
+            AstNode::Use { module, members } => {
+                let mut children = vec![*module];
+                children.extend(members);
+                children
+            }
+            AstNode::Export(node) | AstNode::ExportEnv(node) | AstNode::OverlayNew(node) => {
+                vec![*node]
+            }
+            AstNode::OverlayUse { module, name } => {
+                [Some(*module), *name].into_iter().flatten().collect()
+            }
+            AstNode::OverlayHide(name) => name.iter().copied().collect(),
+            AstNode::Hide(parts) => parts.clone(),
+            AstNode::Attribute { name, args } => {
+                let mut children = vec![*name];
+                children.extend(args);
+                children
+            }
+            AstNode::Call { parts } | AstNode::Pipeline(parts) => parts.clone(),
+            AstNode::EnvShorthand { assignments, block } => {
+                let mut children: Vec<_> = assignments
+                    .iter()
+                    .flat_map(|(name, value)| [*name, *value])
+                    .collect();
+                children.push(*block);
+                children
+            }
+            AstNode::NamedValue { name, value } => vec![*name, *value],
+            AstNode::Redirection { target, .. } => target.iter().copied().collect(),
+            AstNode::BinaryOp { lhs, op, rhs } => vec![*lhs, *op, *rhs],
+            AstNode::UnaryOp { op, value } => vec![*op, *value],
+            AstNode::Range { from, next, to, .. } => {
+                [*from, *next, *to].into_iter().flatten().collect()
+            }
+            AstNode::Table { header, rows } => {
+                let mut children = vec![*header];
+                children.extend(rows);
+                children
+            }
+            AstNode::Record { entries } => entries.iter().flat_map(RecordEntry::nodes).collect(),
+            AstNode::Spread(value) => vec![*value],
+            AstNode::Match { target, match_arms } => {
+                let mut children = vec![*target];
+                children.extend(match_arms.iter().flat_map(|(lhs, rhs)| [*lhs, *rhs]));
+                children
+            }
+            AstNode::OrPattern(alternatives) => alternatives.clone(),
+            AstNode::GuardedPattern { pattern, guard } => vec![*pattern, *guard],
+            AstNode::CellPath { head, members } => {
+                let mut children = vec![*head];
+                children.extend(members.iter().map(|member| member.member));
+                children
+            }
+            AstNode::Block(block_id) => self.blocks[block_id.0].nodes.clone(),
+            AstNode::If {
+                condition,
+                then_block,
+                else_block,
+            } => [Some(*condition), Some(*then_block), *else_block]
+                .into_iter()
+                .flatten()
+                .collect(),
+            AstNode::Statement(node) | AstNode::Subexpression(node) => vec![*node],
+            _ => vec![],
+        }
+    }
+
+    /// The variables bound by the pattern of a `match` arm, e.g., `$x` and `$y` in `[$x, {a: $y}]`
+    pub fn pattern_variables(&self, pattern: NodeId) -> Vec<NodeId> {
+        match self.get_node(pattern) {
+            AstNode::Variable => vec![pattern],
+            AstNode::List(items) => items
+                .iter()
+                .flat_map(|item| self.pattern_variables(*item))
+                .collect(),
+            AstNode::Record { entries } => entries
+                .iter()
+                .flat_map(|entry| match entry {
+                    RecordEntry::Pair(_, val) => self.pattern_variables(*val),
+                    RecordEntry::Spread(_) => vec![],
+                })
+                .collect(),
+            AstNode::OrPattern(alternatives) => alternatives
+                .iter()
+                .flat_map(|alternative| self.pattern_variables(*alternative))
+                .collect(),
+            AstNode::GuardedPattern { pattern, .. } => self.pattern_variables(*pattern),
+            _ => vec![],
+        }
+    }
+
     pub fn get_rollback_point(&self, token_pos: usize) -> RollbackPoint {
         RollbackPoint {
             idx_span_start: self.spans.len(),
@@ -207,10 +883,87 @@ impl Compiler {
         self.ast_nodes.truncate(rbp.idx_nodes);
         self.errors.truncate(rbp.idx_errors);
         self.spans.truncate(rbp.idx_span_start);
+        self.sources.retain(|node_id, _| node_id.0 < rbp.idx_nodes);
 
         rbp.token_pos
     }
 
+    /// Remember the current state, e.g., before compiling a new REPL line
+    ///
+    /// Only the scope frames that are currently entered are copied, everything else is restored
+    /// by truncating the compiler's storage, so a checkpoint is cheap to create.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            idx_nodes: self.ast_nodes.len(),
+            idx_node_types: self.node_types.len(),
+            idx_types: self.types.len(),
+            idx_blocks: self.blocks.len(),
+            idx_source: self.source.len(),
+            idx_file_offsets: self.file_offsets.len(),
+            idx_scope: self.scope.len(),
+            idx_variables: self.variables.len(),
+            idx_decls: self.decls.len(),
+            idx_modules: self.modules.len(),
+            idx_errors: self.errors.len(),
+            scope_stack: self.scope_stack.clone(),
+            entered_frames: self
+                .scope_stack
+                .iter()
+                .map(|scope_id| (*scope_id, self.scope[scope_id.0].clone()))
+                .collect(),
+        }
+    }
+
+    /// Discard everything added to the compiler since the checkpoint was created
+    ///
+    /// The checkpoint must have been created from this compiler and no rollback to an earlier
+    /// checkpoint may have happened in the meantime.
+    pub fn rollback(&mut self, checkpoint: Checkpoint) {
+        self.spans.truncate(checkpoint.idx_nodes);
+        self.ast_nodes.truncate(checkpoint.idx_nodes);
+        self.node_types.truncate(checkpoint.idx_node_types);
+        self.types.truncate(checkpoint.idx_types);
+        self.blocks.truncate(checkpoint.idx_blocks);
+        self.source.truncate(checkpoint.idx_source);
+        self.file_offsets.truncate(checkpoint.idx_file_offsets);
+        self.line_indexes.truncate(checkpoint.idx_file_offsets);
+        self.errors.truncate(checkpoint.idx_errors);
+        self.lint_directives
+            .retain(|directive| directive.span.start < checkpoint.idx_source);
+
+        self.scope.truncate(checkpoint.idx_scope);
+        for (scope_id, frame) in checkpoint.entered_frames {
+            self.scope[scope_id.0] = frame;
+        }
+        self.scope_stack = checkpoint.scope_stack;
+
+        self.variables.truncate(checkpoint.idx_variables);
+        self.decls.truncate(checkpoint.idx_decls);
+        self.var_resolution
+            .retain(|node_id, _| node_id.0 < checkpoint.idx_nodes);
+        self.decl_resolution
+            .retain(|node_id, _| node_id.0 < checkpoint.idx_nodes);
+        self.call_name_parts
+            .retain(|node_id, _| node_id.0 < checkpoint.idx_nodes);
+        self.modules.truncate(checkpoint.idx_modules);
+        self.module_resolution
+            .retain(|node_id, _| node_id.0 < checkpoint.idx_nodes);
+        self.sources
+            .retain(|node_id, _| node_id.0 < checkpoint.idx_nodes);
+        self.doc_comments
+            .retain(|node_id, _| node_id.0 < checkpoint.idx_nodes);
+        self.attributes
+            .retain(|node_id, _| node_id.0 < checkpoint.idx_nodes);
+        self.constants
+            .retain(|node_id, _| node_id.0 < checkpoint.idx_nodes);
+        self.engine_ids
+            .variables
+            .retain(|var_id, _| var_id.0 < checkpoint.idx_variables);
+        self.engine_ids
+            .decls
+            .retain(|decl_id, _| decl_id.0 < checkpoint.idx_decls);
+    }
+
     /// Get span of node
     pub fn get_span(&self, node_id: NodeId) -> Span {
         *self
@@ -227,6 +980,95 @@ impl Compiler {
             .expect("internal error: missing source of span")
     }
 
+    /// File containing the span as `(fname, start, end)`
+    pub fn file_of(&self, span: Span) -> Option<&(String, usize, usize)> {
+        self.file_idx(span).map(|idx| &self.file_offsets[idx])
+    }
+
+    fn file_idx(&self, span: Span) -> Option<usize> {
+        self.file_offsets
+            .iter()
+            .position(|(_, start, end)| *start <= span.start && span.end <= *end)
+    }
+
+    /// Line and column of a byte offset within the file containing it
+    ///
+    /// Both are 1-based and the column counts characters, not bytes.
+    pub fn line_column(&self, offset: usize) -> (usize, usize) {
+        self.line_column_in_file(self.file_idx(Span::new(offset, offset)), offset)
+    }
+
+    /// Lines and columns of the start and the end of a span, see `line_column()`
+    ///
+    /// Unlike an offset, a non-empty span at the boundary of two files belongs to only one of
+    /// them.
+    pub fn span_line_columns(&self, span: Span) -> ((usize, usize), (usize, usize)) {
+        let file_idx = self.file_idx(span);
+        (
+            self.line_column_in_file(file_idx, span.start),
+            self.line_column_in_file(file_idx, span.end),
+        )
+    }
+
+    /// 1-based line of a byte offset within the file containing it and the offset at which the
+    /// line starts
+    pub fn line_of(&self, offset: usize) -> (usize, usize) {
+        self.line_in_file(self.file_idx(Span::new(offset, offset)), offset)
+    }
+
+    /// Start and end of a 1-based line of the file `fname`, the end is before the line's newline
+    ///
+    /// Returns None if there is no such file or line.
+    pub fn line_range(&self, fname: &str, line: usize) -> Option<(usize, usize)> {
+        let file_idx = self
+            .file_offsets
+            .iter()
+            .position(|(name, _, _)| name == fname)?;
+        let file_start = self.file_offsets[file_idx].1;
+        let (start, end) = self.line_indexes[file_idx].line_range(line.checked_sub(1)?)?;
+
+        Some((file_start + start, file_start + end))
+    }
+
+    /// Byte offset of a 1-based line and column in the file `fname`, the inverse of
+    /// `line_column()`
+    ///
+    /// Columns past the end of the line are moved to the end of the line. Returns None if there
+    /// is no such file or line, or the line is not valid UTF-8.
+    pub fn offset(&self, fname: &str, line: usize, column: usize) -> Option<usize> {
+        let (start, end) = self.line_range(fname, line)?;
+        let contents = std::str::from_utf8(&self.source[start..end]).ok()?;
+        let column = contents
+            .char_indices()
+            .nth(column.saturating_sub(1))
+            .map_or(contents.len(), |(idx, _)| idx);
+
+        Some(start + column)
+    }
+
+    fn line_in_file(&self, file_idx: Option<usize>, offset: usize) -> (usize, usize) {
+        // the files cover the whole source, an offset in no file is on the first line, e.g., in
+        // an empty compiler
+        let Some(idx) = file_idx else {
+            return (1, 0);
+        };
+        let file_start = self.file_offsets[idx].1;
+        let line_index = &self.line_indexes[idx];
+        let line = line_index.line(offset - file_start);
+        let (line_start, _) = line_index
+            .line_range(line)
+            .expect("internal error: missing line of offset");
+
+        (line + 1, file_start + line_start)
+    }
+
+    fn line_column_in_file(&self, file_idx: Option<usize>, offset: usize) -> (usize, usize) {
+        let (line, line_start) = self.line_in_file(file_idx, offset);
+        let before = String::from_utf8_lossy(&self.source[line_start..offset]);
+
+        (line, before.chars().count() + 1)
+    }
+
     /// Get the source contents of a span
     pub fn get_span_contents_manual(&self, span_start: usize, span_end: usize) -> &[u8] {
         self.source
@@ -234,3 +1076,122 @@ impl Compiler {
             .expect("internal error: missing source of span")
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{lex, Compiler, Parser, StageObserver};
+    use crate::resolver::Resolver;
+    use crate::typechecker::Typechecker;
+
+    #[test]
+    fn offsets_of_line_columns() {
+        let mut compiler = Compiler::new();
+        compiler.add_file("first.nu", b"1\n");
+        compiler.add_file("second.nu", "'ä' + 1\n\n2".as_bytes());
+
+        // the offset 2 is the end of the first file and 4 is within 'ä'
+        for offset in (3..=compiler.source.len()).filter(|offset| *offset != 4) {
+            let (line, column) = compiler.line_column(offset);
+            assert_eq!(
+                compiler.offset("second.nu", line, column),
+                Some(offset),
+                "offset {offset} at {line}:{column}"
+            );
+        }
+
+        assert_eq!(compiler.line_of(2 + 6), (1, 2));
+        assert_eq!(compiler.line_of(2 + 10), (3, 2 + 10));
+        assert_eq!(compiler.line_range("second.nu", 2), Some((2 + 9, 2 + 9)));
+        // past the end of the line
+        assert_eq!(compiler.offset("second.nu", 1, 100), Some(2 + 8));
+        assert_eq!(compiler.offset("second.nu", 4, 1), None);
+        assert_eq!(compiler.offset("second.nu", 0, 1), None);
+        assert_eq!(compiler.offset("third.nu", 1, 1), None);
+    }
+
+    #[test]
+    fn line_of_empty_source() {
+        let compiler = Compiler::new();
+        assert_eq!(compiler.line_of(0), (1, 0));
+        assert_eq!(compiler.line_column(0), (1, 1));
+    }
+
+    /// Names of the stages that ran
+    #[derive(Default)]
+    struct Stages(Vec<&'static str>);
+
+    impl StageObserver for Stages {
+        fn parsed(&mut self, _compiler: &Compiler) {
+            self.0.push("parse");
+        }
+
+        fn resolved(&mut self, _resolver: &Resolver) {
+            self.0.push("resolve");
+        }
+
+        fn typechecked(&mut self, _typechecker: &Typechecker) {
+            self.0.push("typecheck");
+        }
+    }
+
+    #[test]
+    fn stages_stop_at_fatal_errors() {
+        let mut compiler = Compiler::new();
+        let mut stages = Stages::default();
+        let types = compiler.run_stages("first.nu", b"let x = 1; $x + 'a'", &mut stages);
+        assert_eq!(stages.0, ["parse", "resolve", "typecheck"]);
+        // the type errors are merged and left to the caller
+        let types = types.expect("the names are resolved");
+        assert_eq!(types.errors.len(), 1);
+        assert_eq!(compiler.errors.len(), 1);
+        assert_eq!(compiler.node_types.len(), compiler.ast_nodes.len());
+
+        let mut stages = Stages::default();
+        assert!(compiler
+            .run_stages("second.nu", b"$y", &mut stages)
+            .is_none());
+        assert_eq!(stages.0, ["parse", "resolve"]);
+
+        let mut stages = Stages::default();
+        assert!(compiler.run_stages("third.nu", b"(", &mut stages).is_none());
+        assert_eq!(stages.0, ["parse"]);
+    }
+
+    #[test]
+    fn check_same_as_parsing_all_tokens() {
+        let dirs = ["tests", "tests/lex", "tests/ir", "tests/config"];
+        let mut sources: Vec<Vec<u8>> = dirs
+            .into_iter()
+            .flat_map(|dir| std::fs::read_dir(dir).expect("missing tests"))
+            .map(|path| path.expect("failed to read tests").path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "nu"))
+            .map(|path| std::fs::read(path).expect("failed to read test"))
+            .collect();
+        sources.extend([
+            b"let x = 1\n$\"(\n$x)\"\nlet y = 'a".to_vec(),
+            b"ls\n\xff\nls # nu-lint: allow(unused_variable)\n".to_vec(),
+            b"".to_vec(),
+        ]);
+
+        for src in sources {
+            let mut compiler = Compiler::new();
+            compiler.add_file("first.nu", b"1\n");
+            let mut expected = compiler.clone();
+
+            let no_errors = compiler.check("test.nu", &src);
+
+            let span_offset = expected.span_offset();
+            expected.add_file("test.nu", &src);
+            let (tokens, result) = lex(&src, span_offset);
+            match result {
+                Ok(()) => expected = Parser::new(expected, tokens).parse(),
+                Err(error) => expected.push_lex_error(error),
+            }
+
+            let src = String::from_utf8_lossy(&src);
+            assert_eq!(compiler.display_state(), expected.display_state(), "{src}");
+            assert_eq!(compiler.lint_directives, expected.lint_directives, "{src}");
+            assert_eq!(no_errors, !expected.has_fatal_errors(&expected.errors));
+        }
+    }
+}
@@ -0,0 +1,274 @@
+//! Checking of Nushell's configuration files (env.nu and config.nu)
+//!
+//! With `Profile::Config`, the assignments to the fields of `$env.config` are checked against the
+//! schema of the configuration record below: the field must exist and the assigned value must have
+//! the field's type. `source`, `use` and `overlay use` must be given a path known at parse time.
+use crate::compiler::Compiler;
+use crate::parser::{AstNode, NodeId};
+
+/// Expected value of a configuration field
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigValue {
+    /// Any value, the field is not checked further
+    Any,
+    Bool,
+    Int,
+    String,
+    /// String with one of the listed values
+    Choice(&'static [&'static str]),
+    Closure,
+    List,
+    Record(&'static [(&'static str, ConfigValue)]),
+}
+
+impl ConfigValue {
+    /// Field of a record by its name, None if the record has no such field or this is not a record
+    ///
+    /// All fields of an unchecked value are unchecked as well.
+    pub fn field(&self, name: &[u8]) -> Option<&ConfigValue> {
+        match self {
+            ConfigValue::Any => Some(&ConfigValue::Any),
+            ConfigValue::Record(fields) => fields
+                .iter()
+                .find(|(field, _)| field.as_bytes() == name)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    /// Names of the fields if this is a record
+    pub fn field_names(&self) -> impl Iterator<Item = &'static [u8]> {
+        let fields = match self {
+            ConfigValue::Record(fields) => *fields,
+            _ => &[],
+        };
+        fields.iter().map(|(name, _)| name.as_bytes())
+    }
+
+    /// How the expected value is described in diagnostics
+    pub fn description(&self) -> &'static str {
+        match self {
+            ConfigValue::Any => "any",
+            ConfigValue::Bool => "bool",
+            ConfigValue::Int => "int",
+            ConfigValue::String | ConfigValue::Choice(_) => "string",
+            ConfigValue::Closure => "closure",
+            ConfigValue::List => "list",
+            ConfigValue::Record(_) => "record",
+        }
+    }
+}
+
+const CURSOR_SHAPES: ConfigValue = ConfigValue::Choice(&[
+    "block",
+    "underscore",
+    "line",
+    "blink_block",
+    "blink_underscore",
+    "blink_line",
+    "inherit",
+]);
+
+/// Schema of `$env.config`, following the configuration of Nushell 0.101
+pub const CONFIG_SCHEMA: ConfigValue = ConfigValue::Record(&[
+    ("show_banner", ConfigValue::Bool),
+    ("edit_mode", ConfigValue::Choice(&["emacs", "vi"])),
+    ("buffer_editor", ConfigValue::Any),
+    ("use_ansi_coloring", ConfigValue::Bool),
+    ("bracketed_paste", ConfigValue::Bool),
+    ("render_right_prompt_on_last_line", ConfigValue::Bool),
+    ("use_kitty_protocol", ConfigValue::Bool),
+    ("highlight_resolved_externals", ConfigValue::Bool),
+    ("footer_mode", ConfigValue::Any),
+    ("float_precision", ConfigValue::Int),
+    ("recursion_limit", ConfigValue::Int),
+    ("error_style", ConfigValue::Choice(&["fancy", "plain"])),
+    ("color_config", ConfigValue::Any),
+    ("explore", ConfigValue::Any),
+    ("keybindings", ConfigValue::List),
+    ("menus", ConfigValue::List),
+    ("plugins", ConfigValue::Any),
+    ("plugin_gc", ConfigValue::Any),
+    (
+        "history",
+        ConfigValue::Record(&[
+            ("max_size", ConfigValue::Int),
+            ("sync_on_enter", ConfigValue::Bool),
+            ("file_format", ConfigValue::Choice(&["plaintext", "sqlite"])),
+            ("isolation", ConfigValue::Bool),
+        ]),
+    ),
+    (
+        "completions",
+        ConfigValue::Record(&[
+            ("case_sensitive", ConfigValue::Bool),
+            ("quick", ConfigValue::Bool),
+            ("partial", ConfigValue::Bool),
+            ("algorithm", ConfigValue::Choice(&["prefix", "fuzzy"])),
+            ("sort", ConfigValue::Choice(&["smart", "alphabetical"])),
+            ("use_ls_colors", ConfigValue::Bool),
+            (
+                "external",
+                ConfigValue::Record(&[
+                    ("enable", ConfigValue::Bool),
+                    ("max_results", ConfigValue::Int),
+                    ("completer", ConfigValue::Any),
+                ]),
+            ),
+        ]),
+    ),
+    (
+        "table",
+        ConfigValue::Record(&[
+            (
+                "mode",
+                ConfigValue::Choice(&[
+                    "ascii_rounded",
+                    "basic",
+                    "basic_compact",
+                    "compact",
+                    "compact_double",
+                    "default",
+                    "dots",
+                    "heavy",
+                    "light",
+                    "markdown",
+                    "none",
+                    "psql",
+                    "reinforced",
+                    "restructured",
+                    "rounded",
+                    "thin",
+                    "with_love",
+                ]),
+            ),
+            (
+                "index_mode",
+                ConfigValue::Choice(&["always", "never", "auto"]),
+            ),
+            ("show_empty", ConfigValue::Bool),
+            ("padding", ConfigValue::Any),
+            ("trim", ConfigValue::Any),
+            ("header_on_separator", ConfigValue::Bool),
+            ("abbreviated_row_count", ConfigValue::Any),
+            ("footer_inheritance", ConfigValue::Bool),
+        ]),
+    ),
+    (
+        "ls",
+        ConfigValue::Record(&[
+            ("use_ls_colors", ConfigValue::Bool),
+            ("clickable_links", ConfigValue::Bool),
+        ]),
+    ),
+    (
+        "rm",
+        ConfigValue::Record(&[("always_trash", ConfigValue::Bool)]),
+    ),
+    (
+        "filesize",
+        ConfigValue::Record(&[
+            ("metric", ConfigValue::Bool),
+            ("format", ConfigValue::String),
+        ]),
+    ),
+    (
+        "cursor_shape",
+        ConfigValue::Record(&[
+            ("emacs", CURSOR_SHAPES),
+            ("vi_insert", CURSOR_SHAPES),
+            ("vi_normal", CURSOR_SHAPES),
+        ]),
+    ),
+    (
+        "datetime_format",
+        ConfigValue::Record(&[("normal", ConfigValue::Any), ("table", ConfigValue::Any)]),
+    ),
+    (
+        "hooks",
+        ConfigValue::Record(&[
+            ("pre_prompt", ConfigValue::Any),
+            ("pre_execution", ConfigValue::Any),
+            ("env_change", ConfigValue::Any),
+            ("display_output", ConfigValue::Any),
+            ("command_not_found", ConfigValue::Any),
+        ]),
+    ),
+    (
+        "shell_integration",
+        ConfigValue::Record(&[
+            ("osc2", ConfigValue::Bool),
+            ("osc7", ConfigValue::Bool),
+            ("osc8", ConfigValue::Bool),
+            ("osc9_9", ConfigValue::Bool),
+            ("osc133", ConfigValue::Bool),
+            ("osc633", ConfigValue::Bool),
+            ("reset_application_mode", ConfigValue::Bool),
+        ]),
+    ),
+]);
+
+/// The field nodes of a cell path into the configuration, e.g., `table` and `mode` for
+/// `$env.config.table.mode`
+///
+/// Returns None if the node is not a cell path starting with `$env.config`, and an empty path for
+/// `$env.config` itself.
+pub fn config_path(compiler: &Compiler, node_id: NodeId) -> Option<Vec<NodeId>> {
+    let AstNode::CellPath { head, members } = &compiler.ast_nodes[node_id.0] else {
+        return None;
+    };
+    let (AstNode::Variable, Some(config)) = (&compiler.ast_nodes[head.0], members.first()) else {
+        return None;
+    };
+    if compiler.get_span_contents(*head) != b"$env"
+        || compiler.get_span_contents(config.member) != b"config"
+    {
+        return None;
+    }
+
+    Some(members[1..].iter().map(|member| member.member).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{config_path, ConfigValue, CONFIG_SCHEMA};
+    use crate::compiler::Compiler;
+    use crate::parser::AstNode;
+
+    #[test]
+    fn schema_lookup() {
+        let table = CONFIG_SCHEMA
+            .field(b"table")
+            .expect("table is in the schema");
+        assert!(matches!(table.field(b"mode"), Some(ConfigValue::Choice(_))));
+        assert_eq!(table.field(b"modes"), None);
+        assert_eq!(ConfigValue::Bool.field(b"mode"), None);
+        assert_eq!(ConfigValue::Any.field(b"mode"), Some(&ConfigValue::Any));
+        assert!(CONFIG_SCHEMA
+            .field_names()
+            .any(|name| name == b"show_banner"));
+    }
+
+    #[test]
+    fn config_paths() {
+        let mut compiler = Compiler::new();
+        assert!(compiler.check("test.nu", b"$env.config.table.mode\n$env.config\n$env.PATH"));
+
+        let paths: Vec<_> = compiler
+            .ast_nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| matches!(node, AstNode::CellPath { .. }))
+            .filter_map(|(idx, _)| config_path(&compiler, crate::parser::NodeId(idx)))
+            .map(|fields| {
+                fields
+                    .iter()
+                    .map(|field| String::from_utf8_lossy(compiler.get_span_contents(*field)))
+                    .collect::<Vec<_>>()
+                    .join(".")
+            })
+            .collect();
+
+        assert_eq!(paths, ["table.mode", ""]);
+    }
+}
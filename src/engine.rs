@@ -0,0 +1,173 @@
+//! Integration with the nushell engine
+//!
+//! When running inside nushell, names that are not defined in the compiled source may refer to
+//! commands, variables and modules already known to the engine. The resolver looks them up through
+//! `EngineLookup` and records the engine's ids in `EngineIds`, so that later stages can refer to the
+//! engine's definitions instead of this crate's placeholders.
+use std::collections::HashMap;
+
+use nu_protocol::engine::{EngineState, StateWorkingSet};
+
+use crate::resolver::{DeclId, VarId};
+
+/// Lookup of names defined by the engine
+pub trait EngineLookup {
+    /// Find a command by its full name, e.g., `str length`
+    fn find_decl(&self, name: &[u8]) -> Option<nu_protocol::DeclId>;
+    /// Find a variable by its name without the leading `$`
+    fn find_variable(&self, name: &[u8]) -> Option<nu_protocol::VarId>;
+    fn find_module(&self, name: &[u8]) -> Option<nu_protocol::ModuleId>;
+}
+
+impl EngineLookup for EngineState {
+    fn find_decl(&self, name: &[u8]) -> Option<nu_protocol::DeclId> {
+        EngineState::find_decl(self, name, &[])
+    }
+
+    fn find_variable(&self, name: &[u8]) -> Option<nu_protocol::VarId> {
+        let name = [b"$", name].concat();
+        self.active_overlays(&[])
+            .rev()
+            .find_map(|overlay_frame| overlay_frame.vars.get(&name).copied())
+    }
+
+    fn find_module(&self, name: &[u8]) -> Option<nu_protocol::ModuleId> {
+        EngineState::find_module(self, name, &[])
+    }
+}
+
+impl EngineLookup for StateWorkingSet<'_> {
+    fn find_decl(&self, name: &[u8]) -> Option<nu_protocol::DeclId> {
+        StateWorkingSet::find_decl(self, name)
+    }
+
+    fn find_variable(&self, name: &[u8]) -> Option<nu_protocol::VarId> {
+        StateWorkingSet::find_variable(self, name)
+    }
+
+    fn find_module(&self, name: &[u8]) -> Option<nu_protocol::ModuleId> {
+        StateWorkingSet::find_module(self, name)
+    }
+}
+
+/// Mapping of declarations and variables resolved from the engine to the engine's ids
+#[derive(Debug, Clone, Default)]
+pub struct EngineIds {
+    pub decls: HashMap<DeclId, nu_protocol::DeclId>,
+    pub variables: HashMap<VarId, nu_protocol::VarId>,
+    /// The engine's `run-external`, found if the source calls any external commands
+    pub run_external: Option<nu_protocol::DeclId>,
+}
+
+impl EngineIds {
+    /// Find the declaration that was already created for the engine's declaration
+    pub fn find_decl(&self, engine_decl_id: nu_protocol::DeclId) -> Option<DeclId> {
+        self.decls
+            .iter()
+            .find_map(|(decl_id, id)| (*id == engine_decl_id).then_some(*decl_id))
+    }
+
+    /// Find the variable that was already created for the engine's variable
+    pub fn find_variable(&self, engine_var_id: nu_protocol::VarId) -> Option<VarId> {
+        self.variables
+            .iter()
+            .find_map(|(var_id, id)| (*id == engine_var_id).then_some(*var_id))
+    }
+
+    pub fn extend(&mut self, other: EngineIds) {
+        self.decls.extend(other.decls);
+        self.variables.extend(other.variables);
+        self.run_external = self.run_external.or(other.run_external);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::EngineLookup;
+    use crate::compiler::Compiler;
+    use crate::lexer::lex;
+    use crate::parser::{AstNode, NodeId, Parser};
+    use crate::resolver::Resolver;
+
+    struct TestEngine;
+
+    impl EngineLookup for TestEngine {
+        fn find_decl(&self, name: &[u8]) -> Option<nu_protocol::DeclId> {
+            (name == b"str length").then(|| nu_protocol::DeclId::new(42))
+        }
+
+        fn find_variable(&self, name: &[u8]) -> Option<nu_protocol::VarId> {
+            (name == b"nu").then(|| nu_protocol::VarId::new(7))
+        }
+
+        fn find_module(&self, _name: &[u8]) -> Option<nu_protocol::ModuleId> {
+            None
+        }
+    }
+
+    #[test]
+    fn names_are_resolved_from_engine() {
+        let src = b"str length $nu\nstr length $nu\nlet x = 1\n$x\n$unknown";
+        let mut compiler = Compiler::new();
+        compiler.add_file("test.nu", src);
+
+        let (tokens, err) = lex(src, 0);
+        assert!(err.is_ok());
+        let compiler = Parser::new(compiler, tokens).parse();
+        assert!(compiler.errors.is_empty());
+
+        let mut resolver = Resolver::new(&compiler).with_engine(&TestEngine);
+        resolver.resolve();
+
+        let calls: Vec<_> = (0..compiler.ast_nodes.len())
+            .map(NodeId)
+            .filter(|node_id| matches!(compiler.get_node(*node_id), AstNode::Call { .. }))
+            .collect();
+
+        assert_eq!(calls.len(), 2);
+        let decl_id = resolver.decl_resolution[&calls[0]];
+        assert_eq!(resolver.decl_resolution[&calls[1]], decl_id);
+        assert_eq!(resolver.decls[decl_id.0].name(), "str length");
+        assert_eq!(
+            resolver.engine_ids.decls.get(&decl_id),
+            Some(&nu_protocol::DeclId::new(42))
+        );
+
+        // $nu is shared by both calls, $x is local and $unknown is still an error
+        assert_eq!(resolver.engine_ids.variables.len(), 1);
+        assert_eq!(resolver.errors.len(), 1);
+        assert_eq!(
+            resolver.errors[0].message(&compiler),
+            "variable `unknown` not found"
+        );
+    }
+
+    #[test]
+    fn hidden_engine_commands_are_external() {
+        let src = b"str length\nhide str length\nstr length\nhide unknown";
+        let mut compiler = Compiler::new();
+        compiler.add_file("test.nu", src);
+
+        let (tokens, err) = lex(src, 0);
+        assert!(err.is_ok());
+        let compiler = Parser::new(compiler, tokens).parse();
+        assert!(compiler.errors.is_empty());
+
+        let mut resolver = Resolver::new(&compiler).with_engine(&TestEngine);
+        resolver.resolve();
+
+        let calls: Vec<_> = (0..compiler.ast_nodes.len())
+            .map(NodeId)
+            .filter(|node_id| matches!(compiler.get_node(*node_id), AstNode::Call { .. }))
+            .collect();
+
+        assert_eq!(calls.len(), 2);
+        assert!(resolver.decl_resolution.contains_key(&calls[0]));
+        assert!(!resolver.decl_resolution.contains_key(&calls[1]));
+        assert_eq!(resolver.errors.len(), 1);
+        assert_eq!(
+            resolver.errors[0].message(&compiler),
+            "`unknown` is not defined, so it cannot be hidden"
+        );
+    }
+}
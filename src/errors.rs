@@ -1,14 +1,973 @@
+use std::borrow::Cow;
+
+use miette::{LabeledSpan, MietteDiagnostic, NamedSource};
+
+use crate::compiler::{Compiler, Span};
 use crate::parser::NodeId;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Severity {
     Error,
+    /// Suspicious code that still compiles, unless warnings are treated as errors
+    Warning,
     Note,
 }
 
+impl From<Severity> for miette::Severity {
+    fn from(severity: Severity) -> Self {
+        match severity {
+            Severity::Error => miette::Severity::Error,
+            Severity::Warning => miette::Severity::Warning,
+            Severity::Note => miette::Severity::Advice,
+        }
+    }
+}
+
+/// Stable identifier of a kind of error, e.g., for suppressing it or linking to an explanation
+///
+/// The codes are of the form `nu::<stage>::<name>` and must not change once released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ErrorCode {
+    // Lexer
+    LexError,
+    // Parser
+    InvalidUtf8,
+    MissingSpace,
+    AssignmentInExpression,
+    AssignmentInCondition,
+    IncompleteExpression,
+    EmptyParens,
+    InvalidCellPath,
+    InvalidVariableName,
+    InvalidTable,
+    InvalidRedirection,
+    ExpectedToken,
+    UnclosedDelimiter,
+    UnknownOperator,
+    UnsupportedSyntax,
+    IntOverflow,
+    InvalidDatetime,
+    InvalidSignature,
+    FileNotFound,
+    RecursiveSource,
+    NonConstantValue,
+    // Resolver
+    VariableNotFound,
+    ImmutableAssignment,
+    ModuleMemberNotFound,
+    OverlayNotFound,
+    DeclNotFound,
+    // Typechecker
+    NonBoolCondition,
+    NonListIteration,
+    LoopBlockValue,
+    TypeMismatch,
+    InvalidListType,
+    UnsupportedOperation,
+    TypecheckUnsupportedNode,
+    UnknownFlag,
+    MissingArgument,
+    ExtraArgument,
+    InvalidErrorRecord,
+    // IR generator
+    IrUnsupportedNode,
+    IrUnsupportedOperator,
+    IrInvalidLiteral,
+    IrUnresolvedVariable,
+    // AST converter
+    ConvertUnsupportedNode,
+    ConvertUnsupportedOperator,
+    ConvertInvalidLiteral,
+    ConvertUnresolvedVariable,
+    // Configuration files
+    ConfigUnknownField,
+    ConfigInvalidValue,
+    ConfigNonConstantPath,
+    // Lints
+    LintUnusedVariable,
+    LintShadowing,
+    LintDeprecatedSyntax,
+    LintImplicitAny,
+    LintConfigOverwrite,
+}
+
+impl ErrorCode {
+    /// All error codes, a new variant must be added here as well
+    pub const ALL: &'static [ErrorCode] = &[
+        ErrorCode::LexError,
+        ErrorCode::InvalidUtf8,
+        ErrorCode::MissingSpace,
+        ErrorCode::AssignmentInExpression,
+        ErrorCode::AssignmentInCondition,
+        ErrorCode::IncompleteExpression,
+        ErrorCode::EmptyParens,
+        ErrorCode::InvalidCellPath,
+        ErrorCode::InvalidVariableName,
+        ErrorCode::InvalidTable,
+        ErrorCode::InvalidRedirection,
+        ErrorCode::ExpectedToken,
+        ErrorCode::UnclosedDelimiter,
+        ErrorCode::UnknownOperator,
+        ErrorCode::UnsupportedSyntax,
+        ErrorCode::IntOverflow,
+        ErrorCode::InvalidDatetime,
+        ErrorCode::InvalidSignature,
+        ErrorCode::FileNotFound,
+        ErrorCode::RecursiveSource,
+        ErrorCode::NonConstantValue,
+        ErrorCode::VariableNotFound,
+        ErrorCode::ImmutableAssignment,
+        ErrorCode::ModuleMemberNotFound,
+        ErrorCode::OverlayNotFound,
+        ErrorCode::DeclNotFound,
+        ErrorCode::NonBoolCondition,
+        ErrorCode::NonListIteration,
+        ErrorCode::LoopBlockValue,
+        ErrorCode::TypeMismatch,
+        ErrorCode::InvalidListType,
+        ErrorCode::UnsupportedOperation,
+        ErrorCode::TypecheckUnsupportedNode,
+        ErrorCode::UnknownFlag,
+        ErrorCode::MissingArgument,
+        ErrorCode::ExtraArgument,
+        ErrorCode::InvalidErrorRecord,
+        ErrorCode::IrUnsupportedNode,
+        ErrorCode::IrUnsupportedOperator,
+        ErrorCode::IrInvalidLiteral,
+        ErrorCode::IrUnresolvedVariable,
+        ErrorCode::ConvertUnsupportedNode,
+        ErrorCode::ConvertUnsupportedOperator,
+        ErrorCode::ConvertInvalidLiteral,
+        ErrorCode::ConvertUnresolvedVariable,
+        ErrorCode::ConfigUnknownField,
+        ErrorCode::ConfigInvalidValue,
+        ErrorCode::ConfigNonConstantPath,
+        ErrorCode::LintUnusedVariable,
+        ErrorCode::LintShadowing,
+        ErrorCode::LintDeprecatedSyntax,
+        ErrorCode::LintImplicitAny,
+        ErrorCode::LintConfigOverwrite,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::LexError => "nu::lexer::lex_error",
+            ErrorCode::InvalidUtf8 => "nu::parser::invalid_utf8",
+            ErrorCode::MissingSpace => "nu::parser::missing_space",
+            ErrorCode::AssignmentInExpression => "nu::parser::assignment_in_expression",
+            ErrorCode::AssignmentInCondition => "nu::parser::assignment_in_condition",
+            ErrorCode::IncompleteExpression => "nu::parser::incomplete_expression",
+            ErrorCode::EmptyParens => "nu::parser::empty_parens",
+            ErrorCode::InvalidCellPath => "nu::parser::invalid_cell_path",
+            ErrorCode::InvalidVariableName => "nu::parser::invalid_variable_name",
+            ErrorCode::InvalidTable => "nu::parser::invalid_table",
+            ErrorCode::InvalidRedirection => "nu::parser::invalid_redirection",
+            ErrorCode::ExpectedToken => "nu::parser::expected_token",
+            ErrorCode::UnclosedDelimiter => "nu::parser::unclosed_delimiter",
+            ErrorCode::UnknownOperator => "nu::parser::unknown_operator",
+            ErrorCode::UnsupportedSyntax => "nu::parser::unsupported_syntax",
+            ErrorCode::IntOverflow => "nu::parser::int_overflow",
+            ErrorCode::InvalidDatetime => "nu::parser::invalid_datetime",
+            ErrorCode::InvalidSignature => "nu::parser::invalid_signature",
+            ErrorCode::FileNotFound => "nu::parser::file_not_found",
+            ErrorCode::RecursiveSource => "nu::parser::recursive_source",
+            ErrorCode::NonConstantValue => "nu::parser::non_constant_value",
+            ErrorCode::VariableNotFound => "nu::resolver::variable_not_found",
+            ErrorCode::ImmutableAssignment => "nu::resolver::immutable_assignment",
+            ErrorCode::ModuleMemberNotFound => "nu::resolver::module_member_not_found",
+            ErrorCode::OverlayNotFound => "nu::resolver::overlay_not_found",
+            ErrorCode::DeclNotFound => "nu::resolver::decl_not_found",
+            ErrorCode::NonBoolCondition => "nu::typechecker::non_bool_condition",
+            ErrorCode::NonListIteration => "nu::typechecker::non_list_iteration",
+            ErrorCode::LoopBlockValue => "nu::typechecker::loop_block_value",
+            ErrorCode::TypeMismatch => "nu::typechecker::type_mismatch",
+            ErrorCode::InvalidListType => "nu::typechecker::invalid_list_type",
+            ErrorCode::UnsupportedOperation => "nu::typechecker::unsupported_operation",
+            ErrorCode::TypecheckUnsupportedNode => "nu::typechecker::unsupported_node",
+            ErrorCode::UnknownFlag => "nu::typechecker::unknown_flag",
+            ErrorCode::MissingArgument => "nu::typechecker::missing_argument",
+            ErrorCode::ExtraArgument => "nu::typechecker::extra_argument",
+            ErrorCode::InvalidErrorRecord => "nu::typechecker::invalid_error_record",
+            ErrorCode::IrUnsupportedNode => "nu::ir::unsupported_node",
+            ErrorCode::IrUnsupportedOperator => "nu::ir::unsupported_operator",
+            ErrorCode::IrInvalidLiteral => "nu::ir::invalid_literal",
+            ErrorCode::IrUnresolvedVariable => "nu::ir::unresolved_variable",
+            ErrorCode::ConvertUnsupportedNode => "nu::ast_converter::unsupported_node",
+            ErrorCode::ConvertUnsupportedOperator => "nu::ast_converter::unsupported_operator",
+            ErrorCode::ConvertInvalidLiteral => "nu::ast_converter::invalid_literal",
+            ErrorCode::ConvertUnresolvedVariable => "nu::ast_converter::unresolved_variable",
+            ErrorCode::ConfigUnknownField => "nu::config::unknown_field",
+            ErrorCode::ConfigInvalidValue => "nu::config::invalid_value",
+            ErrorCode::ConfigNonConstantPath => "nu::config::non_constant_path",
+            ErrorCode::LintUnusedVariable => "nu::lint::unused_variable",
+            ErrorCode::LintShadowing => "nu::lint::shadowing",
+            ErrorCode::LintDeprecatedSyntax => "nu::lint::deprecated_syntax",
+            ErrorCode::LintImplicitAny => "nu::lint::implicit_any",
+            ErrorCode::LintConfigOverwrite => "nu::lint::config_overwrite",
+        }
+    }
+
+    /// Find the error code by its string form, e.g., `nu::parser::expected_token`
+    pub fn from_code(code: &str) -> Option<ErrorCode> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|error_code| error_code.as_str() == code)
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Another location involved in an error, e.g., where the unclosed delimiter was opened
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RelatedSpan {
+    pub span: Span,
+    pub message: String,
+}
+
+impl RelatedSpan {
+    pub fn new(span: Span, message: impl Into<String>) -> Self {
+        Self {
+            span,
+            message: message.into(),
+        }
+    }
+}
+
+/// Replacement of a span of the source, an insertion if the span is empty
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TextEdit {
+    pub span: Span,
+    pub replacement: String,
+}
+
+impl TextEdit {
+    pub fn replace(span: Span, replacement: impl Into<String>) -> Self {
+        Self {
+            span,
+            replacement: replacement.into(),
+        }
+    }
+
+    pub fn insert(offset: usize, text: impl Into<String>) -> Self {
+        Self::replace(Span::new(offset, offset), text)
+    }
+}
+
+/// Suggested change of the source that fixes the error and can be applied by tools without
+/// asking the user, e.g., as an editor's code action
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Fix {
+    /// Description of the fix, e.g., "insert the missing ')'"
+    pub message: String,
+    /// Non-overlapping edits of the file containing the error
+    pub edits: Vec<TextEdit>,
+}
+
+impl Fix {
+    pub fn new(message: impl Into<String>, edits: Vec<TextEdit>) -> Self {
+        Self {
+            message: message.into(),
+            edits,
+        }
+    }
+
+    /// Apply the edits to the contents of a file
+    ///
+    /// The spans of the edits are offsets into the compiler's source, `span_offset` is the offset
+    /// at which the file starts. Fails if an edit is outside of the contents or overlaps another
+    /// edit, e.g., when the fix belongs to a different file or an older version of it.
+    pub fn apply(&self, contents: &[u8], span_offset: usize) -> Result<Vec<u8>, InvalidEdit> {
+        let mut edits: Vec<_> = self.edits.iter().collect();
+        edits.sort_by_key(|edit| edit.span.start);
+
+        let mut result = Vec::with_capacity(contents.len());
+        let mut pos = 0;
+        for edit in edits {
+            let invalid = || InvalidEdit { span: edit.span };
+            let start = edit
+                .span
+                .start
+                .checked_sub(span_offset)
+                .ok_or_else(invalid)?;
+            let end = edit.span.end.checked_sub(span_offset).ok_or_else(invalid)?;
+            if start < pos || end < start || end > contents.len() {
+                return Err(invalid());
+            }
+
+            result.extend_from_slice(&contents[pos..start]);
+            result.extend_from_slice(edit.replacement.as_bytes());
+            pos = end;
+        }
+        result.extend_from_slice(&contents[pos..]);
+
+        Ok(result)
+    }
+}
+
+/// Edit of a fix that cannot be applied to the given contents
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidEdit {
+    pub span: Span,
+}
+
+impl std::fmt::Display for InvalidEdit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "edit of {}..{} is outside of the contents or overlaps another edit",
+            self.span.start, self.span.end
+        )
+    }
+}
+
+impl std::error::Error for InvalidEdit {}
+
+/// Text of a diagnostic, rendered only when the diagnostic is shown
+///
+/// Most messages are fixed or quote a piece of the source code, neither of which needs to be
+/// formatted when the error is created. This keeps the error path cheap in parses with many
+/// errors, e.g., while the user is typing.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Message {
+    Text(Cow<'static, str>),
+    /// The source code of the span between two fixed parts, e.g., "variable `" and "` not found"
+    Source {
+        before: Cow<'static, str>,
+        span: Span,
+        after: Cow<'static, str>,
+    },
+}
+
+impl Message {
+    pub fn source(before: &'static str, span: Span, after: &'static str) -> Self {
+        Message::Source {
+            before: Cow::Borrowed(before),
+            span,
+            after: Cow::Borrowed(after),
+        }
+    }
+
+    /// The text of the message, the spans are offsets into the source
+    pub fn render<'a>(&'a self, source: &[u8]) -> Cow<'a, str> {
+        match self {
+            Message::Text(text) => Cow::Borrowed(text),
+            Message::Source {
+                before,
+                span,
+                after,
+            } => {
+                let contents = source.get(span.start..span.end).unwrap_or_default();
+                Cow::Owned(format!(
+                    "{before}{}{after}",
+                    String::from_utf8_lossy(contents)
+                ))
+            }
+        }
+    }
+}
+
+impl From<&'static str> for Message {
+    fn from(message: &'static str) -> Self {
+        Message::Text(Cow::Borrowed(message))
+    }
+}
+
+impl From<String> for Message {
+    fn from(message: String) -> Self {
+        Message::Text(Cow::Owned(message))
+    }
+}
+
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SourceError {
-    pub message: String,
+    pub code: ErrorCode,
+    /// Use `message()` for the text
+    pub message: Message,
     pub node_id: NodeId,
     pub severity: Severity,
+    /// Hint on how to fix the error, e.g., a similar name that is defined
+    pub help: Option<String>,
+    /// Background for understanding the error, e.g., the language rule that was broken
+    pub note: Option<String>,
+    pub related: Vec<RelatedSpan>,
+    pub fixes: Vec<Fix>,
+}
+
+impl SourceError {
+    /// Error at an opening delimiter that is never closed, e.g., `(` or a quote
+    ///
+    /// The closing delimiter is expected at `close_at`, where the fix inserts it.
+    pub fn unclosed_delimiter(
+        node_id: NodeId,
+        opening: &str,
+        closing: &str,
+        close_at: usize,
+    ) -> Self {
+        SourceError {
+            code: ErrorCode::UnclosedDelimiter,
+            message: format!("unclosed '{opening}'").into(),
+            node_id,
+            severity: Severity::Error,
+            help: None,
+            note: None,
+            related: vec![RelatedSpan::new(
+                Span::new(close_at, close_at),
+                format!("expected '{closing}' to close the '{opening}'"),
+            )],
+            fixes: vec![Fix::new(
+                format!("insert the missing '{closing}'"),
+                vec![TextEdit::insert(close_at, closing)],
+            )],
+        }
+    }
+
+    pub fn message(&self, compiler: &Compiler) -> Cow<'_, str> {
+        self.message.render(&compiler.source)
+    }
+
+    /// Format the error for the stages' `display_state()`
+    pub fn display(&self, compiler: &Compiler) -> String {
+        let mut result = format!(
+            "{:?} (NodeId {}): {}\n",
+            self.severity,
+            self.node_id.0,
+            self.message(compiler)
+        );
+
+        for related in &self.related {
+            result.push_str(&format!(
+                "  related ({} to {}): {}\n",
+                related.span.start, related.span.end, related.message
+            ));
+        }
+
+        if let Some(help) = &self.help {
+            result.push_str(&format!("  help: {help}\n"));
+        }
+
+        if let Some(note) = &self.note {
+            result.push_str(&format!("  note: {note}\n"));
+        }
+
+        for fix in &self.fixes {
+            result.push_str(&format!("  fix: {}\n", fix.message));
+            for edit in &fix.edits {
+                result.push_str(&format!(
+                    "    ({} to {}) => {:?}\n",
+                    edit.span.start, edit.span.end, edit.replacement
+                ));
+            }
+        }
+
+        result
+    }
+
+    /// Whether the error should stop the compilation
+    pub fn is_fatal(&self, warnings_as_errors: bool) -> bool {
+        match self.severity {
+            Severity::Error => true,
+            Severity::Warning => warnings_as_errors,
+            Severity::Note => false,
+        }
+    }
+
+    /// Convert the error to JSON for machine-readable output
+    pub fn to_json(&self, compiler: &Compiler) -> serde_json::Value {
+        serde_json::json!({
+            "severity": format!("{:?}", self.severity),
+            "code": self.code.as_str(),
+            "node_id": self.node_id.0,
+            "message": self.message(compiler),
+            "help": self.help,
+            "note": self.note,
+            "related": self
+                .related
+                .iter()
+                .map(|related| {
+                    serde_json::json!({
+                        "start": related.span.start,
+                        "end": related.span.end,
+                        "message": related.message,
+                    })
+                })
+                .collect::<Vec<_>>(),
+            "fixes": self
+                .fixes
+                .iter()
+                .map(|fix| {
+                    let edits: Vec<_> = fix
+                        .edits
+                        .iter()
+                        .map(|edit| {
+                            serde_json::json!({
+                                "start": edit.span.start,
+                                "end": edit.span.end,
+                                "replacement": edit.replacement,
+                            })
+                        })
+                        .collect();
+                    serde_json::json!({ "message": fix.message, "edits": edits })
+                })
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    /// Convert the error to JSON with the file and line/column ranges instead of NodeIds, for
+    /// tools that consume the diagnostics, e.g., editors or CI
+    ///
+    /// Besides the line and the column, each position has the byte offset within its file.
+    pub fn to_diagnostic_json(&self, compiler: &Compiler) -> serde_json::Value {
+        let location_json = |span: Span| {
+            let file = compiler.file_of(span);
+            let path = file.map(|(fname, _, _)| fname.as_str());
+            let file_start = file.map_or(0, |(_, start, _)| *start);
+            let ((start_line, start_column), (end_line, end_column)) =
+                compiler.span_line_columns(span);
+
+            serde_json::json!({
+                "path": path,
+                "start": {
+                    "line": start_line,
+                    "column": start_column,
+                    "offset": span.start - file_start,
+                },
+                "end": {
+                    "line": end_line,
+                    "column": end_column,
+                    "offset": span.end - file_start,
+                },
+            })
+        };
+
+        let related: Vec<_> = self
+            .related
+            .iter()
+            .map(|related| {
+                serde_json::json!({
+                    "location": location_json(related.span),
+                    "message": related.message,
+                })
+            })
+            .collect();
+
+        let fixes: Vec<_> = self
+            .fixes
+            .iter()
+            .map(|fix| {
+                let edits: Vec<_> = fix
+                    .edits
+                    .iter()
+                    .map(|edit| {
+                        serde_json::json!({
+                            "location": location_json(edit.span),
+                            "replacement": edit.replacement,
+                        })
+                    })
+                    .collect();
+                serde_json::json!({ "message": fix.message, "edits": edits })
+            })
+            .collect();
+
+        serde_json::json!({
+            "location": location_json(compiler.get_span(self.node_id)),
+            "severity": format!("{:?}", self.severity).to_lowercase(),
+            "code": self.code.as_str(),
+            "message": self.message(compiler),
+            "help": self.help,
+            "note": self.note,
+            "related": related,
+            "fixes": fixes,
+        })
+    }
+
+    /// Convert the error to a miette diagnostic labeling the span of the error's node
+    ///
+    /// The spans are relative to the start of the file containing the node, related spans in other
+    /// files are left out. Use `to_report()` to render the diagnostic with the source code.
+    pub fn to_diagnostic(&self, compiler: &Compiler) -> MietteDiagnostic {
+        let span = compiler.get_span(self.node_id);
+        let (file_start, file_end) = compiler
+            .file_of(span)
+            .map_or((0, compiler.source.len()), |(_, start, end)| (*start, *end));
+
+        let related_labels = self
+            .related
+            .iter()
+            .filter(|related| file_start <= related.span.start && related.span.end <= file_end)
+            .map(|related| {
+                LabeledSpan::new_with_span(
+                    Some(related.message.clone()),
+                    (related.span.start - file_start)..(related.span.end - file_start),
+                )
+            });
+
+        // miette has no notes, show them along with the help
+        let help = match (&self.help, &self.note) {
+            (Some(help), Some(note)) => Some(format!("{help}\nnote: {note}")),
+            (Some(help), None) => Some(help.clone()),
+            (None, Some(note)) => Some(format!("note: {note}")),
+            (None, None) => None,
+        };
+
+        let mut diagnostic = MietteDiagnostic::new(self.message(compiler));
+        if let Some(help) = help {
+            diagnostic = diagnostic.with_help(help);
+        }
+
+        diagnostic
+            .with_code(self.code.as_str())
+            .with_severity(self.severity.into())
+            .with_label(LabeledSpan::new_primary_with_span(
+                None,
+                (span.start - file_start)..(span.end - file_start),
+            ))
+            .and_labels(related_labels)
+    }
+
+    /// Convert the error to a miette report with the source code of the file containing the node
+    pub fn to_report(&self, compiler: &Compiler) -> miette::Report {
+        let report = miette::Report::new(self.to_diagnostic(compiler));
+
+        match compiler.file_of(compiler.get_span(self.node_id)) {
+            Some((fname, file_start, file_end)) => report.with_source_code(NamedSource::new(
+                fname,
+                compiler.source[*file_start..*file_end].to_vec(),
+            )),
+            None => report,
+        }
+    }
+}
+
+/// Convert the errors to a JSON array of `SourceError::to_diagnostic_json()` objects, for tools
+/// that consume all diagnostics of a compilation at once
+pub fn diagnostics_json<'a>(
+    compiler: &Compiler,
+    errors: impl IntoIterator<Item = &'a SourceError>,
+) -> serde_json::Value {
+    errors
+        .into_iter()
+        .map(|error| error.to_diagnostic_json(compiler))
+        .collect()
+}
+
+/// Find the candidate most similar to the unknown name, for a "did you mean" hint
+pub fn did_you_mean<'a>(
+    candidates: impl IntoIterator<Item = &'a [u8]>,
+    name: &[u8],
+) -> Option<String> {
+    let candidates: Vec<_> = candidates
+        .into_iter()
+        .map(|candidate| String::from_utf8_lossy(candidate).into_owned())
+        .collect();
+
+    nu_protocol::did_you_mean(&candidates, &String::from_utf8_lossy(name))
+}
+
+/// Number of errors of each severity
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ErrorCounts {
+    pub errors: usize,
+    pub warnings: usize,
+    pub notes: usize,
+}
+
+impl ErrorCounts {
+    pub fn new(errors: &[SourceError]) -> Self {
+        let mut counts = Self::default();
+        counts.add(errors);
+        counts
+    }
+
+    /// Count more errors, e.g., of a later stage
+    pub fn add(&mut self, errors: &[SourceError]) {
+        for error in errors {
+            match error.severity {
+                Severity::Error => self.errors += 1,
+                Severity::Warning => self.warnings += 1,
+                Severity::Note => self.notes += 1,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use miette::NarratableReportHandler;
+
+    use std::borrow::Cow;
+
+    use super::{
+        diagnostics_json, ErrorCode, ErrorCounts, InvalidEdit, Message, RelatedSpan, Severity,
+        SourceError,
+    };
+    use crate::compiler::{Compiler, Span};
+    use crate::lexer::lex;
+    use crate::parser::{NodeId, Parser};
+    use crate::resolver::Resolver;
+
+    fn error(severity: Severity) -> SourceError {
+        SourceError {
+            code: ErrorCode::ExpectedToken,
+            message: format!("{severity:?}").into(),
+            node_id: NodeId(0),
+            severity,
+            help: None,
+            note: None,
+            related: vec![],
+            fixes: vec![],
+        }
+    }
+
+    #[test]
+    fn warnings_as_errors() {
+        let mut compiler = Compiler::new();
+        let errors = [
+            error(Severity::Warning),
+            error(Severity::Note),
+            error(Severity::Warning),
+        ];
+
+        assert!(!compiler.has_fatal_errors(&errors));
+        assert!(compiler.has_fatal_errors(&[error(Severity::Error)]));
+
+        compiler.options.warnings_as_errors = true;
+        assert!(compiler.has_fatal_errors(&errors));
+        assert!(!compiler.has_fatal_errors(&[error(Severity::Note)]));
+
+        let mut counts = ErrorCounts::new(&errors);
+        assert_eq!(
+            counts,
+            ErrorCounts {
+                errors: 0,
+                warnings: 2,
+                notes: 1
+            }
+        );
+
+        counts.add(&[error(Severity::Error)]);
+        assert_eq!(counts.errors, 1);
+    }
+
+    #[test]
+    fn lazy_messages() {
+        let message = Message::source("variable `", Span::new(1, 2), "` not found");
+        assert_eq!(message.render(b"$x + 1"), "variable `x` not found");
+        assert!(matches!(
+            Message::from("expected name").render(b""),
+            Cow::Borrowed("expected name")
+        ));
+
+        let mut compiler = Compiler::new();
+        compiler.add_file("first.nu", b"1\n");
+        assert!(compiler.check("second.nu", b"let x = 1\n$y + $x"));
+        let mut resolver = Resolver::new(&compiler);
+        resolver.resolve();
+
+        let error = &resolver.errors[0];
+        assert!(matches!(error.message, Message::Source { .. }));
+        assert_eq!(error.message(&compiler), "variable `y` not found");
+    }
+
+    #[test]
+    fn error_codes_are_unique() {
+        for (idx, code) in ErrorCode::ALL.iter().enumerate() {
+            assert!(code.as_str().starts_with("nu::"));
+            assert_eq!(ErrorCode::from_code(code.as_str()), Some(*code));
+            assert!(!ErrorCode::ALL[..idx]
+                .iter()
+                .any(|other| other.as_str() == code.as_str()));
+        }
+    }
+
+    #[test]
+    fn miette_report() {
+        let src = b"let x = 1";
+        let mut compiler = Compiler::new();
+        compiler.add_file("first.nu", b"1\n");
+        let span_offset = compiler.span_offset();
+        compiler.add_file("second.nu", src);
+
+        let (tokens, err) = lex(src, span_offset);
+        assert!(err.is_ok());
+        let compiler = Parser::new(compiler, tokens).parse();
+
+        let node_id = (0..compiler.ast_nodes.len())
+            .map(NodeId)
+            .find(|node_id| compiler.get_span_contents(*node_id) == b"1")
+            .expect("missing int");
+        let error = SourceError {
+            code: ErrorCode::TypeMismatch,
+            message: "unused variable".into(),
+            node_id,
+            severity: Severity::Warning,
+            help: Some("remove the variable".into()),
+            note: None,
+            related: vec![RelatedSpan::new(Span::new(6, 7), "declared here")],
+            fixes: vec![],
+        };
+
+        let diagnostic = error.to_diagnostic(&compiler);
+        assert_eq!(diagnostic.severity, Some(miette::Severity::Warning));
+        assert_eq!(
+            diagnostic.code.as_deref(),
+            Some("nu::typechecker::type_mismatch")
+        );
+        let labels = diagnostic.labels.expect("missing labels");
+        assert_eq!(labels.len(), 2);
+        assert_eq!((labels[0].offset(), labels[0].len()), (8, 1));
+        assert_eq!((labels[1].offset(), labels[1].len()), (4, 1));
+
+        let mut rendered = String::new();
+        NarratableReportHandler::new()
+            .render_report(&mut rendered, error.to_report(&compiler).as_ref())
+            .expect("rendering to a string does not fail");
+        assert!(rendered.contains("snippet for second.nu"));
+        assert!(rendered.contains("label at line 1, column 9"));
+        assert!(rendered.contains("remove the variable"));
+        assert!(rendered.contains("declared here"));
+    }
+
+    #[test]
+    fn diagnostic_json() {
+        let src = "let x = 1\nlet ä = ($x + 1";
+        let mut compiler = Compiler::new();
+        compiler.add_file("test.nu", src.as_bytes());
+
+        let (tokens, err) = lex(src.as_bytes(), 0);
+        assert!(err.is_ok());
+        let compiler = Parser::new(compiler, tokens).parse();
+
+        let error = compiler
+            .errors
+            .iter()
+            .find(|error| error.code == ErrorCode::UnclosedDelimiter)
+            .expect("missing unclosed delimiter error");
+
+        assert_eq!(
+            error.to_diagnostic_json(&compiler),
+            serde_json::json!({
+                "location": {
+                    "path": "test.nu",
+                    "start": { "line": 2, "column": 9, "offset": 19 },
+                    "end": { "line": 2, "column": 10, "offset": 20 },
+                },
+                "severity": "error",
+                "code": "nu::parser::unclosed_delimiter",
+                "message": "unclosed '('",
+                "help": null,
+                "note": null,
+                "related": [{
+                    "location": {
+                        "path": "test.nu",
+                        "start": { "line": 2, "column": 16, "offset": 26 },
+                        "end": { "line": 2, "column": 16, "offset": 26 },
+                    },
+                    "message": "expected ')' to close the '('",
+                }],
+                "fixes": [{
+                    "message": "insert the missing ')'",
+                    "edits": [{
+                        "location": {
+                            "path": "test.nu",
+                            "start": { "line": 2, "column": 16, "offset": 26 },
+                            "end": { "line": 2, "column": 16, "offset": 26 },
+                        },
+                        "replacement": ")",
+                    }],
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn diagnostics_json_offsets() {
+        let mut compiler = Compiler::new();
+        compiler.add_file("first.nu", b"1\n");
+        assert!(!compiler.check("second.nu", b"[1 2\n(3"));
+
+        let diagnostics = diagnostics_json(&compiler, &compiler.errors);
+        let diagnostics = diagnostics.as_array().expect("not an array");
+        assert_eq!(diagnostics.len(), 2);
+
+        // the offsets are within the file, not the compiler's source
+        assert_eq!(
+            diagnostics[0]["location"],
+            serde_json::json!({
+                "path": "second.nu",
+                "start": { "line": 2, "column": 1, "offset": 5 },
+                "end": { "line": 2, "column": 2, "offset": 6 },
+            })
+        );
+        assert_eq!(diagnostics[1]["location"]["start"]["offset"], 0);
+        assert_eq!(
+            diagnostics[1]["related"][0]["location"]["start"]["offset"],
+            7
+        );
+    }
+
+    #[test]
+    fn locations_at_file_boundary() {
+        let mut compiler = Compiler::new();
+        compiler.add_file("first.nu", b"1\n2");
+        compiler.add_file("second.nu", b"(3");
+
+        // the first file ends where the second one starts
+        assert_eq!(
+            compiler.span_line_columns(Span::new(1, 3)),
+            ((1, 2), (2, 2))
+        );
+        assert_eq!(
+            compiler.span_line_columns(Span::new(3, 4)),
+            ((1, 1), (1, 2))
+        );
+        assert_eq!(
+            compiler.span_line_columns(Span::new(3, 3)),
+            ((2, 2), (2, 2))
+        );
+    }
+
+    #[test]
+    fn apply_fixes() {
+        let src = b"(1 = 2";
+        let mut compiler = Compiler::new();
+        compiler.add_file("first.nu", b"1\n");
+        let span_offset = compiler.span_offset();
+        compiler.add_file("second.nu", src);
+
+        let (tokens, err) = lex(src, span_offset);
+        assert!(err.is_ok());
+        let compiler = Parser::new(compiler, tokens).parse();
+
+        let fixes: Vec<_> = compiler
+            .errors
+            .iter()
+            .flat_map(|error| &error.fixes)
+            .collect();
+        assert_eq!(fixes.len(), 2);
+
+        assert_eq!(fixes[0].apply(src, span_offset), Ok(b"(1 == 2".to_vec()));
+        assert_eq!(fixes[1].apply(src, span_offset), Ok(b"(1 = 2)".to_vec()));
+
+        // the spans are past the end of the contents without the file's offset
+        assert_eq!(
+            fixes[1].apply(src, 0),
+            Err(InvalidEdit {
+                span: Span::new(8, 8)
+            })
+        );
+        assert!(fixes[0].apply(b"", span_offset).is_err());
+        assert!(fixes[0].apply(src, span_offset + 4).is_err());
+    }
 }
@@ -0,0 +1,219 @@
+//! C ABI for embedding the parser in non-Rust hosts
+//!
+//! The matching C declarations are in `include/new_nu_parser.h`. A host compiles a source buffer
+//! with `nnp_compile()`, queries the result and releases it with `nnp_compilation_free()`. All
+//! strings returned by the library are NUL-terminated, UTF-8 encoded and owned by the compilation
+//! they came from, so they stay valid until the compilation is freed.
+use std::ffi::{c_char, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use crate::bindings::compile;
+use crate::errors::Severity;
+
+const FILE_NAME: &str = "ffi.nu";
+
+/// Result of compiling a source buffer (opaque to C)
+pub struct NnpCompilation {
+    diagnostics: Vec<OwnedDiagnostic>,
+    ast_json: CString,
+}
+
+/// Diagnostic with the strings that `NnpDiagnostic` points to
+struct OwnedDiagnostic {
+    severity: NnpSeverity,
+    start: usize,
+    end: usize,
+    message: CString,
+    help: Option<CString>,
+    note: Option<CString>,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NnpSeverity {
+    Error = 0,
+    Note = 1,
+    Warning = 2,
+}
+
+impl From<Severity> for NnpSeverity {
+    fn from(severity: Severity) -> Self {
+        match severity {
+            Severity::Error => NnpSeverity::Error,
+            Severity::Warning => NnpSeverity::Warning,
+            Severity::Note => NnpSeverity::Note,
+        }
+    }
+}
+
+/// Diagnostic filled in by `nnp_diagnostic_get()`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct NnpDiagnostic {
+    pub severity: NnpSeverity,
+    /// Start of the span as a byte offset into the source buffer
+    pub start: usize,
+    /// End of the span as a byte offset into the source buffer (exclusive)
+    pub end: usize,
+    /// Message owned by the compilation
+    pub message: *const c_char,
+    /// Hint on how to fix the error owned by the compilation, NULL if there is none
+    pub help: *const c_char,
+    /// Background for understanding the error owned by the compilation, NULL if there is none
+    pub note: *const c_char,
+}
+
+fn to_c_string(s: String) -> CString {
+    // Interior NUL bytes can only come from the source code, drop them rather than failing
+    CString::new(s.replace('\0', "")).unwrap_or_default()
+}
+
+/// Compile `len` bytes of source code starting at `source`
+///
+/// Returns NULL if `source` is NULL or if the compiler crashed. The result must be released with
+/// `nnp_compilation_free()`.
+///
+/// # Safety
+///
+/// `source` must point to at least `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn nnp_compile(source: *const u8, len: usize) -> *mut NnpCompilation {
+    if source.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let source = std::slice::from_raw_parts(source, len);
+
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        let compiled = compile(FILE_NAME, source);
+
+        let diagnostics = compiled
+            .diagnostics()
+            .into_iter()
+            .map(|diagnostic| OwnedDiagnostic {
+                severity: diagnostic.severity.into(),
+                start: diagnostic.span.start,
+                end: diagnostic.span.end,
+                message: to_c_string(diagnostic.message),
+                help: diagnostic.help.map(to_c_string),
+                note: diagnostic.note.map(to_c_string),
+            })
+            .collect();
+
+        NnpCompilation {
+            diagnostics,
+            ast_json: to_c_string(compiled.compiler.to_json().to_string()),
+        }
+    }));
+
+    match res {
+        Ok(compilation) => Box::into_raw(Box::new(compilation)),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Release a compilation returned by `nnp_compile()`; NULL is ignored
+///
+/// # Safety
+///
+/// `compilation` must be NULL or a pointer returned by `nnp_compile()` that was not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn nnp_compilation_free(compilation: *mut NnpCompilation) {
+    if !compilation.is_null() {
+        drop(Box::from_raw(compilation));
+    }
+}
+
+/// Number of diagnostics of all compiler stages that ran
+///
+/// # Safety
+///
+/// `compilation` must be a valid pointer returned by `nnp_compile()`.
+#[no_mangle]
+pub unsafe extern "C" fn nnp_diagnostic_count(compilation: *const NnpCompilation) -> usize {
+    (*compilation).diagnostics.len()
+}
+
+/// Fill `out` with the diagnostic at `idx`; returns false if `idx` is out of bounds
+///
+/// # Safety
+///
+/// `compilation` must be a valid pointer returned by `nnp_compile()` and `out` must point to
+/// writable memory for one `NnpDiagnostic`.
+#[no_mangle]
+pub unsafe extern "C" fn nnp_diagnostic_get(
+    compilation: *const NnpCompilation,
+    idx: usize,
+    out: *mut NnpDiagnostic,
+) -> bool {
+    let Some(diagnostic) = (*compilation).diagnostics.get(idx) else {
+        return false;
+    };
+
+    let as_ptr = |s: &Option<CString>| s.as_ref().map_or(std::ptr::null(), |s| s.as_ptr());
+
+    *out = NnpDiagnostic {
+        severity: diagnostic.severity,
+        start: diagnostic.start,
+        end: diagnostic.end,
+        message: diagnostic.message.as_ptr(),
+        help: as_ptr(&diagnostic.help),
+        note: as_ptr(&diagnostic.note),
+    };
+
+    true
+}
+
+/// The AST nodes and parser errors as JSON, in the same format as `new-nu-parser --ast --json`
+///
+/// # Safety
+///
+/// `compilation` must be a valid pointer returned by `nnp_compile()`.
+#[no_mangle]
+pub unsafe extern "C" fn nnp_ast_json(compilation: *const NnpCompilation) -> *const c_char {
+    (*compilation).ast_json.as_ptr()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ffi::CStr;
+
+    #[test]
+    fn compile_and_iterate_diagnostics() {
+        let source = b"let x = 1\n$x + $y";
+
+        unsafe {
+            let compilation = nnp_compile(source.as_ptr(), source.len());
+            assert!(!compilation.is_null());
+
+            assert_eq!(nnp_diagnostic_count(compilation), 1);
+
+            let mut diagnostic = NnpDiagnostic {
+                severity: NnpSeverity::Note,
+                start: 0,
+                end: 0,
+                message: std::ptr::null(),
+                help: std::ptr::null(),
+                note: std::ptr::null(),
+            };
+            assert!(nnp_diagnostic_get(compilation, 0, &mut diagnostic));
+            assert!(!nnp_diagnostic_get(compilation, 1, &mut diagnostic));
+
+            assert_eq!(diagnostic.severity, NnpSeverity::Error);
+            assert_eq!(&source[diagnostic.start..diagnostic.end], b"$y");
+            assert_eq!(
+                CStr::from_ptr(diagnostic.message).to_str(),
+                Ok("variable `y` not found")
+            );
+            assert!(diagnostic.help.is_null());
+
+            let ast: serde_json::Value =
+                serde_json::from_slice(CStr::from_ptr(nnp_ast_json(compilation)).to_bytes())
+                    .expect("AST is valid JSON");
+            assert!(ast["nodes"].is_array());
+
+            nnp_compilation_free(compilation);
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! Loading of the files referred to by `source` and `use`
+//!
+//! The compiler reads the files through a `FileProvider`, so that the same code can compile files
+//! from the disk, from an editor's unsaved buffers or from memory in tests and sandboxes.
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Commands whose argument is a file or module read when the file is parsed
+pub const PARSE_TIME_COMMANDS: &[&str] = &["source", "source-env", "use", "overlay use"];
+
+pub trait FileProvider: Send + Sync {
+    /// Read the whole contents of the file
+    fn read_file(&self, path: &Path) -> io::Result<Vec<u8>>;
+}
+
+/// Reads the files from the file system
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFileProvider;
+
+impl FileProvider for RealFileProvider {
+    fn read_file(&self, path: &Path) -> io::Result<Vec<u8>> {
+        std::fs::read(path)
+    }
+}
+
+/// Serves the files added to it, any other file is not found
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFileProvider {
+    files: HashMap<PathBuf, Vec<u8>>,
+}
+
+impl MemoryFileProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_file(mut self, path: impl Into<PathBuf>, contents: impl Into<Vec<u8>>) -> Self {
+        self.add_file(path, contents);
+        self
+    }
+
+    /// Add the file, replacing its previous contents
+    pub fn add_file(&mut self, path: impl Into<PathBuf>, contents: impl Into<Vec<u8>>) {
+        self.files.insert(path.into(), contents.into());
+    }
+}
+
+impl FileProvider for MemoryFileProvider {
+    fn read_file(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.files.get(path).cloned().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} not found", path.display()),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MemoryFileProvider;
+    use crate::compiler::Compiler;
+    use crate::errors::ErrorCode;
+    use crate::resolver::Resolver;
+
+    fn compile(files: MemoryFileProvider, fname: &str, contents: &[u8]) -> Compiler {
+        let mut compiler = Compiler::new().with_file_provider(files);
+        compiler.check(fname, contents);
+
+        let mut resolver = Resolver::new(&compiler);
+        resolver.resolve();
+        compiler.merge_name_bindings(resolver.to_name_bindings());
+        compiler
+    }
+
+    fn error_codes(compiler: &Compiler) -> Vec<ErrorCode> {
+        compiler.errors.iter().map(|error| error.code).collect()
+    }
+
+    #[test]
+    fn sourced_definitions_are_in_scope() {
+        let files = MemoryFileProvider::new()
+            .with_file("lib/defs.nu", "source 'consts.nu'\ndef foo [] { $x }")
+            .with_file("lib/consts.nu", "let x = 1");
+        let compiler = compile(files, "main.nu", b"source \"lib/defs.nu\"\nfoo\n$x");
+
+        assert_eq!(error_codes(&compiler), []);
+        assert_eq!(compiler.sources.len(), 2);
+        assert_eq!(compiler.file_offsets.len(), 3);
+        assert_eq!(compiler.file_offsets[1].0, "lib/defs.nu");
+        assert_eq!(compiler.file_offsets[2].0, "lib/consts.nu");
+    }
+
+    #[test]
+    fn modules_are_loaded_by_path_only() {
+        let files = MemoryFileProvider::new().with_file("mod.nu", "def foo [] { 1 }");
+        let compiler = compile(
+            files,
+            "main.nu",
+            b"use std\nuse \"mod.nu\"\noverlay use 'mod.nu'",
+        );
+
+        assert_eq!(error_codes(&compiler), []);
+        assert_eq!(compiler.sources.len(), 2);
+    }
+
+    #[test]
+    fn used_files_are_modules() {
+        let files = MemoryFileProvider::new()
+            .with_file("lib/spam.nu", "export def foo [] { 1 }\ndef bar [] { 2 }");
+        let compiler = compile(
+            files,
+            "main.nu",
+            b"use 'lib/spam.nu'\nspam foo\nuse 'lib/spam.nu' [foo bar]\nfoo",
+        );
+
+        assert_eq!(error_codes(&compiler), [ErrorCode::ModuleMemberNotFound]);
+        assert_eq!(compiler.modules.len(), 2);
+        assert_eq!(compiler.modules[0].name, "spam");
+        assert_eq!(compiler.call_name_parts.values().max(), Some(&2));
+    }
+
+    #[test]
+    fn used_files_are_overlays() {
+        let files = MemoryFileProvider::new().with_file("lib/spam.nu", "export def foo [] { 1 }");
+        let compiler = compile(
+            files,
+            "main.nu",
+            b"overlay use 'lib/spam.nu'\nfoo\noverlay hide spam\nfoo",
+        );
+
+        assert_eq!(error_codes(&compiler), []);
+        assert_eq!(compiler.modules[0].name, "spam");
+        // only the call made while the overlay is active is resolved
+        assert_eq!(compiler.call_name_parts.len(), 1);
+    }
+
+    #[test]
+    fn constant_paths_are_loaded() {
+        let files = MemoryFileProvider::new()
+            .with_file("lib/spam.nu", "export def foo [] { 1 }")
+            .with_file("lib/eggs.nu", "let x = 1");
+        let compiler = compile(
+            files,
+            "main.nu",
+            b"const dir = 'lib/'\nconst spam = $dir + 'spam.nu'\nuse $spam\nspam foo\nsource ($dir + 'eggs.nu')\nsource $spam",
+        );
+
+        // `source` of a subexpression is not loaded, its value is not known when parsing
+        assert_eq!(error_codes(&compiler), []);
+        assert_eq!(compiler.sources.len(), 2);
+        assert_eq!(compiler.modules[0].name, "spam");
+    }
+
+    #[test]
+    fn missing_and_recursive_files() {
+        let files = MemoryFileProvider::new().with_file("lib/a.nu", "source '../main.nu'");
+        let compiler = compile(
+            files,
+            "main.nu",
+            b"source 'missing.nu'\nsource './lib/a.nu'",
+        );
+
+        assert_eq!(
+            error_codes(&compiler),
+            [ErrorCode::FileNotFound, ErrorCode::RecursiveSource]
+        );
+    }
+
+    #[test]
+    fn no_files_are_loaded_without_provider() {
+        let mut compiler = Compiler::new();
+        assert!(compiler.check("main.nu", b"source 'missing.nu'"));
+        assert!(compiler.sources.is_empty());
+    }
+}
@@ -0,0 +1,151 @@
+//! Formatting of the source code
+//!
+//! The formatter works on the lexed tokens, which cover the whole source losslessly together with
+//! the whitespace between them. Only the whitespace around lines is changed: lines are indented by
+//! the depth of the brackets they are in (and one more level after a line ending with a pipe),
+//! trailing whitespace is removed and consecutive empty lines are collapsed into one. The tokens
+//! themselves and the spacing between them are kept as written, lines inside multiline strings are
+//! kept untouched.
+use crate::compiler::Spanned;
+use crate::lexer::{lex, LexError, Token};
+
+#[derive(Debug, Clone)]
+pub struct FormatOptions {
+    /// Number of spaces per indentation level
+    pub indent_width: usize,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self { indent_width: 4 }
+    }
+}
+
+/// How a single line of the source is formatted
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Line {
+    /// The line starts inside a multiline token and is kept as is
+    Verbatim,
+    Empty,
+    Code {
+        indent: usize,
+        /// The line ends inside a multiline token, so its trailing whitespace is kept
+        keep_end: bool,
+    },
+}
+
+/// Format the source, failing if it cannot be lexed
+pub fn format_source(
+    contents: &[u8],
+    options: &FormatOptions,
+) -> Result<Vec<u8>, Spanned<LexError>> {
+    let (tokens, err) = lex(contents, 0);
+    err?;
+
+    let mut tokens = tokens
+        .iter()
+        .filter(|(token, _)| *token != Token::Eof)
+        .peekable();
+    let mut output = Vec::with_capacity(contents.len());
+
+    // depth of the brackets and string interpolations at the current token
+    let mut depth = 0usize;
+    let mut interp_depth = 0usize;
+    // end of the furthest reaching token so far, after the line start if the token is multiline
+    let mut tokens_end = 0;
+    // the previous line with code ended with a pipe
+    let mut continued = false;
+    let mut empty_pending = false;
+
+    let mut start = 0;
+    while start <= contents.len() {
+        let end = contents[start..]
+            .iter()
+            .position(|c| *c == b'\n')
+            .map_or(contents.len(), |pos| start + pos);
+
+        let verbatim = tokens_end > start || interp_depth > 0;
+
+        let mut indent = depth;
+        let mut leading = true;
+        let mut last_token = None;
+
+        // the newline ending the line is the last token of the line
+        while let Some((token, span)) = tokens.next_if(|(_, span)| span.start <= end) {
+            match token {
+                Token::LParen | Token::LSquare | Token::LCurly => depth += 1,
+                Token::RParen | Token::RSquare | Token::RCurly => depth = depth.saturating_sub(1),
+                Token::DqStringInterpStart | Token::SqStringInterpStart => interp_depth += 1,
+                Token::StrInterpEnd => interp_depth = interp_depth.saturating_sub(1),
+                _ => (),
+            }
+
+            if leading && matches!(token, Token::RParen | Token::RSquare | Token::RCurly) {
+                indent = indent.saturating_sub(1);
+            } else {
+                leading = false;
+            }
+
+            if token != Token::Newline {
+                tokens_end = tokens_end.max(span.end);
+            }
+            if !matches!(token, Token::Newline | Token::Comment) {
+                last_token = Some(token);
+            }
+        }
+        let keep_end = tokens_end > end;
+
+        let text = &contents[start..end];
+        let line = if verbatim {
+            Line::Verbatim
+        } else if text.trim_ascii().is_empty() {
+            Line::Empty
+        } else {
+            Line::Code {
+                indent: indent + continued as usize,
+                keep_end,
+            }
+        };
+
+        match line {
+            Line::Empty => empty_pending = !output.is_empty(),
+            Line::Verbatim | Line::Code { .. } => {
+                if std::mem::take(&mut empty_pending) {
+                    output.push(b'\n');
+                }
+            }
+        }
+
+        match line {
+            Line::Verbatim => output.extend_from_slice(text),
+            Line::Empty => (),
+            Line::Code { indent, keep_end } => {
+                let text = if keep_end {
+                    text.trim_ascii_start()
+                } else {
+                    text.trim_ascii()
+                };
+                output.extend(std::iter::repeat(b' ').take(indent * options.indent_width));
+                output.extend_from_slice(text);
+            }
+        }
+
+        if line != Line::Empty && end < contents.len() {
+            output.push(b'\n');
+        }
+        if let Some(token) = last_token {
+            continued = matches!(
+                token,
+                Token::Pipe | Token::ErrGreaterThanPipe | Token::OutErrGreaterThanPipe
+            );
+        }
+
+        start = end + 1;
+    }
+
+    if !output.is_empty() && !output.ends_with(b"\n") {
+        output.push(b'\n');
+    }
+
+    Ok(output)
+}
@@ -0,0 +1,113 @@
+//! Syntax highlighting of the line being edited in Nushell's line editor (reedline)
+//!
+//! ```ignore
+//! let line_editor = Reedline::create().with_highlighter(Box::new(Highlighter::default()));
+//! ```
+use std::collections::HashMap;
+
+use nu_ansi_term::{Color, Style};
+use reedline::StyledText;
+
+use crate::compiler::Compiler;
+use crate::semantic_tokens::{semantic_tokens, SemanticTokenKind};
+
+/// Highlighter styling the semantic tokens of the line
+///
+/// The line is only parsed, so highlighting stays fast even for long lines. Parts of the line
+/// without a semantic token, and the whole line if it cannot be lexed, are left unstyled.
+pub struct Highlighter {
+    pub styles: HashMap<SemanticTokenKind, Style>,
+}
+
+impl Default for Highlighter {
+    /// Colors similar to the default ones of Nushell
+    fn default() -> Self {
+        let styles = HashMap::from([
+            (SemanticTokenKind::Number, Color::Purple.bold()),
+            (SemanticTokenKind::String, Color::Green.normal()),
+            (SemanticTokenKind::Variable, Color::Purple.normal()),
+            (SemanticTokenKind::Keyword, Color::LightCyan.normal()),
+            (SemanticTokenKind::Parameter, Color::Blue.bold()),
+            (SemanticTokenKind::Type, Color::Blue.normal()),
+            (SemanticTokenKind::Function, Color::Cyan.bold()),
+            (SemanticTokenKind::Operator, Color::Yellow.normal()),
+        ]);
+
+        Self { styles }
+    }
+}
+
+impl reedline::Highlighter for Highlighter {
+    fn highlight(&self, line: &str, _cursor: usize) -> StyledText {
+        let mut compiler = Compiler::new();
+        compiler.check("repl", line.as_bytes());
+
+        let mut styled = StyledText::new();
+        let mut pos = 0;
+
+        for token in semantic_tokens(&compiler) {
+            // nested tokens are styled as part of the outer one
+            if token.span.start < pos {
+                continue;
+            }
+
+            let (Some(before), Some(text)) = (
+                line.get(pos..token.span.start),
+                line.get(token.span.start..token.span.end),
+            ) else {
+                continue;
+            };
+
+            let style = self.styles.get(&token.kind).copied().unwrap_or_default();
+
+            if !before.is_empty() {
+                styled.push((Style::default(), before.to_string()));
+            }
+            styled.push((style, text.to_string()));
+            pos = token.span.end;
+        }
+
+        if pos < line.len() {
+            styled.push((Style::default(), line[pos..].to_string()));
+        }
+
+        styled
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nu_ansi_term::Style;
+    use reedline::Highlighter as _;
+
+    use super::Highlighter;
+    use crate::semantic_tokens::SemanticTokenKind;
+
+    #[test]
+    fn highlight_line() {
+        let highlighter = Highlighter::default();
+        let styled = highlighter.highlight("let x = 1 + $x", 0);
+
+        let style = |kind| highlighter.styles[&kind];
+        assert_eq!(
+            styled.buffer,
+            vec![
+                (Style::default(), "let ".to_string()),
+                (style(SemanticTokenKind::Variable), "x".to_string()),
+                (Style::default(), " = ".to_string()),
+                (style(SemanticTokenKind::Number), "1".to_string()),
+                (Style::default(), " ".to_string()),
+                (style(SemanticTokenKind::Operator), "+".to_string()),
+                (Style::default(), " ".to_string()),
+                (style(SemanticTokenKind::Variable), "$x".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn unlexable_line_is_kept() {
+        let line = "let x = \"unclosed";
+        let styled = Highlighter::default().highlight(line, 0);
+        assert_eq!(styled.raw_string(), line);
+    }
+}
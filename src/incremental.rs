@@ -0,0 +1,273 @@
+//! Support for recompiling edited sources
+//!
+//! Reparsing an edited file produces a new AST with new NodeIds. `NodeIdMap` matches the unchanged
+//! subtrees of the old AST with the new one, so that data keyed by NodeId (caches, editor
+//! annotations, etc.) can be carried over to the new compilation. `Analysis::update()` maps the
+//! nodes of every version of a document it compiles.
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use crate::compiler::Compiler;
+use crate::parser::NodeId;
+
+/// Mapping of NodeIds of unchanged subtrees between two compilations
+#[derive(Debug, Default)]
+pub struct NodeIdMap {
+    old_to_new: HashMap<NodeId, NodeId>,
+    new_to_old: HashMap<NodeId, NodeId>,
+}
+
+impl NodeIdMap {
+    /// Match the ASTs of two compilations, starting from their last (root) nodes
+    ///
+    /// A node is mapped only if its whole subtree is unchanged, i.e., it has the same kind, the
+    /// same source text and the same structure of children. Unchanged subtrees are found even if
+    /// they moved within their (changed) parent, as long as the parents have the same kind.
+    pub fn new(old: &Compiler, new: &Compiler) -> Self {
+        let mut map = NodeIdMap::default();
+
+        if old.ast_nodes.is_empty() || new.ast_nodes.is_empty() {
+            return map;
+        }
+
+        let mut matcher = Matcher {
+            old: SubtreeHashes::new(old),
+            new: SubtreeHashes::new(new),
+            map: &mut map,
+        };
+
+        matcher.match_nodes(
+            NodeId(old.ast_nodes.len() - 1),
+            NodeId(new.ast_nodes.len() - 1),
+        );
+
+        map
+    }
+
+    /// Get the new NodeId of a node from the old compilation
+    pub fn get(&self, old_node_id: NodeId) -> Option<NodeId> {
+        self.old_to_new.get(&old_node_id).copied()
+    }
+
+    /// Get the old NodeId of a node from the new compilation
+    pub fn get_old(&self, new_node_id: NodeId) -> Option<NodeId> {
+        self.new_to_old.get(&new_node_id).copied()
+    }
+
+    /// Number of mapped nodes
+    pub fn len(&self) -> usize {
+        self.old_to_new.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.old_to_new.is_empty()
+    }
+
+    fn insert(&mut self, old_node_id: NodeId, new_node_id: NodeId) {
+        self.old_to_new.insert(old_node_id, new_node_id);
+        self.new_to_old.insert(new_node_id, old_node_id);
+    }
+}
+
+/// Hashes of the subtrees of all nodes of a compilation, indexed by NodeId
+struct SubtreeHashes<'a> {
+    compiler: &'a Compiler,
+    hashes: Vec<Option<u64>>,
+}
+
+impl<'a> SubtreeHashes<'a> {
+    fn new(compiler: &'a Compiler) -> Self {
+        Self {
+            compiler,
+            hashes: vec![None; compiler.ast_nodes.len()],
+        }
+    }
+
+    fn get(&mut self, node_id: NodeId) -> u64 {
+        if let Some(hash) = self.hashes[node_id.0] {
+            return hash;
+        }
+
+        let mut hasher = DefaultHasher::new();
+        std::mem::discriminant(self.compiler.get_node(node_id)).hash(&mut hasher);
+        self.compiler.get_span_contents(node_id).hash(&mut hasher);
+        for child in self.compiler.node_children(node_id) {
+            self.get(child).hash(&mut hasher);
+        }
+
+        let hash = hasher.finish();
+        self.hashes[node_id.0] = Some(hash);
+        hash
+    }
+
+    /// Whether the subtrees have the same kinds, source text and structure, for ruling out hash
+    /// collisions after their hashes matched
+    fn same_subtree(&self, node_id: NodeId, other: &SubtreeHashes, other_node_id: NodeId) -> bool {
+        let children = self.compiler.node_children(node_id);
+        let other_children = other.compiler.node_children(other_node_id);
+
+        self.same_kind(node_id, other, other_node_id)
+            && self.compiler.get_span_contents(node_id)
+                == other.compiler.get_span_contents(other_node_id)
+            && children.len() == other_children.len()
+            && children
+                .into_iter()
+                .zip(other_children)
+                .all(|(child, other_child)| self.same_subtree(child, other, other_child))
+    }
+
+    fn same_kind(&self, node_id: NodeId, other: &SubtreeHashes, other_node_id: NodeId) -> bool {
+        std::mem::discriminant(self.compiler.get_node(node_id))
+            == std::mem::discriminant(other.compiler.get_node(other_node_id))
+    }
+}
+
+struct Matcher<'a, 'b> {
+    old: SubtreeHashes<'a>,
+    new: SubtreeHashes<'a>,
+    map: &'b mut NodeIdMap,
+}
+
+impl Matcher<'_, '_> {
+    fn unchanged(&mut self, old_node_id: NodeId, new_node_id: NodeId) -> bool {
+        self.old.get(old_node_id) == self.new.get(new_node_id)
+            && self.old.same_subtree(old_node_id, &self.new, new_node_id)
+    }
+
+    fn match_nodes(&mut self, old_node_id: NodeId, new_node_id: NodeId) {
+        if self.unchanged(old_node_id, new_node_id) {
+            self.map_subtree(old_node_id, new_node_id);
+            return;
+        }
+
+        if !self.old.same_kind(old_node_id, &self.new, new_node_id) {
+            return;
+        }
+
+        let old_children = self.old.compiler.node_children(old_node_id);
+        let new_children = self.new.compiler.node_children(new_node_id);
+
+        // Unchanged children, keeping their order
+        let mut anchors = vec![];
+        let mut next_old = 0;
+        for (new_idx, new_child) in new_children.iter().enumerate() {
+            let found = (next_old..old_children.len())
+                .find(|old_idx| self.unchanged(old_children[*old_idx], *new_child));
+
+            if let Some(old_idx) = found {
+                self.map_subtree(old_children[old_idx], *new_child);
+                anchors.push((old_idx, new_idx));
+                next_old = old_idx + 1;
+            }
+        }
+        anchors.push((old_children.len(), new_children.len()));
+
+        // Changed children between the same unchanged ones might still contain unchanged subtrees
+        let (mut old_start, mut new_start) = (0, 0);
+        for (old_end, new_end) in anchors {
+            let mut next_old = old_start;
+            for new_child in &new_children[new_start..new_end] {
+                let found = (next_old..old_end).find(|old_idx| {
+                    self.old
+                        .same_kind(old_children[*old_idx], &self.new, *new_child)
+                });
+
+                if let Some(old_idx) = found {
+                    self.match_nodes(old_children[old_idx], *new_child);
+                    next_old = old_idx + 1;
+                }
+            }
+
+            (old_start, new_start) = (old_end + 1, new_end + 1);
+        }
+    }
+
+    fn map_subtree(&mut self, old_node_id: NodeId, new_node_id: NodeId) {
+        self.map.insert(old_node_id, new_node_id);
+
+        let old_children = self.old.compiler.node_children(old_node_id);
+        let new_children = self.new.compiler.node_children(new_node_id);
+
+        for (old_child, new_child) in old_children.into_iter().zip(new_children) {
+            self.map_subtree(old_child, new_child);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Matcher, NodeIdMap, SubtreeHashes};
+    use crate::compiler::Compiler;
+    use crate::lexer::lex;
+    use crate::parser::{NodeId, Parser};
+
+    fn parse(src: &[u8]) -> Compiler {
+        let mut compiler = Compiler::new();
+        compiler.add_file("test.nu", src);
+
+        let (tokens, err) = lex(src, 0);
+        assert!(err.is_ok());
+
+        Parser::new(compiler, tokens).parse()
+    }
+
+    fn find(compiler: &Compiler, text: &[u8]) -> NodeId {
+        let idx = (0..compiler.ast_nodes.len())
+            .find(|idx| compiler.get_span_contents(NodeId(*idx)) == text)
+            .expect("node not found");
+        NodeId(idx)
+    }
+
+    #[test]
+    fn unchanged_subtrees_are_mapped() {
+        let old = parse(b"let x = 1 + 2\nlet y = 3 * 4");
+        let new = parse(b"let z = 10\nlet x = 1 + 2\nlet y = 3 * 5");
+        let map = NodeIdMap::new(&old, &new);
+
+        let old_let_x = find(&old, b"let x = 1 + 2");
+        let new_let_x = find(&new, b"let x = 1 + 2");
+        assert_ne!(old_let_x, new_let_x);
+        assert_eq!(map.get(old_let_x), Some(new_let_x));
+        assert_eq!(map.get_old(new_let_x), Some(old_let_x));
+        assert_eq!(map.get(find(&old, b"2")), Some(find(&new, b"2")));
+
+        // the changed binary operation keeps its unchanged operands, but not itself
+        assert_eq!(map.get(find(&old, b"3 * 4")), None);
+        assert_eq!(map.get(find(&old, b"3")), Some(find(&new, b"3")));
+        assert_eq!(map.get(find(&old, b"4")), None);
+    }
+
+    #[test]
+    fn colliding_hashes_are_not_mapped() {
+        let old = parse(b"let x = 1 + 2");
+        let new = parse(b"let x = 1 - 3");
+        let mut map = NodeIdMap::default();
+
+        // every subtree has the same hash
+        let mut matcher = Matcher {
+            old: SubtreeHashes::new(&old),
+            new: SubtreeHashes::new(&new),
+            map: &mut map,
+        };
+        matcher.old.hashes.fill(Some(0));
+        matcher.new.hashes.fill(Some(0));
+        matcher.match_nodes(
+            NodeId(old.ast_nodes.len() - 1),
+            NodeId(new.ast_nodes.len() - 1),
+        );
+
+        assert_eq!(map.get(find(&old, b"let x = 1 + 2")), None);
+        assert_eq!(map.get(find(&old, b"2")), None);
+        assert_eq!(map.get(find(&old, b"1")), Some(find(&new, b"1")));
+    }
+
+    #[test]
+    fn different_roots_are_not_mapped() {
+        let old = parse(b"1");
+        let new = parse(b"");
+        let map = NodeIdMap::new(&old, &new);
+
+        assert!(map.is_empty());
+    }
+}
@@ -0,0 +1,1282 @@
+//! Emission of the instructions of Nushell's IR
+//!
+//! The IR generator decides what to emit as `IrInstruction`s, an `IrEmitter` decides how the
+//! instructions look. The instructions and the engine's ids are owned by this crate, so the trait
+//! does not depend on a nu-protocol version. Only `NuProtocolEmitter`, for the nu-protocol version
+//! in Cargo.toml, is implemented, there are no features selecting another version. The changes of
+//! nu-protocol's instruction set are made in the emitter, not in the IR generator.
+use std::sync::Arc;
+
+use chrono::{DateTime, FixedOffset};
+use nu_protocol::ast::{
+    Bits, Boolean, CellPath, Comparison, Expr, Expression, MatchPattern, Math, Operator,
+    PathMember, Pattern, RangeInclusion,
+};
+use nu_protocol::engine::ENV_VARIABLE_ID;
+use nu_protocol::ir::{DataSlice, Instruction, IrBlock, Literal};
+use nu_protocol::{BlockId, DeclId, Filesize, RegId, Span, Type, VarId};
+
+use crate::resolver;
+
+/// Binary operators of the `BinaryOp` instruction
+///
+/// `and` and `or` are not here, they are generated as branches to skip their right-hand side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOperator {
+    Plus,
+    Minus,
+    Multiply,
+    Divide,
+    FloorDivide,
+    Modulo,
+    Pow,
+    Append,
+    Equal,
+    NotEqual,
+    LessThan,
+    GreaterThan,
+    LessThanOrEqual,
+    GreaterThanOrEqual,
+    RegexMatch,
+    NotRegexMatch,
+    In,
+    NotIn,
+    StartsWith,
+    EndsWith,
+    Xor,
+    BitOr,
+    BitXor,
+    BitAnd,
+    ShiftLeft,
+    ShiftRight,
+}
+
+/// Engine's id of a command
+///
+/// The ids are plain indexes, the emitter converts them to the id types of its nu-protocol
+/// version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EngineDeclId(pub usize);
+
+/// Engine's id of a variable, see `EngineDeclId`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EngineVarId(pub usize);
+
+impl From<nu_protocol::DeclId> for EngineDeclId {
+    fn from(decl_id: nu_protocol::DeclId) -> Self {
+        Self(decl_id.get())
+    }
+}
+
+impl From<nu_protocol::VarId> for EngineVarId {
+    fn from(var_id: nu_protocol::VarId) -> Self {
+        Self(var_id.get())
+    }
+}
+
+/// Variable loaded or stored by the instructions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrVariable {
+    /// Variable defined in the compiled source, by the resolver's id
+    Local(resolver::VarId),
+    /// Variable resolved from the engine, by the engine's id
+    Engine(EngineVarId),
+}
+
+/// Member of a cell path, e.g., `foo` or `0` of `$x.foo.0`
+#[derive(Debug, Clone, PartialEq)]
+pub enum IrPathMember {
+    String {
+        name: String,
+        /// Missing member is nothing instead of an error, e.g., `foo?`
+        optional: bool,
+        span: crate::compiler::Span,
+    },
+    Int {
+        index: usize,
+        optional: bool,
+        span: crate::compiler::Span,
+    },
+}
+
+/// Pattern of a `match` arm
+#[derive(Debug, Clone, PartialEq)]
+pub struct IrPattern {
+    pub kind: IrPatternKind,
+    /// Source of the pattern, e.g., for the errors of nested patterns
+    pub span: crate::compiler::Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IrPatternKind {
+    Int(i64),
+    Float(f64),
+    String(Vec<u8>),
+    Bool(bool),
+    Nothing,
+    /// `_`, matching any value
+    IgnoreValue,
+    /// Matches any value, binding it to the variable
+    Variable(IrVariable),
+    /// List with exactly the given items
+    List(Vec<IrPattern>),
+    /// Record with at least the given fields
+    Record(Vec<(String, IrPattern)>),
+    /// Matches if any of the patterns matches
+    Or(Vec<IrPattern>),
+}
+
+/// Instruction of the IR, in registers of the type `R`
+///
+/// The instructions leave the registers they take a value from empty, except for `Match`. The
+/// targets are instruction indexes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IrInstruction<'a, R> {
+    LoadInt {
+        dst: R,
+        value: i64,
+    },
+    LoadFloat {
+        dst: R,
+        value: f64,
+    },
+    LoadFilesize {
+        dst: R,
+        bytes: i64,
+    },
+    LoadDuration {
+        dst: R,
+        nanoseconds: i64,
+    },
+    LoadString {
+        dst: R,
+        value: &'a [u8],
+    },
+    /// Glob pattern that is expanded by the command it is passed to
+    LoadGlob {
+        dst: R,
+        value: &'a [u8],
+    },
+    LoadBinary {
+        dst: R,
+        value: &'a [u8],
+    },
+    LoadDatetime {
+        dst: R,
+        value: DateTime<FixedOffset>,
+    },
+    LoadBool {
+        dst: R,
+        value: bool,
+    },
+    LoadNothing {
+        dst: R,
+    },
+    /// Empty list, with space for `capacity` items
+    LoadList {
+        dst: R,
+        capacity: usize,
+    },
+    /// Empty record, with space for `capacity` fields
+    LoadRecord {
+        dst: R,
+        capacity: usize,
+    },
+    /// Range with the bounds in the registers, `next` is the second value of the range and the
+    /// bounds that are missing are nothing
+    LoadRange {
+        dst: R,
+        start: R,
+        next: R,
+        end: R,
+        exclusive: bool,
+    },
+    LoadCellPath {
+        dst: R,
+        members: &'a [IrPathMember],
+    },
+    /// Closure generated as the `closure_index`th closure of the compiled file
+    LoadClosure {
+        dst: R,
+        closure_index: usize,
+    },
+    LoadVariable {
+        dst: R,
+        var: IrVariable,
+    },
+    StoreVariable {
+        var: IrVariable,
+        src: R,
+    },
+    /// Environment variable, failing if it is not set
+    LoadEnv {
+        dst: R,
+        key: &'a [u8],
+    },
+    /// Environment variable, or nothing if it is not set
+    LoadEnvOpt {
+        dst: R,
+        key: &'a [u8],
+    },
+    /// Set the environment variable of the block being evaluated
+    StoreEnv {
+        key: &'a [u8],
+        src: R,
+    },
+    Move {
+        dst: R,
+        src: R,
+    },
+    /// Append the value in `item` to the list in `src_dst`
+    ListPush {
+        src_dst: R,
+        item: R,
+    },
+    /// Append all the items of the list in `items` to the list in `src_dst`
+    ListSpread {
+        src_dst: R,
+        items: R,
+    },
+    /// Append the value in `val` converted to a string to the string in `src_dst`
+    StringAppend {
+        src_dst: R,
+        val: R,
+    },
+    RecordInsert {
+        src_dst: R,
+        key: R,
+        val: R,
+    },
+    /// Insert all the fields of the record in `items` to the record in `src_dst`
+    RecordSpread {
+        src_dst: R,
+        items: R,
+    },
+    /// Replace the value in `src_dst` by its member at the cell path in `path`
+    FollowCellPath {
+        src_dst: R,
+        path: R,
+    },
+    /// Set the member at the cell path in `path` of the value in `src_dst`
+    UpsertCellPath {
+        src_dst: R,
+        path: R,
+        new_value: R,
+    },
+    /// Compute `lhs_dst <op> rhs`, storing the result in `lhs_dst`
+    BinaryOp {
+        lhs_dst: R,
+        op: BinaryOperator,
+        rhs: R,
+    },
+    /// Replace the boolean in `src_dst` by its negation
+    Not {
+        src_dst: R,
+    },
+    /// Add a positional argument to the next call
+    PushPositional {
+        src: R,
+    },
+    /// Add all the items of the list in `src` as rest arguments of the next call
+    AppendRest {
+        src: R,
+    },
+    /// Add a long flag (without the leading `--`) to the next call
+    PushFlag {
+        name: &'a [u8],
+    },
+    /// Add a short flag (without the leading `-`) to the next call
+    PushShortFlag {
+        short: &'a [u8],
+    },
+    /// Add a long flag with a value to the next call
+    PushNamed {
+        name: &'a [u8],
+        src: R,
+    },
+    PushShortNamed {
+        short: &'a [u8],
+        src: R,
+    },
+    /// Call the engine's command with the arguments pushed since the previous call
+    ///
+    /// The command's input is taken from `src_dst` and its output is stored there.
+    Call {
+        decl_id: EngineDeclId,
+        src_dst: R,
+    },
+    /// Run the value to completion, discarding it, e.g., for a statement whose value is not used
+    Drain {
+        src: R,
+    },
+    /// Discard the value without running it to completion
+    Drop {
+        src: R,
+    },
+    /// Continue at `target` if the value in `cond` is true
+    BranchIf {
+        cond: R,
+        target: usize,
+    },
+    Jump {
+        target: usize,
+    },
+    /// Put the next value of `stream` to `dst`, or continue at `end_target` if there are no more
+    /// values
+    Iterate {
+        dst: R,
+        stream: R,
+        end_target: usize,
+    },
+    /// Continue at `target` if the value in `src` matches the pattern, storing the values bound by
+    /// the pattern in its variables
+    ///
+    /// The value stays in `src`, to be matched against the next pattern.
+    Match {
+        pattern: &'a IrPattern,
+        src: R,
+        target: usize,
+    },
+    /// Fail unless the value of a match guard is a boolean
+    CheckMatchGuard {
+        src: R,
+    },
+    /// Continue at `target` if any of the following instructions fails, until the handler is
+    /// popped
+    OnError {
+        target: usize,
+    },
+    /// Same as `OnError`, storing the error in `dst` before continuing at `target`
+    OnErrorInto {
+        target: usize,
+        dst: R,
+    },
+    /// Remove the error handler added last
+    PopErrorHandler,
+    Return {
+        src: R,
+    },
+}
+
+pub trait IrEmitter {
+    /// Register holding an intermediate value
+    type Reg: Copy + PartialEq;
+    /// The block of instructions once all of them were emitted
+    type Block;
+
+    /// New emitter for the block of a closure, emitting the instructions the same way
+    fn nested(&self) -> Self;
+
+    /// Returns an unused register, reusing the freed ones first
+    fn next_register(&mut self) -> Self::Reg;
+    /// The register was emptied by the last instruction using it, so it can be returned by
+    /// `next_register()` again
+    fn free_register(&mut self, reg: Self::Reg);
+    /// Number of instructions emitted so far
+    fn instruction_count(&self) -> usize;
+    /// Set the span of the source code the instruction was generated from, e.g., for the errors
+    /// reported when evaluating it
+    fn set_span(&mut self, instruction_index: usize, span: crate::compiler::Span);
+
+    /// Append the instruction in the emitter's instruction set
+    fn emit(&mut self, instruction: IrInstruction<Self::Reg>);
+    /// Change the target of the branch, jump, iteration, match or error handler at
+    /// `instruction_index`, e.g., of a forward jump emitted before the index of its target was
+    /// known
+    fn set_jump_target(&mut self, instruction_index: usize, target: usize);
+
+    /// Human readable listing of the block, one instruction per line
+    fn display(&self) -> String;
+    /// Fields of the block for machine-readable output, including the "instructions"
+    fn to_json(&self) -> serde_json::Map<String, serde_json::Value>;
+    fn finish(self) -> Self::Block;
+}
+
+/// Emitter of the IR of the nu-protocol version this crate depends on
+pub struct NuProtocolEmitter {
+    block: IrBlock,
+    /// Contents of the block's data, e.g., of the string literals, referred to by `DataSlice`s
+    data: Vec<u8>,
+    free_registers: Vec<RegId>,
+    /// Engine's BlockId of the first closure, the following closures are added after it
+    first_block_id: usize,
+}
+
+impl Default for NuProtocolEmitter {
+    fn default() -> Self {
+        Self {
+            block: IrBlock {
+                instructions: Default::default(),
+                spans: Default::default(),
+                data: Arc::new([]),
+                ast: Default::default(),
+                comments: Default::default(),
+                register_count: 0,
+                file_count: 0,
+            },
+            data: vec![],
+            free_registers: vec![],
+            first_block_id: 0,
+        }
+    }
+}
+
+impl NuProtocolEmitter {
+    /// The closures are going to be added to the engine in order, starting with `first_block_id`
+    pub fn with_first_block_id(first_block_id: BlockId) -> Self {
+        Self {
+            first_block_id: first_block_id.get(),
+            ..Self::default()
+        }
+    }
+
+    fn push(&mut self, instruction: Instruction) {
+        self.block.spans.push(Span::unknown());
+        self.block.ast.push(None);
+        self.block.instructions.push(instruction);
+    }
+
+    /// Engine's VarId of the variable
+    ///
+    /// The local variables are numbered after the variables reserved by nushell, e.g., `$env`.
+    pub fn var_id(var: IrVariable) -> VarId {
+        match var {
+            IrVariable::Local(var_id) => VarId::new(ENV_VARIABLE_ID.get() + 1 + var_id.0),
+            IrVariable::Engine(var_id) => VarId::new(var_id.0),
+        }
+    }
+
+    /// nu-protocol's operator of the `BinaryOp` instruction
+    fn operator(op: BinaryOperator) -> Operator {
+        match op {
+            BinaryOperator::Plus => Operator::Math(Math::Plus),
+            BinaryOperator::Minus => Operator::Math(Math::Minus),
+            BinaryOperator::Multiply => Operator::Math(Math::Multiply),
+            BinaryOperator::Divide => Operator::Math(Math::Divide),
+            BinaryOperator::FloorDivide => Operator::Math(Math::FloorDivision),
+            BinaryOperator::Modulo => Operator::Math(Math::Modulo),
+            BinaryOperator::Pow => Operator::Math(Math::Pow),
+            BinaryOperator::Append => Operator::Math(Math::Concat),
+            BinaryOperator::Equal => Operator::Comparison(Comparison::Equal),
+            BinaryOperator::NotEqual => Operator::Comparison(Comparison::NotEqual),
+            BinaryOperator::LessThan => Operator::Comparison(Comparison::LessThan),
+            BinaryOperator::GreaterThan => Operator::Comparison(Comparison::GreaterThan),
+            BinaryOperator::LessThanOrEqual => Operator::Comparison(Comparison::LessThanOrEqual),
+            BinaryOperator::GreaterThanOrEqual => {
+                Operator::Comparison(Comparison::GreaterThanOrEqual)
+            }
+            BinaryOperator::RegexMatch => Operator::Comparison(Comparison::RegexMatch),
+            BinaryOperator::NotRegexMatch => Operator::Comparison(Comparison::NotRegexMatch),
+            BinaryOperator::In => Operator::Comparison(Comparison::In),
+            BinaryOperator::NotIn => Operator::Comparison(Comparison::NotIn),
+            BinaryOperator::StartsWith => Operator::Comparison(Comparison::StartsWith),
+            BinaryOperator::EndsWith => Operator::Comparison(Comparison::EndsWith),
+            BinaryOperator::Xor => Operator::Boolean(Boolean::Xor),
+            BinaryOperator::BitOr => Operator::Bits(Bits::BitOr),
+            BinaryOperator::BitXor => Operator::Bits(Bits::BitXor),
+            BinaryOperator::BitAnd => Operator::Bits(Bits::BitAnd),
+            BinaryOperator::ShiftLeft => Operator::Bits(Bits::ShiftLeft),
+            BinaryOperator::ShiftRight => Operator::Bits(Bits::ShiftRight),
+        }
+    }
+
+    /// nu-protocol's cell path, keeping the spans of the members
+    fn cell_path(members: &[IrPathMember]) -> CellPath {
+        let span = |span: crate::compiler::Span| Span::new(span.start, span.end);
+        let members = members
+            .iter()
+            .map(|member| match member {
+                IrPathMember::String {
+                    name,
+                    optional,
+                    span: member_span,
+                } => PathMember::string(name.clone(), *optional, span(*member_span)),
+                IrPathMember::Int {
+                    index,
+                    optional,
+                    span: member_span,
+                } => PathMember::int(*index, *optional, span(*member_span)),
+            })
+            .collect();
+        CellPath { members }
+    }
+
+    /// nu-protocol's pattern, the literals are matched by their expressions
+    fn pattern(pattern: &IrPattern) -> Pattern {
+        let span = Span::new(pattern.span.start, pattern.span.end);
+        let nested = |pattern: &IrPattern| MatchPattern {
+            pattern: Self::pattern(pattern),
+            guard: None,
+            span: Span::new(pattern.span.start, pattern.span.end),
+        };
+        let literal = |expr, ty| Pattern::Value(Box::new(Expression::new_unknown(expr, span, ty)));
+        match &pattern.kind {
+            IrPatternKind::Int(val) => literal(Expr::Int(*val), Type::Int),
+            IrPatternKind::Float(val) => literal(Expr::Float(*val), Type::Float),
+            IrPatternKind::String(val) => literal(
+                Expr::String(String::from_utf8_lossy(val).into_owned()),
+                Type::String,
+            ),
+            IrPatternKind::Bool(val) => literal(Expr::Bool(*val), Type::Bool),
+            IrPatternKind::Nothing => literal(Expr::Nothing, Type::Nothing),
+            IrPatternKind::IgnoreValue => Pattern::IgnoreValue,
+            IrPatternKind::Variable(var) => Pattern::Variable(Self::var_id(*var)),
+            IrPatternKind::List(items) => Pattern::List(items.iter().map(nested).collect()),
+            IrPatternKind::Record(fields) => Pattern::Record(
+                fields
+                    .iter()
+                    .map(|(name, field)| (name.clone(), nested(field)))
+                    .collect(),
+            ),
+            IrPatternKind::Or(alternatives) => {
+                Pattern::Or(alternatives.iter().map(nested).collect())
+            }
+        }
+    }
+
+    /// Append the bytes to the block's data
+    fn add_data(&mut self, bytes: &[u8]) -> DataSlice {
+        let slice = DataSlice {
+            start: self.data.len() as u32,
+            len: bytes.len() as u32,
+        };
+        self.data.extend_from_slice(bytes);
+        slice
+    }
+}
+
+impl IrEmitter for NuProtocolEmitter {
+    type Reg = RegId;
+    type Block = IrBlock;
+
+    fn nested(&self) -> Self {
+        Self {
+            first_block_id: self.first_block_id,
+            ..Self::default()
+        }
+    }
+
+    fn next_register(&mut self) -> RegId {
+        if let Some(r) = self.free_registers.pop() {
+            return r;
+        }
+
+        let r = RegId::new(self.block.register_count);
+        self.block.register_count += 1;
+        r
+    }
+
+    fn free_register(&mut self, reg: RegId) {
+        self.free_registers.push(reg);
+    }
+
+    fn instruction_count(&self) -> usize {
+        self.block.instructions.len()
+    }
+
+    fn set_span(&mut self, instruction_index: usize, span: crate::compiler::Span) {
+        self.block.spans[instruction_index] = Span::new(span.start, span.end);
+    }
+
+    fn emit(&mut self, instruction: IrInstruction<RegId>) {
+        let instruction = match instruction {
+            IrInstruction::LoadInt { dst, value } => Instruction::LoadLiteral {
+                dst,
+                lit: Literal::Int(value),
+            },
+            IrInstruction::LoadFloat { dst, value } => Instruction::LoadLiteral {
+                dst,
+                lit: Literal::Float(value),
+            },
+            IrInstruction::LoadFilesize { dst, bytes } => Instruction::LoadLiteral {
+                dst,
+                lit: Literal::Filesize(Filesize::new(bytes)),
+            },
+            IrInstruction::LoadDuration { dst, nanoseconds } => Instruction::LoadLiteral {
+                dst,
+                lit: Literal::Duration(nanoseconds),
+            },
+            IrInstruction::LoadString { dst, value } => {
+                let slice = self.add_data(value);
+                Instruction::LoadLiteral {
+                    dst,
+                    lit: Literal::String(slice),
+                }
+            }
+            IrInstruction::LoadGlob { dst, value } => {
+                let val = self.add_data(value);
+                Instruction::LoadLiteral {
+                    dst,
+                    lit: Literal::GlobPattern {
+                        val,
+                        no_expand: false,
+                    },
+                }
+            }
+            IrInstruction::LoadBinary { dst, value } => {
+                let slice = self.add_data(value);
+                Instruction::LoadLiteral {
+                    dst,
+                    lit: Literal::Binary(slice),
+                }
+            }
+            IrInstruction::LoadDatetime { dst, value } => Instruction::LoadLiteral {
+                dst,
+                lit: Literal::Date(Box::new(value)),
+            },
+            IrInstruction::LoadBool { dst, value } => Instruction::LoadLiteral {
+                dst,
+                lit: Literal::Bool(value),
+            },
+            IrInstruction::LoadNothing { dst } => Instruction::LoadLiteral {
+                dst,
+                lit: Literal::Nothing,
+            },
+            IrInstruction::LoadList { dst, capacity } => Instruction::LoadLiteral {
+                dst,
+                lit: Literal::List { capacity },
+            },
+            IrInstruction::LoadRecord { dst, capacity } => Instruction::LoadLiteral {
+                dst,
+                lit: Literal::Record { capacity },
+            },
+            IrInstruction::LoadRange {
+                dst,
+                start,
+                next,
+                end,
+                exclusive,
+            } => {
+                let inclusion = if exclusive {
+                    RangeInclusion::RightExclusive
+                } else {
+                    RangeInclusion::Inclusive
+                };
+                Instruction::LoadLiteral {
+                    dst,
+                    lit: Literal::Range {
+                        start,
+                        step: next,
+                        end,
+                        inclusion,
+                    },
+                }
+            }
+            IrInstruction::LoadCellPath { dst, members } => Instruction::LoadLiteral {
+                dst,
+                lit: Literal::CellPath(Box::new(Self::cell_path(members))),
+            },
+            IrInstruction::LoadClosure { dst, closure_index } => {
+                let block_id = BlockId::new(self.first_block_id + closure_index);
+                Instruction::LoadLiteral {
+                    dst,
+                    lit: Literal::Closure(block_id),
+                }
+            }
+            IrInstruction::LoadVariable { dst, var } => {
+                let var_id = Self::var_id(var);
+                Instruction::LoadVariable { dst, var_id }
+            }
+            IrInstruction::StoreVariable { var, src } => {
+                let var_id = Self::var_id(var);
+                Instruction::StoreVariable { var_id, src }
+            }
+            IrInstruction::LoadEnv { dst, key } => {
+                let key = self.add_data(key);
+                Instruction::LoadEnv { dst, key }
+            }
+            IrInstruction::LoadEnvOpt { dst, key } => {
+                let key = self.add_data(key);
+                Instruction::LoadEnvOpt { dst, key }
+            }
+            IrInstruction::StoreEnv { key, src } => {
+                let key = self.add_data(key);
+                Instruction::StoreEnv { key, src }
+            }
+            IrInstruction::Move { dst, src } => Instruction::Move { dst, src },
+            IrInstruction::ListPush { src_dst, item } => Instruction::ListPush { src_dst, item },
+            IrInstruction::ListSpread { src_dst, items } => {
+                Instruction::ListSpread { src_dst, items }
+            }
+            IrInstruction::StringAppend { src_dst, val } => {
+                Instruction::StringAppend { src_dst, val }
+            }
+            IrInstruction::RecordInsert { src_dst, key, val } => {
+                Instruction::RecordInsert { src_dst, key, val }
+            }
+            IrInstruction::RecordSpread { src_dst, items } => {
+                Instruction::RecordSpread { src_dst, items }
+            }
+            IrInstruction::FollowCellPath { src_dst, path } => {
+                Instruction::FollowCellPath { src_dst, path }
+            }
+            IrInstruction::UpsertCellPath {
+                src_dst,
+                path,
+                new_value,
+            } => Instruction::UpsertCellPath {
+                src_dst,
+                path,
+                new_value,
+            },
+            IrInstruction::BinaryOp { lhs_dst, op, rhs } => Instruction::BinaryOp {
+                lhs_dst,
+                op: Self::operator(op),
+                rhs,
+            },
+            IrInstruction::Not { src_dst } => Instruction::Not { src_dst },
+            IrInstruction::PushPositional { src } => Instruction::PushPositional { src },
+            IrInstruction::AppendRest { src } => Instruction::AppendRest { src },
+            IrInstruction::PushFlag { name } => {
+                let name = self.add_data(name);
+                Instruction::PushFlag { name }
+            }
+            IrInstruction::PushShortFlag { short } => {
+                let short = self.add_data(short);
+                Instruction::PushShortFlag { short }
+            }
+            IrInstruction::PushNamed { name, src } => {
+                let name = self.add_data(name);
+                Instruction::PushNamed { name, src }
+            }
+            IrInstruction::PushShortNamed { short, src } => {
+                let short = self.add_data(short);
+                Instruction::PushShortNamed { short, src }
+            }
+            IrInstruction::Call { decl_id, src_dst } => Instruction::Call {
+                decl_id: DeclId::new(decl_id.0),
+                src_dst,
+            },
+            IrInstruction::Drain { src } => Instruction::Drain { src },
+            IrInstruction::BranchIf { cond, target } => Instruction::BranchIf {
+                cond,
+                index: target,
+            },
+            IrInstruction::Jump { target } => Instruction::Jump { index: target },
+            IrInstruction::Iterate {
+                dst,
+                stream,
+                end_target,
+            } => Instruction::Iterate {
+                dst,
+                stream,
+                end_index: end_target,
+            },
+            IrInstruction::Match {
+                pattern,
+                src,
+                target,
+            } => Instruction::Match {
+                pattern: Box::new(Self::pattern(pattern)),
+                src,
+                index: target,
+            },
+            IrInstruction::CheckMatchGuard { src } => Instruction::CheckMatchGuard { src },
+            IrInstruction::OnError { target } => Instruction::OnError { index: target },
+            IrInstruction::OnErrorInto { target, dst } => {
+                Instruction::OnErrorInto { index: target, dst }
+            }
+            IrInstruction::PopErrorHandler => Instruction::PopErrorHandler,
+            IrInstruction::Drop { src } => Instruction::Drop { src },
+            IrInstruction::Return { src } => Instruction::Return { src },
+        };
+        self.push(instruction);
+    }
+
+    fn set_jump_target(&mut self, instruction_index: usize, target: usize) {
+        match &mut self.block.instructions[instruction_index] {
+            Instruction::BranchIf { index, .. }
+            | Instruction::Jump { index }
+            | Instruction::Iterate {
+                end_index: index, ..
+            }
+            | Instruction::Match { index, .. }
+            | Instruction::OnError { index }
+            | Instruction::OnErrorInto { index, .. } => *index = target,
+            instruction => panic!("{instruction:?} is not a jump"),
+        }
+    }
+
+    fn display(&self) -> String {
+        let mut result = String::new();
+        result.push_str(&format!("register_count: {}\n", self.block.register_count));
+        result.push_str(&format!("file_count: {}\n", self.block.file_count));
+        if !self.data.is_empty() {
+            result.push_str(&format!(
+                "data: {:?}\n",
+                String::from_utf8_lossy(&self.data)
+            ));
+        }
+
+        for (idx, instruction) in self.block.instructions.iter().enumerate() {
+            result.push_str(&format!("{}: {:?}\n", idx, instruction));
+        }
+        result
+    }
+
+    fn to_json(&self) -> serde_json::Map<String, serde_json::Value> {
+        let instructions: Vec<_> = self
+            .block
+            .instructions
+            .iter()
+            .map(|instruction| {
+                serde_json::to_value(instruction).expect("IR instruction is always serializable")
+            })
+            .collect();
+
+        let mut fields = serde_json::Map::new();
+        fields.insert("register_count".into(), self.block.register_count.into());
+        fields.insert("file_count".into(), self.block.file_count.into());
+        fields.insert(
+            "data".into(),
+            String::from_utf8_lossy(&self.data).into_owned().into(),
+        );
+        fields.insert("instructions".into(), instructions.into());
+        fields
+    }
+
+    fn finish(mut self) -> IrBlock {
+        self.block.data = self.data.into();
+        #[cfg(debug_assertions)]
+        crate::ir_validator::assert_valid(&self.block);
+        self.block
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{IrEmitter, IrInstruction};
+    use crate::compiler::Compiler;
+    use crate::engine::EngineLookup;
+    use crate::ir_generator::IrGenerator;
+    use crate::resolver::Resolver;
+
+    /// Emitter of a made up instruction set, standing in for another nu-protocol version
+    #[derive(Default)]
+    struct TextEmitter {
+        registers: usize,
+        free_registers: Vec<usize>,
+        lines: Vec<String>,
+    }
+
+    impl IrEmitter for TextEmitter {
+        type Reg = usize;
+        type Block = Vec<String>;
+
+        fn nested(&self) -> Self {
+            Self::default()
+        }
+
+        fn next_register(&mut self) -> usize {
+            self.free_registers.pop().unwrap_or_else(|| {
+                self.registers += 1;
+                self.registers - 1
+            })
+        }
+
+        fn free_register(&mut self, reg: usize) {
+            self.free_registers.push(reg);
+        }
+
+        fn instruction_count(&self) -> usize {
+            self.lines.len()
+        }
+
+        fn set_span(&mut self, _instruction_index: usize, _span: crate::compiler::Span) {}
+
+        fn emit(&mut self, instruction: IrInstruction<usize>) {
+            let line = match instruction {
+                IrInstruction::LoadInt { dst, value } => format!("r{dst} = {value}"),
+                IrInstruction::LoadFloat { dst, value } => format!("r{dst} = {value:?}"),
+                IrInstruction::LoadFilesize { dst, bytes } => format!("r{dst} = {bytes}b"),
+                IrInstruction::LoadDuration { dst, nanoseconds } => {
+                    format!("r{dst} = {nanoseconds}ns")
+                }
+                IrInstruction::LoadString { dst, value } => {
+                    let value = String::from_utf8_lossy(value);
+                    format!("r{dst} = {value:?}")
+                }
+                IrInstruction::LoadGlob { dst, value } => {
+                    let value = String::from_utf8_lossy(value);
+                    format!("r{dst} = glob {value:?}")
+                }
+                IrInstruction::LoadBinary { dst, value } => format!("r{dst} = 0x{value:02x?}"),
+                IrInstruction::LoadDatetime { dst, value } => {
+                    format!("r{dst} = {}", value.to_rfc3339())
+                }
+                IrInstruction::LoadBool { dst, value } => format!("r{dst} = {value}"),
+                IrInstruction::LoadNothing { dst } => format!("r{dst} = nothing"),
+                IrInstruction::LoadList { dst, .. } => format!("r{dst} = []"),
+                IrInstruction::LoadRecord { dst, .. } => format!("r{dst} = {{}}"),
+                IrInstruction::LoadRange {
+                    dst,
+                    start,
+                    next,
+                    end,
+                    exclusive,
+                } => {
+                    let op = if exclusive { "..<" } else { ".." };
+                    format!("r{dst} = r{start}..r{next}{op}r{end}")
+                }
+                IrInstruction::LoadCellPath { dst, members } => format!("r{dst} = {members:?}"),
+                IrInstruction::LoadClosure { dst, closure_index } => {
+                    format!("r{dst} = closure {closure_index}")
+                }
+                IrInstruction::LoadVariable { dst, var } => format!("r{dst} = {var:?}"),
+                IrInstruction::StoreVariable { var, src } => format!("{var:?} = r{src}"),
+                IrInstruction::LoadEnv { dst, key } => {
+                    let key = String::from_utf8_lossy(key);
+                    format!("r{dst} = env {key}")
+                }
+                IrInstruction::LoadEnvOpt { dst, key } => {
+                    let key = String::from_utf8_lossy(key);
+                    format!("r{dst} = env {key}?")
+                }
+                IrInstruction::StoreEnv { key, src } => {
+                    let key = String::from_utf8_lossy(key);
+                    format!("env {key} = r{src}")
+                }
+                IrInstruction::Move { dst, src } => format!("r{dst} = move r{src}"),
+                IrInstruction::ListPush { src_dst, item } => format!("r{src_dst} push r{item}"),
+                IrInstruction::ListSpread { src_dst, items } => {
+                    format!("r{src_dst} push ...r{items}")
+                }
+                IrInstruction::StringAppend { src_dst, val } => format!("r{src_dst} append r{val}"),
+                IrInstruction::RecordInsert { src_dst, key, val } => {
+                    format!("r{src_dst} insert r{key} r{val}")
+                }
+                IrInstruction::RecordSpread { src_dst, items } => {
+                    format!("r{src_dst} spread r{items}")
+                }
+                IrInstruction::FollowCellPath { src_dst, path } => {
+                    format!("r{src_dst} follow r{path}")
+                }
+                IrInstruction::UpsertCellPath {
+                    src_dst,
+                    path,
+                    new_value,
+                } => format!("r{src_dst} upsert r{path} r{new_value}"),
+                IrInstruction::BinaryOp { lhs_dst, op, rhs } => {
+                    format!("r{lhs_dst} {op:?}= r{rhs}")
+                }
+                IrInstruction::Not { src_dst } => format!("r{src_dst} = not r{src_dst}"),
+                IrInstruction::PushPositional { src } => format!("push r{src}"),
+                IrInstruction::AppendRest { src } => format!("push ...r{src}"),
+                IrInstruction::PushFlag { name } => {
+                    let name = String::from_utf8_lossy(name);
+                    format!("push --{name}")
+                }
+                IrInstruction::PushShortFlag { short } => {
+                    let short = String::from_utf8_lossy(short);
+                    format!("push -{short}")
+                }
+                IrInstruction::PushNamed { name, src } => {
+                    let name = String::from_utf8_lossy(name);
+                    format!("push --{name} r{src}")
+                }
+                IrInstruction::PushShortNamed { short, src } => {
+                    let short = String::from_utf8_lossy(short);
+                    format!("push -{short} r{src}")
+                }
+                IrInstruction::Call { decl_id, src_dst } => {
+                    format!("r{src_dst} = call {} r{src_dst}", decl_id.0)
+                }
+                IrInstruction::Drain { src } => format!("drain r{src}"),
+                IrInstruction::BranchIf { cond, target } => format!("if r{cond} goto {target}"),
+                IrInstruction::Jump { target } => format!("goto {target}"),
+                IrInstruction::Match {
+                    pattern,
+                    src,
+                    target,
+                } => format!("if r{src} matches {pattern:?} goto {target}"),
+                IrInstruction::CheckMatchGuard { src } => format!("check match guard r{src}"),
+                IrInstruction::OnError { target } => format!("on error goto {target}"),
+                IrInstruction::OnErrorInto { target, dst } => {
+                    format!("on error r{dst} = error, goto {target}")
+                }
+                IrInstruction::PopErrorHandler => "pop error handler".into(),
+                IrInstruction::Drop { src } => format!("drop r{src}"),
+                IrInstruction::Iterate {
+                    dst,
+                    stream,
+                    end_target,
+                } => format!("r{dst} = next r{stream} else goto {end_target}"),
+                IrInstruction::Return { src } => format!("return r{src}"),
+            };
+            self.lines.push(line);
+        }
+
+        fn set_jump_target(&mut self, instruction_index: usize, target: usize) {
+            let line = &mut self.lines[instruction_index];
+            let start = line.rfind(' ').expect("jumps end with the target") + 1;
+            line.replace_range(start.., &target.to_string());
+        }
+
+        fn display(&self) -> String {
+            self.lines.join("\n")
+        }
+
+        fn to_json(&self) -> serde_json::Map<String, serde_json::Value> {
+            Default::default()
+        }
+
+        fn finish(self) -> Vec<String> {
+            self.lines
+        }
+    }
+
+    #[test]
+    fn generate_with_another_emitter() {
+        let mut compiler = Compiler::new();
+        assert!(compiler.check("test.nu", b"1 + 2 * 3\n4"));
+
+        let mut ir_generator = IrGenerator::with_emitter(&compiler, TextEmitter::default());
+        ir_generator.generate();
+        assert!(ir_generator.errors().is_empty());
+        assert_eq!(ir_generator.source_map().len(), 8);
+
+        assert_eq!(
+            ir_generator.block(),
+            [
+                "r0 = 1",
+                "r1 = 2",
+                "r2 = 3",
+                "r1 Multiply= r2",
+                "r0 Plus= r1",
+                "drain r0",
+                "r0 = 4",
+                "return r0"
+            ]
+        );
+    }
+
+    #[test]
+    fn forward_jumps_are_patched() {
+        let mut compiler = Compiler::new();
+        assert!(compiler.check("test.nu", b"if true { 1 } else { 2 }"));
+
+        let mut ir_generator = IrGenerator::with_emitter(&compiler, TextEmitter::default());
+        ir_generator.generate();
+        assert!(ir_generator.errors().is_empty());
+
+        assert_eq!(
+            ir_generator.block(),
+            [
+                "r0 = true",
+                "if r0 goto 4",
+                "r0 = 2",
+                "goto 6",
+                "r1 = 1",
+                "r0 = move r1",
+                "return r0"
+            ]
+        );
+    }
+
+    /// Engine with the `print` command only
+    struct PrintEngine;
+
+    impl EngineLookup for PrintEngine {
+        fn find_decl(&self, name: &[u8]) -> Option<nu_protocol::DeclId> {
+            match name {
+                b"print" => Some(nu_protocol::DeclId::new(7)),
+                b"run-external" => Some(nu_protocol::DeclId::new(8)),
+                _ => None,
+            }
+        }
+
+        fn find_variable(&self, _name: &[u8]) -> Option<nu_protocol::VarId> {
+            None
+        }
+
+        fn find_module(&self, _name: &[u8]) -> Option<nu_protocol::ModuleId> {
+            None
+        }
+    }
+
+    /// Lines of the block and the error messages of generating the source resolved in `PrintEngine`
+    fn generate_with_engine(src: &[u8]) -> (Vec<String>, Vec<String>) {
+        let mut compiler = Compiler::new();
+        assert!(compiler.check("test.nu", src));
+        let mut resolver = Resolver::new(&compiler).with_engine(&PrintEngine);
+        resolver.resolve();
+        compiler.merge_name_bindings(resolver.to_name_bindings());
+
+        let mut ir_generator = IrGenerator::with_emitter(&compiler, TextEmitter::default());
+        ir_generator.generate();
+        let errors = ir_generator
+            .errors()
+            .iter()
+            .map(|error| error.message(&compiler).into_owned())
+            .collect();
+        (ir_generator.block(), errors)
+    }
+
+    #[test]
+    fn calls_of_engine_commands() {
+        let (lines, errors) = generate_with_engine(b"print 1 abc (2 + 3)");
+        assert!(errors.is_empty());
+        assert_eq!(
+            lines,
+            [
+                "r0 = 1",
+                "push r0",
+                "r0 = \"abc\"",
+                "push r0",
+                "r0 = 2",
+                "r1 = 3",
+                "r0 Plus= r1",
+                "push r0",
+                "r0 = call 7 r0",
+                "return r0"
+            ]
+        );
+
+        // the spread items are the rest arguments
+        let (lines, errors) = generate_with_engine(b"print 1 ...[2]");
+        assert!(errors.is_empty());
+        assert_eq!(
+            lines,
+            [
+                "r0 = 1",
+                "push r0",
+                "r0 = []",
+                "r1 = 2",
+                "r0 push r1",
+                "push ...r0",
+                "r0 = call 7 r0",
+                "return r0"
+            ]
+        );
+
+        // the value of `--flag=value` is named
+        let (lines, errors) = generate_with_engine(b"print --indent=2 -n=true");
+        assert!(errors.is_empty());
+        assert_eq!(
+            lines,
+            [
+                "r0 = 2",
+                "push --indent r0",
+                "r0 = true",
+                "push -n r0",
+                "r0 = call 7 r0",
+                "return r0"
+            ]
+        );
+
+        // bare words with wildcards are globs without a typechecked parameter
+        let (lines, errors) = generate_with_engine(b"print *.rs ../src");
+        assert!(errors.is_empty());
+        assert_eq!(
+            lines,
+            [
+                "r0 = glob \"*.rs\"",
+                "push r0",
+                "r0 = \"../src\"",
+                "push r0",
+                "r0 = call 7 r0",
+                "return r0"
+            ]
+        );
+    }
+
+    #[test]
+    fn row_conditions_are_closures() {
+        let mut compiler = Compiler::new();
+        assert!(compiler.check("test.nu", b"where size > 1"));
+        let mut resolver = Resolver::new(&compiler).with_engine(&PrintEngine);
+        resolver.resolve();
+        compiler.merge_name_bindings(resolver.to_name_bindings());
+
+        let mut ir_generator = IrGenerator::with_emitter(&compiler, TextEmitter::default());
+        ir_generator.generate();
+        assert!(ir_generator.errors().is_empty());
+        let (block, closures) = ir_generator.blocks();
+        assert_eq!(
+            block,
+            [
+                "r0 = \"where\"",
+                "push r0",
+                "r0 = closure 0",
+                "push r0",
+                "r0 = call 8 r0",
+                "return r0"
+            ]
+        );
+        assert_eq!(closures.len(), 1);
+        assert_eq!(closures[0].params.len(), 1);
+        // `$it` is the closure's only parameter
+        assert_eq!(closures[0].block[0], "r0 = Local(VarId(0))");
+        assert_eq!(closures[0].block[2], "r0 follow r1");
+    }
+
+    #[test]
+    fn calls_of_external_commands() {
+        let expected = [
+            "r0 = \"foo\"",
+            "push r0",
+            "r0 = 1",
+            "push r0",
+            "r0 = \"bar\"",
+            "push r0",
+            "r0 = call 8 r0",
+            "return r0",
+        ];
+        let (lines, errors) = generate_with_engine(b"foo 1 bar");
+        assert!(errors.is_empty());
+        assert_eq!(lines, expected);
+
+        // forced external call of a command defined in the engine
+        let (lines, errors) = generate_with_engine(b"^print 1 bar");
+        assert!(errors.is_empty());
+        assert_eq!(lines[0], "r0 = \"print\"");
+        assert_eq!(lines[6], "r0 = call 8 r0");
+    }
+
+    #[test]
+    fn pipelines() {
+        // the output of each element is the input of the next call
+        let (lines, errors) = generate_with_engine(b"[1, 2] | print 3 | foo\n4");
+        assert!(errors.is_empty());
+        assert_eq!(
+            lines,
+            [
+                "r0 = []",
+                "r1 = 1",
+                "r0 push r1",
+                "r1 = 2",
+                "r0 push r1",
+                "r1 = 3",
+                "push r1",
+                "r0 = call 7 r0",
+                "r1 = \"foo\"",
+                "push r1",
+                "r0 = call 8 r0",
+                "drain r0",
+                "r0 = 4",
+                "return r0"
+            ]
+        );
+
+        let (_, errors) = generate_with_engine(b"1 | 2");
+        assert_eq!(
+            errors,
+            ["only commands can take the input of a pipeline yet"]
+        );
+
+        let (_, errors) = generate_with_engine(b"print 1 e>| print 2");
+        assert_eq!(errors, ["redirections are not supported yet"]);
+    }
+
+    #[test]
+    fn registers_are_reused() {
+        let src = vec!["1"; 100].join(" + ");
+        let mut compiler = Compiler::new();
+        assert!(compiler.check("test.nu", src.as_bytes()));
+
+        let mut ir_generator = IrGenerator::new(&compiler);
+        ir_generator.generate();
+        assert!(ir_generator.errors().is_empty());
+        assert_eq!(ir_generator.block().register_count, 2);
+    }
+}
@@ -0,0 +1,1957 @@
+use chrono::{DateTime, FixedOffset};
+
+use crate::compiler::Compiler;
+use crate::errors::{ErrorCode, Message, RelatedSpan, Severity, SourceError};
+use crate::ir_emitter::{
+    BinaryOperator, IrEmitter, IrInstruction, IrPathMember, IrPattern, IrPatternKind, IrVariable,
+    NuProtocolEmitter,
+};
+use crate::ir_optimizer::{fold_constant, Constant};
+use crate::parser::{
+    binary_literal_value, datetime_literal_value, float_literal_value, int_literal_value,
+    interpolation_chunk_value, raw_string_value, string_literal_value, unit_literal_value, unquote,
+    AstNode, CellPathMember, NodeId, RecordEntry, DURATION_UNITS, FILESIZE_UNITS,
+};
+use crate::typechecker::Type;
+use nu_protocol::ENV_VARIABLE_ID;
+
+/// Target of a forward jump until it is patched by `patch_jump()`
+const UNKNOWN_TARGET: usize = usize::MAX;
+
+/// Jump targets of the innermost loop being generated
+struct LoopContext {
+    continue_target: usize,
+    /// Forward jumps of the `break`s, patched to the end of the loop
+    breaks: Vec<usize>,
+}
+
+/// Variables of the closure being generated
+struct ClosureScope {
+    /// Parameters and the variables defined in the closure's block
+    defined: Vec<IrVariable>,
+    /// Variables of the enclosing blocks used in the closure
+    captures: Vec<IrVariable>,
+}
+
+/// Block of a closure, generated separately from the block the closure is defined in
+pub struct IrClosure<B> {
+    pub block: B,
+    pub source_map: IrSourceMap,
+    /// Parameters of the closure, in order
+    pub params: Vec<IrVariable>,
+    /// Variables of the enclosing blocks used in the closure, their values are captured when the
+    /// closure is loaded
+    pub captures: Vec<IrVariable>,
+}
+
+/// Closure whose block is still open for display
+struct GeneratedClosure<E> {
+    emitter: E,
+    source_map: IrSourceMap,
+    params: Vec<IrVariable>,
+    captures: Vec<IrVariable>,
+}
+
+/// Mapping of generated instructions back to the AST nodes they were generated from.
+#[derive(Debug, Clone, Default)]
+pub struct IrSourceMap {
+    /// Originating node of each instruction, indexed by the instruction index
+    nodes: Vec<NodeId>,
+    /// Span of each node in `nodes`
+    spans: Vec<crate::compiler::Span>,
+}
+
+impl IrSourceMap {
+    /// Returns the node that the instruction at `instruction_index` was generated from.
+    pub fn node_id(&self, instruction_index: usize) -> Option<NodeId> {
+        self.nodes.get(instruction_index).copied()
+    }
+
+    /// Returns the source span of the instruction at `instruction_index`.
+    pub fn span(&self, instruction_index: usize) -> Option<crate::compiler::Span> {
+        self.spans.get(instruction_index).copied()
+    }
+
+    /// Returns the number of mapped instructions.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+}
+
+/// Generates IR (Intermediate Representation) from nu AST.
+///
+/// The instructions are emitted by `E`, by default for the nu-protocol version this crate depends
+/// on.
+pub struct IrGenerator<'a, E: IrEmitter = NuProtocolEmitter> {
+    // Immutable reference to a compiler after the typechecker pass
+    compiler: &'a Compiler,
+    errors: Vec<SourceError>,
+    emitter: E,
+    source_map: IrSourceMap,
+    /// Loops enclosing the node being generated, the innermost last
+    loops: Vec<LoopContext>,
+    /// Closures enclosing the node being generated, the innermost last
+    closure_scopes: Vec<ClosureScope>,
+    /// Closures in the order they were generated, indexed by `load_closure()`
+    closures: Vec<GeneratedClosure<E>>,
+}
+
+impl<'a> IrGenerator<'a> {
+    pub fn new(compiler: &'a Compiler) -> Self {
+        Self::with_emitter(compiler, NuProtocolEmitter::default())
+    }
+}
+
+impl<'a, E: IrEmitter> IrGenerator<'a, E> {
+    pub fn with_emitter(compiler: &'a Compiler, emitter: E) -> Self {
+        Self {
+            compiler,
+            errors: Default::default(),
+            emitter,
+            source_map: Default::default(),
+            loops: vec![],
+            closure_scopes: vec![],
+            closures: vec![],
+        }
+    }
+
+    /// Returns generated IR block.
+    ///
+    /// Call `generate` before using this method and ensure there are no errors.
+    pub fn block(self) -> E::Block {
+        self.emitter.finish()
+    }
+
+    /// Returns the generated IR block together with the blocks of the closures defined in it.
+    ///
+    /// Call `generate` before using this method and ensure there are no errors.
+    pub fn blocks(self) -> (E::Block, Vec<IrClosure<E::Block>>) {
+        let closures = self
+            .closures
+            .into_iter()
+            .map(|closure| IrClosure {
+                block: closure.emitter.finish(),
+                source_map: closure.source_map,
+                params: closure.params,
+                captures: closure.captures,
+            })
+            .collect();
+        (self.emitter.finish(), closures)
+    }
+
+    /// Returns the mapping of the generated instructions to their source.
+    ///
+    /// Call `generate` before using this method.
+    pub fn source_map(&self) -> &IrSourceMap {
+        &self.source_map
+    }
+
+    /// Returns errors encountered during IR generation step.
+    ///
+    /// Call `generate` before using this method.
+    pub fn errors(&self) -> &Vec<SourceError> {
+        &self.errors
+    }
+
+    /// Prints the internal state to standard output.
+    pub fn print(&self) {
+        let output = self.display_state();
+        print!("{output}");
+    }
+
+    /// Displays the state of the IR generator.
+    /// The output can be used for human debugging and for snapshot tests.
+    pub fn display_state(&self) -> String {
+        let mut result = String::new();
+        result.push_str("==== IR ====\n");
+        result.push_str(&self.emitter.display());
+        result.push_str(&display_source_map(&self.source_map));
+
+        for (idx, closure) in self.closures.iter().enumerate() {
+            result.push_str(&format!("==== IR CLOSURE {idx} ====\n"));
+            result.push_str(&format!("params: {:?}\n", closure.params));
+            result.push_str(&format!("captures: {:?}\n", closure.captures));
+            result.push_str(&closure.emitter.display());
+            result.push_str(&display_source_map(&closure.source_map));
+        }
+
+        if !self.errors.is_empty() {
+            result.push_str("==== IR ERRORS ====\n");
+            for error in &self.errors {
+                result.push_str(&error.display(self.compiler));
+            }
+        }
+        result
+    }
+
+    /// Formats the state of the IR generator as JSON for machine-readable output.
+    pub fn to_json(&self) -> serde_json::Value {
+        let closures: Vec<serde_json::Value> = self
+            .closures
+            .iter()
+            .map(|closure| {
+                let mut fields = closure.emitter.to_json();
+                fields.insert("source_map".into(), source_map_json(&closure.source_map));
+                fields.insert("params".into(), variables_json(&closure.params));
+                fields.insert("captures".into(), variables_json(&closure.captures));
+                fields.into()
+            })
+            .collect();
+        let errors: Vec<_> = self
+            .errors
+            .iter()
+            .map(|error| error.to_json(self.compiler))
+            .collect();
+
+        let mut fields = self.emitter.to_json();
+        fields.insert("source_map".into(), source_map_json(&self.source_map));
+        fields.insert("closures".into(), closures.into());
+        fields.insert("errors".into(), errors.into());
+        fields.into()
+    }
+
+    /// Generates the IR from the given state of the compiler.
+    /// After this is called, use `block` and `errors` to get the result.
+    pub fn generate(&mut self) {
+        if self.compiler.ast_nodes.is_empty() {
+            return;
+        }
+        let node_id = NodeId(self.compiler.ast_nodes.len() - 1);
+        let Some(reg) = self.generate_node(node_id) else {
+            return;
+        };
+        self.emit(node_id, IrInstruction::Return { src: reg });
+    }
+
+    fn generate_node(&mut self, node_id: NodeId) -> Option<E::Reg> {
+        let ast_node = &self.compiler.ast_nodes[node_id.0];
+        if matches!(ast_node, AstNode::BinaryOp { .. } | AstNode::UnaryOp { .. }) {
+            if let Some(constant) = self.constant(node_id) {
+                return Some(self.load_constant(node_id, &constant));
+            }
+        }
+
+        match ast_node {
+            AstNode::Int => {
+                let next_reg = self.emitter.next_register();
+                let val = self.span_to_i64(node_id)?;
+                self.emit(
+                    node_id,
+                    IrInstruction::LoadInt {
+                        dst: next_reg,
+                        value: val,
+                    },
+                );
+                Some(next_reg)
+            }
+            AstNode::Float => {
+                let next_reg = self.emitter.next_register();
+                let val = self.span_to_f64(node_id)?;
+                self.emit(
+                    node_id,
+                    IrInstruction::LoadFloat {
+                        dst: next_reg,
+                        value: val,
+                    },
+                );
+                Some(next_reg)
+            }
+            AstNode::Filesize => {
+                let next_reg = self.emitter.next_register();
+                let val = self.span_to_unit_value(node_id, FILESIZE_UNITS)?;
+                self.emit(
+                    node_id,
+                    IrInstruction::LoadFilesize {
+                        dst: next_reg,
+                        bytes: val,
+                    },
+                );
+                Some(next_reg)
+            }
+            AstNode::Duration => {
+                let next_reg = self.emitter.next_register();
+                let val = self.span_to_unit_value(node_id, DURATION_UNITS)?;
+                self.emit(
+                    node_id,
+                    IrInstruction::LoadDuration {
+                        dst: next_reg,
+                        nanoseconds: val,
+                    },
+                );
+                Some(next_reg)
+            }
+            AstNode::Binary => {
+                let next_reg = self.emitter.next_register();
+                let val = self.span_to_binary_value(node_id)?;
+                self.emit(
+                    node_id,
+                    IrInstruction::LoadBinary {
+                        dst: next_reg,
+                        value: &val,
+                    },
+                );
+                Some(next_reg)
+            }
+            AstNode::Datetime => {
+                let next_reg = self.emitter.next_register();
+                let val = self.span_to_datetime_value(node_id)?;
+                self.emit(
+                    node_id,
+                    IrInstruction::LoadDatetime {
+                        dst: next_reg,
+                        value: val,
+                    },
+                );
+                Some(next_reg)
+            }
+            AstNode::String | AstNode::RawString | AstNode::GlobPattern => {
+                let next_reg = self.emitter.next_register();
+                self.load_string_or_glob(node_id, next_reg)?;
+                Some(next_reg)
+            }
+            AstNode::True | AstNode::False => {
+                let next_reg = self.emitter.next_register();
+                let val = matches!(ast_node, AstNode::True);
+                self.emit(
+                    node_id,
+                    IrInstruction::LoadBool {
+                        dst: next_reg,
+                        value: val,
+                    },
+                );
+                Some(next_reg)
+            }
+            AstNode::Null => Some(self.load_nothing(node_id)),
+            AstNode::Variable => {
+                let var = self.variable(node_id)?;
+                self.capture(var);
+                let next_reg = self.emitter.next_register();
+                self.emit(node_id, IrInstruction::LoadVariable { dst: next_reg, var });
+                Some(next_reg)
+            }
+            AstNode::Let {
+                variable_name,
+                initializer,
+                ..
+            }
+            | AstNode::Const {
+                variable_name,
+                initializer,
+                ..
+            } => {
+                let var = self.variable(*variable_name)?;
+                self.define(var);
+                let reg = self.generate_node(*initializer)?;
+                // `let` itself evaluates to nothing
+                self.emit(node_id, IrInstruction::StoreVariable { var, src: reg });
+                self.emit(node_id, IrInstruction::LoadNothing { dst: reg });
+                Some(reg)
+            }
+            AstNode::Block(block_id) => {
+                let block = &self.compiler.blocks[block_id.0];
+                let mut last = None;
+                for (idx, id) in block.nodes.iter().enumerate() {
+                    // a discarded constant has no effect
+                    if idx + 1 < block.nodes.len() && self.constant(*id).is_some() {
+                        continue;
+                    }
+                    // the value of the previous statement is not used
+                    if let Some((prev_id, reg)) = last {
+                        self.emit(prev_id, IrInstruction::Drain { src: reg });
+                        self.emitter.free_register(reg);
+                    }
+                    last = Some((*id, self.generate_node(*id)?));
+                }
+
+                match last {
+                    Some((_, reg)) => Some(reg),
+                    None => Some(self.load_nothing(node_id)),
+                }
+            }
+            AstNode::Statement(inner) => {
+                let reg = self.generate_node(*inner)?;
+                // the value of a statement followed by `;` is discarded
+                self.emit(node_id, IrInstruction::Drain { src: reg });
+                self.emit(node_id, IrInstruction::LoadNothing { dst: reg });
+                Some(reg)
+            }
+            // the block is evaluated in place, its value is the subexpression's
+            AstNode::Subexpression(block) => self.generate_node(*block),
+            AstNode::If {
+                condition,
+                then_block,
+                else_block,
+            } => {
+                let cond = self.generate_node(*condition)?;
+                let branch_to_then = self.emitter.instruction_count();
+                self.emit(
+                    node_id,
+                    IrInstruction::BranchIf {
+                        cond,
+                        target: UNKNOWN_TARGET,
+                    },
+                );
+                self.emitter.free_register(cond);
+
+                // The else arm follows the branch, both arms leave their value in `out`
+                let out = match else_block {
+                    Some(else_block) => self.generate_node(*else_block)?,
+                    None => self.load_nothing(node_id),
+                };
+                let jump_to_end = self.emitter.instruction_count();
+                self.emit(
+                    node_id,
+                    IrInstruction::Jump {
+                        target: UNKNOWN_TARGET,
+                    },
+                );
+
+                self.patch_jump(branch_to_then);
+                let then = self.generate_node(*then_block)?;
+                if then != out {
+                    self.emit(
+                        *then_block,
+                        IrInstruction::Move {
+                            dst: out,
+                            src: then,
+                        },
+                    );
+                    self.emitter.free_register(then);
+                }
+                self.patch_jump(jump_to_end);
+
+                Some(out)
+            }
+            AstNode::Match { target, match_arms } => {
+                self.generate_match(node_id, *target, match_arms)
+            }
+            AstNode::Loop { block } => {
+                let start = self.emitter.instruction_count();
+                self.generate_loop_body(node_id, *block, start, vec![])?;
+                Some(self.load_nothing(node_id))
+            }
+            AstNode::While { condition, block } => {
+                let start = self.emitter.instruction_count();
+                let cond = self.generate_node(*condition)?;
+                let branch_to_body = self.emitter.instruction_count();
+                self.emit(
+                    node_id,
+                    IrInstruction::BranchIf {
+                        cond,
+                        target: UNKNOWN_TARGET,
+                    },
+                );
+                self.emitter.free_register(cond);
+                let jump_to_end = self.emitter.instruction_count();
+                self.emit(
+                    node_id,
+                    IrInstruction::Jump {
+                        target: UNKNOWN_TARGET,
+                    },
+                );
+
+                self.patch_jump(branch_to_body);
+                self.generate_loop_body(node_id, *block, start, vec![jump_to_end])?;
+                Some(self.load_nothing(node_id))
+            }
+            AstNode::For {
+                variable,
+                range,
+                block,
+            } => {
+                let var = self.variable(*variable)?;
+                self.define(var);
+                let stream = self.generate_node(*range)?;
+                let value = self.emitter.next_register();
+
+                let start = self.emitter.instruction_count();
+                self.emit(
+                    node_id,
+                    IrInstruction::Iterate {
+                        dst: value,
+                        stream,
+                        end_target: UNKNOWN_TARGET,
+                    },
+                );
+                self.emit(node_id, IrInstruction::StoreVariable { var, src: value });
+                self.emitter.free_register(value);
+                self.generate_loop_body(node_id, *block, start, vec![start])?;
+
+                // a `break` leaves the rest of the values in the stream
+                self.emit(node_id, IrInstruction::Drop { src: stream });
+                self.emitter.free_register(stream);
+                Some(self.load_nothing(node_id))
+            }
+            AstNode::Break | AstNode::Continue => {
+                let jump = self.emitter.instruction_count();
+                let Some(loop_context) = self.loops.last_mut() else {
+                    self.error(
+                        ErrorCode::IrUnsupportedNode,
+                        "`break` and `continue` must be used inside a loop",
+                        node_id,
+                    );
+                    self.add_help("use `return` to leave a command or a closure early");
+                    return None;
+                };
+
+                let target = if matches!(ast_node, AstNode::Break) {
+                    loop_context.breaks.push(jump);
+                    UNKNOWN_TARGET
+                } else {
+                    loop_context.continue_target
+                };
+                self.emit(node_id, IrInstruction::Jump { target });
+
+                // The jump does not produce a value, nothing after it is reached, though
+                Some(self.emitter.next_register())
+            }
+            AstNode::List(items) => {
+                let list = self.emitter.next_register();
+                self.emit(
+                    node_id,
+                    IrInstruction::LoadList {
+                        dst: list,
+                        capacity: items.len(),
+                    },
+                );
+                for item in items {
+                    if let AstNode::Spread(value) = self.compiler.get_node(*item) {
+                        let items_reg = self.generate_node(*value)?;
+                        self.emit(
+                            *item,
+                            IrInstruction::ListSpread {
+                                src_dst: list,
+                                items: items_reg,
+                            },
+                        );
+                        self.emitter.free_register(items_reg);
+                        continue;
+                    }
+                    let item_reg = self.generate_node(*item)?;
+                    self.emit(
+                        *item,
+                        IrInstruction::ListPush {
+                            src_dst: list,
+                            item: item_reg,
+                        },
+                    );
+                    self.emitter.free_register(item_reg);
+                }
+                Some(list)
+            }
+            AstNode::Range {
+                from,
+                next,
+                to,
+                exclusive,
+            } => {
+                let mut bounds = vec![];
+                for bound in [from, next, to] {
+                    let reg = match bound {
+                        Some(bound) => self.generate_node(*bound)?,
+                        None => self.load_nothing(node_id),
+                    };
+                    bounds.push(reg);
+                }
+                let range = self.emitter.next_register();
+                self.emit(
+                    node_id,
+                    IrInstruction::LoadRange {
+                        dst: range,
+                        start: bounds[0],
+                        next: bounds[1],
+                        end: bounds[2],
+                        exclusive: *exclusive,
+                    },
+                );
+                for reg in bounds {
+                    self.emitter.free_register(reg);
+                }
+                Some(range)
+            }
+            AstNode::Table { header, rows } => {
+                // the parser checks that the header and the rows are lists of the same length
+                let AstNode::List(columns) = self.compiler.get_node(*header) else {
+                    panic!("internal error: table header is not a list");
+                };
+                let table = self.emitter.next_register();
+                self.emit(
+                    node_id,
+                    IrInstruction::LoadList {
+                        dst: table,
+                        capacity: rows.len(),
+                    },
+                );
+                for row in rows {
+                    let AstNode::List(values) = self.compiler.get_node(*row) else {
+                        panic!("internal error: table row is not a list");
+                    };
+                    let record = self.emitter.next_register();
+                    self.emit(
+                        *row,
+                        IrInstruction::LoadRecord {
+                            dst: record,
+                            capacity: columns.len(),
+                        },
+                    );
+                    for (column, value) in columns.iter().zip(values) {
+                        let key_reg = self.generate_node(*column)?;
+                        let val_reg = self.generate_node(*value)?;
+                        self.emit(
+                            *value,
+                            IrInstruction::RecordInsert {
+                                src_dst: record,
+                                key: key_reg,
+                                val: val_reg,
+                            },
+                        );
+                        self.emitter.free_register(val_reg);
+                        self.emitter.free_register(key_reg);
+                    }
+                    self.emit(
+                        *row,
+                        IrInstruction::ListPush {
+                            src_dst: table,
+                            item: record,
+                        },
+                    );
+                    self.emitter.free_register(record);
+                }
+                Some(table)
+            }
+            AstNode::StringInterpolation(parts) => {
+                let double_quoted = self.compiler.get_span_contents(node_id).starts_with(b"$\"");
+                let string = self.emitter.next_register();
+                self.emit(
+                    node_id,
+                    IrInstruction::LoadString {
+                        dst: string,
+                        value: b"",
+                    },
+                );
+                for part in parts {
+                    let part_reg = if *self.compiler.get_node(*part) == AstNode::String {
+                        let value = self.chunk_value(*part, double_quoted)?;
+                        let reg = self.emitter.next_register();
+                        self.emit(
+                            *part,
+                            IrInstruction::LoadString {
+                                dst: reg,
+                                value: &value,
+                            },
+                        );
+                        reg
+                    } else {
+                        self.generate_node(*part)?
+                    };
+                    self.emit(
+                        *part,
+                        IrInstruction::StringAppend {
+                            src_dst: string,
+                            val: part_reg,
+                        },
+                    );
+                    self.emitter.free_register(part_reg);
+                }
+                Some(string)
+            }
+            AstNode::Record { entries } => {
+                let record = self.emitter.next_register();
+                self.emit(
+                    node_id,
+                    IrInstruction::LoadRecord {
+                        dst: record,
+                        capacity: entries.len(),
+                    },
+                );
+                for entry in entries {
+                    match *entry {
+                        RecordEntry::Pair(key, val) => {
+                            let key_reg = self.generate_node(key)?;
+                            let val_reg = self.generate_node(val)?;
+                            self.emit(
+                                val,
+                                IrInstruction::RecordInsert {
+                                    src_dst: record,
+                                    key: key_reg,
+                                    val: val_reg,
+                                },
+                            );
+                            self.emitter.free_register(val_reg);
+                            self.emitter.free_register(key_reg);
+                        }
+                        RecordEntry::Spread(spread) => {
+                            let AstNode::Spread(value) = *self.compiler.get_node(spread) else {
+                                panic!("internal error: record spread is not a Spread node");
+                            };
+                            let items = self.generate_node(value)?;
+                            self.emit(
+                                spread,
+                                IrInstruction::RecordSpread {
+                                    src_dst: record,
+                                    items,
+                                },
+                            );
+                            self.emitter.free_register(items);
+                        }
+                    }
+                }
+                Some(record)
+            }
+            AstNode::Call { parts } => self.generate_call(node_id, parts, None),
+            AstNode::Pipeline(elements) => {
+                let mut value = self.generate_node(elements[0])?;
+                for element in &elements[1..] {
+                    let AstNode::Call { parts } = self.compiler.get_node(*element) else {
+                        self.error(
+                            ErrorCode::IrUnsupportedNode,
+                            "only commands can take the input of a pipeline yet",
+                            *element,
+                        );
+                        return None;
+                    };
+                    value = self.generate_call(*element, parts, Some(value))?;
+                }
+                Some(value)
+            }
+            AstNode::Closure { params, block } => {
+                self.generate_closure(node_id, *params, *block, &[])
+            }
+            AstNode::Try { block, catch } => self.generate_try(node_id, *block, *catch),
+            AstNode::EnvShorthand { assignments, block } => {
+                self.generate_env_shorthand(node_id, assignments, *block)
+            }
+            AstNode::CellPath { .. } => {
+                let (target, members) = self.cell_path(node_id)?;
+                let (reg, members) = match self.env_key(target, &members) {
+                    // `$env.FOO` is loaded directly, without the whole environment
+                    Some((key, optional, rest)) => {
+                        let reg = self.emitter.next_register();
+                        self.emit(
+                            target,
+                            match optional {
+                                true => IrInstruction::LoadEnvOpt {
+                                    dst: reg,
+                                    key: key.as_bytes(),
+                                },
+                                false => IrInstruction::LoadEnv {
+                                    dst: reg,
+                                    key: key.as_bytes(),
+                                },
+                            },
+                        );
+                        (reg, rest)
+                    }
+                    None => (self.generate_node(target)?, &members[..]),
+                };
+                if !members.is_empty() {
+                    let path = self.emitter.next_register();
+                    self.emit(node_id, IrInstruction::LoadCellPath { dst: path, members });
+                    self.emit(
+                        node_id,
+                        IrInstruction::FollowCellPath { src_dst: reg, path },
+                    );
+                    self.emitter.free_register(path);
+                }
+                Some(reg)
+            }
+            AstNode::BinaryOp { lhs, op, rhs } if self.compiler.get_node(*op).is_assignment() => {
+                let op = match self.compiler.get_node(*op) {
+                    AstNode::Assignment => None,
+                    _ => Some(self.assignment_operator(*op)?),
+                };
+                self.generate_assignment(node_id, *lhs, op, *rhs)
+            }
+            AstNode::BinaryOp { lhs, op, rhs }
+                if matches!(self.compiler.get_node(*op), AstNode::And | AstNode::Or) =>
+            {
+                self.generate_short_circuit(*lhs, *op, *rhs)
+            }
+            AstNode::BinaryOp { lhs, op, rhs } => {
+                let l = self.generate_node(*lhs)?;
+                let r = self.generate_node(*rhs)?;
+                let op = self.node_to_operator(*op)?;
+                self.emit(
+                    node_id,
+                    IrInstruction::BinaryOp {
+                        lhs_dst: l,
+                        op,
+                        rhs: r,
+                    },
+                );
+                self.emitter.free_register(r);
+                Some(l)
+            }
+            AstNode::UnaryOp { op, value } => {
+                if matches!(self.compiler.get_node(*op), AstNode::Not) {
+                    let reg = self.generate_node(*value)?;
+                    self.emit(node_id, IrInstruction::Not { src_dst: reg });
+                    return Some(reg);
+                }
+
+                // the negation is computed as `0 - value`
+                let zero = self.emitter.next_register();
+                self.emit(
+                    node_id,
+                    IrInstruction::LoadInt {
+                        dst: zero,
+                        value: 0,
+                    },
+                );
+                let reg = self.generate_node(*value)?;
+                self.emit(
+                    node_id,
+                    IrInstruction::BinaryOp {
+                        lhs_dst: zero,
+                        op: BinaryOperator::Minus,
+                        rhs: reg,
+                    },
+                );
+                self.emitter.free_register(reg);
+                Some(zero)
+            }
+            // Already reported by the parser
+            AstNode::Garbage => None,
+            _ => {
+                self.error(
+                    ErrorCode::IrUnsupportedNode,
+                    format!("node {:?} not supported yet", ast_node),
+                    node_id,
+                );
+                None
+            }
+        }
+    }
+
+    /// Emit the instruction generated from the node, mapping it back to the node
+    fn emit(&mut self, node_id: NodeId, instruction: IrInstruction<'_, E::Reg>) {
+        let count = self.emitter.instruction_count();
+        self.emitter.emit(instruction);
+
+        let span = self.compiler.get_span(node_id);
+        for idx in count..self.emitter.instruction_count() {
+            self.emitter.set_span(idx, span);
+            self.source_map.nodes.push(node_id);
+            self.source_map.spans.push(span);
+        }
+    }
+
+    /// Assign to a variable or to a cell path of a variable, e.g., `$x = 1` or `$x.foo += 1`
+    ///
+    /// With a compound operator, the new value is computed from the current one, `$x += 1` is
+    /// generated as `$x = $x + 1`.
+    fn generate_assignment(
+        &mut self,
+        node_id: NodeId,
+        lhs: NodeId,
+        op: Option<BinaryOperator>,
+        rhs: NodeId,
+    ) -> Option<E::Reg> {
+        let (target, members) = match self.compiler.get_node(lhs) {
+            AstNode::CellPath { .. } => self.cell_path(lhs)?,
+            _ => (lhs, vec![]),
+        };
+        if !matches!(self.compiler.get_node(target), AstNode::Variable) {
+            self.error(
+                ErrorCode::IrUnsupportedNode,
+                "only variables and their cell paths can be assigned to",
+                lhs,
+            );
+            return None;
+        }
+        let var = self.variable(target)?;
+        self.capture(var);
+
+        // an environment variable is stored by its name, e.g., `$env.FOO = 1`
+        let (env_key, members) = match self.env_key(target, &members) {
+            Some((key, _, rest)) => (Some(key), rest),
+            None if var == IrVariable::Engine(ENV_VARIABLE_ID.into()) => {
+                self.error(
+                    ErrorCode::IrUnsupportedNode,
+                    "only the fields of `$env` can be assigned to",
+                    lhs,
+                );
+                self.add_help("assign each variable separately, e.g., `$env.FOO = 1`");
+                return None;
+            }
+            None => (None, &members[..]),
+        };
+
+        let value = if members.is_empty() {
+            self.generate_assigned_value(node_id, lhs, op, rhs)?
+        } else {
+            let reg = match &env_key {
+                Some(key) => {
+                    let reg = self.emitter.next_register();
+                    self.emit(
+                        target,
+                        IrInstruction::LoadEnv {
+                            dst: reg,
+                            key: key.as_bytes(),
+                        },
+                    );
+                    reg
+                }
+                None => self.generate_node(target)?,
+            };
+            let path = self.emitter.next_register();
+            self.emit(lhs, IrInstruction::LoadCellPath { dst: path, members });
+            let new_value = self.generate_assigned_value(node_id, lhs, op, rhs)?;
+            self.emit(
+                node_id,
+                IrInstruction::UpsertCellPath {
+                    src_dst: reg,
+                    path,
+                    new_value,
+                },
+            );
+            self.emitter.free_register(new_value);
+            self.emitter.free_register(path);
+            reg
+        };
+
+        // assignments evaluate to nothing
+        let store = match &env_key {
+            Some(key) => IrInstruction::StoreEnv {
+                key: key.as_bytes(),
+                src: value,
+            },
+            None => IrInstruction::StoreVariable { var, src: value },
+        };
+        self.emit(node_id, store);
+        self.emit(node_id, IrInstruction::LoadNothing { dst: value });
+        Some(value)
+    }
+
+    /// The name of the environment variable accessed by a cell path of `$env`, whether it is
+    /// optional and the members after it
+    fn env_key<'m>(
+        &self,
+        target: NodeId,
+        members: &'m [IrPathMember],
+    ) -> Option<(String, bool, &'m [IrPathMember])> {
+        let var_id = self.compiler.var_resolution.get(&target)?;
+        if self.compiler.engine_var_id(*var_id) != Some(ENV_VARIABLE_ID) {
+            return None;
+        }
+
+        match members.split_first()? {
+            (IrPathMember::String { name, optional, .. }, rest) => {
+                Some((name.clone(), *optional, rest))
+            }
+            _ => None,
+        }
+    }
+
+    /// The value stored by an assignment, the right side combined with the current value of the
+    /// target for compound operators
+    fn generate_assigned_value(
+        &mut self,
+        node_id: NodeId,
+        lhs: NodeId,
+        op: Option<BinaryOperator>,
+        rhs: NodeId,
+    ) -> Option<E::Reg> {
+        let Some(op) = op else {
+            return self.generate_node(rhs);
+        };
+        let current = self.generate_node(lhs)?;
+        let value = self.generate_node(rhs)?;
+        self.emit(
+            node_id,
+            IrInstruction::BinaryOp {
+                lhs_dst: current,
+                op,
+                rhs: value,
+            },
+        );
+        self.emitter.free_register(value);
+        Some(current)
+    }
+
+    /// The head of a cell path and the members accessed on it
+    fn cell_path(&mut self, node_id: NodeId) -> Option<(NodeId, Vec<IrPathMember>)> {
+        let AstNode::CellPath { head, members } = self.compiler.get_node(node_id) else {
+            return Some((node_id, vec![]));
+        };
+
+        let mut ir_members = vec![];
+        for CellPathMember { member, optional } in members {
+            let contents = self.compiler.get_span_contents(*member);
+            let span = self.compiler.get_span(*member);
+
+            let ir_member = match self.compiler.get_node(*member) {
+                AstNode::Int => IrPathMember::Int {
+                    index: self.span_to_index(*member, contents)?,
+                    optional: *optional,
+                    span,
+                },
+                _ => IrPathMember::String {
+                    name: String::from_utf8_lossy(unquote(contents)).into_owned(),
+                    optional: *optional,
+                    span,
+                },
+            };
+            ir_members.push(ir_member);
+        }
+
+        Some((*head, ir_members))
+    }
+
+    /// Generate `FOO=bar cmd` as `do { $env.FOO = bar; cmd }`
+    ///
+    /// The environment of the closure called by `do` is dropped when it returns, so the variables
+    /// are set only for the command.
+    fn generate_env_shorthand(
+        &mut self,
+        node_id: NodeId,
+        assignments: &[(NodeId, NodeId)],
+        block: NodeId,
+    ) -> Option<E::Reg> {
+        let engine_decl_id = self
+            .compiler
+            .decl_resolution
+            .get(&node_id)
+            .and_then(|decl_id| self.compiler.engine_decl_id(*decl_id));
+        let Some(engine_decl_id) = engine_decl_id else {
+            self.error(
+                ErrorCode::IrUnsupportedNode,
+                "setting environment variables for a command needs the engine's `do`",
+                node_id,
+            );
+            return None;
+        };
+
+        let closure = self.generate_closure(node_id, None, block, assignments)?;
+        let out = self.emitter.next_register();
+        self.emit(node_id, IrInstruction::PushPositional { src: closure });
+        self.emit(
+            node_id,
+            IrInstruction::Call {
+                decl_id: engine_decl_id.into(),
+                src_dst: out,
+            },
+        );
+        self.emitter.free_register(closure);
+        Some(out)
+    }
+
+    /// Generate the closure's block separately and load the closure
+    ///
+    /// The environment variables in `env` are set at the start of the closure, each is a pair of a
+    /// Name and its value.
+    fn generate_closure(
+        &mut self,
+        node_id: NodeId,
+        params: Option<NodeId>,
+        block: NodeId,
+        env: &[(NodeId, NodeId)],
+    ) -> Option<E::Reg> {
+        let param_vars = self.closure_params(params)?;
+
+        // `break` and `continue` cannot leave the closure
+        let outer_loops = std::mem::take(&mut self.loops);
+        let nested = self.emitter.nested();
+        let outer_emitter = std::mem::replace(&mut self.emitter, nested);
+        let outer_source_map = std::mem::take(&mut self.source_map);
+        self.closure_scopes.push(ClosureScope {
+            defined: param_vars.clone(),
+            captures: vec![],
+        });
+
+        let body = self
+            .generate_closure_env(env)
+            .and_then(|_| self.generate_node(block));
+        if let Some(reg) = body {
+            self.emit(block, IrInstruction::Return { src: reg });
+        }
+
+        let scope = self.closure_scopes.pop().expect("closure scope was pushed");
+        let emitter = std::mem::replace(&mut self.emitter, outer_emitter);
+        let source_map = std::mem::replace(&mut self.source_map, outer_source_map);
+        self.loops = outer_loops;
+        body?;
+
+        self.closures.push(GeneratedClosure {
+            emitter,
+            source_map,
+            params: param_vars,
+            captures: scope.captures,
+        });
+        let closure_index = self.closures.len() - 1;
+
+        let next_reg = self.emitter.next_register();
+        self.emit(
+            node_id,
+            IrInstruction::LoadClosure {
+                dst: next_reg,
+                closure_index,
+            },
+        );
+        Some(next_reg)
+    }
+
+    fn generate_closure_env(&mut self, env: &[(NodeId, NodeId)]) -> Option<()> {
+        for (name, value) in env {
+            let reg = self.generate_node(*value)?;
+            let key = self.compiler.get_span_contents(*name);
+            self.emit(*name, IrInstruction::StoreEnv { key, src: reg });
+            self.emitter.free_register(reg);
+        }
+        Some(())
+    }
+
+    fn closure_params(&mut self, params: Option<NodeId>) -> Option<Vec<IrVariable>> {
+        let mut param_vars = vec![];
+        if let Some(AstNode::Params(params)) = params.map(|id| self.compiler.get_node(id)) {
+            for param in params {
+                if let AstNode::Param { name, .. } = self.compiler.get_node(*param) {
+                    param_vars.push(self.variable(*name)?);
+                }
+            }
+        }
+        Some(param_vars)
+    }
+
+    /// The variable is defined in the block being generated
+    fn define(&mut self, var: IrVariable) {
+        if let Some(scope) = self.closure_scopes.last_mut() {
+            scope.defined.push(var);
+        }
+    }
+
+    /// The variable is used in the block being generated, capture it by the closures between the
+    /// definition and the use
+    fn capture(&mut self, var: IrVariable) {
+        if let IrVariable::Engine(_) = var {
+            return;
+        }
+
+        for scope in self.closure_scopes.iter_mut().rev() {
+            if scope.defined.contains(&var) {
+                return;
+            }
+            if !scope.captures.contains(&var) {
+                scope.captures.push(var);
+            }
+        }
+    }
+
+    /// Push the arguments of the call and call the engine's command, without any input
+    /// Generate `and` or `or`, the right-hand side is evaluated only if the left-hand side does
+    /// not decide the result
+    fn generate_short_circuit(&mut self, lhs: NodeId, op: NodeId, rhs: NodeId) -> Option<E::Reg> {
+        let is_and = matches!(self.compiler.get_node(op), AstNode::And);
+
+        let out = self.generate_node(lhs)?;
+        let branch_if_true = self.emitter.instruction_count();
+        self.emit(
+            op,
+            IrInstruction::BranchIf {
+                cond: out,
+                target: UNKNOWN_TARGET,
+            },
+        );
+
+        // `branch_if` consumed the value, the result is loaded again where it is known
+        let skip_rhs = if is_and {
+            let jump_to_false = self.emitter.instruction_count();
+            self.emit(
+                op,
+                IrInstruction::Jump {
+                    target: UNKNOWN_TARGET,
+                },
+            );
+            self.patch_jump(branch_if_true);
+            Some(jump_to_false)
+        } else {
+            None
+        };
+
+        let value = self.generate_node(rhs)?;
+        self.emit(
+            rhs,
+            IrInstruction::Move {
+                dst: out,
+                src: value,
+            },
+        );
+        self.emitter.free_register(value);
+        let jump_to_end = self.emitter.instruction_count();
+        self.emit(
+            op,
+            IrInstruction::Jump {
+                target: UNKNOWN_TARGET,
+            },
+        );
+
+        match skip_rhs {
+            Some(jump_to_false) => self.patch_jump(jump_to_false),
+            None => self.patch_jump(branch_if_true),
+        }
+        self.emit(
+            op,
+            IrInstruction::LoadBool {
+                dst: out,
+                value: !is_and,
+            },
+        );
+        self.patch_jump(jump_to_end);
+
+        Some(out)
+    }
+
+    /// Generate the call with the value in `input` as its input, or without input
+    fn generate_call(
+        &mut self,
+        node_id: NodeId,
+        parts: &[NodeId],
+        input: Option<E::Reg>,
+    ) -> Option<E::Reg> {
+        let Some(decl_id) = self.compiler.decl_resolution.get(&node_id) else {
+            return self.generate_external_call(node_id, parts, input);
+        };
+        let Some(engine_decl_id) = self.compiler.engine_decl_id(*decl_id) else {
+            self.error(
+                ErrorCode::IrUnsupportedNode,
+                "only the commands of the engine can be called yet",
+                node_id,
+            );
+            return None;
+        };
+
+        let num_name_parts = self.compiler.call_name_parts[&node_id];
+        for part in &parts[num_name_parts..] {
+            self.generate_argument(*part)?;
+        }
+
+        // freed registers are empty, so without input the command gets none
+        let src_dst = input.unwrap_or_else(|| self.emitter.next_register());
+        self.emit(
+            node_id,
+            IrInstruction::Call {
+                decl_id: engine_decl_id.into(),
+                src_dst,
+            },
+        );
+        Some(src_dst)
+    }
+
+    /// Match the target against the patterns in order, the first arm that matches is evaluated
+    ///
+    /// The `Match` instructions of all arms come first, each jumping to its arm. If no arm
+    /// matches, the `match` evaluates to nothing.
+    fn generate_match(
+        &mut self,
+        node_id: NodeId,
+        target: NodeId,
+        match_arms: &[(NodeId, NodeId)],
+    ) -> Option<E::Reg> {
+        let value = self.generate_node(target)?;
+        let out = self.emitter.next_register();
+
+        let mut branches = vec![];
+        for (pattern, _) in match_arms {
+            let ir_pattern = self.pattern(*pattern)?;
+            branches.push(self.emitter.instruction_count());
+            self.emit(
+                *pattern,
+                IrInstruction::Match {
+                    pattern: &ir_pattern,
+                    src: value,
+                    target: UNKNOWN_TARGET,
+                },
+            );
+        }
+        self.emit(node_id, IrInstruction::LoadNothing { dst: out });
+        let mut jumps_to_end = vec![self.emitter.instruction_count()];
+        self.emit(
+            node_id,
+            IrInstruction::Jump {
+                target: UNKNOWN_TARGET,
+            },
+        );
+
+        for ((pattern, result), branch) in match_arms.iter().zip(branches) {
+            self.patch_jump(branch);
+            if let AstNode::GuardedPattern { guard, .. } = self.compiler.get_node(*pattern) {
+                // a failed guard continues with the next pattern
+                let cond = self.generate_node(*guard)?;
+                self.emit(*guard, IrInstruction::CheckMatchGuard { src: cond });
+                self.emit(*guard, IrInstruction::Not { src_dst: cond });
+                self.emit(
+                    *guard,
+                    IrInstruction::BranchIf {
+                        cond,
+                        target: branch + 1,
+                    },
+                );
+                self.emitter.free_register(cond);
+            }
+            // the block of an arm is evaluated in place
+            let reg = match self.compiler.get_node(*result) {
+                AstNode::Closure {
+                    params: None,
+                    block,
+                } => self.generate_node(*block)?,
+                _ => self.generate_node(*result)?,
+            };
+            self.emit(*result, IrInstruction::Move { dst: out, src: reg });
+            self.emitter.free_register(reg);
+            // the last arm ends right before the end
+            if Some(*result) != match_arms.last().map(|(_, result)| *result) {
+                jumps_to_end.push(self.emitter.instruction_count());
+                self.emit(
+                    *result,
+                    IrInstruction::Jump {
+                        target: UNKNOWN_TARGET,
+                    },
+                );
+            }
+        }
+        for jump in jumps_to_end {
+            self.patch_jump(jump);
+        }
+
+        self.emit(node_id, IrInstruction::Drop { src: value });
+        self.emitter.free_register(value);
+        Some(out)
+    }
+
+    fn pattern(&mut self, node_id: NodeId) -> Option<IrPattern> {
+        let kind = match self.compiler.get_node(node_id) {
+            AstNode::Int => IrPatternKind::Int(self.span_to_i64(node_id)?),
+            AstNode::Float => IrPatternKind::Float(self.span_to_f64(node_id)?),
+            AstNode::Wildcard => IrPatternKind::IgnoreValue,
+            AstNode::String => IrPatternKind::String(self.span_to_string_value(node_id)?),
+            AstNode::True => IrPatternKind::Bool(true),
+            AstNode::False => IrPatternKind::Bool(false),
+            AstNode::Null => IrPatternKind::Nothing,
+            AstNode::Variable => {
+                let var = self.variable(node_id)?;
+                self.define(var);
+                IrPatternKind::Variable(var)
+            }
+            AstNode::List(items) => {
+                let mut patterns = vec![];
+                for item in items {
+                    patterns.push(self.pattern(*item)?);
+                }
+                IrPatternKind::List(patterns)
+            }
+            AstNode::Record { entries } => {
+                let mut fields = vec![];
+                for entry in entries {
+                    let RecordEntry::Pair(key, val) = entry else {
+                        self.error(
+                            ErrorCode::IrUnsupportedNode,
+                            "spread in a record pattern is not supported",
+                            node_id,
+                        );
+                        return None;
+                    };
+                    let name = self.span_to_string_value(*key)?;
+                    let name = String::from_utf8_lossy(&name).into_owned();
+                    fields.push((name, self.pattern(*val)?));
+                }
+                IrPatternKind::Record(fields)
+            }
+            AstNode::OrPattern(alternatives) => {
+                let mut patterns = vec![];
+                for alternative in alternatives {
+                    patterns.push(self.pattern(*alternative)?);
+                }
+                IrPatternKind::Or(patterns)
+            }
+            AstNode::GuardedPattern { pattern, .. } => return self.pattern(*pattern),
+            node => {
+                self.error(
+                    ErrorCode::IrUnsupportedNode,
+                    format!("pattern {node:?} not supported yet"),
+                    node_id,
+                );
+                return None;
+            }
+        };
+        Some(IrPattern {
+            kind,
+            span: self.compiler.get_span(node_id),
+        })
+    }
+
+    /// Call the external command through the engine's `run-external`, with the command's name as
+    /// the first argument
+    fn generate_external_call(
+        &mut self,
+        node_id: NodeId,
+        parts: &[NodeId],
+        input: Option<E::Reg>,
+    ) -> Option<E::Reg> {
+        let Some(run_external) = self.compiler.engine_run_external() else {
+            self.error(
+                ErrorCode::IrUnsupportedNode,
+                "external commands can only be called with the engine's `run-external`",
+                node_id,
+            );
+            return None;
+        };
+
+        let head = self.compiler.get_span_contents(parts[0]);
+        let head = head.strip_prefix(b"^").unwrap_or(head).to_vec();
+        let src = self.emitter.next_register();
+        self.emit(
+            parts[0],
+            IrInstruction::LoadString {
+                dst: src,
+                value: &head,
+            },
+        );
+        self.emit(parts[0], IrInstruction::PushPositional { src });
+        self.emitter.free_register(src);
+
+        for part in &parts[1..] {
+            self.generate_argument(*part)?;
+        }
+
+        let src_dst = input.unwrap_or_else(|| self.emitter.next_register());
+        self.emit(
+            node_id,
+            IrInstruction::Call {
+                decl_id: run_external.into(),
+                src_dst,
+            },
+        );
+        Some(src_dst)
+    }
+
+    /// Generate `try { ... } catch {|err| ... }`, inlining the block and the closure's block
+    ///
+    /// The error handler continues at the catch block, which stores the error to its parameter.
+    /// Without the catch block, a failed `try` evaluates to nothing.
+    fn generate_try(
+        &mut self,
+        node_id: NodeId,
+        body: NodeId,
+        catch: Option<NodeId>,
+    ) -> Option<E::Reg> {
+        let catch = match catch {
+            Some(catch) => Some(self.inline_closure(catch, 1)?),
+            None => None,
+        };
+        let error_var = catch
+            .as_ref()
+            .and_then(|(params, _)| params.first().copied());
+
+        let out = self.emitter.next_register();
+        let on_error = self.emitter.instruction_count();
+        self.emit(
+            node_id,
+            match error_var {
+                Some(_) => IrInstruction::OnErrorInto {
+                    target: UNKNOWN_TARGET,
+                    dst: out,
+                },
+                None => IrInstruction::OnError {
+                    target: UNKNOWN_TARGET,
+                },
+            },
+        );
+        let reg = self.generate_node(body)?;
+        self.emit(body, IrInstruction::Move { dst: out, src: reg });
+        self.emitter.free_register(reg);
+        self.emit(node_id, IrInstruction::PopErrorHandler);
+        let jump_to_end = self.emitter.instruction_count();
+        self.emit(
+            node_id,
+            IrInstruction::Jump {
+                target: UNKNOWN_TARGET,
+            },
+        );
+
+        self.patch_jump(on_error);
+        match catch {
+            Some((_, catch_block)) => {
+                if let Some(var) = error_var {
+                    self.define(var);
+                    self.emit(node_id, IrInstruction::StoreVariable { var, src: out });
+                }
+                let reg = self.generate_node(catch_block)?;
+                self.emit(catch_block, IrInstruction::Move { dst: out, src: reg });
+                self.emitter.free_register(reg);
+            }
+            None => self.emit(node_id, IrInstruction::LoadNothing { dst: out }),
+        }
+        self.patch_jump(jump_to_end);
+
+        Some(out)
+    }
+
+    /// The parameters and the block of a closure literal whose block is generated in place
+    fn inline_closure(
+        &mut self,
+        node_id: NodeId,
+        max_params: usize,
+    ) -> Option<(Vec<IrVariable>, NodeId)> {
+        let AstNode::Closure { params, block } = *self.compiler.get_node(node_id) else {
+            self.error(
+                ErrorCode::IrUnsupportedNode,
+                "only blocks and closures written in place are supported here yet",
+                node_id,
+            );
+            return None;
+        };
+
+        let param_vars = self.closure_params(params)?;
+        if param_vars.len() > max_params {
+            let related = params
+                .map(|params| {
+                    RelatedSpan::new(
+                        self.compiler.get_span(params),
+                        format!("{} parameters declared here", param_vars.len()),
+                    )
+                })
+                .into_iter()
+                .collect();
+            self.error_with_related(
+                ErrorCode::IrUnsupportedNode,
+                format!("expected at most {max_params} parameters"),
+                node_id,
+                related,
+            );
+            return None;
+        }
+        Some((param_vars, block))
+    }
+
+    fn generate_argument(&mut self, node_id: NodeId) -> Option<()> {
+        let contents = self.compiler.get_span_contents(node_id);
+        match self.compiler.get_node(node_id) {
+            AstNode::FlagLong => self.emit(
+                node_id,
+                IrInstruction::PushFlag {
+                    name: &contents[2..],
+                },
+            ),
+            AstNode::FlagShort => self.emit(
+                node_id,
+                IrInstruction::PushShortFlag {
+                    short: &contents[1..],
+                },
+            ),
+            AstNode::FlagShortGroup => {
+                for short in contents[1..].chunks(1) {
+                    self.emit(node_id, IrInstruction::PushShortFlag { short });
+                }
+            }
+            AstNode::NamedValue { name, value } => {
+                let name = self.compiler.get_span_contents(*name);
+                let src = self.generate_node(*value)?;
+                self.emit(
+                    node_id,
+                    match name.strip_prefix(b"--") {
+                        Some(long) => IrInstruction::PushNamed { name: long, src },
+                        None => IrInstruction::PushShortNamed {
+                            short: &name[1..],
+                            src,
+                        },
+                    },
+                );
+                self.emitter.free_register(src);
+            }
+            AstNode::Spread(value) => {
+                let src = self.generate_node(*value)?;
+                self.emit(node_id, IrInstruction::AppendRest { src });
+                self.emitter.free_register(src);
+            }
+            AstNode::Redirection { .. } => {
+                self.error(
+                    ErrorCode::IrUnsupportedNode,
+                    "redirections are not supported yet",
+                    node_id,
+                );
+                return None;
+            }
+            // barewords are strings
+            AstNode::Name => {
+                let src = self.emitter.next_register();
+                self.load_string_or_glob(node_id, src)?;
+                self.emit(node_id, IrInstruction::PushPositional { src });
+                self.emitter.free_register(src);
+            }
+            _ => {
+                let src = self.generate_node(node_id)?;
+                self.emit(node_id, IrInstruction::PushPositional { src });
+                self.emitter.free_register(src);
+            }
+        }
+        Some(())
+    }
+
+    /// Generate the body of a loop, discarding its value and jumping back to `continue_target`
+    /// after it
+    ///
+    /// `breaks` are the forward jumps out of the loop emitted before the body, they are patched to
+    /// the instruction after the loop together with the body's `break`s.
+    fn generate_loop_body(
+        &mut self,
+        node_id: NodeId,
+        block: NodeId,
+        continue_target: usize,
+        breaks: Vec<usize>,
+    ) -> Option<()> {
+        self.loops.push(LoopContext {
+            continue_target,
+            breaks,
+        });
+        let body = self.generate_node(block);
+        let loop_context = self.loops.pop().expect("loop context was pushed");
+        let body = body?;
+
+        self.emit(block, IrInstruction::Drain { src: body });
+        self.emitter.free_register(body);
+        self.emit(
+            node_id,
+            IrInstruction::Jump {
+                target: continue_target,
+            },
+        );
+
+        for jump in loop_context.breaks {
+            self.patch_jump(jump);
+        }
+        Some(())
+    }
+
+    /// Load nothing to a new register, the value of statements like loops
+    /// The value of the node if it is known at compile time and the IR is optimized
+    fn constant(&self, node_id: NodeId) -> Option<Constant> {
+        if !self.compiler.options.optimize_ir {
+            return None;
+        }
+        fold_constant(self.compiler, node_id)
+    }
+
+    fn load_constant(&mut self, node_id: NodeId, constant: &Constant) -> E::Reg {
+        let reg = self.emitter.next_register();
+        self.emit(
+            node_id,
+            match constant {
+                Constant::Int(val) => IrInstruction::LoadInt {
+                    dst: reg,
+                    value: *val,
+                },
+                Constant::Float(val) => IrInstruction::LoadFloat {
+                    dst: reg,
+                    value: *val,
+                },
+                Constant::String(val) => IrInstruction::LoadString {
+                    dst: reg,
+                    value: val,
+                },
+                Constant::Bool(val) => IrInstruction::LoadBool {
+                    dst: reg,
+                    value: *val,
+                },
+            },
+        );
+        reg
+    }
+
+    fn load_nothing(&mut self, node_id: NodeId) -> E::Reg {
+        let next_reg = self.emitter.next_register();
+        self.emit(node_id, IrInstruction::LoadNothing { dst: next_reg });
+        next_reg
+    }
+
+    /// Make the forward jump at `instruction_index` continue at the next emitted instruction
+    fn patch_jump(&mut self, instruction_index: usize) {
+        let target = self.emitter.instruction_count();
+        self.emitter.set_jump_target(instruction_index, target);
+    }
+
+    /// The variable the node was resolved to
+    fn variable(&mut self, node_id: NodeId) -> Option<IrVariable> {
+        let Some(var_id) = self.compiler.var_resolution.get(&node_id) else {
+            self.error(
+                ErrorCode::IrUnresolvedVariable,
+                "variable is not resolved",
+                node_id,
+            );
+            return None;
+        };
+
+        Some(match self.compiler.engine_var_id(*var_id) {
+            Some(engine_var_id) => IrVariable::Engine(engine_var_id.into()),
+            None => IrVariable::Local(*var_id),
+        })
+    }
+
+    fn node_to_operator(&mut self, node_id: NodeId) -> Option<BinaryOperator> {
+        match self.compiler.get_node(node_id) {
+            AstNode::Plus => Some(BinaryOperator::Plus),
+            AstNode::Minus => Some(BinaryOperator::Minus),
+            AstNode::Multiply => Some(BinaryOperator::Multiply),
+            AstNode::Divide => Some(BinaryOperator::Divide),
+            AstNode::FloorDiv => Some(BinaryOperator::FloorDivide),
+            AstNode::Modulo => Some(BinaryOperator::Modulo),
+            AstNode::Pow => Some(BinaryOperator::Pow),
+            AstNode::Append => Some(BinaryOperator::Append),
+            AstNode::Equal => Some(BinaryOperator::Equal),
+            AstNode::NotEqual => Some(BinaryOperator::NotEqual),
+            AstNode::LessThan => Some(BinaryOperator::LessThan),
+            AstNode::GreaterThan => Some(BinaryOperator::GreaterThan),
+            AstNode::LessThanOrEqual => Some(BinaryOperator::LessThanOrEqual),
+            AstNode::GreaterThanOrEqual => Some(BinaryOperator::GreaterThanOrEqual),
+            AstNode::RegexMatch => Some(BinaryOperator::RegexMatch),
+            AstNode::NotRegexMatch => Some(BinaryOperator::NotRegexMatch),
+            AstNode::In => Some(BinaryOperator::In),
+            AstNode::NotIn => Some(BinaryOperator::NotIn),
+            AstNode::StartsWith => Some(BinaryOperator::StartsWith),
+            AstNode::EndsWith => Some(BinaryOperator::EndsWith),
+            AstNode::Xor => Some(BinaryOperator::Xor),
+            AstNode::BitOr => Some(BinaryOperator::BitOr),
+            AstNode::BitXor => Some(BinaryOperator::BitXor),
+            AstNode::BitAnd => Some(BinaryOperator::BitAnd),
+            AstNode::ShiftLeft => Some(BinaryOperator::ShiftLeft),
+            AstNode::ShiftRight => Some(BinaryOperator::ShiftRight),
+            node => {
+                self.error(
+                    ErrorCode::IrUnsupportedOperator,
+                    format!("unrecognized operator {:?}", node),
+                    node_id,
+                );
+                None
+            }
+        }
+    }
+
+    /// The operator applied by a compound assignment, e.g., `+` for `+=`
+    fn assignment_operator(&mut self, node_id: NodeId) -> Option<BinaryOperator> {
+        match self.compiler.get_node(node_id) {
+            AstNode::AddAssignment => Some(BinaryOperator::Plus),
+            AstNode::SubtractAssignment => Some(BinaryOperator::Minus),
+            AstNode::MultiplyAssignment => Some(BinaryOperator::Multiply),
+            AstNode::DivideAssignment => Some(BinaryOperator::Divide),
+            AstNode::AppendAssignment => Some(BinaryOperator::Append),
+            node => {
+                self.error(
+                    ErrorCode::IrUnsupportedOperator,
+                    format!("unrecognized assignment operator {:?}", node),
+                    node_id,
+                );
+                None
+            }
+        }
+    }
+
+    fn span_to_i64(&mut self, node_id: NodeId) -> Option<i64> {
+        match int_literal_value(self.compiler.get_span_contents(node_id)) {
+            Ok(val) => Some(val),
+            Err(err) => {
+                self.error(
+                    ErrorCode::IrInvalidLiteral,
+                    format!("failed to convert a node to i64: {err}"),
+                    node_id,
+                );
+                None
+            }
+        }
+    }
+
+    fn span_to_index(&mut self, node_id: NodeId, contents: &[u8]) -> Option<usize> {
+        match String::from_utf8_lossy(contents).parse() {
+            Ok(val) => Some(val),
+            Err(err) => {
+                self.error(
+                    ErrorCode::IrInvalidLiteral,
+                    format!("failed to convert a node to an index: {err}"),
+                    node_id,
+                );
+                None
+            }
+        }
+    }
+
+    fn span_to_f64(&mut self, node_id: NodeId) -> Option<f64> {
+        match float_literal_value(self.compiler.get_span_contents(node_id)) {
+            Ok(val) => Some(val),
+            Err(err) => {
+                self.error(
+                    ErrorCode::IrInvalidLiteral,
+                    format!("failed to convert a node to f64: {err}"),
+                    node_id,
+                );
+                None
+            }
+        }
+    }
+
+    fn span_to_unit_value(&mut self, node_id: NodeId, units: &[(&str, i64)]) -> Option<i64> {
+        match unit_literal_value(self.compiler.get_span_contents(node_id), units) {
+            Ok(val) => Some(val),
+            Err(err) => {
+                self.error(
+                    ErrorCode::IrInvalidLiteral,
+                    format!("failed to convert a node to i64: {err}"),
+                    node_id,
+                );
+                None
+            }
+        }
+    }
+
+    fn span_to_binary_value(&mut self, node_id: NodeId) -> Option<Vec<u8>> {
+        match binary_literal_value(self.compiler.get_span_contents(node_id)) {
+            Ok(val) => Some(val),
+            Err(err) => {
+                self.error(
+                    ErrorCode::IrInvalidLiteral,
+                    format!("failed to convert a node to binary: {err}"),
+                    node_id,
+                );
+                None
+            }
+        }
+    }
+
+    fn span_to_datetime_value(&mut self, node_id: NodeId) -> Option<DateTime<FixedOffset>> {
+        match datetime_literal_value(self.compiler.get_span_contents(node_id)) {
+            Ok(val) => Some(val),
+            Err(err) => {
+                self.error(
+                    ErrorCode::IrInvalidLiteral,
+                    format!("failed to convert a node to datetime: {err}"),
+                    node_id,
+                );
+                None
+            }
+        }
+    }
+
+    /// Load a string literal or a bare word, which is a glob if the typechecker decided so from the
+    /// parameter it is passed to
+    fn load_string_or_glob(&mut self, node_id: NodeId, dst: E::Reg) -> Option<()> {
+        let val = self.span_to_string_value(node_id)?;
+        let is_glob = match self.node_type(node_id) {
+            Some(Type::Glob) => true,
+            Some(Type::String) => false,
+            _ => *self.compiler.get_node(node_id) == AstNode::GlobPattern,
+        };
+        self.emit(node_id, {
+            if is_glob {
+                IrInstruction::LoadGlob { dst, value: &val }
+            } else {
+                IrInstruction::LoadString { dst, value: &val }
+            }
+        });
+        Some(())
+    }
+
+    /// Type of the node, if the compiler was typechecked
+    fn node_type(&self, node_id: NodeId) -> Option<Type> {
+        let type_id = self.compiler.node_types.get(node_id.0)?;
+        self.compiler.types.get(type_id.0).copied()
+    }
+
+    fn span_to_string_value(&mut self, node_id: NodeId) -> Option<Vec<u8>> {
+        let contents = self.compiler.get_span_contents(node_id);
+        if *self.compiler.get_node(node_id) == AstNode::RawString {
+            return Some(raw_string_value(contents).to_vec());
+        }
+
+        match string_literal_value(contents) {
+            Ok(val) => Some(val),
+            Err(err) => {
+                self.error(
+                    ErrorCode::IrInvalidLiteral,
+                    format!("failed to convert a node to string: {err}"),
+                    node_id,
+                );
+                None
+            }
+        }
+    }
+
+    fn chunk_value(&mut self, node_id: NodeId, double_quoted: bool) -> Option<Vec<u8>> {
+        let contents = self.compiler.get_span_contents(node_id);
+        match interpolation_chunk_value(contents, double_quoted) {
+            Ok(val) => Some(val),
+            Err(err) => {
+                self.error(
+                    ErrorCode::IrInvalidLiteral,
+                    format!("failed to convert a node to string: {err}"),
+                    node_id,
+                );
+                None
+            }
+        }
+    }
+
+    fn error(&mut self, code: ErrorCode, message: impl Into<Message>, node: NodeId) {
+        self.error_with_related(code, message, node, vec![]);
+    }
+
+    /// Same as error() but also points at other nodes involved in the error
+    fn error_with_related(
+        &mut self,
+        code: ErrorCode,
+        message: impl Into<Message>,
+        node: NodeId,
+        related: Vec<RelatedSpan>,
+    ) {
+        self.errors.push(SourceError {
+            code,
+            message: message.into(),
+            node_id: node,
+            severity: Severity::Error,
+            help: None,
+            note: None,
+            related,
+            fixes: vec![],
+        })
+    }
+
+    /// Attach a hint to the last reported error
+    fn add_help(&mut self, help: &str) {
+        if let Some(error) = self.errors.last_mut() {
+            error.help = Some(help.into());
+        }
+    }
+}
+
+fn display_source_map(source_map: &IrSourceMap) -> String {
+    let mut result = String::new();
+    if source_map.is_empty() {
+        return result;
+    }
+
+    result.push_str("==== IR SOURCE MAP ====\n");
+    for (idx, (node_id, span)) in source_map.nodes.iter().zip(&source_map.spans).enumerate() {
+        result.push_str(&format!(
+            "{}: NodeId {} ({} to {})\n",
+            idx, node_id.0, span.start, span.end
+        ));
+    }
+    result
+}
+
+fn source_map_json(source_map: &IrSourceMap) -> serde_json::Value {
+    let entries: Vec<_> = source_map
+        .nodes
+        .iter()
+        .zip(&source_map.spans)
+        .map(|(node_id, span)| {
+            serde_json::json!({ "node_id": node_id.0, "start": span.start, "end": span.end })
+        })
+        .collect();
+    entries.into()
+}
+
+fn variables_json(variables: &[IrVariable]) -> serde_json::Value {
+    let names: Vec<_> = variables.iter().map(|var| format!("{var:?}")).collect();
+    names.into()
+}
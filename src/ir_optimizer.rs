@@ -0,0 +1,123 @@
+//! Constant folding of the generated IR
+//!
+//! With `CompilerOptions::optimize_ir`, the IR generator evaluates the expressions made only of
+//! literals at compile time: `1 + 2 * 3` is loaded as the single literal `7` instead of three
+//! literals and two binary operations, and statements whose constant value is discarded generate
+//! no instructions at all.
+//!
+//! The parser evaluates the values of `const` declarations the same way, variables referring to
+//! constants are folded to their values.
+use crate::compiler::Compiler;
+use crate::parser::{
+    float_literal_value, int_literal_value, raw_string_value, string_literal_value, AstNode, NodeId,
+};
+
+/// Value of an expression known at compile time
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Int(i64),
+    Float(f64),
+    String(Vec<u8>),
+    Bool(bool),
+}
+
+/// The value of the node if it consists only of literals and operators on them
+///
+/// Returns None if the value is not known at compile time or if computing it fails, e.g., on an
+/// integer overflow, so that the error is reported when the expression is evaluated.
+pub fn fold_constant(compiler: &Compiler, node_id: NodeId) -> Option<Constant> {
+    let contents = compiler.get_span_contents(node_id);
+    match compiler.get_node(node_id) {
+        AstNode::Int => int_literal_value(contents).ok().map(Constant::Int),
+        AstNode::Float => float_literal_value(contents).ok().map(Constant::Float),
+        AstNode::String => string_literal_value(contents).ok().map(Constant::String),
+        AstNode::RawString => Some(Constant::String(raw_string_value(contents).to_vec())),
+        AstNode::True => Some(Constant::Bool(true)),
+        // a variable referring to a `const`
+        AstNode::Variable => compiler.constants.get(&node_id).cloned(),
+        AstNode::False => Some(Constant::Bool(false)),
+        AstNode::BinaryOp { lhs, op, rhs } => {
+            let lhs = fold_constant(compiler, *lhs)?;
+            let rhs = fold_constant(compiler, *rhs)?;
+            fold_binary_op(lhs, compiler.get_node(*op), rhs)
+        }
+        AstNode::UnaryOp { op, value } => {
+            match (compiler.get_node(*op), fold_constant(compiler, *value)?) {
+                (AstNode::Not, Constant::Bool(val)) => Some(Constant::Bool(!val)),
+                (AstNode::Minus, Constant::Int(val)) => val.checked_neg().map(Constant::Int),
+                (AstNode::Minus, Constant::Float(val)) => Some(Constant::Float(-val)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn fold_binary_op(lhs: Constant, op: &AstNode, rhs: Constant) -> Option<Constant> {
+    match (lhs, op, rhs) {
+        (Constant::Int(l), AstNode::Plus, Constant::Int(r)) => l.checked_add(r).map(Constant::Int),
+        (Constant::Int(l), AstNode::Multiply, Constant::Int(r)) => {
+            l.checked_mul(r).map(Constant::Int)
+        }
+        (Constant::String(mut l), AstNode::Plus, Constant::String(r)) => {
+            l.extend(r);
+            Some(Constant::String(l))
+        }
+        (lhs, op, rhs) => {
+            // an int mixed with a float is computed as a float
+            let l = as_float(&lhs)?;
+            let r = as_float(&rhs)?;
+            match op {
+                AstNode::Plus => Some(Constant::Float(l + r)),
+                AstNode::Multiply => Some(Constant::Float(l * r)),
+                _ => None,
+            }
+        }
+    }
+}
+
+fn as_float(constant: &Constant) -> Option<f64> {
+    match constant {
+        Constant::Int(val) => Some(*val as f64),
+        Constant::Float(val) => Some(*val),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{fold_constant, Constant};
+    use crate::compiler::Compiler;
+    use crate::parser::NodeId;
+
+    fn fold(src: &str) -> Option<Constant> {
+        let mut compiler = Compiler::new();
+        assert!(compiler.check("test.nu", src.as_bytes()));
+        // the expression is the only statement of the top-level block
+        fold_constant(&compiler, NodeId(compiler.ast_nodes.len() - 2))
+    }
+
+    #[test]
+    fn constants_are_folded() {
+        assert_eq!(fold("1 + 2 * 3"), Some(Constant::Int(7)));
+        assert_eq!(fold("1 + 0.5"), Some(Constant::Float(1.5)));
+        assert_eq!(fold("'a' + \"b\""), Some(Constant::String(b"ab".to_vec())));
+        assert_eq!(
+            fold("r#'\\n'# + `b`"),
+            Some(Constant::String(br"\nb".to_vec()))
+        );
+        assert_eq!(fold("true"), Some(Constant::Bool(true)));
+        assert_eq!(fold("not true"), Some(Constant::Bool(false)));
+        assert_eq!(fold("-(1 + 0.5)"), Some(Constant::Float(-1.5)));
+        assert_eq!(fold("const x = 2\n$x * 3"), Some(Constant::Int(6)));
+    }
+
+    #[test]
+    fn failing_and_unknown_values_are_not_folded() {
+        assert_eq!(fold("9223372036854775807 + 1"), None);
+        assert_eq!(fold("1 + 'a'"), None);
+        assert_eq!(fold("true + true"), None);
+        assert_eq!(fold("not 1"), None);
+        assert_eq!(fold("let x = 1\n$x + 1"), None);
+    }
+}
@@ -0,0 +1,374 @@
+//! Structural checks of generated IR blocks
+//!
+//! The engine trusts the IR it evaluates, a block with a dangling jump or a register that is read
+//! before it is written fails at run time with a confusing error, or not at all. `IrValidator`
+//! finds such mistakes of the IR generator instead. It runs on every block finished by
+//! `NuProtocolEmitter` in debug builds.
+use std::fmt;
+
+use nu_protocol::ir::{Instruction, IrBlock, Literal};
+use nu_protocol::RegId;
+
+/// Structural error of an IR block
+#[derive(Debug, Clone, PartialEq)]
+pub struct IrValidationError {
+    pub instruction_index: usize,
+    pub message: String,
+}
+
+impl fmt::Display for IrValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "instruction {}: {}",
+            self.instruction_index, self.message
+        )
+    }
+}
+
+/// Checks an IR block for the mistakes the engine does not report
+///
+/// - a register read before it is written on some path to the instruction
+/// - a register id that is not below the block's `register_count`
+/// - a jump outside of the block
+/// - an instruction after a `Return` that no jump reaches
+pub struct IrValidator<'a> {
+    block: &'a IrBlock,
+    errors: Vec<IrValidationError>,
+}
+
+impl<'a> IrValidator<'a> {
+    pub fn new(block: &'a IrBlock) -> Self {
+        Self {
+            block,
+            errors: vec![],
+        }
+    }
+
+    /// Returns errors found by `validate`.
+    pub fn errors(&self) -> &Vec<IrValidationError> {
+        &self.errors
+    }
+
+    pub fn validate(&mut self) {
+        let instructions = &self.block.instructions;
+        for (idx, instruction) in instructions.iter().enumerate() {
+            for reg in input_registers(instruction)
+                .into_iter()
+                .chain(instruction.output_register())
+            {
+                if reg.get() >= self.block.register_count {
+                    self.error(
+                        idx,
+                        format!(
+                            "register {reg} is out of range, the block has {} registers",
+                            self.block.register_count
+                        ),
+                    );
+                }
+            }
+
+            if let Some(target) = instruction.branch_target() {
+                if target >= instructions.len() {
+                    self.error(
+                        idx,
+                        format!(
+                            "jump to {target} is out of range, the block has {} instructions",
+                            instructions.len()
+                        ),
+                    );
+                }
+            }
+        }
+
+        // Nothing else can be checked reliably with the previous errors
+        if self.errors.is_empty() {
+            self.check_registers_written();
+        }
+    }
+
+    /// Check that every register is written before it is read, on all paths from the start
+    ///
+    /// The registers written on all paths are computed for each instruction, until they do not
+    /// change anymore. Instructions not reached by any path are not checked.
+    fn check_registers_written(&mut self) {
+        let instructions = &self.block.instructions;
+
+        // None for the instructions not reached so far
+        let mut written: Vec<Option<Vec<bool>>> = vec![None; instructions.len()];
+        if instructions.is_empty() {
+            return;
+        }
+        written[0] = Some(vec![false; self.block.register_count as usize]);
+
+        let mut pending = vec![0];
+        while let Some(idx) = pending.pop() {
+            let mut after = written[idx]
+                .clone()
+                .expect("only reached instructions are pending");
+            if let Some(reg) = instruction_output(&instructions[idx]) {
+                after[reg.get() as usize] = true;
+            }
+
+            for next in successors(&instructions[idx], idx, instructions.len()) {
+                let changed = match &mut written[next] {
+                    Some(before) => {
+                        let mut changed = false;
+                        for (before, after) in before.iter_mut().zip(&after) {
+                            if *before && !after {
+                                *before = false;
+                                changed = true;
+                            }
+                        }
+                        changed
+                    }
+                    unreached => {
+                        *unreached = Some(after.clone());
+                        true
+                    }
+                };
+                if changed && !pending.contains(&next) {
+                    pending.push(next);
+                }
+            }
+        }
+
+        let mut after_return = false;
+        for (idx, instruction) in instructions.iter().enumerate() {
+            let Some(written) = &written[idx] else {
+                if after_return {
+                    self.error(idx, "instruction after `return` is never reached");
+                }
+                continue;
+            };
+            after_return = false;
+
+            for reg in input_registers(instruction) {
+                if !written[reg.get() as usize] {
+                    self.error(idx, format!("register {reg} is read before it is written"));
+                }
+            }
+            if matches!(instruction, Instruction::Return { .. }) {
+                after_return = true;
+            }
+        }
+    }
+
+    fn error(&mut self, instruction_index: usize, message: impl Into<String>) {
+        self.errors.push(IrValidationError {
+            instruction_index,
+            message: message.into(),
+        })
+    }
+}
+
+/// Panics if the block is not valid, this is a bug of the IR generator
+pub fn assert_valid(block: &IrBlock) {
+    let mut validator = IrValidator::new(block);
+    validator.validate();
+
+    let errors = validator.errors();
+    if !errors.is_empty() {
+        let errors: Vec<_> = errors.iter().map(ToString::to_string).collect();
+        panic!("invalid IR block:\n{}\n{block:#?}", errors.join("\n"));
+    }
+}
+
+/// The register written by the instruction, same as `output_register()` except for the handler's
+/// register of `OnErrorInto`
+fn instruction_output(instruction: &Instruction) -> Option<RegId> {
+    match instruction {
+        Instruction::OnErrorInto { dst, .. } => Some(*dst),
+        _ => instruction.output_register(),
+    }
+}
+
+/// Indices of the instructions that can be executed after the instruction at `idx`
+fn successors(instruction: &Instruction, idx: usize, len: usize) -> Vec<usize> {
+    let falls_through = !matches!(
+        instruction,
+        Instruction::Jump { .. }
+            | Instruction::Return { .. }
+            | Instruction::ReturnEarly { .. }
+            | Instruction::Unreachable
+    );
+
+    let next = (falls_through && idx + 1 < len).then_some(idx + 1);
+    next.into_iter()
+        .chain(instruction.branch_target())
+        .collect()
+}
+
+/// The registers whose values are used by the instruction
+///
+/// The input of a `Call` is not included, the command is called without input if the register was
+/// never written.
+fn input_registers(instruction: &Instruction) -> Vec<RegId> {
+    match *instruction {
+        Instruction::Move { src, .. }
+        | Instruction::Clone { src, .. }
+        | Instruction::Drop { src }
+        | Instruction::Drain { src }
+        | Instruction::DrainIfEnd { src }
+        | Instruction::StoreVariable { src, .. }
+        | Instruction::StoreEnv { src, .. }
+        | Instruction::PushPositional { src }
+        | Instruction::AppendRest { src }
+        | Instruction::PushNamed { src, .. }
+        | Instruction::PushShortNamed { src, .. }
+        | Instruction::CheckErrRedirected { src }
+        | Instruction::WriteFile { src, .. }
+        | Instruction::BranchIfEmpty { src, .. }
+        | Instruction::Match { src, .. }
+        | Instruction::CheckMatchGuard { src }
+        | Instruction::ReturnEarly { src }
+        | Instruction::Return { src } => vec![src],
+        Instruction::OpenFile { path, .. } => vec![path],
+        Instruction::BranchIf { cond, .. } => vec![cond],
+        Instruction::Iterate { stream, .. } => vec![stream],
+        Instruction::Collect { src_dst }
+        | Instruction::Span { src_dst }
+        | Instruction::GlobFrom { src_dst, .. }
+        | Instruction::Not { src_dst } => vec![src_dst],
+        Instruction::StringAppend { src_dst, val } => vec![src_dst, val],
+        Instruction::ListPush { src_dst, item } => vec![src_dst, item],
+        Instruction::ListSpread { src_dst, items }
+        | Instruction::RecordSpread { src_dst, items } => vec![src_dst, items],
+        Instruction::RecordInsert { src_dst, key, val } => vec![src_dst, key, val],
+        Instruction::BinaryOp { lhs_dst, rhs, .. } => vec![lhs_dst, rhs],
+        Instruction::FollowCellPath { src_dst, path } => vec![src_dst, path],
+        Instruction::CloneCellPath { src, path, .. } => vec![src, path],
+        Instruction::UpsertCellPath {
+            src_dst,
+            path,
+            new_value,
+        } => vec![src_dst, path, new_value],
+        Instruction::LoadLiteral {
+            lit: Literal::Range {
+                start, step, end, ..
+            },
+            ..
+        } => vec![start, step, end],
+        Instruction::Unreachable
+        | Instruction::LoadLiteral { .. }
+        | Instruction::LoadValue { .. }
+        | Instruction::LoadVariable { .. }
+        | Instruction::DropVariable { .. }
+        | Instruction::LoadEnv { .. }
+        | Instruction::LoadEnvOpt { .. }
+        | Instruction::PushFlag { .. }
+        | Instruction::PushShortFlag { .. }
+        | Instruction::PushParserInfo { .. }
+        | Instruction::RedirectOut { .. }
+        | Instruction::RedirectErr { .. }
+        | Instruction::CloseFile { .. }
+        | Instruction::Call { .. }
+        | Instruction::Jump { .. }
+        | Instruction::OnError { .. }
+        | Instruction::OnErrorInto { .. }
+        | Instruction::PopErrorHandler => vec![],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::IrValidator;
+    use nu_protocol::ir::{Instruction, IrBlock, Literal};
+    use nu_protocol::RegId;
+
+    fn errors(instructions: Vec<Instruction>, register_count: u32) -> Vec<String> {
+        let block = IrBlock {
+            spans: vec![nu_protocol::Span::unknown(); instructions.len()],
+            instructions,
+            data: Default::default(),
+            ast: vec![],
+            comments: vec![],
+            register_count,
+            file_count: 0,
+        };
+        let mut validator = IrValidator::new(&block);
+        validator.validate();
+        validator.errors().iter().map(ToString::to_string).collect()
+    }
+
+    fn reg(idx: u32) -> RegId {
+        RegId::new(idx)
+    }
+
+    fn load(dst: u32) -> Instruction {
+        Instruction::LoadLiteral {
+            dst: reg(dst),
+            lit: Literal::Int(1),
+        }
+    }
+
+    #[test]
+    fn valid_blocks() {
+        assert_eq!(
+            errors(vec![load(0), Instruction::Return { src: reg(0) }], 1),
+            Vec::<String>::new()
+        );
+
+        // both arms write the returned register
+        let branches = vec![
+            load(0),
+            Instruction::BranchIf {
+                cond: reg(0),
+                index: 4,
+            },
+            load(1),
+            Instruction::Jump { index: 5 },
+            load(1),
+            Instruction::Return { src: reg(1) },
+        ];
+        assert_eq!(errors(branches, 2), Vec::<String>::new());
+    }
+
+    #[test]
+    fn out_of_range() {
+        let block = vec![
+            load(1),
+            Instruction::Jump { index: 7 },
+            Instruction::Return { src: reg(1) },
+        ];
+        assert_eq!(
+            errors(block, 1),
+            [
+                "instruction 0: register %1 is out of range, the block has 1 registers",
+                "instruction 1: jump to 7 is out of range, the block has 3 instructions",
+                "instruction 2: register %1 is out of range, the block has 1 registers",
+            ]
+        );
+    }
+
+    #[test]
+    fn registers_read_before_written() {
+        // only one arm writes the returned register
+        let branches = vec![
+            load(0),
+            Instruction::BranchIf {
+                cond: reg(0),
+                index: 3,
+            },
+            load(1),
+            Instruction::Return { src: reg(1) },
+        ];
+        assert_eq!(
+            errors(branches, 2),
+            ["instruction 3: register %1 is read before it is written"]
+        );
+    }
+
+    #[test]
+    fn unreachable_instructions_after_return() {
+        let block = vec![
+            load(0),
+            Instruction::Return { src: reg(0) },
+            Instruction::Drain { src: reg(1) },
+        ];
+        assert_eq!(
+            errors(block, 2),
+            ["instruction 2: instruction after `return` is never reached"]
+        );
+    }
+}
@@ -1,12 +1,20 @@
 use crate::compiler::{Span, Spanned};
+use crate::errors::TextEdit;
 use logos::{Lexer, Logos};
+use std::borrow::Cow;
+use std::ops::Range;
 
 #[derive(Debug, Default, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LexError {
     #[default]
     Generic,
     UnmatchedStrInterpLParen,
     UnmatchedStrInterpRParen,
+    UnterminatedRawString,
+    /// A quote that is never closed, the span is the opening quote (with the `$` of an
+    /// interpolation)
+    UnterminatedString,
 }
 
 /// Average number of bytes per token used for estimating the tokens buffer size.
@@ -34,16 +42,22 @@ pub struct Tokens {
     pos: usize,
     tokens: Vec<Token>,
     spans: Vec<Span>,
+    /// Spans of invalid UTF-8 sequences in the source
+    invalid_utf8: Vec<Span>,
 }
 
 impl Tokens {
     /// Create a new Tokens with allocated storage for the tokens and spans
     pub fn new(source: &[u8]) -> Self {
-        let estimated_num_tokens = source.len() / AVG_BYTES_PER_TOKEN;
+        Self::with_capacity(source.len() / AVG_BYTES_PER_TOKEN)
+    }
+
+    fn with_capacity(num_tokens: usize) -> Self {
         Tokens {
             pos: 0,
-            tokens: Vec::with_capacity(estimated_num_tokens),
-            spans: Vec::with_capacity(estimated_num_tokens),
+            tokens: Vec::with_capacity(num_tokens),
+            spans: Vec::with_capacity(num_tokens),
+            invalid_utf8: vec![],
         }
     }
 
@@ -91,6 +105,74 @@ impl Tokens {
         self.spans[self.pos]
     }
 
+    /// Check the token after the current one, if the current one is not the last
+    pub fn peek_next(&self) -> Option<(Token, Span)> {
+        let pos = self.pos + 1;
+        Some((*self.tokens.get(pos)?, *self.spans.get(pos)?))
+    }
+
+    /// All tokens with their spans, regardless of the current position
+    pub fn iter(&self) -> impl Iterator<Item = (Token, Span)> + '_ {
+        self.tokens.iter().copied().zip(self.spans.iter().copied())
+    }
+
+    /// Spans of all comments, e.g., for finding lint comments
+    pub fn comments(&self) -> impl Iterator<Item = Span> + '_ {
+        self.tokens
+            .iter()
+            .zip(&self.spans)
+            .filter(|(token, _)| **token == Token::Comment)
+            .map(|(_, span)| *span)
+    }
+
+    /// Remove the comments, returning their spans
+    ///
+    /// The position is reset to the first token.
+    pub fn remove_comments(&mut self) -> Vec<Span> {
+        let comments = self.comments().collect();
+
+        let (tokens, spans) = self
+            .tokens
+            .iter()
+            .zip(&self.spans)
+            .filter(|(token, _)| **token != Token::Comment)
+            .unzip();
+        self.tokens = tokens;
+        self.spans = spans;
+        self.pos = 0;
+
+        comments
+    }
+
+    /// Drop the tokens before the current position, which becomes the first one
+    ///
+    /// Positions taken before are not valid anymore.
+    pub fn drop_consumed(&mut self) {
+        self.tokens.drain(..self.pos);
+        self.spans.drain(..self.pos);
+        self.pos = 0;
+    }
+
+    /// Append the tokens of the contents following these ones in place of the EOF
+    pub fn append(&mut self, mut next: Tokens) {
+        if self.tokens.last() == Some(&Token::Eof) {
+            self.tokens.pop();
+            self.spans.pop();
+        }
+        self.tokens.append(&mut next.tokens);
+        self.spans.append(&mut next.spans);
+    }
+
+    /// Whether the current token or the one after it is the last one
+    pub fn is_near_end(&self) -> bool {
+        self.pos + 2 >= self.tokens.len()
+    }
+
+    /// Spans of the invalid UTF-8 sequences that were lexed around
+    pub fn invalid_utf8(&self) -> &[Span] {
+        &self.invalid_utf8
+    }
+
     // Printing
 
     /// Format the tokens into a human-readable output for debugging
@@ -116,9 +198,39 @@ impl Tokens {
             ));
         }
 
+        for span in &self.invalid_utf8 {
+            result.push_str(&format!(
+                "Invalid UTF-8 span: {:4} .. {:4}\n",
+                span.start, span.end
+            ));
+        }
+
         result
     }
 
+    /// Format the tokens as JSON for machine-readable output
+    pub fn to_json(&self, source: &[u8]) -> serde_json::Value {
+        let tokens = self
+            .tokens
+            .iter()
+            .zip(self.spans.iter())
+            .map(|(token, span)| {
+                serde_json::json!({
+                    "token": format!("{:?}", token),
+                    "start": span.start,
+                    "end": span.end,
+                    "text": String::from_utf8_lossy(
+                        source
+                            .get(span.start..span.end)
+                            .expect("missing source of token span")
+                    ),
+                })
+            })
+            .collect();
+
+        serde_json::Value::Array(tokens)
+    }
+
     /// Print the output of display() to standard output
     pub fn print(&self, source: &[u8]) {
         let output = self.display(source);
@@ -228,44 +340,138 @@ fn lex_internal_sq_string_interp(
     Ok(())
 }
 
+/// Tokens of the contents with their spans, same as `Token::lexer(contents).spanned()` except for
+/// integers followed by `..`
+///
+/// The longest match of `1..10` is the float `1.`, followed by the float `.10`. The trailing dot of
+/// such a float is lexed again as the start of `..` instead, so that `1..10` is a range of ints.
+struct SpannedTokens<'a> {
+    contents: &'a [u8],
+    lexer: Lexer<'a, Token>,
+    /// Start of the lexer's input within contents
+    offset: usize,
+}
+
+fn spanned_tokens(contents: &[u8]) -> SpannedTokens<'_> {
+    SpannedTokens {
+        contents,
+        lexer: Token::lexer(contents),
+        offset: 0,
+    }
+}
+
+impl Iterator for SpannedTokens<'_> {
+    type Item = (Result<Token, LexError>, Range<usize>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let res = self.lexer.next()?;
+        let span = self.lexer.span();
+        let span = span.start + self.offset..span.end + self.offset;
+
+        if res == Ok(Token::Float)
+            && self.contents[span.end - 1] == b'.'
+            && self.contents.get(span.end) == Some(&b'.')
+        {
+            let int_end = span.end - 1;
+            self.offset = int_end;
+            self.lexer = Token::lexer(&self.contents[int_end..]);
+            return Some((Ok(Token::Int), span.start..int_end));
+        }
+
+        Some((res, span))
+    }
+}
+
 fn lex_internal(
     contents: &[u8],
     span_offset: usize,
     tokens: &mut Tokens,
 ) -> Result<(), Spanned<LexError>> {
-    let lexer = Token::lexer(contents).spanned();
+    for (res, span) in spanned_tokens(contents) {
+        lex_token(contents, span_offset, res, span, tokens)?;
+    }
 
-    for (res, span) in lexer {
-        let new_span = Span::new(span.start + span_offset, span.end + span_offset);
-        match res {
-            Ok(Token::DqStrInterp) => lex_internal_dq_string_interp(
-                &contents[span.start..span.end],
-                span_offset + span.start,
-                tokens,
-            )?,
-            Ok(Token::SqStrInterp) => lex_internal_sq_string_interp(
-                &contents[span.start..span.end],
-                span_offset + span.start,
-                tokens,
-            )?,
-            Ok(token) => tokens.push(token, new_span),
-            Err(e) => {
-                return Err(Spanned::new(e, new_span));
-            }
+    Ok(())
+}
+
+/// Push a token lexed from `contents`, lexing string interpolations separately
+fn lex_token(
+    contents: &[u8],
+    span_offset: usize,
+    res: Result<Token, LexError>,
+    span: Range<usize>,
+    tokens: &mut Tokens,
+) -> Result<(), Spanned<LexError>> {
+    let new_span = Span::new(span.start + span_offset, span.end + span_offset);
+    match res {
+        Ok(Token::DqStrInterp) => lex_internal_dq_string_interp(
+            &contents[span.start..span.end],
+            span_offset + span.start,
+            tokens,
+        ),
+        Ok(Token::SqStrInterp) => lex_internal_sq_string_interp(
+            &contents[span.start..span.end],
+            span_offset + span.start,
+            tokens,
+        ),
+        Ok(token) => {
+            tokens.push(token, new_span);
+            Ok(())
         }
+        Err(LexError::Generic) if is_unterminated_string(&contents[span.start..]) => {
+            let quote_len = if contents[span.start] == b'$' { 2 } else { 1 };
+            Err(Spanned::new(
+                LexError::UnterminatedString,
+                Span::new(new_span.start, new_span.start + quote_len),
+            ))
+        }
+        Err(e) => Err(Spanned::new(e, new_span)),
     }
+}
 
-    Ok(())
+/// Whether the contents start with a quote that is not closed anywhere after it, e.g., `"foo` or
+/// `$'foo`
+fn is_unterminated_string(contents: &[u8]) -> bool {
+    let contents = contents.strip_prefix(b"$").unwrap_or(contents);
+
+    match contents.split_first() {
+        Some((b'"', rest)) => {
+            let mut rest = rest.iter();
+            while let Some(c) = rest.next() {
+                match c {
+                    b'\\' => {
+                        rest.next();
+                    }
+                    b'"' => return false,
+                    _ => (),
+                }
+            }
+            true
+        }
+        Some((quote @ (b'\'' | b'`'), rest)) => !rest.contains(quote),
+        _ => false,
+    }
 }
 
 /// Lex the source contents and return allocated Tokens.
 ///
 /// In the case of error, you can look up the last stored token to get a clue what went wrong. The
 /// last stored token is always End Of File (EOF), so there will always be at least one token.
+///
+/// Invalid UTF-8 sequences do not stop the lexing. They are lexed as if they were ASCII letters,
+/// so they end up in the surrounding barewords or strings, and their spans are available in
+/// `Tokens::invalid_utf8()`.
 pub fn lex(contents: &[u8], span_offset: usize) -> (Tokens, Result<(), Spanned<LexError>>) {
     // TODO: We might require the contents to always end with a newline, in which case return an error
     let mut tokens = Tokens::new(contents);
-    let res = lex_internal(contents, span_offset, &mut tokens);
+
+    let res = if std::str::from_utf8(contents).is_ok() {
+        lex_internal(contents, span_offset, &mut tokens)
+    } else {
+        let (valid_contents, invalid_utf8) = replace_invalid_utf8(contents, span_offset);
+        tokens.invalid_utf8 = invalid_utf8;
+        lex_internal(&valid_contents, span_offset, &mut tokens)
+    };
 
     tokens.push(
         Token::Eof,
@@ -279,14 +485,419 @@ pub fn lex(contents: &[u8], span_offset: usize) -> (Tokens, Result<(), Spanned<L
     (tokens, Ok(()))
 }
 
+/// Result of `relex()`
+pub struct Relexed {
+    pub tokens: Tokens,
+    pub result: Result<(), Spanned<LexError>>,
+    /// Indices of the tokens that were lexed again. The tokens before are the same as the previous
+    /// tokens and the tokens after are the previous tokens shifted by the length difference of the
+    /// edit.
+    pub relexed: Range<usize>,
+}
+
+/// Lex the edited source contents, reusing the tokens of the contents before the edit.
+///
+/// Lexing starts from the last newline before the edit and stops at the first newline after the
+/// edit that matches a newline of the previous tokens. Newlines inside string interpolations are
+/// not used as boundaries. `old_tokens` must be the result of a successful `lex()` (or `relex()`)
+/// of the contents before the edit with the same `span_offset`, and the span of `edit` is in the
+/// same offsets as the tokens. If lexing fails or the edit is out of the range of the previous
+/// tokens or does not match the length of the new contents, the whole contents are lexed again.
+pub fn relex(
+    old_tokens: &Tokens,
+    new_contents: &[u8],
+    span_offset: usize,
+    edit: &TextEdit,
+) -> Relexed {
+    let full_lex = || {
+        let (tokens, result) = lex(new_contents, span_offset);
+        let relexed = 0..tokens.tokens.len();
+        Relexed {
+            tokens,
+            result,
+            relexed,
+        }
+    };
+
+    // Range of the edit relative to the contents, an edit that does not fit the previous tokens
+    // and the new contents cannot reuse the tokens
+    let old_len = old_tokens
+        .spans
+        .last()
+        .map_or(0, |eof| eof.end.saturating_sub(span_offset));
+    let (Some(edit_start), Some(old_end)) = (
+        edit.span.start.checked_sub(span_offset),
+        edit.span.end.checked_sub(span_offset),
+    ) else {
+        return full_lex();
+    };
+    if edit_start > old_end
+        || old_end > old_len
+        || old_len - (old_end - edit_start) + edit.replacement.len() != new_contents.len()
+    {
+        return full_lex();
+    }
+    let new_end = edit_start + edit.replacement.len();
+
+    let (valid_contents, invalid_utf8) = if std::str::from_utf8(new_contents).is_ok() {
+        (Cow::Borrowed(new_contents), vec![])
+    } else {
+        let (valid_contents, invalid_utf8) = replace_invalid_utf8(new_contents, span_offset);
+        (Cow::Owned(valid_contents), invalid_utf8)
+    };
+
+    // Newlines outside of string interpolations, the only safe places to start and stop lexing
+    let mut interp_depth = 0usize;
+    let boundaries: Vec<usize> = old_tokens
+        .tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, token)| {
+            match token {
+                Token::DqStringInterpStart | Token::SqStringInterpStart => interp_depth += 1,
+                Token::StrInterpEnd => interp_depth = interp_depth.saturating_sub(1),
+                _ => (),
+            }
+            **token == Token::Newline && interp_depth == 0
+        })
+        .map(|(idx, _)| idx)
+        .collect();
+
+    let safe_boundary = boundaries
+        .iter()
+        .rev()
+        .find(|idx| old_tokens.spans[**idx].end <= edit.span.start);
+
+    let (first_relexed, restart) = match safe_boundary {
+        Some(idx) => (idx + 1, old_tokens.spans[*idx].end - span_offset),
+        None => (0, 0),
+    };
+
+    let mut tokens = Tokens::with_capacity(old_tokens.tokens.len());
+    tokens
+        .tokens
+        .extend_from_slice(&old_tokens.tokens[..first_relexed]);
+    tokens
+        .spans
+        .extend_from_slice(&old_tokens.spans[..first_relexed]);
+    tokens.invalid_utf8 = invalid_utf8;
+
+    let contents = &valid_contents[restart..];
+    let contents_offset = span_offset + restart;
+
+    for (res, span) in spanned_tokens(contents) {
+        let is_newline = res == Ok(Token::Newline);
+        let start = restart + span.start;
+
+        if lex_token(contents, contents_offset, res, span, &mut tokens).is_err() {
+            return full_lex();
+        }
+
+        if !is_newline || start < new_end {
+            continue;
+        }
+
+        // Both lexings are in sync at the same newline, the rest of the tokens is the same
+        let old_start = span_offset + start + old_end - new_end;
+        let Ok(boundary) =
+            boundaries.binary_search_by_key(&old_start, |idx| old_tokens.spans[*idx].start)
+        else {
+            continue;
+        };
+
+        let relexed = first_relexed..tokens.tokens.len();
+        let old_rest = boundaries[boundary] + 1;
+
+        tokens
+            .tokens
+            .extend_from_slice(&old_tokens.tokens[old_rest..]);
+        tokens.spans.extend(
+            old_tokens.spans[old_rest..].iter().map(|span| {
+                Span::new(span.start + new_end - old_end, span.end + new_end - old_end)
+            }),
+        );
+
+        return Relexed {
+            tokens,
+            result: Ok(()),
+            relexed,
+        };
+    }
+
+    let end = new_contents.len() + span_offset;
+    tokens.push(Token::Eof, Span::new(end, end));
+    let relexed = first_relexed..tokens.tokens.len();
+
+    Relexed {
+        tokens,
+        result: Ok(()),
+        relexed,
+    }
+}
+
+/// A token produced by `token_stream()`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LexedToken {
+    pub token: Token,
+    pub span: Span,
+    /// Whitespace skipped before the token (can be empty)
+    pub leading_trivia: Span,
+}
+
+/// Lex the source contents lazily, one token at a time.
+///
+/// This is meant for tools that need only the tokens, e.g., syntax highlighters. Unlike `lex()`,
+/// the skipped whitespace is reported as trivia of the following token and there is no EOF token
+/// at the end. Invalid UTF-8 is handled the same way as in `lex()`. The iterator ends after the
+/// first error.
+pub fn token_stream(contents: &[u8], span_offset: usize) -> TokenStream<'_> {
+    let contents = if std::str::from_utf8(contents).is_ok() {
+        Cow::Borrowed(contents)
+    } else {
+        Cow::Owned(replace_invalid_utf8(contents, span_offset).0)
+    };
+
+    TokenStream {
+        contents,
+        span_offset,
+        pos: 0,
+        last_end: span_offset,
+        pending: Tokens::with_capacity(0),
+        done: false,
+    }
+}
+
+/// Iterator over tokens, see `token_stream()`
+pub struct TokenStream<'a> {
+    contents: Cow<'a, [u8]>,
+    span_offset: usize,
+    /// Position after the last lexed token within contents
+    pos: usize,
+    /// End of the last returned token
+    last_end: usize,
+    /// Tokens lexed from string interpolation that were not returned yet
+    pending: Tokens,
+    done: bool,
+}
+
+impl Iterator for TokenStream<'_> {
+    type Item = Result<LexedToken, Spanned<LexError>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pending.pos >= self.pending.tokens.len() {
+            if self.done {
+                return None;
+            }
+
+            let contents = &self.contents[self.pos..];
+            let span_offset = self.span_offset + self.pos;
+
+            let Some((res, span)) = spanned_tokens(contents).next() else {
+                self.done = true;
+                return None;
+            };
+
+            self.pos += span.end;
+            self.pending = Tokens::with_capacity(1);
+
+            if let Err(e) = lex_token(contents, span_offset, res, span, &mut self.pending) {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+
+        let (token, span) = self.pending.peek();
+        self.pending.advance();
+
+        let leading_trivia = Span::new(self.last_end, span.start);
+        self.last_end = span.end;
+
+        Some(Ok(LexedToken {
+            token,
+            span,
+            leading_trivia,
+        }))
+    }
+}
+
+/// Lex the source contents one top-level statement at a time.
+///
+/// Each item contains the tokens of a single statement followed by an EOF, in the same format as
+/// returned by `lex()`. This keeps the memory used by tokens proportional to the largest statement
+/// rather than to the whole source, which matters for large files, e.g., the history.
+/// `Compiler::check()` parses files this way, see `Parser::with_statements()`. Statements are
+/// separated by newlines or semicolons outside of any parentheses, brackets or braces, unless the
+/// line ends with a pipe. Empty lines and comments are kept with the following statement.
+///
+/// Lexing stops after the first error.
+pub fn lex_statements(contents: &[u8], span_offset: usize) -> StatementTokens<'_> {
+    StatementTokens {
+        contents,
+        lexer: StatementLexer::new(contents, span_offset),
+    }
+}
+
+/// Iterator over the tokens of top-level statements, see `lex_statements()`
+pub struct StatementTokens<'a> {
+    contents: &'a [u8],
+    lexer: StatementLexer,
+}
+
+impl Iterator for StatementTokens<'_> {
+    type Item = (Tokens, Result<(), Spanned<LexError>>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.lexer.next_statement(self.contents)
+    }
+}
+
+/// State of `lex_statements()` without the contents, which are passed to each call instead, e.g.,
+/// by the parser borrowing them from the compiler
+pub struct StatementLexer {
+    span_offset: usize,
+    /// Start of the next statement within contents
+    pos: usize,
+    /// Contents with the invalid UTF-8 sequences replaced, None if the contents are valid
+    valid_contents: Option<Vec<u8>>,
+    /// Spans of invalid UTF-8 sequences in the remaining contents
+    invalid_utf8: Vec<Span>,
+    done: bool,
+}
+
+impl StatementLexer {
+    pub fn new(contents: &[u8], span_offset: usize) -> Self {
+        let (valid_contents, invalid_utf8) = if std::str::from_utf8(contents).is_ok() {
+            (None, vec![])
+        } else {
+            let (valid_contents, invalid_utf8) = replace_invalid_utf8(contents, span_offset);
+            (Some(valid_contents), invalid_utf8)
+        };
+
+        StatementLexer {
+            span_offset,
+            pos: 0,
+            valid_contents,
+            invalid_utf8,
+            done: false,
+        }
+    }
+
+    /// Spans of the invalid UTF-8 sequences not returned with the tokens yet
+    pub fn invalid_utf8(&self) -> &[Span] {
+        &self.invalid_utf8
+    }
+
+    /// Lex the next statement, `contents` must be the same as the ones passed to `new()`
+    pub fn next_statement(
+        &mut self,
+        contents: &[u8],
+    ) -> Option<(Tokens, Result<(), Spanned<LexError>>)> {
+        let contents = self.valid_contents.as_deref().unwrap_or(contents);
+        if self.done || self.pos >= contents.len() {
+            return None;
+        }
+
+        let contents = &contents[self.pos..];
+        let span_offset = self.span_offset + self.pos;
+        let mut tokens = Tokens::with_capacity(0);
+        let mut res = Ok(());
+
+        let mut end = contents.len();
+        let mut depth = 0usize;
+        let mut last_token = None;
+
+        for (token, span) in spanned_tokens(contents) {
+            if let Err(e) = lex_token(contents, span_offset, token, span.clone(), &mut tokens) {
+                res = Err(e);
+                self.done = true;
+                break;
+            }
+
+            let Ok(token) = token else {
+                continue;
+            };
+
+            match token {
+                Token::LParen | Token::LSquare | Token::LCurly => depth += 1,
+                Token::RParen | Token::RSquare | Token::RCurly => depth = depth.saturating_sub(1),
+                Token::Newline | Token::Semicolon
+                    if depth == 0 && last_token.is_some() && last_token != Some(Token::Pipe) =>
+                {
+                    end = span.end;
+                    break;
+                }
+                _ => (),
+            }
+
+            if !matches!(token, Token::Newline | Token::Comment) {
+                last_token = Some(token);
+            }
+        }
+
+        let end_offset = span_offset + end;
+        tokens.push(Token::Eof, Span::new(end_offset, end_offset));
+
+        let num_invalid = self
+            .invalid_utf8
+            .iter()
+            .take_while(|span| span.start < end_offset)
+            .count();
+        tokens.invalid_utf8 = self.invalid_utf8.drain(..num_invalid).collect();
+
+        self.pos += end;
+        Some((tokens, res))
+    }
+}
+
+/// Replace each byte of invalid UTF-8 sequences with an ASCII placeholder, keeping the byte offsets
+/// intact, and return the spans of the replaced sequences
+fn replace_invalid_utf8(contents: &[u8], span_offset: usize) -> (Vec<u8>, Vec<Span>) {
+    let mut valid_contents = Vec::with_capacity(contents.len());
+    let mut invalid_utf8 = vec![];
+
+    for chunk in contents.utf8_chunks() {
+        valid_contents.extend_from_slice(chunk.valid().as_bytes());
+
+        let invalid = chunk.invalid();
+        if !invalid.is_empty() {
+            let start = valid_contents.len() + span_offset;
+            let end = start + invalid.len();
+
+            // consecutive invalid bytes are reported as a single sequence
+            match invalid_utf8.last_mut() {
+                Some(Span { end: last_end, .. }) if *last_end == start => *last_end = end,
+                _ => invalid_utf8.push(Span::new(start, end)),
+            }
+            valid_contents.resize(valid_contents.len() + invalid.len(), b'x');
+        }
+    }
+
+    (valid_contents, invalid_utf8)
+}
+
 #[derive(Logos, Debug, Clone, Copy, PartialEq)]
 #[logos(skip r"[ \t]+")]
 #[logos(source = [u8], error = LexError)]
 pub enum Token {
-    #[regex("(0[xob])?[0-9][0-9_]*", priority = 10)]
+    #[regex("[0-9][0-9_]*|0x[0-9a-fA-F_]+|0o[0-7_]+|0b[01_]+", priority = 10)]
     Int,
+    /// `0x[ff 00]`, `0o[777]` or `0b[1010]`
+    #[regex(r"0x\[[0-9a-fA-F \t\r\n]*\]|0o\[[0-7 \t\r\n]*\]|0b\[[01 \t\r\n]*\]")]
+    Binary,
     #[regex(r"([0-9][0-9_]*)*\.([0-9][0-9_]*)*([eE][+-]?[0-9_]+)?")]
     Float,
+    /// Number of bytes, e.g., `10kb` or `1.5GiB`
+    #[regex(
+        r"[0-9][0-9_]*(\.[0-9][0-9_]*)?(?i:b|kb|mb|gb|tb|pb|eb|kib|mib|gib|tib|pib|eib)",
+        priority = 10
+    )]
+    Filesize,
+    /// Length of time, e.g., `500ms` or `1.5day`
+    #[regex(
+        r"[0-9][0-9_]*(\.[0-9][0-9_]*)?(ns|us|µs|ms|sec|min|hr|day|wk)",
+        priority = 10
+    )]
+    Duration,
     #[regex("\n|\r\n|\x0C")]
     Newline,
     #[regex(r#""([^"\\]|\\["\\bnfrt])*""#)]
@@ -295,6 +906,9 @@ pub enum Token {
     SingleQuotedString,
     #[regex(r#"`[^`]*`"#)]
     BacktickBareword,
+    /// Raw string `r#'...'#`, closed by a quote followed by as many `#` as the opening one has
+    #[regex(r"r#+'", raw_string)]
+    RawString,
     // #[regex(r#"[ \t]+"#)]
     // HorizontalWhitespace,
     #[regex(r#"[0-9]{4}-[0-9]{2}-[0-9]{2}(T[0-9]{2}:[0-9]{2}:[0-9]{2}(\.[0-9]+)?)?(Z|[\+-][0-9]{2}:[0-9]{2})?"#)]
@@ -388,27 +1002,45 @@ pub enum Token {
     QuestionMark,
     #[token("^")]
     Caret,
+    /// `~` of a home directory path, e.g., `~/.config`
+    #[token("~")]
+    Tilde,
     #[token("@")]
     At,
     #[token("||")]
     PipePipe,
     #[token("|")]
     Pipe,
+    // Redirections, the streams can be written in short or long form and in any order
     #[token("o>")]
+    #[token("out>")]
     OutGreaterThan,
     #[token("o>>")]
+    #[token("out>>")]
     OutGreaterGreaterThan,
     #[token("e>")]
+    #[token("err>")]
     ErrGreaterThan,
     #[token("e>>")]
+    #[token("err>>")]
     ErrGreaterGreaterThan,
     #[token("o+e>")]
+    #[token("e+o>")]
+    #[token("out+err>")]
+    #[token("err+out>")]
     OutErrGreaterThan,
     #[token("o+e>>")]
+    #[token("e+o>>")]
+    #[token("out+err>>")]
+    #[token("err+out>>")]
     OutErrGreaterGreaterThan,
     #[token("e>|")]
+    #[token("err>|")]
     ErrGreaterThanPipe,
     #[token("o+e>|")]
+    #[token("e+o>|")]
+    #[token("out+err>|")]
+    #[token("err+out>|")]
     OutErrGreaterThanPipe,
     /// Double quoted string interpolation $"..."
     ///
@@ -439,6 +1071,26 @@ pub enum Token {
     Eof,
 }
 
+fn raw_string(lexer: &mut Lexer<Token>) -> Result<(), LexError> {
+    // the opening `r`, the hashes and the quote
+    let num_hashes = lexer.slice().len() - 2;
+    let remainder = lexer.remainder();
+
+    let end = (0..remainder.len())
+        .find(|&pos| {
+            remainder[pos] == b'\''
+                && remainder[pos + 1..]
+                    .iter()
+                    .take_while(|c| **c == b'#')
+                    .count()
+                    >= num_hashes
+        })
+        .ok_or(LexError::UnterminatedRawString)?;
+
+    lexer.bump(end + 1 + num_hashes);
+    Ok(())
+}
+
 fn match_subexpression<'a, T: Logos<'a>>(
     remainder: &[u8],
     lexer: &mut Lexer<'a, T>,
@@ -506,7 +1158,8 @@ enum SqStrInterpToken {
 mod test {
     /// Lexer tests useful for smaller sources, errors and corner cases
     use crate::compiler::{Span, Spanned};
-    use crate::lexer::{lex, Token};
+    use crate::errors::TextEdit;
+    use crate::lexer::{lex, lex_statements, relex, token_stream, LexedToken, Token, Tokens};
 
     use super::LexError;
 
@@ -537,11 +1190,34 @@ mod test {
 
     #[test]
     fn lex_unmatched_string() {
-        // TODO: Make unmatched delimiters nicer
         test_lex(
             b"'unmatched string",
             &[(Token::Eof, span(17, 17))],
-            Err(Spanned::new(LexError::Generic, Span::new(0, 17))),
+            Err(Spanned::new(LexError::UnterminatedString, Span::new(0, 1))),
+        );
+
+        test_lex(
+            br#"x "escaped \" quote"#,
+            &[(Token::Bareword, span(0, 1)), (Token::Eof, span(19, 19))],
+            Err(Spanned::new(LexError::UnterminatedString, Span::new(2, 3))),
+        );
+
+        test_lex(
+            br#"$"interpolation (1)"#,
+            &[(Token::Eof, span(19, 19))],
+            Err(Spanned::new(LexError::UnterminatedString, Span::new(0, 2))),
+        );
+    }
+
+    #[test]
+    fn lex_unterminated_raw_string() {
+        test_lex(
+            b"r##'raw'# string",
+            &[(Token::Eof, span(16, 16))],
+            Err(Spanned::new(
+                LexError::UnterminatedRawString,
+                Span::new(0, 4),
+            )),
         );
     }
 
@@ -573,4 +1249,217 @@ mod test {
             )),
         );
     }
+
+    /// All tokens except EOF
+    fn collect_tokens(mut tokens: Tokens) -> Vec<(Token, Span)> {
+        let mut result = vec![];
+        while tokens.peek_token() != Token::Eof {
+            result.push(tokens.peek());
+            tokens.advance();
+        }
+        result
+    }
+
+    #[test]
+    fn lex_statements_split() {
+        let src = b"let x = {\n  1\n}\n\n# comment\nls |\n  get name; $x";
+        let statements: Vec<_> = lex_statements(src, 10)
+            .map(|(tokens, res)| {
+                assert_eq!(res, Ok(()));
+                let (_, first) = tokens.peek();
+                let last = collect_tokens(tokens).last().expect("empty statement").1;
+                String::from_utf8_lossy(&src[first.start - 10..last.end - 10]).to_string()
+            })
+            .collect();
+
+        assert_eq!(
+            statements,
+            vec![
+                "let x = {\n  1\n}\n",
+                "\n# comment\nls |\n  get name;",
+                "$x"
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_statements_same_as_lex() {
+        for path in std::fs::read_dir("tests").expect("missing tests") {
+            let path = path.expect("failed to read tests").path();
+            if path.extension().map_or(true, |ext| ext != "nu") {
+                continue;
+            }
+            let src = std::fs::read(&path).expect("failed to read test");
+
+            let (all_tokens, res) = lex(&src, 0);
+            if res.is_err() {
+                continue;
+            }
+
+            let statement_tokens: Vec<_> = lex_statements(&src, 0)
+                .flat_map(|(tokens, res)| {
+                    assert_eq!(res, Ok(()));
+                    collect_tokens(tokens)
+                })
+                .collect();
+
+            assert_eq!(
+                collect_tokens(all_tokens),
+                statement_tokens,
+                "{}",
+                path.display()
+            );
+        }
+    }
+
+    #[test]
+    fn token_stream_trivia() {
+        let src = b"ls  | get $\"(1)\"";
+        let tokens: Vec<_> = token_stream(src, 5)
+            .map(|token| token.expect("lexing error"))
+            .collect();
+
+        let token = |token, start, end, trivia_start, trivia_end| LexedToken {
+            token,
+            span: span(start, end),
+            leading_trivia: span(trivia_start, trivia_end),
+        };
+
+        assert_eq!(
+            tokens,
+            vec![
+                token(Token::Bareword, 5, 7, 5, 5),
+                token(Token::Pipe, 9, 10, 7, 9),
+                token(Token::Bareword, 11, 14, 10, 11),
+                token(Token::DqStringInterpStart, 15, 17, 14, 15),
+                token(Token::StrInterpLParen, 17, 18, 17, 17),
+                token(Token::Int, 18, 19, 18, 18),
+                token(Token::StrInterpRParen, 19, 20, 19, 19),
+                token(Token::StrInterpEnd, 20, 21, 20, 20),
+            ]
+        );
+    }
+
+    #[test]
+    fn token_stream_same_as_lex() {
+        for path in std::fs::read_dir("tests/lex").expect("missing tests") {
+            let src = std::fs::read(path.expect("failed to read tests").path())
+                .expect("failed to read test");
+
+            let (all_tokens, res) = lex(&src, 0);
+            let stream: Vec<_> = token_stream(&src, 0).collect();
+
+            let stream_tokens: Vec<_> = stream
+                .iter()
+                .filter_map(|token| token.as_ref().ok())
+                .map(|token| (token.token, token.span))
+                .collect();
+            assert_eq!(collect_tokens(all_tokens), stream_tokens);
+            assert_eq!(
+                res.err().as_ref(),
+                stream.last().and_then(|token| token.as_ref().err())
+            );
+        }
+    }
+
+    /// Replace `old_end - start` bytes at `start` with `replacement`, the edit's span starts at
+    /// `span_offset`
+    fn edit(
+        src: &[u8],
+        span_offset: usize,
+        start: usize,
+        old_end: usize,
+        replacement: &str,
+    ) -> (Vec<u8>, TextEdit) {
+        let new_src = [&src[..start], replacement.as_bytes(), &src[old_end..]].concat();
+        let span = Span::new(span_offset + start, span_offset + old_end);
+        (new_src, TextEdit::replace(span, replacement))
+    }
+
+    #[test]
+    fn relex_reuses_tokens() {
+        let src = b"let x = 1\nlet y = 2\nlet z = $\"(\n$x)\"\n";
+        let (tokens, res) = lex(src, 3);
+        assert_eq!(res, Ok(()));
+
+        // changing `y` to `long_name` relexes only the second line
+        let (new_src, text_edit) = edit(src, 3, 14, 15, "long_name");
+        let relexed = relex(&tokens, &new_src, 3, &text_edit);
+        assert_eq!(relexed.result, Ok(()));
+        assert_eq!(relexed.relexed, 5..10);
+
+        let (expected, _) = lex(&new_src, 3);
+        assert_eq!(collect_tokens(relexed.tokens), collect_tokens(expected));
+    }
+
+    #[test]
+    fn relex_out_of_range_edits() {
+        let src = b"let x = 1\nlet y = 2\n";
+        let (tokens, res) = lex(src, 3);
+        assert_eq!(res, Ok(()));
+        let (new_src, _) = edit(src, 3, 14, 15, "long_name");
+        let (expected, _) = lex(&new_src, 3);
+
+        let edits = [
+            // before the span offset
+            TextEdit::replace(Span::new(1, 2), "long_name"),
+            // past the end of the previous contents
+            TextEdit::replace(Span::new(17, 100), "long_name"),
+            // reversed span
+            TextEdit::replace(Span::new(18, 17), "long_name"),
+            // not the edit that produced the new contents
+            TextEdit::replace(Span::new(17, 18), "y"),
+        ];
+        for text_edit in edits {
+            let relexed = relex(&tokens, &new_src, 3, &text_edit);
+            assert_eq!(relexed.result, Ok(()));
+            assert_eq!(relexed.relexed, 0..expected.tokens.len(), "{text_edit:?}");
+            assert_eq!(
+                collect_tokens(relexed.tokens),
+                collect_tokens(lex(&new_src, 3).0)
+            );
+        }
+    }
+
+    #[test]
+    fn relex_same_as_lex() {
+        let replacements = ["", "\n", "\"", "(", "foo bar", "$\"(1)\"", "# x\n"];
+
+        for path in std::fs::read_dir("tests").expect("missing tests") {
+            let path = path.expect("failed to read tests").path();
+            if path.extension().map_or(true, |ext| ext != "nu") {
+                continue;
+            }
+            let src = std::fs::read(&path).expect("failed to read test");
+
+            let (tokens, res) = lex(&src, 0);
+            if res.is_err() {
+                continue;
+            }
+
+            for start in 0..src.len() {
+                for old_end in [
+                    start,
+                    (start + 1).min(src.len()),
+                    (start + 5).min(src.len()),
+                ] {
+                    for replacement in replacements {
+                        let (new_src, text_edit) = edit(&src, 0, start, old_end, replacement);
+                        let relexed = relex(&tokens, &new_src, 0, &text_edit);
+                        let (expected, expected_res) = lex(&new_src, 0);
+
+                        assert_eq!(relexed.result, expected_res, "{}", path.display());
+                        if expected_res.is_ok() {
+                            assert_eq!(
+                                collect_tokens(relexed.tokens),
+                                collect_tokens(expected),
+                                "{}: {text_edit:?}",
+                                path.display()
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
 }
@@ -1,9 +1,40 @@
+pub mod analysis;
+pub mod ast_converter;
+pub mod ast_diff;
+#[cfg(any(feature = "wasm", feature = "ffi", feature = "python"))]
+mod bindings;
+pub mod cache;
 pub mod compiler;
+pub mod config;
+pub mod engine;
 pub mod errors;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod files;
+pub mod format;
+#[cfg(feature = "reedline")]
+pub mod highlighter;
+pub mod incremental;
+pub mod ir_emitter;
+pub mod ir_generator;
+pub mod ir_optimizer;
+pub mod ir_validator;
 pub mod lexer;
+pub mod line_index;
+pub mod lint;
+#[cfg(feature = "lsp")]
+pub mod lsp;
 pub mod parser;
+#[cfg(test)]
+mod proptests;
 pub mod protocol;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod render;
 pub mod resolver;
+pub mod semantic_tokens;
 #[cfg(test)]
 mod test;
 pub mod typechecker;
+#[cfg(feature = "wasm")]
+pub mod wasm;
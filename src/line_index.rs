@@ -0,0 +1,76 @@
+//! Mapping between byte offsets and lines of a file
+
+/// Starts of the lines of a file, for finding the line of a byte offset in O(log n)
+///
+/// Built once when the file is added to the compiler. The offsets are relative to the start of
+/// the file and a newline belongs to the line it ends.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LineIndex {
+    /// Offset of the first byte of each line, the first line starts at 0
+    line_starts: Vec<usize>,
+    len: usize,
+}
+
+impl LineIndex {
+    pub fn new(contents: &[u8]) -> Self {
+        let newlines = contents
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| **c == b'\n')
+            .map(|(idx, _)| idx + 1);
+
+        Self {
+            line_starts: std::iter::once(0).chain(newlines).collect(),
+            len: contents.len(),
+        }
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// 0-based line containing the offset, offsets past the end are on the last line
+    pub fn line(&self, offset: usize) -> usize {
+        self.line_starts.partition_point(|start| *start <= offset) - 1
+    }
+
+    /// Start and end of a 0-based line, the end is before the line's newline
+    pub fn line_range(&self, line: usize) -> Option<(usize, usize)> {
+        let start = *self.line_starts.get(line)?;
+        let end = self
+            .line_starts
+            .get(line + 1)
+            .map_or(self.len, |next_start| next_start - 1);
+
+        Some((start, end))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LineIndex;
+
+    #[test]
+    fn lines_of_offsets() {
+        let index = LineIndex::new(b"ab\n\ncd\n");
+        assert_eq!(index.line_count(), 4);
+
+        let lines: Vec<_> = (0..=7).map(|offset| index.line(offset)).collect();
+        assert_eq!(lines, [0, 0, 0, 1, 2, 2, 2, 3]);
+        assert_eq!(index.line(100), 3);
+
+        assert_eq!(index.line_range(0), Some((0, 2)));
+        assert_eq!(index.line_range(1), Some((3, 3)));
+        assert_eq!(index.line_range(2), Some((4, 6)));
+        assert_eq!(index.line_range(3), Some((7, 7)));
+        assert_eq!(index.line_range(4), None);
+    }
+
+    #[test]
+    fn empty_file() {
+        let index = LineIndex::new(b"");
+        assert_eq!(index.line_count(), 1);
+        assert_eq!(index.line(0), 0);
+        assert_eq!(index.line_range(0), Some((0, 0)));
+    }
+}
@@ -0,0 +1,172 @@
+//! Lints for code that compiles but is likely a mistake
+//!
+//! Each lint has a level (allow, warn or deny) which can be changed with
+//! `CompilerOptions::lint_levels` or, for a part of the source, with a comment:
+//!
+//! ```nu
+//! # nu-lint: allow(unused_variable, shadowing)
+//! let x = 1
+//! ```
+//!
+//! A lint comment applies to its own line and the line after it.
+
+use crate::compiler::Span;
+use crate::errors::ErrorCode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Lint {
+    /// A `let`/`mut` or `for` variable is never used
+    UnusedVariable,
+    /// A variable hides another variable with the same name
+    Shadowing,
+    /// Syntax that has been replaced by a newer one, e.g., `def-env`
+    DeprecatedSyntax,
+    /// A parameter without a type annotation, which makes it `any`
+    ImplicitAny,
+    /// An assignment to the whole `$env.config` in a configuration file, dropping the defaults
+    ConfigOverwrite,
+}
+
+impl Lint {
+    /// All lints, a new variant must be added here as well
+    pub const ALL: &'static [Lint] = &[
+        Lint::UnusedVariable,
+        Lint::Shadowing,
+        Lint::DeprecatedSyntax,
+        Lint::ImplicitAny,
+        Lint::ConfigOverwrite,
+    ];
+
+    /// Name of the lint used in the options and lint comments
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::UnusedVariable => "unused_variable",
+            Lint::Shadowing => "shadowing",
+            Lint::DeprecatedSyntax => "deprecated_syntax",
+            Lint::ImplicitAny => "implicit_any",
+            Lint::ConfigOverwrite => "config_overwrite",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Lint> {
+        Self::ALL.iter().copied().find(|lint| lint.name() == name)
+    }
+
+    /// Level of the lint unless configured otherwise
+    ///
+    /// Shadowing and untyped parameters are idiomatic in a lot of Nushell code, so these lints
+    /// must be enabled explicitly.
+    pub fn default_level(&self) -> LintLevel {
+        match self {
+            Lint::UnusedVariable | Lint::DeprecatedSyntax | Lint::ConfigOverwrite => {
+                LintLevel::Warn
+            }
+            Lint::Shadowing | Lint::ImplicitAny => LintLevel::Allow,
+        }
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Lint::UnusedVariable => ErrorCode::LintUnusedVariable,
+            Lint::Shadowing => ErrorCode::LintShadowing,
+            Lint::DeprecatedSyntax => ErrorCode::LintDeprecatedSyntax,
+            Lint::ImplicitAny => ErrorCode::LintImplicitAny,
+            Lint::ConfigOverwrite => ErrorCode::LintConfigOverwrite,
+        }
+    }
+}
+
+impl std::fmt::Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LintLevel {
+    /// The lint is not reported
+    Allow,
+    /// The lint is reported as a warning
+    Warn,
+    /// The lint is reported as an error
+    Deny,
+}
+
+impl LintLevel {
+    pub fn from_name(name: &str) -> Option<LintLevel> {
+        match name {
+            "allow" => Some(LintLevel::Allow),
+            "warn" => Some(LintLevel::Warn),
+            "deny" => Some(LintLevel::Deny),
+            _ => None,
+        }
+    }
+}
+
+/// Level of a lint set by a lint comment for a part of the source
+#[derive(Debug, Clone, PartialEq)]
+pub struct LintDirective {
+    pub lint: Lint,
+    pub level: LintLevel,
+    /// The lines the comment applies to
+    pub span: Span,
+}
+
+/// Parse a comment of the form `# nu-lint: <level>(<lint>, ...)`
+///
+/// Returns None if the comment is not a lint comment. Unknown lint names are skipped.
+pub fn parse_lint_comment(comment: &[u8]) -> Option<(LintLevel, Vec<Lint>)> {
+    let comment = std::str::from_utf8(comment).ok()?;
+    let rest = comment
+        .strip_prefix('#')?
+        .trim_start()
+        .strip_prefix("nu-lint:")?
+        .trim();
+
+    let (level, rest) = rest.split_once('(')?;
+    let level = LintLevel::from_name(level.trim())?;
+    let names = rest.strip_suffix(')')?;
+
+    let lints = names
+        .split(',')
+        .filter_map(|name| Lint::from_name(name.trim()))
+        .collect();
+
+    Some((level, lints))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_lint_comment, Lint, LintLevel};
+
+    #[test]
+    fn lint_comments() {
+        assert_eq!(
+            parse_lint_comment(b"# nu-lint: allow(unused_variable, shadowing)"),
+            Some((
+                LintLevel::Allow,
+                vec![Lint::UnusedVariable, Lint::Shadowing]
+            ))
+        );
+        assert_eq!(
+            parse_lint_comment(b"#nu-lint: deny( implicit_any )"),
+            Some((LintLevel::Deny, vec![Lint::ImplicitAny]))
+        );
+        assert_eq!(
+            parse_lint_comment(b"# nu-lint: warn(no_such_lint)"),
+            Some((LintLevel::Warn, vec![]))
+        );
+        assert_eq!(parse_lint_comment(b"# allow(shadowing)"), None);
+        assert_eq!(parse_lint_comment(b"# nu-lint: ignore(shadowing)"), None);
+        assert_eq!(parse_lint_comment(b"# nu-lint: allow(shadowing"), None);
+    }
+
+    #[test]
+    fn lint_names() {
+        for lint in Lint::ALL {
+            assert_eq!(Lint::from_name(lint.name()), Some(*lint));
+        }
+    }
+}
@@ -0,0 +1,493 @@
+//! Conversion of diagnostics and language features to the Language Server Protocol
+//!
+//! LSP positions are 0-based lines and columns counted in UTF-16 code units, unlike the byte
+//! offsets used by the compiler. The language features (go to definition, hover, completion)
+//! take byte offsets, use `to_offset()` to convert the position of a request.
+use std::collections::{BTreeMap, HashMap};
+
+use lsp_types::{
+    CodeAction, CodeActionKind, CompletionItem, CompletionItemKind, Diagnostic,
+    DiagnosticRelatedInformation, DiagnosticSeverity, Hover, HoverContents, Location,
+    MarkupContent, MarkupKind, NumberOrString, Position, Range, SemanticToken, SemanticTokenType,
+    SemanticTokens, SemanticTokensLegend, TextEdit, Uri, WorkspaceEdit,
+};
+
+use crate::compiler::{Compiler, Span};
+use crate::errors::{Severity, SourceError};
+use crate::parser::{AstNode, NodeId};
+use crate::semantic_tokens::{semantic_tokens, SemanticTokenKind};
+use crate::typechecker::Types;
+
+impl From<Severity> for DiagnosticSeverity {
+    fn from(severity: Severity) -> Self {
+        match severity {
+            Severity::Error => DiagnosticSeverity::ERROR,
+            Severity::Warning => DiagnosticSeverity::WARNING,
+            Severity::Note => DiagnosticSeverity::INFORMATION,
+        }
+    }
+}
+
+/// Position of a byte offset within the file containing it
+pub fn to_position(compiler: &Compiler, offset: usize) -> Position {
+    let (line, line_start) = compiler.line_of(offset);
+    let character = String::from_utf8_lossy(&compiler.source[line_start..offset])
+        .encode_utf16()
+        .count();
+
+    Position::new(line as u32 - 1, character as u32)
+}
+
+/// Byte offset of a position in the file `fname`
+///
+/// Positions past the end of a line are moved to the end of the line. Returns None if there is
+/// no such file or line.
+pub fn to_offset(compiler: &Compiler, fname: &str, position: Position) -> Option<usize> {
+    let (line_start, line_end) = compiler.line_range(fname, position.line as usize + 1)?;
+    let line = std::str::from_utf8(&compiler.source[line_start..line_end]).ok()?;
+
+    let mut units = 0;
+    let column = line
+        .char_indices()
+        .find(|(_, c)| {
+            let found = units >= position.character as usize;
+            units += c.len_utf16();
+            found
+        })
+        .map_or(line.len(), |(idx, _)| idx);
+
+    Some(line_start + column)
+}
+
+pub fn to_range(compiler: &Compiler, span: Span) -> Range {
+    Range::new(
+        to_position(compiler, span.start),
+        to_position(compiler, span.end),
+    )
+}
+
+/// Convert the error to an LSP diagnostic of the document at `uri`
+///
+/// The error is expected to be in the document, related spans in other files are left out.
+pub fn to_diagnostic(compiler: &Compiler, error: &SourceError, uri: &Uri) -> Diagnostic {
+    let span = compiler.get_span(error.node_id);
+    let file = compiler.file_of(span);
+
+    let related_information: Vec<_> = error
+        .related
+        .iter()
+        .filter(|related| compiler.file_of(related.span) == file)
+        .map(|related| DiagnosticRelatedInformation {
+            location: Location::new(uri.clone(), to_range(compiler, related.span)),
+            message: related.message.clone(),
+        })
+        .collect();
+
+    let mut message = error.message(compiler).into_owned();
+    if let Some(help) = &error.help {
+        message.push_str(&format!("\nhelp: {help}"));
+    }
+    if let Some(note) = &error.note {
+        message.push_str(&format!("\nnote: {note}"));
+    }
+
+    Diagnostic {
+        range: to_range(compiler, span),
+        severity: Some(error.severity.into()),
+        code: Some(NumberOrString::String(error.code.as_str().into())),
+        source: Some("nu".into()),
+        message,
+        related_information: (!related_information.is_empty()).then_some(related_information),
+        ..Default::default()
+    }
+}
+
+/// Convert the fixes of the error to quick fix code actions for the document at `uri`
+pub fn to_code_actions(compiler: &Compiler, error: &SourceError, uri: &Uri) -> Vec<CodeAction> {
+    error
+        .fixes
+        .iter()
+        .map(|fix| {
+            let edits = fix
+                .edits
+                .iter()
+                .map(|edit| TextEdit::new(to_range(compiler, edit.span), edit.replacement.clone()))
+                .collect();
+
+            CodeAction {
+                title: fix.message.clone(),
+                kind: Some(CodeActionKind::QUICKFIX),
+                diagnostics: Some(vec![to_diagnostic(compiler, error, uri)]),
+                edit: Some(WorkspaceEdit::new(HashMap::from([(uri.clone(), edits)]))),
+                // the error itself says what the fix should be
+                is_preferred: Some(error.fixes.len() == 1),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+/// The innermost node at the offset, including the offset right after a node, e.g., the cursor
+/// at the end of a variable
+pub fn node_at(compiler: &Compiler, offset: usize) -> Option<NodeId> {
+    compiler
+        .spans
+        .iter()
+        .enumerate()
+        .filter(|(_, span)| span.start <= offset && offset <= span.end)
+        .min_by_key(|(_, span)| span.end - span.start)
+        .map(|(idx, _)| NodeId(idx))
+}
+
+/// Node defining the variable or the command referred to by the node
+///
+/// Variables and commands coming from the engine have no definition in the source.
+pub fn definition(compiler: &Compiler, node_id: NodeId) -> Option<NodeId> {
+    if let Some(var_id) = compiler.var_resolution.get(&node_id) {
+        return compiler
+            .scope
+            .iter()
+            .flat_map(|frame| frame.variables.values())
+            .find(|definition| compiler.var_resolution.get(definition) == Some(var_id))
+            .copied();
+    }
+
+    // Commands are resolved on the whole call, not just on its name
+    let call = compiler
+        .ast_nodes
+        .iter()
+        .position(|ast_node| matches!(ast_node, AstNode::Call { parts } if parts.first() == Some(&node_id)))
+        .map_or(node_id, NodeId);
+
+    let decl_id = compiler.decl_resolution.get(&call)?;
+    compiler
+        .scope
+        .iter()
+        .flat_map(|frame| frame.decls.values())
+        .find(|definition| compiler.decl_resolution.get(definition) == Some(decl_id))
+        .copied()
+}
+
+/// Location of the definition of the variable or command at the offset of the document at `uri`
+pub fn goto_definition(compiler: &Compiler, offset: usize, uri: &Uri) -> Option<Location> {
+    let definition = definition(compiler, node_at(compiler, offset)?)?;
+    Some(Location::new(
+        uri.clone(),
+        to_range(compiler, compiler.get_span(definition)),
+    ))
+}
+
+/// Type of the innermost node at the offset, along with the name of a variable
+pub fn hover(compiler: &Compiler, types: &Types, offset: usize) -> Option<Hover> {
+    let node_id = node_at(compiler, offset)?;
+    let ty = types.type_to_string(*types.node_types.get(node_id.0)?);
+
+    let value = match compiler.get_node(node_id) {
+        AstNode::Variable => format!(
+            "{}: {ty}",
+            String::from_utf8_lossy(compiler.get_span_contents(node_id))
+        ),
+        _ => ty,
+    };
+
+    Some(Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: format!("```nu\n{value}\n```"),
+        }),
+        range: Some(to_range(compiler, compiler.get_span(node_id))),
+    })
+}
+
+/// Variables and commands visible at the offset, sorted by name
+///
+/// Variables are only visible after their definition, commands in the whole scope frame.
+pub fn completions(compiler: &Compiler, offset: usize) -> Vec<CompletionItem> {
+    let mut items = BTreeMap::new();
+
+    let frames = compiler.scope.iter().filter(|frame| {
+        let span = compiler.get_span(frame.node_id);
+        span.start <= offset && offset <= span.end
+    });
+
+    for frame in frames {
+        for (name, definition) in &frame.variables {
+            if compiler.get_span(*definition).end <= offset {
+                let label = format!("${}", String::from_utf8_lossy(name));
+                items.insert(label, CompletionItemKind::VARIABLE);
+            }
+        }
+
+        for name in frame.decls.keys() {
+            items.insert(
+                String::from_utf8_lossy(name).to_string(),
+                CompletionItemKind::FUNCTION,
+            );
+        }
+    }
+
+    items
+        .into_iter()
+        .map(|(label, kind)| CompletionItem {
+            label,
+            kind: Some(kind),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Semantic token types in the order used by `to_semantic_tokens()`
+pub fn semantic_tokens_legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: SemanticTokenKind::ALL
+            .iter()
+            .map(|kind| SemanticTokenType::new(kind.as_str()))
+            .collect(),
+        token_modifiers: vec![],
+    }
+}
+
+/// Semantic tokens of the parsed source, encoded relative to each other as LSP requires
+///
+/// Tokens spanning multiple lines are cut at the end of their first line.
+pub fn to_semantic_tokens(compiler: &Compiler) -> SemanticTokens {
+    let mut data = vec![];
+    let mut previous = Position::new(0, 0);
+
+    for token in semantic_tokens(compiler) {
+        let contents = String::from_utf8_lossy(
+            compiler.get_span_contents_manual(token.span.start, token.span.end),
+        );
+        let length = contents
+            .split('\n')
+            .next()
+            .unwrap_or_default()
+            .encode_utf16()
+            .count();
+
+        let start = to_position(compiler, token.span.start);
+        let delta_line = start.line - previous.line;
+        let delta_start = if delta_line == 0 {
+            start.character - previous.character
+        } else {
+            start.character
+        };
+
+        data.push(SemanticToken {
+            delta_line,
+            delta_start,
+            length: length as u32,
+            token_type: SemanticTokenKind::ALL
+                .iter()
+                .position(|kind| *kind == token.kind)
+                .unwrap_or_default() as u32,
+            token_modifiers_bitset: 0,
+        });
+        previous = start;
+    }
+
+    SemanticTokens {
+        result_id: None,
+        data,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use lsp_types::{
+        DiagnosticSeverity, HoverContents, MarkupContent, MarkupKind, NumberOrString, Position,
+        Range, Uri,
+    };
+
+    use super::{
+        completions, goto_definition, hover, semantic_tokens_legend, to_code_actions,
+        to_diagnostic, to_offset, to_position, to_semantic_tokens,
+    };
+    use crate::compiler::Compiler;
+    use crate::errors::ErrorCode;
+    use crate::lexer::lex;
+    use crate::parser::Parser;
+    use crate::resolver::Resolver;
+    use crate::typechecker::{Typechecker, Types};
+
+    fn compile(src: &str) -> (Compiler, Types) {
+        let mut compiler = Compiler::new();
+        compiler.add_file("test.nu", src.as_bytes());
+
+        let (tokens, err) = lex(src.as_bytes(), 0);
+        assert!(err.is_ok());
+        let mut compiler = Parser::new(compiler, tokens).parse();
+
+        let mut resolver = Resolver::new(&compiler);
+        resolver.resolve();
+        compiler.merge_name_bindings(resolver.to_name_bindings());
+
+        let mut typechecker = Typechecker::new(&compiler);
+        typechecker.typecheck();
+        let types = typechecker.to_types();
+
+        (compiler, types)
+    }
+
+    #[test]
+    fn utf16_positions() {
+        let mut compiler = Compiler::new();
+        compiler.add_file("first.nu", b"1\n");
+        compiler.add_file("second.nu", "'😀' + 1\n'é'".as_bytes());
+
+        assert_eq!(to_position(&compiler, 0), Position::new(0, 0));
+        // the emoji is 4 bytes in UTF-8 and 2 code units in UTF-16
+        assert_eq!(to_position(&compiler, 2 + 6), Position::new(0, 4));
+        assert_eq!(to_position(&compiler, 2 + 11), Position::new(1, 0));
+        assert_eq!(to_position(&compiler, 2 + 14), Position::new(1, 2));
+    }
+
+    #[test]
+    fn unclosed_delimiter() {
+        let src = "let x = 1\nlet ä = ($x + 1";
+        let mut compiler = Compiler::new();
+        compiler.add_file("test.nu", src.as_bytes());
+
+        let (tokens, err) = lex(src.as_bytes(), 0);
+        assert!(err.is_ok());
+        let compiler = Parser::new(compiler, tokens).parse();
+
+        let error = compiler
+            .errors
+            .iter()
+            .find(|error| error.code == ErrorCode::UnclosedDelimiter)
+            .expect("missing unclosed delimiter error");
+
+        let uri = Uri::from_str("file:///test.nu").expect("valid URI");
+        let diagnostic = to_diagnostic(&compiler, error, &uri);
+
+        assert_eq!(
+            diagnostic.range,
+            Range::new(Position::new(1, 8), Position::new(1, 9))
+        );
+        assert_eq!(diagnostic.severity, Some(DiagnosticSeverity::ERROR));
+        assert_eq!(
+            diagnostic.code,
+            Some(NumberOrString::String(
+                "nu::parser::unclosed_delimiter".into()
+            ))
+        );
+
+        let related = diagnostic
+            .related_information
+            .expect("missing related span");
+        assert_eq!(related.len(), 1);
+        assert_eq!(related[0].location.uri, uri);
+        assert_eq!(
+            related[0].location.range,
+            Range::new(Position::new(1, 15), Position::new(1, 15))
+        );
+
+        let actions = to_code_actions(&compiler, error, &uri);
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0].title, "insert the missing ')'");
+
+        let edits = actions[0]
+            .edit
+            .as_ref()
+            .and_then(|edit| edit.changes.as_ref()?.get(&uri))
+            .expect("missing edit");
+        assert_eq!(
+            *edits,
+            vec![lsp_types::TextEdit::new(
+                Range::new(Position::new(1, 15), Position::new(1, 15)),
+                ")".into()
+            )]
+        );
+    }
+
+    #[test]
+    fn offsets() {
+        let mut compiler = Compiler::new();
+        compiler.add_file("first.nu", b"1\n");
+        compiler.add_file("second.nu", "'😀' + 1\n'é'".as_bytes());
+
+        assert_eq!(
+            to_offset(&compiler, "second.nu", Position::new(0, 0)),
+            Some(2)
+        );
+        assert_eq!(
+            to_offset(&compiler, "second.nu", Position::new(0, 4)),
+            Some(2 + 6)
+        );
+        assert_eq!(
+            to_offset(&compiler, "second.nu", Position::new(1, 2)),
+            Some(2 + 14)
+        );
+        // past the end of the line
+        assert_eq!(
+            to_offset(&compiler, "second.nu", Position::new(0, 100)),
+            Some(2 + 10)
+        );
+        assert_eq!(to_offset(&compiler, "second.nu", Position::new(2, 0)), None);
+        assert_eq!(to_offset(&compiler, "third.nu", Position::new(0, 0)), None);
+    }
+
+    #[test]
+    fn language_features() {
+        let src = "def foo [x: int] { $x + 1 }\nlet y = 1\nfoo $y\n";
+        let (compiler, types) = compile(src);
+        let uri = Uri::from_str("file:///test.nu").expect("valid URI");
+        let offset = |line, character| {
+            to_offset(&compiler, "test.nu", Position::new(line, character)).expect("valid position")
+        };
+
+        // `$y` in the call
+        let location = goto_definition(&compiler, offset(2, 5), &uri).expect("missing definition");
+        assert_eq!(
+            location.range,
+            Range::new(Position::new(1, 4), Position::new(1, 5))
+        );
+        // `foo` in the call
+        let location = goto_definition(&compiler, offset(2, 1), &uri).expect("missing definition");
+        assert_eq!(
+            location.range,
+            Range::new(Position::new(0, 4), Position::new(0, 7))
+        );
+        assert_eq!(goto_definition(&compiler, offset(1, 8), &uri), None);
+
+        let hover = hover(&compiler, &types, offset(0, 20)).expect("missing hover");
+        assert_eq!(
+            hover.contents,
+            HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: "```nu\n$x: int\n```".into(),
+            })
+        );
+
+        let labels = |offset| {
+            completions(&compiler, offset)
+                .into_iter()
+                .map(|item| item.label)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(labels(offset(0, 20)), vec!["$x", "foo"]);
+        assert_eq!(labels(offset(1, 0)), vec!["foo"]);
+        assert_eq!(labels(offset(2, 4)), vec!["$y", "foo"]);
+
+        let legend = semantic_tokens_legend();
+        let tokens = to_semantic_tokens(&compiler).data;
+        // `foo` in the call is the first token on the last line
+        let call = tokens
+            .iter()
+            .scan(0, |line, token| {
+                *line += token.delta_line;
+                Some((*line, token))
+            })
+            .find(|(line, _)| *line == 2)
+            .map(|(_, token)| token)
+            .expect("missing token of the call");
+        assert_eq!((call.delta_start, call.length), (0, 3));
+        assert_eq!(
+            legend.token_types[call.token_type as usize].as_str(),
+            "function"
+        );
+    }
+}
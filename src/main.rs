@@ -1,85 +1,549 @@
+use std::io::{BufRead, IsTerminal, Write};
 use std::process::exit;
 
-use new_nu_parser::compiler::Compiler;
-use new_nu_parser::lexer::lex;
+use new_nu_parser::ast_diff::AstDiff;
+use new_nu_parser::compiler::{Compiler, CompilerOptions, Profile, Spanned, StageObserver};
+use new_nu_parser::errors::{diagnostics_json, SourceError};
+use new_nu_parser::files::RealFileProvider;
+use new_nu_parser::format::{format_source, FormatOptions};
+use new_nu_parser::ir_generator::IrGenerator;
+use new_nu_parser::lexer::{lex, LexError, Tokens};
+use new_nu_parser::lint::{Lint, LintLevel};
 use new_nu_parser::parser::Parser;
+use new_nu_parser::render::render_all;
 use new_nu_parser::resolver::Resolver;
-use new_nu_parser::typechecker::Typechecker;
+use new_nu_parser::typechecker::{Typechecker, Types};
 
-fn main() {
-    let mut compiler = Compiler::new();
-    let mut do_print = true;
+const USAGE: &str = "\
+Usage: new-nu-parser [OPTIONS] <FILES>...
+       new-nu-parser --repl
+       new-nu-parser fmt [--check] <FILES>...
+       new-nu-parser diff <OLD_FILE> <NEW_FILE>
+
+Runs the compiler stages on each file and prints their output. Without any stage flags, the
+output of all stages except the IR is printed.
+
+The fmt command formats the files in place. With --check, the files are not modified, the names
+of the files that are not formatted are printed and the exit code reports whether there are any.
+
+The diff command compares the ASTs of two files, ignoring whitespace and comments, and prints the
+added, removed and changed nodes. The exit code reports whether there are any changes.
+
+Options:
+  --tokens    Print the lexed tokens
+  --ast       Print the AST nodes
+  --scope     Print the scope frames produced by name binding
+  --types     Print the types of the AST nodes
+  --ir        Print the generated IR
+  --optimize-ir
+              Fold the constant expressions when generating the IR
+  --json      Print the output as a JSON object per file instead of text, including the file's
+              diagnostics with their codes, labels and byte and line/column ranges
+  --no-print  Do not print anything, only report failure with the exit code
+  --check     Only check the syntax of the files, without running the stages after parsing.
+              All files are checked, the exit code reports whether any of them is invalid.
+  --warnings-as-errors
+              Fail on warnings the same way as on errors
+  --allow=<LINT>, --warn=<LINT>, --deny=<LINT>
+              Set the level of a lint: unused_variable, shadowing, deprecated_syntax,
+              implicit_any or config_overwrite
+  --profile=<PROFILE>
+              Kind of the files: script (default) or config for Nushell's env.nu and
+              config.nu, which predefines $env and checks the fields of $env.config
+  --repl      Compile lines read from the standard input one by one, printing the errors or the
+              IR of each line. Definitions from previous lines stay in scope.
+  --help      Print this help
+";
+
+/// Which stage outputs to print and how
+#[derive(Default)]
+struct Options {
+    tokens: bool,
+    ast: bool,
+    scope: bool,
+    types: bool,
+    ir: bool,
+    json: bool,
+    no_print: bool,
+    check: bool,
+    compiler_options: CompilerOptions,
+    repl: bool,
+}
+
+impl Options {
+    fn parse(args: impl Iterator<Item = String>) -> (Self, Vec<String>) {
+        let mut options = Options::default();
+        let mut fnames = vec![];
 
-    for arg in std::env::args().skip(1) {
-        if arg == "--no-print" {
-            do_print = false;
+        for arg in args {
+            match arg.as_str() {
+                "--tokens" => options.tokens = true,
+                "--ast" => options.ast = true,
+                "--scope" => options.scope = true,
+                "--types" => options.types = true,
+                "--ir" => options.ir = true,
+                "--json" => options.json = true,
+                "--no-print" => options.no_print = true,
+                "--check" => options.check = true,
+                "--warnings-as-errors" => options.compiler_options.warnings_as_errors = true,
+                "--optimize-ir" => options.compiler_options.optimize_ir = true,
+                "--repl" => options.repl = true,
+                "--help" | "-h" => {
+                    print!("{USAGE}");
+                    exit(0);
+                }
+                "--profile=script" => options.compiler_options.profile = Profile::Script,
+                "--profile=config" => options.compiler_options.profile = Profile::Config,
+                _ if arg.starts_with("--") && arg.contains('=') => {
+                    let (flag, name) = arg.split_once('=').expect("Already checked");
+
+                    let level = match flag {
+                        "--allow" => LintLevel::Allow,
+                        "--warn" => LintLevel::Warn,
+                        "--deny" => LintLevel::Deny,
+                        _ => {
+                            eprintln!("unknown flag {arg}\n\n{USAGE}");
+                            exit(1);
+                        }
+                    };
+
+                    let Some(lint) = Lint::from_name(name) else {
+                        eprintln!("unknown lint {name}\n\n{USAGE}");
+                        exit(1);
+                    };
+
+                    options.compiler_options.lint_levels.insert(lint, level);
+                }
+                _ if arg.starts_with("--") => {
+                    eprintln!("unknown flag {arg}\n\n{USAGE}");
+                    exit(1);
+                }
+                _ => fnames.push(arg),
+            }
+        }
+
+        if !(options.tokens || options.ast || options.scope || options.types || options.ir) {
+            options.tokens = true;
+            options.ast = true;
+            options.scope = true;
+            options.types = true;
+        }
+
+        if options.no_print {
+            options.tokens = false;
+            options.ast = false;
+            options.scope = false;
+            options.types = false;
+            options.ir = false;
+            options.json = false;
         }
+
+        (options, fnames)
     }
+}
 
-    for fname in std::env::args().skip(1) {
-        if fname == "--no-print" {
-            continue;
+/// Print the JSON output collected for a single file (if enabled) along with the file's errors of
+/// all stages that ran, otherwise render the errors to the standard error
+fn print_output(
+    options: &Options,
+    fname: &str,
+    output: serde_json::Map<String, serde_json::Value>,
+    compiler: &Compiler,
+    errors: &[&[SourceError]],
+) {
+    if options.json {
+        let diagnostics =
+            diagnostics_json(compiler, errors.iter().flat_map(|errors| errors.iter()));
+
+        let mut file_output = serde_json::Map::new();
+        file_output.insert("file".into(), fname.into());
+        file_output.extend(output);
+        file_output.insert("diagnostics".into(), diagnostics);
+
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&serde_json::Value::Object(file_output))
+                .expect("JSON value is always serializable")
+        );
+    } else if !options.no_print {
+        let color = std::io::stderr().is_terminal();
+        for errors in errors.iter().filter(|errors| !errors.is_empty()) {
+            eprintln!("{}", render_all(compiler, errors, color));
         }
+    }
+}
 
-        let contents = std::fs::read(&fname);
+/// Prints the outputs of the stages enabled in the options, or collects them for the JSON output
+struct StageOutput<'a> {
+    options: &'a Options,
+    output: serde_json::Map<String, serde_json::Value>,
+}
 
-        let Ok(contents) = contents else {
-            eprintln!("can't find {}", fname);
-            exit(1);
+impl StageObserver for StageOutput<'_> {
+    fn lexed(&mut self, compiler: &Compiler, tokens: &Tokens, error: Option<&Spanned<LexError>>) {
+        let print_text = !self.options.json;
+
+        if self.options.tokens {
+            if print_text {
+                tokens.print(&compiler.source);
+            } else {
+                self.output
+                    .insert("tokens".into(), tokens.to_json(&compiler.source));
+            }
+        }
+
+        if let Some(e) = error {
+            let span_contents = String::from_utf8_lossy(
+                compiler.get_span_contents_manual(e.span.start, e.span.end),
+            );
+
+            if self.options.json {
+                self.output.insert(
+                    "lex_error".into(),
+                    serde_json::json!({
+                        "error": format!("{:?}", e.item),
+                        "start": e.span.start,
+                        "end": e.span.end,
+                        "text": span_contents,
+                    }),
+                );
+            } else if !self.options.tokens {
+                tokens.eprint(&compiler.source);
+            }
+        }
+    }
+
+    fn parsed(&mut self, compiler: &Compiler) {
+        if self.options.ast {
+            if self.options.json {
+                self.output.insert("ast".into(), compiler.to_json());
+            } else {
+                compiler.print();
+            }
+        }
+    }
+
+    fn resolved(&mut self, resolver: &Resolver) {
+        if self.options.scope {
+            if self.options.json {
+                self.output.insert("scope".into(), resolver.to_json());
+            } else {
+                resolver.print();
+            }
+        }
+    }
+
+    fn typechecked(&mut self, typechecker: &Typechecker) {
+        if self.options.types {
+            if self.options.json {
+                self.output.insert("types".into(), typechecker.to_json());
+            } else {
+                typechecker.print();
+            }
+        }
+    }
+}
+
+fn print_errors(compiler: &Compiler, errors: &[SourceError]) {
+    if !errors.is_empty() {
+        println!(
+            "{}",
+            render_all(compiler, errors, std::io::stdout().is_terminal())
+        );
+    }
+}
+
+/// Compile lines from the standard input incrementally, keeping the name bindings and types of
+/// previous lines
+fn repl(compiler_options: CompilerOptions) {
+    let mut compiler = Compiler::new();
+    compiler.options = compiler_options;
+    let mut types: Option<Types> = None;
+
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines();
+
+    for line_number in 1.. {
+        print!("> ");
+        let _ = std::io::stdout().flush();
+
+        let Some(Ok(line)) = lines.next() else {
+            println!();
+            break;
         };
 
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        // A line with errors is discarded, so that its definitions do not stay in scope
+        let checkpoint = compiler.checkpoint();
+
         let span_offset = compiler.span_offset();
-        compiler.add_file(&fname, &contents);
+        compiler.add_file(&format!("repl_line_{line_number}"), line.as_bytes());
+
+        // Errors of the previous lines are kept, only print the ones of this line
+        let errors_start = compiler.errors.len();
 
-        let (tokens, err) = lex(&contents, span_offset);
+        let (tokens, err) = lex(line.as_bytes(), span_offset);
         if let Err(e) = err {
-            tokens.print(&compiler.source);
-            eprintln!(
-                "Lexing error. Error: {:?}, '{}'",
-                e,
-                String::from_utf8_lossy(
-                    compiler.get_span_contents_manual(e.span.start, e.span.end)
-                )
-            );
-            exit(1);
+            compiler.push_lex_error(e);
+            print_errors(&compiler, &compiler.errors[errors_start..]);
+            compiler.rollback(checkpoint);
+            continue;
         }
 
-        if do_print {
-            tokens.print(&compiler.source);
+        compiler = Parser::new(compiler, tokens).parse();
+
+        if compiler.has_fatal_errors(&compiler.errors) {
+            print_errors(&compiler, &compiler.errors[errors_start..]);
+            compiler.rollback(checkpoint);
+            continue;
         }
 
-        let parser = Parser::new(compiler, tokens);
+        let name_bindings = compiler.take_name_bindings();
+        let mut resolver = Resolver::with_name_bindings(&compiler, name_bindings);
+        resolver.resolve_incremental();
+        compiler.merge_name_bindings(resolver.to_name_bindings());
 
-        compiler = parser.parse();
+        if compiler.has_fatal_errors(&compiler.errors) {
+            print_errors(&compiler, &compiler.errors[errors_start..]);
+            compiler.rollback(checkpoint);
+            continue;
+        }
+        print_errors(&compiler, &compiler.errors[errors_start..]);
 
-        if do_print {
-            compiler.print();
+        let mut typechecker = match types.take() {
+            Some(types) => Typechecker::with_types(&compiler, types),
+            None => Typechecker::new(&compiler),
+        };
+        typechecker.typecheck();
+        let mut line_types = typechecker.to_types();
+
+        if compiler.has_fatal_errors(&line_types.errors) {
+            print_errors(&compiler, &line_types.errors);
+            compiler.rollback(checkpoint);
+            line_types.truncate(&compiler);
+            types = Some(line_types);
+            continue;
+        }
+        // Only warnings are left
+        print_errors(&compiler, &line_types.errors);
+        types = Some(line_types);
+
+        let mut ir_generator = IrGenerator::new(&compiler);
+        ir_generator.generate();
+
+        if compiler.has_fatal_errors(ir_generator.errors()) {
+            print_errors(&compiler, ir_generator.errors());
+        } else {
+            ir_generator.print();
+        }
+    }
+}
+
+/// Format the files in place, or only report the ones that are not formatted with `--check`
+fn fmt(args: impl Iterator<Item = String>) {
+    let mut check = false;
+    let mut fnames = vec![];
+
+    for arg in args {
+        match arg.as_str() {
+            "--check" => check = true,
+            "--help" | "-h" => {
+                print!("{USAGE}");
+                exit(0);
+            }
+            _ if arg.starts_with("--") => {
+                eprintln!("unknown flag {arg}\n\n{USAGE}");
+                exit(1);
+            }
+            _ => fnames.push(arg),
         }
+    }
+
+    let options = FormatOptions::default();
+    let mut failed = false;
 
-        if !compiler.errors.is_empty() {
+    for fname in &fnames {
+        let Ok(contents) = std::fs::read(fname) else {
+            eprintln!("can't find {}", fname);
             exit(1);
+        };
+
+        let formatted = match format_source(&contents, &options) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                eprintln!("{fname}: Lexing error. Error: {:?}", e);
+                failed = true;
+                continue;
+            }
+        };
+
+        if formatted == contents {
+            continue;
         }
 
-        let mut resolver = Resolver::new(&compiler);
-        resolver.resolve();
+        if check {
+            println!("{fname}");
+            failed = true;
+        } else if let Err(err) = std::fs::write(fname, &formatted) {
+            eprintln!("can't write {fname}: {err}");
+            failed = true;
+        }
+    }
+
+    if failed {
+        exit(1);
+    }
+}
+
+/// Print the structural differences between the ASTs of two files
+fn diff(args: impl Iterator<Item = String>) {
+    let fnames: Vec<_> = args.collect();
+    let [old_fname, new_fname] = fnames.as_slice() else {
+        eprintln!("expected two files to compare\n\n{USAGE}");
+        exit(1);
+    };
+
+    let parse = |fname: &str| {
+        let Ok(contents) = std::fs::read(fname) else {
+            eprintln!("can't find {}", fname);
+            exit(1);
+        };
 
-        if do_print {
-            resolver.print();
+        let mut compiler = Compiler::new();
+        if !compiler.check(fname, &contents) {
+            eprintln!("{}", render_all(&compiler, &compiler.errors, false));
+            exit(1);
         }
+        compiler
+    };
 
-        compiler.merge_name_bindings(resolver.to_name_bindings());
+    let old = parse(old_fname);
+    let new = parse(new_fname);
 
-        if !compiler.errors.is_empty() {
+    let diff = AstDiff::new(&old, &new);
+    print!("{}", diff.display(&old, &new));
+
+    if !diff.is_empty() {
+        exit(1);
+    }
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1).peekable();
+    if args.next_if(|arg| arg == "fmt").is_some() {
+        fmt(args);
+        return;
+    }
+    if args.next_if(|arg| arg == "diff").is_some() {
+        diff(args);
+        return;
+    }
+
+    let (mut options, fnames) = Options::parse(args);
+
+    if options.repl {
+        repl(options.compiler_options);
+        return;
+    }
+
+    let print_text = !options.json;
+
+    let mut compiler = Compiler::new().with_file_provider(RealFileProvider);
+    compiler.options = std::mem::take(&mut options.compiler_options);
+    let mut check_failed = false;
+
+    for fname in &fnames {
+        let contents = std::fs::read(fname);
+
+        let Ok(contents) = contents else {
+            eprintln!("can't find {}", fname);
             exit(1);
+        };
+
+        if options.check {
+            let errors_start = compiler.errors.len();
+            check_failed |= !compiler.check(fname, &contents);
+            print_output(
+                &options,
+                fname,
+                serde_json::Map::new(),
+                &compiler,
+                &[&compiler.errors[errors_start..]],
+            );
+            continue;
         }
 
-        let mut typechecker = Typechecker::new(&compiler);
-        typechecker.typecheck();
+        // Errors of the previous files are kept, only report the ones of this file
+        let errors_start = compiler.errors.len();
+        let mut ir_errors = vec![];
+
+        let mut stage_output = StageOutput {
+            options: &options,
+            output: serde_json::Map::new(),
+        };
+        let typechecked = compiler
+            .run_stages(fname, &contents, &mut stage_output)
+            .is_some();
+        let mut output = stage_output.output;
+
+        if !typechecked {
+            print_output(
+                &options,
+                fname,
+                output,
+                &compiler,
+                &[&compiler.errors[errors_start..]],
+            );
+            exit(1);
+        }
+
+        if options.ir {
+            if compiler.has_fatal_errors(&compiler.errors) {
+                print_output(
+                    &options,
+                    fname,
+                    output,
+                    &compiler,
+                    &[&compiler.errors[errors_start..]],
+                );
+                exit(1);
+            }
 
-        if do_print {
-            typechecker.print();
+            let mut ir_generator = IrGenerator::new(&compiler);
+            ir_generator.generate();
+
+            if print_text {
+                ir_generator.print();
+            } else {
+                output.insert("ir".into(), ir_generator.to_json());
+            }
+
+            ir_errors = ir_generator.errors().clone();
+            if compiler.has_fatal_errors(&ir_errors) {
+                print_output(
+                    &options,
+                    fname,
+                    output,
+                    &compiler,
+                    &[&compiler.errors[errors_start..], &ir_errors],
+                );
+                exit(1);
+            }
         }
 
-        compiler.merge_types(typechecker.to_types());
+        print_output(
+            &options,
+            fname,
+            output,
+            &compiler,
+            &[&compiler.errors[errors_start..], &ir_errors],
+        );
+    }
+
+    if check_failed {
+        exit(1);
     }
 }
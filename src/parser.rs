@@ -1,21 +1,350 @@
-use crate::compiler::{Compiler, RollbackPoint, Span};
-use crate::errors::{Severity, SourceError};
-use crate::lexer::{Token, Tokens};
+use crate::compiler::{Compiler, RollbackPoint, Span, Spanned};
+use crate::errors::{ErrorCode, Fix, Message, RelatedSpan, Severity, SourceError, TextEdit};
+use crate::files::PARSE_TIME_COMMANDS;
+use crate::ir_optimizer::fold_constant;
+use crate::lexer::{LexError, StatementLexer, Token, Tokens};
+use crate::lint::{parse_lint_comment, Lint, LintDirective};
+use crate::protocol::ParamKind;
+
+use chrono::{DateTime, FixedOffset};
+use std::num::{IntErrorKind, ParseFloatError, ParseIntError};
 
 use tracy_client::span;
 
+/// Tokens that end a name consisting of several tokens, e.g., `def-env`
+const NAME_END_TOKENS: &[Token] = &[
+    Token::Eof,
+    Token::Newline,
+    Token::Pipe,
+    Token::Semicolon,
+    Token::RParen,
+    Token::RCurly,
+];
+
+/// Tokens that continue a bare word when there is no whitespace before them
+const BARE_WORD_TOKENS: &[Token] = &[
+    Token::Bareword,
+    Token::Int,
+    Token::Float,
+    Token::Dot,
+    Token::DotDot,
+    Token::ForwardSlash,
+    Token::Asterisk,
+    Token::AsteriskAsterisk,
+    Token::QuestionMark,
+    Token::Dash,
+    Token::Tilde,
+];
+
+fn is_wildcard(token: Token) -> bool {
+    matches!(
+        token,
+        Token::Asterisk | Token::AsteriskAsterisk | Token::QuestionMark
+    )
+}
+
+/// Commands whose first argument is a row condition, unless it is written as a closure
+///
+/// `filter` is not one of them, its argument is always a closure, e.g., `filter $closure`.
+const ROW_CONDITION_COMMANDS: &[&[u8]] = &[b"where"];
+
+const ASSIGNMENT_NOTE: &str =
+    "assignments are statements that do not produce a value, they cannot be used in expressions";
+
+const CONDITION_ASSIGNMENT_NOTE: &str =
+    "`=` assigns a value to a variable, while `==` checks whether two values are equal";
+
+/// Operators made of words joined by dashes, which are lexed as multiple tokens
+const DASHED_OPERATORS: &[(&[u8], AstNode)] = &[
+    (b"not-in", AstNode::NotIn),
+    (b"starts-with", AstNode::StartsWith),
+    (b"ends-with", AstNode::EndsWith),
+    (b"bit-or", AstNode::BitOr),
+    (b"bit-xor", AstNode::BitXor),
+    (b"bit-and", AstNode::BitAnd),
+    (b"bit-shl", AstNode::ShiftLeft),
+    (b"bit-shr", AstNode::ShiftRight),
+];
+
+/// Commands that were replaced by other syntax, along with their replacement
+const DEPRECATED_COMMANDS: &[(&str, &str)] = &[
+    ("def-env", "def --env"),
+    ("extern-wrapped", "def --wrapped"),
+    ("let-env", "$env.NAME = value"),
+];
+
+/// Value of an int literal such as `-1_000` or `0xff`
+///
+/// Fails if the literal is out of range of i64 (the error's kind is `PosOverflow` or
+/// `NegOverflow`) or is not a valid int literal.
+pub fn int_literal_value(contents: &[u8]) -> Result<i64, ParseIntError> {
+    let text = String::from_utf8_lossy(contents).replace('_', "");
+    let (sign, digits) = match text.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", text.as_str()),
+    };
+
+    let (radix, digits) = match digits.get(..2) {
+        Some("0x") => (16, &digits[2..]),
+        Some("0o") => (8, &digits[2..]),
+        Some("0b") => (2, &digits[2..]),
+        _ => (10, digits),
+    };
+
+    i64::from_str_radix(&format!("{sign}{digits}"), radix)
+}
+
+/// Value of a float literal such as `1_000.5`, `.5` or `1e-3`
+pub fn float_literal_value(contents: &[u8]) -> Result<f64, ParseFloatError> {
+    String::from_utf8_lossy(contents).replace('_', "").parse()
+}
+
+/// Digit of a binary literal that is not valid for its base or a byte that is out of range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidBinary;
+
+impl std::fmt::Display for InvalidBinary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid binary literal")
+    }
+}
+
+/// Bytes of a binary literal such as `0x[ff 00]`, `0o[777]` or `0b[1010]`
+///
+/// Whitespace between the digits is ignored. Each byte is made of 2 hex, 3 octal or 8 binary
+/// digits, the digits are padded with zeros at the start if their count is not a multiple of that.
+pub fn binary_literal_value(contents: &[u8]) -> Result<Vec<u8>, InvalidBinary> {
+    let (radix, digits_per_byte) = match contents.get(..3) {
+        Some(b"0x[") => (16, 2),
+        Some(b"0o[") => (8, 3),
+        Some(b"0b[") => (2, 8),
+        _ => return Err(InvalidBinary),
+    };
+    let inner = contents[3..].strip_suffix(b"]").ok_or(InvalidBinary)?;
+
+    let mut digits: Vec<u8> = inner
+        .iter()
+        .copied()
+        .filter(|c| !c.is_ascii_whitespace())
+        .collect();
+    let padding = (digits_per_byte - digits.len() % digits_per_byte) % digits_per_byte;
+    digits.splice(0..0, std::iter::repeat(b'0').take(padding));
+
+    digits
+        .chunks(digits_per_byte)
+        .map(|byte| {
+            let byte = std::str::from_utf8(byte).map_err(|_| InvalidBinary)?;
+            u8::from_str_radix(byte, radix).map_err(|_| InvalidBinary)
+        })
+        .collect()
+}
+
+/// Value of a datetime literal such as `2024-01-15` or `2024-01-15T10:30:00+02:00`
+///
+/// A date without time is at midnight, the offset defaults to UTC if it is not given.
+pub fn datetime_literal_value(
+    contents: &[u8],
+) -> Result<DateTime<FixedOffset>, chrono::ParseError> {
+    // the lexer accepts only ASCII in datetime literals
+    let datetime = String::from_utf8_lossy(contents);
+    let (date, rest) = datetime.split_at(datetime.len().min(10));
+    let (time, offset) = match rest.strip_prefix('T') {
+        Some(rest) => rest.split_at(rest.find(['Z', '+', '-']).unwrap_or(rest.len())),
+        None => ("00:00:00", rest),
+    };
+    let offset = if offset.is_empty() { "+00:00" } else { offset };
+    DateTime::parse_from_rfc3339(&format!("{date}T{time}{offset}"))
+}
+
+/// Units of filesize literals and the number of bytes they stand for, the units are not case
+/// sensitive
+pub const FILESIZE_UNITS: &[(&str, i64)] = &[
+    ("b", 1),
+    ("kb", 1000),
+    ("mb", 1000_i64.pow(2)),
+    ("gb", 1000_i64.pow(3)),
+    ("tb", 1000_i64.pow(4)),
+    ("pb", 1000_i64.pow(5)),
+    ("eb", 1000_i64.pow(6)),
+    ("kib", 1 << 10),
+    ("mib", 1 << 20),
+    ("gib", 1 << 30),
+    ("tib", 1 << 40),
+    ("pib", 1 << 50),
+    ("eib", 1 << 60),
+];
+
+/// Units of duration literals and the number of nanoseconds they stand for
+pub const DURATION_UNITS: &[(&str, i64)] = &[
+    ("ns", 1),
+    ("us", 1000),
+    ("µs", 1000),
+    ("ms", 1000_i64.pow(2)),
+    ("sec", 1000_i64.pow(3)),
+    ("min", 60 * 1000_i64.pow(3)),
+    ("hr", 60 * 60 * 1000_i64.pow(3)),
+    ("day", 24 * 60 * 60 * 1000_i64.pow(3)),
+    ("wk", 7 * 24 * 60 * 60 * 1000_i64.pow(3)),
+];
+
+/// Literal with a unit that is out of range of i64 or is not valid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidUnitValue;
+
+impl std::fmt::Display for InvalidUnitValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "value with unit is out of range or invalid")
+    }
+}
+
+/// Value of a literal with a unit such as `-10kb` or `1.5sec`, in bytes or nanoseconds given by
+/// `units` (`FILESIZE_UNITS` or `DURATION_UNITS`)
+///
+/// A fractional value is truncated to a whole number of bytes or nanoseconds.
+pub fn unit_literal_value(contents: &[u8], units: &[(&str, i64)]) -> Result<i64, InvalidUnitValue> {
+    let text = String::from_utf8_lossy(contents).replace('_', "");
+    let unit_start = text
+        .char_indices()
+        .skip(1)
+        .find(|(_, c)| !c.is_ascii_digit() && *c != '.')
+        .map_or(text.len(), |(idx, _)| idx);
+    let (number, unit) = text.split_at(unit_start);
+
+    let unit = unit.to_lowercase();
+    let (_, factor) = units
+        .iter()
+        .find(|(name, _)| *name == unit)
+        .ok_or(InvalidUnitValue)?;
+
+    if number.contains('.') {
+        let value = number.parse::<f64>().map_err(|_| InvalidUnitValue)? * *factor as f64;
+        // the cast saturates, the bounds are checked before
+        if value.is_finite() && value >= i64::MIN as f64 && value < i64::MAX as f64 {
+            Ok(value as i64)
+        } else {
+            Err(InvalidUnitValue)
+        }
+    } else {
+        let value = number.parse::<i64>().map_err(|_| InvalidUnitValue)?;
+        value.checked_mul(*factor).ok_or(InvalidUnitValue)
+    }
+}
+
+/// The contents of a string literal without its quotes
+pub fn unquote(contents: &[u8]) -> &[u8] {
+    match contents {
+        [b'"' | b'\'' | b'`', inner @ .., last] if *last == contents[0] => inner,
+        _ => contents,
+    }
+}
+
+/// Value of a raw string literal: the contents between `r#'` and `'#` (with any number of `#`) or
+/// between backticks
+pub fn raw_string_value(contents: &[u8]) -> &[u8] {
+    match contents.strip_prefix(b"r") {
+        Some(raw) => {
+            let num_hashes = raw.iter().take_while(|c| **c == b'#').count();
+            raw.get(num_hashes + 1..raw.len().saturating_sub(num_hashes + 1))
+                .unwrap_or_default()
+        }
+        None => unquote(contents),
+    }
+}
+
+/// Escape sequence of a double-quoted string that is not valid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidEscape {
+    /// Offset of the backslash in the literal
+    pub offset: usize,
+}
+
+impl std::fmt::Display for InvalidEscape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid escape sequence at byte {}", self.offset)
+    }
+}
+
+/// Value of a string literal: the contents without the quotes, with the escape sequences of a
+/// double-quoted string (the ones accepted by the lexer) replaced by the characters they stand for
+pub fn string_literal_value(contents: &[u8]) -> Result<Vec<u8>, InvalidEscape> {
+    let inner = unquote(contents);
+    if !contents.starts_with(b"\"") || inner.len() == contents.len() {
+        return Ok(inner.to_vec());
+    }
+
+    // the offset of the backslash includes the opening quote
+    unescape(inner, false).map_err(|err| InvalidEscape {
+        offset: err.offset + 1,
+    })
+}
+
+/// Value of a literal part of a string interpolation
+///
+/// The parts of `$"..."` can contain the escape sequences of a double-quoted string and `\(` for a
+/// parenthesis that does not start a subexpression, the parts of `$'...'` have no escapes.
+pub fn interpolation_chunk_value(
+    contents: &[u8],
+    double_quoted: bool,
+) -> Result<Vec<u8>, InvalidEscape> {
+    if double_quoted {
+        unescape(contents, true)
+    } else {
+        Ok(contents.to_vec())
+    }
+}
+
+fn unescape(inner: &[u8], is_interpolation: bool) -> Result<Vec<u8>, InvalidEscape> {
+    let mut value = Vec::with_capacity(inner.len());
+    let mut pos = 0;
+    while let Some(backslash) = inner[pos..].iter().position(|c| *c == b'\\') {
+        value.extend_from_slice(&inner[pos..pos + backslash]);
+        pos += backslash;
+        let invalid = InvalidEscape { offset: pos };
+
+        let escaped = match inner.get(pos + 1).ok_or(invalid)? {
+            c @ (b'"' | b'\\') => *c,
+            b'(' if is_interpolation => b'(',
+            b'b' => 0x08,
+            b'f' => 0x0c,
+            b'n' => b'\n',
+            b'r' => b'\r',
+            b't' => b'\t',
+            _ => return Err(invalid),
+        };
+
+        value.push(escaped);
+        pos += 2;
+    }
+    value.extend_from_slice(&inner[pos..]);
+
+    Ok(value)
+}
+
 pub struct Parser {
     pub compiler: Compiler,
     tokens: Tokens,
+    /// Spans of the comments, they are removed from the tokens before parsing
+    comments: Vec<Span>,
+    /// Constants visible at the current position: their names and the name nodes of their
+    /// `const`, or None for a variable shadowing a constant
+    constants: Vec<(Vec<u8>, Option<NodeId>)>,
+    /// Lexer of the following statements and the span of the file, when the file is lexed one
+    /// statement at a time
+    statements: Option<(StatementLexer, Span)>,
+    /// Error that stopped lexing the following statements
+    lex_error: Option<Spanned<LexError>>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct NodeId(pub usize);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BlockId(pub usize);
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Block {
     pub nodes: Vec<NodeId>,
 }
@@ -44,6 +373,20 @@ pub enum ParamsContext {
     Pipes,
 }
 
+/// Where an expression is parsed, which decides whether assignments are allowed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpressionContext {
+    /// Expression is a statement and can be an assignment
+    Statement,
+    /// Expression produces a value
+    Value,
+    /// Expression is a condition of `if` or `while`
+    Condition,
+    /// Expression is the row condition of a command like `where`, a bareword operand is a cell
+    /// path on the row, e.g., `size` stands for `$it.size`
+    RowCondition,
+}
+
 #[derive(Debug)]
 pub enum BarewordContext {
     /// Bareword is a string (e.g., in a list)
@@ -52,12 +395,92 @@ pub enum BarewordContext {
     Call,
 }
 
+/// Entry of a record literal
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RecordEntry {
+    /// `key: value`
+    Pair(NodeId, NodeId),
+    /// `...$other`, a Spread node inserting all the fields of another record
+    Spread(NodeId),
+}
+
+impl RecordEntry {
+    pub fn nodes(&self) -> Vec<NodeId> {
+        match self {
+            RecordEntry::Pair(key, val) => vec![*key, *val],
+            RecordEntry::Spread(spread) => vec![*spread],
+        }
+    }
+}
+
+/// Stream of a command's output taken by a redirection
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RedirectionSource {
+    /// `o>`, `out>`
+    Out,
+    /// `e>`, `err>`
+    Err,
+    /// `o+e>`, `out+err>`, both streams together
+    OutErr,
+}
+
+/// The redirected stream and whether the file is appended to, for the tokens redirecting to a file
+fn file_redirection(token: Token) -> Option<(RedirectionSource, bool)> {
+    match token {
+        Token::OutGreaterThan => Some((RedirectionSource::Out, false)),
+        Token::OutGreaterGreaterThan => Some((RedirectionSource::Out, true)),
+        Token::ErrGreaterThan => Some((RedirectionSource::Err, false)),
+        Token::ErrGreaterGreaterThan => Some((RedirectionSource::Err, true)),
+        Token::OutErrGreaterThan => Some((RedirectionSource::OutErr, false)),
+        Token::OutErrGreaterGreaterThan => Some((RedirectionSource::OutErr, true)),
+        _ => None,
+    }
+}
+
+/// The stream piped into the next element of the pipeline, instead of the output by `e>|` or
+/// together with it by `o+e>|`
+fn pipe_redirection(token: Token) -> Option<RedirectionSource> {
+    match token {
+        Token::ErrGreaterThanPipe => Some(RedirectionSource::Err),
+        Token::OutErrGreaterThanPipe => Some(RedirectionSource::OutErr),
+        _ => None,
+    }
+}
+
+/// Member of a cell path
+///
+/// The member node is a Name for `.foo`, a String for `."foo bar"` and an Int for `.0`, its span
+/// excludes the dot and the `?`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CellPathMember {
+    pub member: NodeId,
+    /// `.foo?`, the path evaluates to null instead of failing if the member is missing
+    pub optional: bool,
+}
+
 // TODO: All nodes with Vec<...> should be moved to their own ID (like BlockId) to allow Copy trait
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AstNode {
     Int,
     Float,
+    /// `10kb`, the value is in bytes
+    Filesize,
+    /// `500ms`, the value is in nanoseconds
+    Duration,
+    /// `0x[ff 00]`
+    Binary,
+    /// `2024-01-15` or `2024-01-15T10:30:00+02:00`
+    Datetime,
     String,
+    /// String whose contents are taken as they are, without escape sequences: `r#'...'#` or
+    /// `` `...` ``, see `raw_string_value`
+    RawString,
+    /// Bare word with wildcards, e.g., `*.rs` or `src/**/*.toml`
+    GlobPattern,
     Name,
     Type {
         name: NodeId,
@@ -90,10 +513,20 @@ pub enum AstNode {
     RegexMatch,
     NotRegexMatch,
     In,
+    NotIn,
+    StartsWith,
+    EndsWith,
     Append,
+    BitOr,
+    BitXor,
+    BitAnd,
+    ShiftLeft,
+    ShiftRight,
     And,
     Xor,
     Or,
+    /// `not` of a unary operation, the `-` of a negation is a Minus node
+    Not,
 
     // Assignments
     Assignment,
@@ -110,6 +543,12 @@ pub enum AstNode {
         initializer: NodeId,
         is_mutable: bool,
     },
+    /// `const x = 40 + 2`, the value is evaluated when parsing, see `Compiler::constants`
+    Const {
+        variable_name: NodeId,
+        ty: Option<NodeId>,
+        initializer: NodeId,
+    },
     While {
         condition: NodeId,
         block: NodeId,
@@ -137,17 +576,67 @@ pub enum AstNode {
     Param {
         name: NodeId,
         ty: Option<NodeId>,
+        kind: ParamKind,
+        /// `f` of `--flag (-f)`
+        short: Option<NodeId>,
+        default: Option<NodeId>,
     },
     InOutTypes(Vec<NodeId>),
-    /// Input/output type pair for a command
-    InOutType(NodeId, NodeId),
+    /// Input/output type pair for a command, the input is None for `-> out`
+    InOutType(Option<NodeId>, NodeId),
     Closure {
         params: Option<NodeId>,
         block: NodeId,
     },
+    /// `alias new_name = expansion`, the expansion is a call that the alias's arguments are
+    /// appended to
     Alias {
         new_name: NodeId,
-        old_name: NodeId,
+        expansion: NodeId,
+    },
+    /// `extern name [params]`, the signature of a command that is not defined in Nushell
+    Extern {
+        name: NodeId,
+        params: NodeId,
+    },
+
+    // Modules
+    /// `module name { ... }`, the definitions of the block marked with `export` are its members
+    Module {
+        name: NodeId,
+        block: NodeId,
+    },
+    /// `use module members`, the module is followed by the import pattern
+    ///
+    /// The members are Name or String nodes, a List of them or ImportAll. All the members but the
+    /// last one select submodules.
+    Use {
+        module: NodeId,
+        members: Vec<NodeId>,
+    },
+    /// `*` of `use module *`
+    ImportAll,
+    /// `export` of a definition or a `use` inside a module
+    Export(NodeId),
+    /// `export-env { ... }`, the block runs when the module is used
+    ExportEnv(NodeId),
+    /// `overlay use module` or `overlay use module as name`, the module's definitions become
+    /// visible as a new overlay
+    OverlayUse {
+        module: NodeId,
+        name: Option<NodeId>,
+    },
+    /// `overlay new name`, an empty overlay collecting the definitions that follow
+    OverlayNew(NodeId),
+    /// `overlay hide name`, or `overlay hide` of the last activated overlay
+    OverlayHide(Option<NodeId>),
+    /// `hide foo`, the parts of the hidden name are Name or String nodes
+    Hide(Vec<NodeId>),
+    /// `@example "add two numbers" { add 1 2 }` or `@search-terms foo bar` before a declaration,
+    /// see `Compiler::attributes`
+    Attribute {
+        name: NodeId,
+        args: Vec<NodeId>,
     },
 
     /// Long flag ('--' + one or more letters)
@@ -161,30 +650,64 @@ pub enum AstNode {
     Call {
         parts: Vec<NodeId>,
     },
+    /// `--flag=value`, the name is the flag node without the `=`
     NamedValue {
         name: NodeId,
         value: NodeId,
     },
+    /// `o> file`, `e>> file` or `e>|`, a part of the call whose stream it redirects
+    ///
+    /// The target is the path of the file, or None if the stream goes to the next element of the
+    /// pipeline.
+    Redirection {
+        source: RedirectionSource,
+        target: Option<NodeId>,
+        append: bool,
+    },
     BinaryOp {
         lhs: NodeId,
         op: NodeId,
         rhs: NodeId,
     },
+    UnaryOp {
+        op: NodeId,
+        value: NodeId,
+    },
+    /// `from..to`, `from..<to` or `from..next..to`, with the bounds optional: `..5`, `5..`
     Range {
-        lhs: NodeId,
-        rhs: NodeId,
+        from: Option<NodeId>,
+        next: Option<NodeId>,
+        to: Option<NodeId>,
+        /// `..<`, the range does not include `to`
+        exclusive: bool,
     },
     List(Vec<NodeId>),
+    /// `$"..."` or `$'...'`, the parts are String nodes of the literal chunks and the expressions
+    /// of the parenthesized subexpressions, in order
+    StringInterpolation(Vec<NodeId>),
     Table {
         header: NodeId,
         rows: Vec<NodeId>,
     },
     Record {
-        pairs: Vec<(NodeId, NodeId)>,
+        entries: Vec<RecordEntry>,
     },
-    MemberAccess {
-        target: NodeId,
-        field: NodeId,
+    /// `...` followed by the value whose items are inserted into a list, a record or the rest
+    /// arguments of a call
+    Spread(NodeId),
+    /// `$x.foo.0?` or `(ls).name`, the members accessed on the value of `head`
+    CellPath {
+        head: NodeId,
+        members: Vec<CellPathMember>,
+    },
+    /// Expressions separated by `|`, the value of each one is the input of the next one
+    Pipeline(Vec<NodeId>),
+    /// `FOO=bar cmd`, the environment variables are set only while the block runs
+    ///
+    /// The assignments are pairs of a Name and a value, the block holds the command.
+    EnvShorthand {
+        assignments: Vec<(NodeId, NodeId)>,
+        block: NodeId,
     },
     Block(BlockId),
     If {
@@ -196,7 +719,26 @@ pub enum AstNode {
         target: NodeId,
         match_arms: Vec<(NodeId, NodeId)>,
     },
+    /// `_` in a `match` pattern
+    Wildcard,
+    /// Alternatives of a `match` pattern separated by `|`
+    OrPattern(Vec<NodeId>),
+    /// `try` with its block and the optional closure after `catch`, which can take the error
+    Try {
+        block: NodeId,
+        catch: Option<NodeId>,
+    },
+    /// `match` pattern followed by `if` and a condition
+    GuardedPattern {
+        pattern: NodeId,
+        guard: NodeId,
+    },
     Statement(NodeId),
+    /// `(ls | length)`, a block in parentheses evaluated in place
+    ///
+    /// Parentheses around a single expression, e.g., `(1 + 2) * 3`, only group it and have no
+    /// node.
+    Subexpression(NodeId),
     Garbage,
 }
 
@@ -208,6 +750,7 @@ impl AstNode {
             AstNode::Pow => 100,
             AstNode::Multiply | AstNode::Divide | AstNode::FloorDiv | AstNode::Modulo => 95,
             AstNode::Plus | AstNode::Minus => 90,
+            AstNode::ShiftLeft | AstNode::ShiftRight => 85,
             AstNode::LessThan
             | AstNode::LessThanOrEqual
             | AstNode::GreaterThan
@@ -217,7 +760,13 @@ impl AstNode {
             | AstNode::RegexMatch
             | AstNode::NotRegexMatch
             | AstNode::In
+            | AstNode::NotIn
+            | AstNode::StartsWith
+            | AstNode::EndsWith
             | AstNode::Append => 80,
+            AstNode::BitAnd => 75,
+            AstNode::BitXor => 70,
+            AstNode::BitOr => 60,
             AstNode::And => 50,
             AstNode::Xor => 45,
             AstNode::Or => 40,
@@ -230,11 +779,68 @@ impl AstNode {
             _ => 0,
         }
     }
+
+    /// Whether the node is `=` or one of the compound assignment operators, e.g., `+=`
+    pub fn is_assignment(&self) -> bool {
+        self.precedence() == ASSIGNMENT_PRECEDENCE
+    }
 }
 
 impl Parser {
     pub fn new(compiler: Compiler, tokens: Tokens) -> Self {
-        Self { compiler, tokens }
+        Self {
+            compiler,
+            tokens,
+            comments: vec![],
+            constants: vec![],
+            statements: None,
+            lex_error: None,
+        }
+    }
+
+    /// Parser lexing the file one top-level statement at a time as it parses, keeping only the
+    /// tokens of the statements it is in the middle of instead of the tokens of the whole file
+    ///
+    /// The file is given by its span in the compiler's source. The result is the same as parsing
+    /// the tokens of `lex()`, on a lexing error the nodes are discarded and only the error is
+    /// reported.
+    pub fn with_statements(compiler: Compiler, file: Span) -> Self {
+        let lexer = StatementLexer::new(&compiler.source[file.start..file.end], file.start);
+        let mut tokens = Tokens::new(b"");
+        tokens.push(Token::Eof, Span::new(file.start, file.start));
+
+        Self {
+            statements: Some((lexer, file)),
+            ..Self::new(compiler, tokens)
+        }
+    }
+
+    fn advance(&mut self) {
+        self.tokens.advance();
+        self.lex_statements();
+    }
+
+    /// Lex the next statements while the current token or the one after it is the last one, when
+    /// lexing one statement at a time
+    fn lex_statements(&mut self) {
+        let Some((mut lexer, file)) = self.statements.take() else {
+            return;
+        };
+
+        while self.lex_error.is_none() && self.tokens.is_near_end() {
+            let contents = &self.compiler.source[file.start..file.end];
+            let Some((mut tokens, result)) = lexer.next_statement(contents) else {
+                break;
+            };
+
+            let comments = tokens.remove_comments();
+            self.lint_comments(&comments);
+            self.comments.extend(comments);
+            self.tokens.append(tokens);
+            self.lex_error = result.err();
+        }
+
+        self.statements = Some((lexer, file));
     }
 
     fn position(&mut self) -> usize {
@@ -247,22 +853,186 @@ impl Parser {
 
     pub fn parse(mut self) -> Compiler {
         let _span = span!();
+
+        let checkpoint = self.compiler.checkpoint();
+
+        // The nodes must be created before the block to keep the block as the last node
+        let invalid_utf8 = match &self.statements {
+            Some((lexer, _)) => lexer.invalid_utf8().to_vec(),
+            None => self.tokens.invalid_utf8().to_vec(),
+        };
+        for span in invalid_utf8 {
+            let node_id = self.create_node(AstNode::Garbage, span.start, span.end);
+            self.error_on_node(ErrorCode::InvalidUtf8, "invalid UTF-8 sequence", node_id);
+        }
+
+        let comments = self.tokens.remove_comments();
+        self.lint_comments(&comments);
+        self.comments = comments;
+        self.lex_statements();
+
         self.block(BlockContext::Bare);
 
+        if let Some(error) = self.lex_error.take() {
+            self.compiler.rollback(checkpoint);
+            self.compiler.push_lex_error(error);
+        }
+
         self.compiler
     }
 
+    /// Record the lint levels set by `# nu-lint: <level>(...)` comments
+    fn lint_comments(&mut self, comments: &[Span]) {
+        for span in comments.iter().copied() {
+            let comment = self.compiler.get_span_contents_manual(span.start, span.end);
+            let Some((level, lints)) = parse_lint_comment(comment) else {
+                continue;
+            };
+
+            // The comment applies from the start of its line until the end of the next line
+            let (_, line_start) = self.compiler.line_of(span.start);
+            let file_end = self
+                .compiler
+                .file_of(span)
+                .map_or(self.compiler.source.len(), |(_, _, end)| *end);
+            let end = self.compiler.source[span.end..file_end]
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| **c == b'\n')
+                .nth(1)
+                .map_or(file_end, |(pos, _)| span.end + pos);
+
+            for lint in lints {
+                self.compiler.lint_directives.push(LintDirective {
+                    lint,
+                    level,
+                    span: Span::new(line_start, end),
+                });
+            }
+        }
+    }
+
     pub fn expression_or_assignment(&mut self) -> NodeId {
         let _span = span!();
-        self.math_expression(true)
+        self.pipeline(ExpressionContext::Statement)
+    }
+
+    /// Expression, followed by the elements its value is piped to, if any
+    pub fn pipeline(&mut self, context: ExpressionContext) -> NodeId {
+        let _span = span!();
+        let span_start = self.position();
+
+        let first = if self.is_env_shorthand() {
+            self.env_shorthand()
+        } else {
+            self.math_expression(context)
+        };
+        if !self.is_pipe_after_newlines() {
+            return first;
+        }
+
+        let mut elements = vec![first];
+        while self.is_pipe_after_newlines() {
+            self.skip_newlines();
+            let previous = elements[elements.len() - 1];
+            if pipe_redirection(self.tokens.peek_token()).is_some()
+                && !self.is_redirected_to_pipe(previous)
+            {
+                let span = self.tokens.peek_span();
+                let redirection = self.create_node(AstNode::Garbage, span.start, span.end);
+                self.error_on_node(
+                    ErrorCode::InvalidRedirection,
+                    "only the output of a command can be redirected",
+                    redirection,
+                );
+            }
+            self.advance();
+            self.skip_newlines();
+            elements.push(self.expression());
+        }
+
+        let span_end = self.get_span_end(elements[elements.len() - 1]);
+        self.create_node(AstNode::Pipeline(elements), span_start, span_end)
+    }
+
+    /// Whether the node is a call ending with a redirection into the next element of the pipeline
+    fn is_redirected_to_pipe(&self, node_id: NodeId) -> bool {
+        let AstNode::Call { parts } = self.compiler.get_node(node_id) else {
+            return false;
+        };
+        parts.last().is_some_and(|part| {
+            matches!(
+                self.compiler.get_node(*part),
+                AstNode::Redirection { target: None, .. }
+            )
+        })
+    }
+
+    /// Command preceded by the environment variables to set for it, e.g., `FOO=bar BAZ=1 cmd`
+    pub fn env_shorthand(&mut self) -> NodeId {
+        let _span = span!();
+        let span_start = self.position();
+
+        let mut assignments = vec![];
+        while self.is_env_shorthand() {
+            let name = self.name();
+            let equals_span = self.tokens.peek_span();
+            self.advance();
+
+            let value = if self.is_horizontal_space() || self.is_statement_end() {
+                let value = self.create_node(AstNode::Garbage, equals_span.start, equals_span.end);
+                self.error_on_node(
+                    ErrorCode::ExpectedToken,
+                    "expected value of the environment variable after `=`",
+                    value,
+                );
+                value
+            } else {
+                self.simple_expression(BarewordContext::String)
+            };
+            assignments.push((name, value));
+        }
+
+        let block_start = self.position();
+        let command = if self.is_statement_end() || self.is_pipe() {
+            let command = self.create_node(AstNode::Garbage, span_start, block_start);
+            self.error_on_node(
+                ErrorCode::ExpectedToken,
+                "expected command after the environment variables",
+                command,
+            );
+            command
+        } else {
+            self.math_expression(ExpressionContext::Value)
+        };
+        let span_end = self.get_span_end(command);
+
+        self.compiler.blocks.push(Block::new(vec![command]));
+        let block = self.create_node(
+            AstNode::Block(BlockId(self.compiler.blocks.len() - 1)),
+            block_start,
+            span_end,
+        );
+
+        self.create_node(
+            AstNode::EnvShorthand { assignments, block },
+            span_start,
+            span_end,
+        )
     }
 
     pub fn expression(&mut self) -> NodeId {
         let _span = span!();
-        self.math_expression(false)
+        self.math_expression(ExpressionContext::Value)
+    }
+
+    /// Condition of `if` or `while`
+    pub fn condition(&mut self) -> NodeId {
+        let _span = span!();
+        self.math_expression(ExpressionContext::Condition)
     }
 
-    pub fn math_expression(&mut self, allow_assignment: bool) -> NodeId {
+    pub fn math_expression(&mut self, context: ExpressionContext) -> NodeId {
         let _span = span!();
         let mut expr_stack = Vec::<(NodeId, NodeId)>::new();
 
@@ -275,20 +1045,23 @@ impl Parser {
             return self.if_expression();
         } else if self.is_keyword(b"match") {
             return self.match_expression();
+        } else if self.is_keyword(b"try") {
+            return self.try_expression();
         }
         // TODO
         // } else if self.is_keyword(b"where") {
         // }
 
         // Otherwise assume a math expression
-        let mut leftmost = self.simple_expression(BarewordContext::Call);
+        let mut leftmost = self.condition_operand(context);
 
         if self.is_equals() {
-            if !allow_assignment {
-                self.error("assignment found in expression");
-            }
             let op = self.operator();
 
+            if context != ExpressionContext::Statement {
+                self.assignment_in_expression(op, context);
+            }
+
             let rhs = self.expression();
             let span_end = self.get_span_end(rhs);
 
@@ -310,23 +1083,38 @@ impl Parser {
                 let missing_space_after_op = !self.is_horizontal_space();
 
                 if missing_space_before_op {
-                    self.error_on_node("missing space before operator", op);
+                    self.error_on_node(
+                        ErrorCode::MissingSpace,
+                        "missing space before operator",
+                        op,
+                    );
                 }
 
                 if missing_space_after_op {
-                    self.error_on_node("missing space after operator", op);
+                    self.error_on_node(ErrorCode::MissingSpace, "missing space after operator", op);
                 }
 
                 let op_prec = self.operator_precedence(op);
+                let is_right_associative = matches!(self.compiler.get_node(op), AstNode::Pow);
 
-                if op_prec == ASSIGNMENT_PRECEDENCE && !allow_assignment {
-                    self.error_on_node("assignment found in expression", op);
+                if op_prec == ASSIGNMENT_PRECEDENCE && context != ExpressionContext::Statement {
+                    self.assignment_in_expression(op, context);
                 }
 
-                let rhs = if self.is_simple_expression() {
-                    self.simple_expression(BarewordContext::Call)
+                // `size > 10 and name == "x"` compares two fields of the row
+                let is_boolean_op = matches!(
+                    self.compiler.get_node(op),
+                    AstNode::And | AstNode::Or | AstNode::Xor
+                );
+                let rhs = if is_boolean_op {
+                    self.condition_operand(context)
+                } else if self.is_operand() {
+                    self.operand()
                 } else {
-                    self.error("incomplete math expression")
+                    self.error(
+                        ErrorCode::IncompleteExpression,
+                        "incomplete math expression",
+                    )
                 };
 
                 while op_prec <= last_prec {
@@ -336,7 +1124,8 @@ impl Parser {
 
                     last_prec = self.operator_precedence(op);
 
-                    if last_prec < op_prec {
+                    // `2 ** 3 ** 2` is `2 ** (3 ** 2)`, the other operators are left-associative
+                    if last_prec < op_prec || (last_prec == op_prec && is_right_associative) {
                         expr_stack.push((op, rhs));
                         break;
                     }
@@ -374,12 +1163,80 @@ impl Parser {
         leftmost
     }
 
+    /// Operand of a math expression, a bareword is a cell path on the row in a row condition
+    fn condition_operand(&mut self, context: ExpressionContext) -> NodeId {
+        let is_shorthand = context == ExpressionContext::RowCondition
+            && self.is_name()
+            && !self.is_keyword(b"not")
+            && !self.is_keyword(b"true")
+            && !self.is_keyword(b"false")
+            && !self.is_keyword(b"null");
+        if !is_shorthand {
+            return self.operand();
+        }
+
+        // the implicit `$it` is not in the source, its Variable node has an empty span
+        let span = self.tokens.peek_span();
+        let head = self.create_node(AstNode::Variable, span.start, span.start);
+        let first = self.advance_node(AstNode::Name, span);
+        let optional = self.is_question_mark() && !self.is_horizontal_space();
+        let mut span_end = span.end;
+        if optional {
+            span_end = self.tokens.peek_span().end;
+            self.advance();
+        }
+
+        let members = vec![CellPathMember {
+            member: first,
+            optional,
+        }];
+        self.cell_path_members(head, span.start, span_end, members)
+    }
+
+    /// Operand of a math expression, applying the `not` or `-` in front of it
+    ///
+    /// The unary operators bind tighter than any binary operator, `not $a and $b` is
+    /// `(not $a) and $b`.
+    pub fn operand(&mut self) -> NodeId {
+        let _span = span!();
+
+        let (_, span) = self.tokens.peek();
+        let op = if self.is_keyword(b"not") {
+            self.advance_node(AstNode::Not, span)
+        } else if self.is_negation() {
+            self.advance_node(AstNode::Minus, span)
+        } else {
+            return self.simple_expression(BarewordContext::Call);
+        };
+
+        let value = if self.is_operand() {
+            self.operand()
+        } else {
+            self.error(
+                ErrorCode::IncompleteExpression,
+                "incomplete unary operation",
+            )
+        };
+        let span_end = self.get_span_end(value);
+
+        self.create_node(AstNode::UnaryOp { op, value }, span.start, span_end)
+    }
+
     pub fn simple_expression(&mut self, bareword_context: BarewordContext) -> NodeId {
+        self.simple_expression_or_range_bound(bareword_context, false)
+    }
+
+    /// Same as simple_expression(), a bound does not continue with another range
+    fn simple_expression_or_range_bound(
+        &mut self,
+        bareword_context: BarewordContext,
+        is_range_bound: bool,
+    ) -> NodeId {
         let _span = span!();
 
-        // skip comments and newlines
-        while self.is_comment() || self.is_newline() {
-            self.tokens.advance();
+        // skip newlines
+        while self.is_newline() {
+            self.advance();
         }
 
         let span_start = self.position();
@@ -389,21 +1246,39 @@ impl Parser {
         let mut expr = match token {
             Token::LCurly => self.record_or_closure(),
             Token::LParen => {
-                self.tokens.advance();
+                self.advance();
                 if self.tokens.peek_token() == Token::RParen {
-                    self.error("use null instead of ()")
+                    self.error(ErrorCode::EmptyParens, "use null instead of ()")
                 } else {
-                    let output = self.expression();
-                    self.rparen();
-                    output
+                    self.subexpression(span)
                 }
             }
             Token::LSquare => self.list_or_table(),
-            Token::Int => self.advance_node(AstNode::Int, span),
+            Token::DotDot if !is_range_bound => return self.range(None, span_start),
+            Token::Int => self.int(span),
+            Token::Dash if self.is_negative_int() => {
+                self.advance();
+                let (_, int_span) = self.tokens.peek();
+                self.int(Span::new(span.start, int_span.end))
+            }
+            Token::Dash if self.is_negative_unit_value() => {
+                self.advance();
+                let (token, value_span) = self.tokens.peek();
+                self.unit_value(token, Span::new(span.start, value_span.end))
+            }
             Token::Float => self.advance_node(AstNode::Float, span),
+            Token::Filesize | Token::Duration => self.unit_value(token, span),
+            Token::Binary => self.binary(span),
+            Token::Datetime => self.datetime(span),
             Token::DoubleQuotedString => self.advance_node(AstNode::String, span),
             Token::SingleQuotedString => self.advance_node(AstNode::String, span),
+            Token::RawString | Token::BacktickBareword => {
+                self.advance_node(AstNode::RawString, span)
+            }
             Token::Dollar => self.variable(),
+            Token::DqStringInterpStart | Token::SqStringInterpStart => self.string_interpolation(),
+            // `^foo` always calls the external command
+            Token::Caret if matches!(bareword_context, BarewordContext::Call) => self.call(),
             Token::Bareword => match self.compiler.get_span_contents_manual(span.start, span.end) {
                 b"true" => self.advance_node(AstNode::True, span),
                 b"false" => self.advance_node(AstNode::False, span),
@@ -417,132 +1292,596 @@ impl Parser {
                     BarewordContext::Call => self.call(),
                 },
             },
-            _ => self.error("incomplete expression"),
+            _ => self.error(ErrorCode::IncompleteExpression, "incomplete expression"),
         };
 
         loop {
             if self.is_horizontal_space() {
                 return expr;
-            } else if self.is_dotdot() {
-                // Range
-                self.tokens.advance();
-
-                if self.is_horizontal_space() {
-                    // TODO: implement range from
-                    //
-                    // TODO: tweak the garbage location.
-                    self.error("incomplete range");
-                    return expr;
-                } else {
-                    let rhs = self.simple_expression(BarewordContext::String);
-                    let span_end = self.get_span_end(rhs);
-
-                    expr =
-                        self.create_node(AstNode::Range { lhs: expr, rhs }, span_start, span_end);
-                }
-            } else if self.is_dot() {
-                // Member access
-                self.tokens.advance();
-
-                if self.is_horizontal_space() {
-                    self.error("missing path name");
-                    return expr;
-                }
-
-                let name = self.name();
-
-                let field_or_call = if self.is_lparen() {
-                    self.variable()
-                } else {
-                    name
-                };
-                let span_end = self.get_span_end(field_or_call);
-
-                match self.compiler.get_node_mut(field_or_call) {
-                    AstNode::Variable | AstNode::Name => {
-                        expr = self.create_node(
-                            AstNode::MemberAccess {
-                                target: expr,
-                                field: field_or_call,
-                            },
-                            span_start,
-                            span_end,
-                        );
-                    }
-                    _ => {
-                        self.error("expected field");
-                    }
-                }
+            } else if self.is_dotdot() && !is_range_bound {
+                return self.range(Some(expr), span_start);
+            } else if self.is_dot() || self.is_cell_path_index() {
+                expr = self.cell_path(expr, span_start);
             } else {
                 return expr;
             }
         }
     }
 
-    pub fn advance_node(&mut self, node: AstNode, span: Span) -> NodeId {
-        self.tokens.advance();
-        self.create_node(node, span.start, span.end)
+    /// Cell path accessing members of `head`, e.g., `.foo."bar baz"?.0`
+    fn cell_path(&mut self, head: NodeId, span_start: usize) -> NodeId {
+        let span_end = self.get_span_end(head);
+        self.cell_path_members(head, span_start, span_end, vec![])
     }
 
-    pub fn variable(&mut self) -> NodeId {
-        if self.is_dollar() {
-            let span_start = self.position();
-            self.tokens.advance();
+    /// Cell path continuing after the members parsed already
+    fn cell_path_members(
+        &mut self,
+        head: NodeId,
+        span_start: usize,
+        mut span_end: usize,
+        mut members: Vec<CellPathMember>,
+    ) -> NodeId {
+        let _span = span!();
 
-            if let (Token::Bareword, name_span) = self.tokens.peek() {
-                self.tokens.advance();
-                self.create_node(AstNode::Variable, span_start, name_span.end)
+        loop {
+            let (token, span) = self.tokens.peek();
+            let member = if self.is_cell_path_index() {
+                // `.0` is lexed as a float
+                self.advance();
+                self.create_node(AstNode::Int, span.start + 1, span.end)
+            } else if token == Token::Dot {
+                self.advance();
+
+                let (token, member_span) = self.tokens.peek();
+                let member = match token {
+                    _ if self.is_horizontal_space() => None,
+                    Token::Bareword => Some(AstNode::Name),
+                    Token::DoubleQuotedString | Token::SingleQuotedString => Some(AstNode::String),
+                    Token::Int => Some(AstNode::Int),
+                    _ => None,
+                };
+                let Some(member) = member else {
+                    // the dot is the error, the next token may start another expression
+                    let message = match token {
+                        Token::Newline | Token::Eof => "missing path name",
+                        _ if self.is_horizontal_space() => "missing path name",
+                        _ => "expected field",
+                    };
+                    let garbage = self.create_node(AstNode::Garbage, span.start, span.end);
+                    self.error_on_node(ErrorCode::InvalidCellPath, message, garbage);
+                    break;
+                };
+                self.advance_node(member, member_span)
             } else {
-                self.error("variable name must be a bareword")
+                break;
+            };
+            span_end = self.get_span_end(member);
+
+            let optional = self.is_question_mark() && !self.is_horizontal_space();
+            if optional {
+                span_end = self.tokens.peek_span().end;
+                self.advance();
             }
-        } else {
-            self.error("expected variable starting with '$'")
+            members.push(CellPathMember { member, optional });
+        }
+
+        if members.is_empty() {
+            return head;
         }
+        self.create_node(AstNode::CellPath { head, members }, span_start, span_end)
     }
 
-    pub fn variable_decl(&mut self) -> NodeId {
+    /// Range starting at the `..` after `from`
+    fn range(&mut self, from: Option<NodeId>, span_start: usize) -> NodeId {
         let _span = span!();
 
-        let span_start = self.position();
+        let mut span_end = self.range_operator();
+        let mut exclusive = self.is_range_exclusive();
+        if exclusive {
+            span_end = self.tokens.peek_span().end;
+            self.advance();
+        }
 
-        if self.is_dollar() {
-            self.tokens.advance();
+        let mut next = None;
+        let mut to = self.range_bound();
+
+        // `from..next..to`
+        if !exclusive && to.is_some() && self.is_dotdot() && !self.is_horizontal_space() {
+            next = to;
+            span_end = self.range_operator();
+            exclusive = self.is_range_exclusive();
+            if exclusive {
+                span_end = self.tokens.peek_span().end;
+                self.advance();
+            }
+            to = self.range_bound();
         }
 
-        if let (Token::Bareword, name_span) = self.tokens.peek() {
-            self.tokens.advance();
-            self.create_node(AstNode::Variable, span_start, name_span.end)
-        } else {
-            self.error("variable assignment name must be a bareword")
+        if let Some(to) = to {
+            span_end = self.get_span_end(to);
+        }
+
+        let range = self.create_node(
+            AstNode::Range {
+                from,
+                next,
+                to,
+                exclusive,
+            },
+            span_start,
+            span_end,
+        );
+        if from.is_none() && to.is_none() {
+            self.error_on_node(ErrorCode::IncompleteExpression, "incomplete range", range);
         }
+        range
     }
 
-    pub fn call(&mut self) -> NodeId {
-        let _span = span!();
-        let mut parts = vec![self.call_name()];
-        let mut is_head = true;
-        let span_start = self.position();
+    /// Advance over the `..` of a range, returning its end
+    fn range_operator(&mut self) -> usize {
+        let span_end = self.tokens.peek_span().end;
+        self.advance();
+        span_end
+    }
 
-        while self.has_tokens() {
-            if self.is_newline() {
-                break;
-            }
+    /// `<` directly after `..`
+    fn is_range_exclusive(&mut self) -> bool {
+        self.is_less_than() && !self.is_horizontal_space()
+    }
 
-            if self.is_name() && is_head {
-                parts.push(self.name());
-                continue;
-            }
+    /// The bound directly after `..`, None for an open-ended range
+    fn range_bound(&mut self) -> Option<NodeId> {
+        if self.is_horizontal_space() || !self.is_simple_expression() {
+            return None;
+        }
+        Some(self.simple_expression_or_range_bound(BarewordContext::String, true))
+    }
 
-            // TODO: Add flags
+    /// Binary literal at the current token
+    fn binary(&mut self, span: Span) -> NodeId {
+        let node_id = self.advance_node(AstNode::Binary, span);
+        if binary_literal_value(self.compiler.get_span_contents(node_id)).is_err() {
+            self.error_on_node(
+                ErrorCode::IntOverflow,
+                "byte of binary literal is out of range",
+                node_id,
+            );
+        }
+        node_id
+    }
+
+    /// Datetime literal at the current token
+    fn datetime(&mut self, span: Span) -> NodeId {
+        let node_id = self.advance_node(AstNode::Datetime, span);
+        if let Err(err) = datetime_literal_value(self.compiler.get_span_contents(node_id)) {
+            self.error_on_node(
+                ErrorCode::InvalidDatetime,
+                format!("invalid datetime literal: {err}"),
+                node_id,
+            );
+        }
+        node_id
+    }
+
+    /// Filesize or duration literal, the token is the one of the value
+    fn unit_value(&mut self, token: Token, span: Span) -> NodeId {
+        let (node, units, name) = match token {
+            Token::Filesize => (AstNode::Filesize, FILESIZE_UNITS, "filesize"),
+            _ => (AstNode::Duration, DURATION_UNITS, "duration"),
+        };
+        let node_id = self.advance_node(node, span);
+
+        if unit_literal_value(self.compiler.get_span_contents(node_id), units).is_err() {
+            self.error_on_node(
+                ErrorCode::IntOverflow,
+                format!("{name} literal is out of range"),
+                node_id,
+            );
+        }
+        node_id
+    }
+
+    /// Int literal at the current token, `span` includes the minus sign of a negative literal
+    fn int(&mut self, span: Span) -> NodeId {
+        let node_id = self.advance_node(AstNode::Int, span);
+        let contents = self.compiler.get_span_contents(node_id);
+
+        let Err(err) = int_literal_value(contents) else {
+            return node_id;
+        };
+
+        if !matches!(
+            err.kind(),
+            IntErrorKind::PosOverflow | IntErrorKind::NegOverflow
+        ) {
+            return node_id;
+        }
+
+        let range = format!("between {} and {}", i64::MIN, i64::MAX);
+        let help = if contents.iter().any(|c| matches!(c, b'x' | b'o' | b'b')) {
+            format!("use an int {range}")
+        } else {
+            let float = String::from_utf8_lossy(contents).replace('_', "");
+            format!("use a float, e.g., `{float}.0`, or an int {range}")
+        };
+
+        self.error_on_node(
+            ErrorCode::IntOverflow,
+            "integer literal is out of range for int",
+            node_id,
+        );
+        if let Some(error) = self.compiler.errors.last_mut() {
+            error.help = Some(help);
+        }
+
+        node_id
+    }
+
+    pub fn advance_node(&mut self, node: AstNode, span: Span) -> NodeId {
+        self.advance();
+        self.create_node(node, span.start, span.end)
+    }
+
+    pub fn variable(&mut self) -> NodeId {
+        if self.is_dollar() {
+            let span_start = self.position();
+            self.advance();
+
+            if let (Token::Bareword, name_span) = self.tokens.peek() {
+                self.advance();
+                let variable = self.create_node(AstNode::Variable, span_start, name_span.end);
+                let name = self
+                    .compiler
+                    .get_span_contents_manual(name_span.start, name_span.end);
+                if let Some(value) = self
+                    .find_constant(name)
+                    .and_then(|name| self.compiler.constants.get(&name))
+                {
+                    self.compiler.constants.insert(variable, value.clone());
+                }
+                variable
+            } else {
+                self.error(
+                    ErrorCode::InvalidVariableName,
+                    "variable name must be a bareword",
+                )
+            }
+        } else {
+            self.error(
+                ErrorCode::InvalidVariableName,
+                "expected variable starting with '$'",
+            )
+        }
+    }
+
+    pub fn variable_decl(&mut self) -> NodeId {
+        let _span = span!();
+
+        let span_start = self.position();
+
+        if self.is_dollar() {
+            self.advance();
+        }
+
+        if let (Token::Bareword, name_span) = self.tokens.peek() {
+            self.advance();
+            let name = self
+                .compiler
+                .get_span_contents_manual(name_span.start, name_span.end);
+            self.shadow_constant(name.to_vec());
+            self.create_node(AstNode::Variable, span_start, name_span.end)
+        } else {
+            self.error(
+                ErrorCode::InvalidVariableName,
+                "variable assignment name must be a bareword",
+            )
+        }
+    }
+
+    /// Name node of the `const` of the constant, if the name refers to a constant here
+    fn find_constant(&self, name: &[u8]) -> Option<NodeId> {
+        self.constants
+            .iter()
+            .rev()
+            .find(|(constant, _)| constant == name)
+            .and_then(|(_, name)| *name)
+    }
+
+    /// The variable declared here is not the constant of the same name, if there is one
+    ///
+    /// The shadowing lasts until the end of the block, even for parameters visible only in the
+    /// body of their definition. A variable is never taken for a constant that way, at worst a
+    /// constant is not known in a few places.
+    fn shadow_constant(&mut self, name: Vec<u8>) {
+        if self.find_constant(&name).is_some() {
+            self.constants.push((name, None));
+        }
+    }
+
+    pub fn call(&mut self) -> NodeId {
+        let _span = span!();
+        let name = self.call_name();
+        self.lint_deprecated_command(name);
+        let mut parts = vec![name];
+        let mut is_head = true;
+        let span_start = self.position();
+        let mut span_end = None;
+
+        while self.has_tokens() {
+            // the closing delimiter of the enclosing subexpression or block ends the call
+            if self.is_newline()
+                || self.is_pipe()
+                || self.is_semicolon()
+                || self.is_rparen()
+                || self.is_rcurly()
+            {
+                break;
+            }
+
+            if let Some(source) = pipe_redirection(self.tokens.peek_token()) {
+                // the token stays, it continues the pipeline
+                let span = self.tokens.peek_span();
+                parts.push(self.create_node(
+                    AstNode::Redirection {
+                        source,
+                        target: None,
+                        append: false,
+                    },
+                    span.start,
+                    span.end,
+                ));
+                span_end = Some(span.end);
+                break;
+            }
+
+            if file_redirection(self.tokens.peek_token()).is_some() {
+                is_head = false;
+                parts.push(self.redirection());
+                continue;
+            }
+
+            if parts.len() == 1 && self.is_row_condition_command(name) && !self.is_lcurly() {
+                is_head = false;
+                parts.push(self.row_condition());
+                continue;
+            }
+
+            if self.is_bare_word() {
+                is_head = false;
+                parts.push(self.bare_word());
+                continue;
+            }
+
+            if self.is_name() && is_head {
+                parts.push(self.name());
+                continue;
+            }
 
             is_head = false;
+            if self.is_flag() {
+                parts.push(self.flag());
+                continue;
+            }
+
+            if self.is_dotdotdot() {
+                parts.push(self.spread());
+                continue;
+            }
+
             let arg_id = self.simple_expression(BarewordContext::String);
             parts.push(arg_id);
         }
 
-        let span_end = self.position();
+        let span_end = span_end.unwrap_or_else(|| self.position());
+
+        let call = self.create_node(
+            AstNode::Call {
+                parts: parts.clone(),
+            },
+            span_start,
+            span_end,
+        );
+        self.load_source(call, &parts);
+        call
+    }
+
+    fn is_row_condition_command(&self, name: NodeId) -> bool {
+        ROW_CONDITION_COMMANDS.contains(&self.compiler.get_span_contents(name))
+    }
+
+    /// Row condition, e.g., `size > 10` of `where size > 10`, parsed as the closure
+    /// `{|it| $it.size > 10 }`
+    ///
+    /// The closure's parameter is not in the source, its name has an empty span.
+    pub fn row_condition(&mut self) -> NodeId {
+        let _span = span!();
+        let span_start = self.position();
+
+        let name = self.create_node(AstNode::Name, span_start, span_start);
+        let param = self.create_node(
+            AstNode::Param {
+                name,
+                ty: None,
+                kind: ParamKind::Required,
+                short: None,
+                default: None,
+            },
+            span_start,
+            span_start,
+        );
+        let params = self.create_node(AstNode::Params(vec![param]), span_start, span_start);
+
+        let condition = self.math_expression(ExpressionContext::RowCondition);
+        let span_end = self.get_span_end(condition);
+
+        self.compiler.blocks.push(Block::new(vec![condition]));
+        let block = self.create_node(
+            AstNode::Block(BlockId(self.compiler.blocks.len() - 1)),
+            span_start,
+            span_end,
+        );
+        self.create_node(
+            AstNode::Closure {
+                params: Some(params),
+                block,
+            },
+            span_start,
+            span_end,
+        )
+    }
 
-        self.create_node(AstNode::Call { parts }, span_start, span_end)
+    /// `o> file` or `o>> file`, redirecting a stream of the call to the file
+    pub fn redirection(&mut self) -> NodeId {
+        let _span = span!();
+        let (token, operator) = self.tokens.peek();
+        let (source, append) =
+            file_redirection(token).expect("internal error: missing file redirection");
+        self.advance();
+
+        let target = if self.is_statement_end() || self.is_pipe() {
+            let target = self.create_node(AstNode::Garbage, operator.start, operator.end);
+            self.error_on_node(
+                ErrorCode::InvalidRedirection,
+                "expected file path after the redirection",
+                target,
+            );
+            target
+        } else if self.is_bare_word() {
+            self.bare_word()
+        } else {
+            self.simple_expression(BarewordContext::String)
+        };
+        let span_end = self.get_span_end(target);
+
+        self.create_node(
+            AstNode::Redirection {
+                source,
+                target: Some(target),
+                append,
+            },
+            operator.start,
+            span_end,
+        )
+    }
+
+    /// Argument without quotes that is not an expression, e.g., the path `../src/main.rs`
+    ///
+    /// It is a string, or a glob pattern if it has wildcards. The typechecker converts between
+    /// them according to the parameter of the called command.
+    pub fn bare_word(&mut self) -> NodeId {
+        let _span = span!();
+        let (token, span) = self.tokens.peek();
+        let span_start = span.start;
+        let mut span_end = span.end;
+        let mut is_glob = is_wildcard(token);
+        self.advance();
+
+        loop {
+            let (token, span) = self.tokens.peek();
+            if span.start != span_end || !BARE_WORD_TOKENS.contains(&token) {
+                break;
+            }
+            is_glob |= is_wildcard(token);
+            span_end = span.end;
+            self.advance();
+        }
+
+        let node = if is_glob {
+            AstNode::GlobPattern
+        } else {
+            AstNode::String
+        };
+        self.create_node(node, span_start, span_end)
+    }
+
+    /// Load the file of a `source` or `use` call with a literal or constant path
+    fn load_source(&mut self, call: NodeId, parts: &[NodeId]) {
+        if self.compiler.file_provider.is_none() {
+            return;
+        }
+
+        let first_start = self.compiler.get_span(parts[0]).start;
+        let Some((name_parts, command)) = parts
+            .iter()
+            .take_while(|part| self.compiler.ast_nodes[part.0] == AstNode::Name)
+            .enumerate()
+            .find_map(|(idx, part)| {
+                let end = self.compiler.get_span(*part).end;
+                let name = self.compiler.get_span_contents_manual(first_start, end);
+                PARSE_TIME_COMMANDS
+                    .iter()
+                    .find(|command| command.as_bytes() == name)
+                    .map(|command| (idx + 1, *command))
+            })
+        else {
+            return;
+        };
+
+        let Some(path) = parts.get(name_parts) else {
+            return;
+        };
+        let Some(relative) = self.compiler.parse_time_path(*path) else {
+            return;
+        };
+
+        // modules can be used by their name as well, e.g., `use std`
+        let is_file = command.starts_with("source") || relative.ends_with(b".nu");
+        if is_file {
+            self.compiler.load_source(call, *path);
+        }
+    }
+
+    /// String interpolation, the lexer already split it into the chunks and the subexpressions
+    pub fn string_interpolation(&mut self) -> NodeId {
+        let _span = span!();
+        let span_start = self.position();
+        self.advance();
+
+        let mut parts = vec![];
+        loop {
+            let (token, span) = self.tokens.peek();
+            match token {
+                Token::StrInterpChunk => parts.push(self.advance_node(AstNode::String, span)),
+                Token::StrInterpLParen => {
+                    self.advance();
+                    parts.push(self.expression());
+
+                    if self.tokens.peek_token() == Token::StrInterpRParen {
+                        self.advance();
+                    } else {
+                        self.error(
+                            ErrorCode::IncompleteExpression,
+                            "expected: right paren ')' closing the subexpression",
+                        );
+                        while !matches!(
+                            self.tokens.peek_token(),
+                            Token::StrInterpRParen | Token::StrInterpEnd | Token::Eof
+                        ) {
+                            self.advance();
+                        }
+                        if self.tokens.peek_token() == Token::StrInterpRParen {
+                            self.advance();
+                        }
+                    }
+                }
+                Token::StrInterpEnd => {
+                    self.advance();
+                    return self.create_node(
+                        AstNode::StringInterpolation(parts),
+                        span_start,
+                        span.end,
+                    );
+                }
+                _ => {
+                    // the lexer reported the error already
+                    return self.error(
+                        ErrorCode::IncompleteExpression,
+                        "incomplete string interpolation",
+                    );
+                }
+            }
+        }
     }
 
     pub fn list_or_table(&mut self) -> NodeId {
@@ -551,38 +1890,49 @@ impl Parser {
         let mut is_table = false;
         let mut items = vec![];
 
-        self.lsquare();
-        let mut span_end = self.position();
+        let opened = self.lsquare();
+        let span_end;
 
         loop {
             if self.is_rsquare() {
                 span_end = self.position();
-                self.tokens.advance();
+                self.advance();
+                break;
+            } else if self.is_rparen() || self.is_rcurly() || self.is_eof() {
+                // closes an enclosing expression, the list is missing its `]`
+                span_end = self.position();
+                self.rsquare(opened);
                 break;
             } else if self.is_comma() || self.is_newline() {
                 // TODO: should we disallow `[,,,]`?
-                self.tokens.advance();
+                self.advance();
             } else if self.is_semicolon() {
                 if items.len() != 1 {
-                    self.error("semicolon to create table should immediately follow headers");
+                    self.error(
+                        ErrorCode::InvalidTable,
+                        "semicolon to create table should immediately follow headers",
+                    );
                 } else if !matches!(self.compiler.get_node(items[0]), AstNode::List(_)) {
-                    self.error_on_node("tables require a list for their headers", items[0])
+                    self.error_on_node(
+                        ErrorCode::InvalidTable,
+                        "tables require a list for their headers",
+                        items[0],
+                    )
                 }
-                self.tokens.advance();
+                self.advance();
                 is_table = true;
+            } else if self.is_dotdotdot() {
+                items.push(self.spread());
             } else if self.is_simple_expression() {
                 items.push(self.simple_expression(BarewordContext::String));
             } else {
-                items.push(self.error("expected list item"));
-                if self.is_eof() {
-                    // prevent forever looping if there is no token to put the error on
-                    break;
-                }
+                items.push(self.error(ErrorCode::ExpectedToken, "expected list item"));
             }
         }
 
         if is_table {
             let header = items.remove(0);
+            self.check_table_rows(header, &items);
             self.create_node(
                 AstNode::Table {
                     header,
@@ -596,6 +1946,29 @@ impl Parser {
         }
     }
 
+    /// Every row of a table must be a list with one item per column
+    fn check_table_rows(&mut self, header: NodeId, rows: &[NodeId]) {
+        let AstNode::List(columns) = self.compiler.get_node(header) else {
+            // reported already
+            return;
+        };
+        let num_columns = columns.len();
+
+        for row in rows {
+            match self.compiler.get_node(*row) {
+                AstNode::List(values) if values.len() != num_columns => {
+                    let message = format!(
+                        "table row has {} columns, but the header has {num_columns}",
+                        values.len()
+                    );
+                    self.error_on_node(ErrorCode::InvalidTable, message, *row);
+                }
+                AstNode::List(_) => (),
+                _ => self.error_on_node(ErrorCode::InvalidTable, "table rows must be lists", *row),
+            }
+        }
+    }
+
     pub fn record_or_closure(&mut self) -> NodeId {
         let _span = span!();
         let span_start = self.position();
@@ -606,15 +1979,15 @@ impl Parser {
         // For the record
         let mut items = vec![];
 
-        self.lcurly();
+        let opened = self.lcurly();
         self.skip_newlines();
 
         // Explicit closure case
-        if self.is_pipe() {
-            let args = Some(self.signature_params(ParamsContext::Pipes));
+        if self.is_pipe() || self.tokens.peek_token() == Token::PipePipe {
+            let args = Some(self.closure_params());
             let block = self.block(BlockContext::Closure);
-            self.rcurly();
-            span_end = self.position();
+            span_end = self.closing_curly_end();
+            self.rcurly(opened);
 
             return self.create_node(
                 AstNode::Closure {
@@ -630,20 +2003,31 @@ impl Parser {
         loop {
             self.skip_newlines();
             if self.is_rcurly() {
-                self.rcurly();
-                span_end = self.position();
+                span_end = self.closing_curly_end();
+                self.rcurly(opened);
                 break;
             }
-            let key = self.simple_expression(BarewordContext::String);
-            self.skip_newlines();
-            if first_pass && !self.is_colon() {
-                is_closure = true;
+            if !first_pass && (self.is_rparen() || self.is_rsquare()) {
+                // closes an enclosing expression, the record is missing its `}`
+                span_end = self.position();
+                self.rcurly(opened);
                 break;
             }
-            self.colon();
-            self.skip_newlines();
-            let val = self.simple_expression(BarewordContext::String);
-            items.push((key, val));
+            if self.is_dotdotdot() {
+                // a block cannot start with `...`, so this is a record
+                items.push(RecordEntry::Spread(self.spread()));
+            } else {
+                let key = self.simple_expression(BarewordContext::String);
+                self.skip_newlines();
+                if first_pass && !self.is_colon() {
+                    is_closure = true;
+                    break;
+                }
+                self.colon();
+                self.skip_newlines();
+                let val = self.simple_expression(BarewordContext::String);
+                items.push(RecordEntry::Pair(key, val));
+            }
             first_pass = false;
 
             if self.is_comma() {
@@ -658,9 +2042,8 @@ impl Parser {
         if is_closure {
             self.apply_rollback(rollback_point);
             let block = self.block(BlockContext::Closure);
-            self.rcurly();
-
-            span_end = self.position();
+            span_end = self.closing_curly_end();
+            self.rcurly(opened);
 
             self.create_node(
                 AstNode::Closure {
@@ -671,11 +2054,31 @@ impl Parser {
                 span_end,
             )
         } else {
-            self.create_node(AstNode::Record { pairs: items }, span_start, span_end)
+            self.create_node(AstNode::Record { entries: items }, span_start, span_end)
         }
     }
 
+    /// `...` directly followed by the value to spread
+    pub fn spread(&mut self) -> NodeId {
+        let _span = span!();
+        let span_start = self.position();
+
+        self.advance();
+        let value = self.simple_expression(BarewordContext::String);
+        let span_end = self.get_span_end(value);
+
+        self.create_node(AstNode::Spread(value), span_start, span_end)
+    }
+
     pub fn operator(&mut self) -> NodeId {
+        if let Some((op, span)) = self.dashed_operator() {
+            // the words and the dash between them
+            for _ in 0..3 {
+                self.advance();
+            }
+            return self.create_node(op, span.start, span.end);
+        }
+
         let (token, span) = self.tokens.peek();
 
         match token {
@@ -706,15 +2109,31 @@ impl Parser {
                 b"and" => self.advance_node(AstNode::And, span),
                 b"xor" => self.advance_node(AstNode::Xor, span),
                 b"or" => self.advance_node(AstNode::Or, span),
-                op => self.error(format!(
-                    "Unknown operator: '{}'",
-                    String::from_utf8_lossy(op)
-                )),
+                _ => self.error(
+                    ErrorCode::UnknownOperator,
+                    Message::source("Unknown operator: '", span, "'"),
+                ),
             },
-            _ => self.error("expected: operator"),
+            _ => self.error(ErrorCode::ExpectedToken, "expected: operator"),
         }
     }
 
+    /// Operator of `DASHED_OPERATORS` at the current token, with its span
+    fn dashed_operator(&self) -> Option<(AstNode, Span)> {
+        let (token, span) = self.tokens.peek();
+        if token != Token::Bareword {
+            return None;
+        }
+
+        let source = &self.compiler.source;
+        DASHED_OPERATORS.iter().find_map(|(name, op)| {
+            let end = span.start + name.len();
+            let is_word_end = source.get(end).map_or(true, |c| c.is_ascii_whitespace());
+            (source[span.start..].starts_with(name) && is_word_end)
+                .then(|| (op.clone(), Span::new(span.start, end)))
+        })
+    }
+
     pub fn operator_precedence(&mut self, operator: NodeId) -> usize {
         self.compiler.get_node(operator).precedence()
     }
@@ -730,30 +2149,36 @@ impl Parser {
         match self.tokens.peek() {
             (Token::DoubleQuotedString, span) => self.advance_node(AstNode::String, span),
             (Token::SingleQuotedString, span) => self.advance_node(AstNode::String, span),
-            _ => self.error("expected: string"),
+            _ => self.error(ErrorCode::ExpectedToken, "expected: string"),
         }
     }
 
     pub fn name(&mut self) -> NodeId {
         match self.tokens.peek() {
             (Token::Bareword, span) => self.advance_node(AstNode::Name, span),
-            _ => self.error("expected: name"),
+            _ => self.error(ErrorCode::ExpectedToken, "expected: name"),
         }
     }
 
     pub fn call_name(&mut self) -> NodeId {
+        let span = self.name_span();
+        self.create_node(AstNode::Name, span.start, span.end)
+    }
+
+    /// Advance past the tokens forming a name without whitespace, e.g., `def-env`
+    fn name_span(&mut self) -> Span {
         let (mut token, mut span) = self.tokens.peek();
 
         loop {
-            if [Token::Eof, Token::Newline].contains(&token) {
+            if NAME_END_TOKENS.contains(&token) {
                 break;
             }
 
-            self.tokens.advance();
+            self.advance();
             let (next_token, next_span) = self.tokens.peek();
 
-            if next_span.start > span.end {
-                // horizontal whitespace
+            // horizontal whitespace, or the end of the statement or pipeline element
+            if next_span.start > span.end || NAME_END_TOKENS.contains(&next_token) {
                 break;
             }
 
@@ -761,57 +2186,263 @@ impl Parser {
             span.end = next_span.end;
         }
 
-        self.create_node(AstNode::Name, span.start, span.end)
+        span
+    }
+
+    /// Whether the statement starts with a deprecated command, which might be mistaken for a
+    /// keyword otherwise (`def-env` is lexed as `def`, `-` and `env`)
+    fn is_deprecated_command(&mut self) -> bool {
+        let pos = self.tokens.pos();
+        let span = self.name_span();
+        self.tokens.set_pos(pos);
+
+        let name = self.compiler.get_span_contents_manual(span.start, span.end);
+        DEPRECATED_COMMANDS
+            .iter()
+            .any(|(old, _)| old.as_bytes() == name)
+    }
+
+    /// Whether the statement starts with the name, which can consist of several tokens
+    fn is_statement_name(&mut self, name: &[u8]) -> bool {
+        let pos = self.tokens.pos();
+        let span = self.name_span();
+        self.tokens.set_pos(pos);
+
+        self.compiler.get_span_contents_manual(span.start, span.end) == name
+    }
+
+    fn lint_deprecated_command(&mut self, name: NodeId) {
+        let contents = self.compiler.get_span_contents(name);
+        let Some((_, new)) = DEPRECATED_COMMANDS
+            .iter()
+            .find(|(old, _)| old.as_bytes() == contents)
+        else {
+            return;
+        };
+
+        if let Some(mut error) = self.compiler.lint(
+            Lint::DeprecatedSyntax,
+            Message::source("`", self.compiler.get_span(name), "` is deprecated"),
+            name,
+        ) {
+            error.help = Some(format!("use `{new}` instead"));
+            self.compiler.errors.push(error);
+        }
+    }
+
+    pub fn has_tokens(&mut self) -> bool {
+        self.tokens.peek_token() != Token::Eof
     }
 
-    pub fn has_tokens(&mut self) -> bool {
-        self.tokens.peek_token() != Token::Eof
+    pub fn match_expression(&mut self) -> NodeId {
+        let _span = span!();
+        let span_start = self.position();
+        let span_end;
+
+        self.keyword(b"match");
+        let target = self.simple_expression(BarewordContext::String);
+
+        let mut match_arms = vec![];
+
+        if !self.is_lcurly() {
+            return self.error(ErrorCode::ExpectedToken, "expected left curly brace '{'");
+        }
+
+        let opened = self.lcurly();
+
+        loop {
+            if self.is_rcurly() {
+                span_end = self.position() + 1;
+                self.rcurly(opened);
+                break;
+            } else if self.is_simple_expression() {
+                let pattern = self.match_pattern();
+
+                if !self.is_thick_arrow() {
+                    return self.error(
+                        ErrorCode::ExpectedToken,
+                        "expected thick arrow (=>) between match cases",
+                    );
+                }
+                self.advance();
+
+                // a bareword is a string like in a list, anything else can be a math expression
+                let pattern_result = if self.is_name() || self.is_bare_word() {
+                    self.simple_expression(BarewordContext::String)
+                } else {
+                    self.expression()
+                };
+
+                if self.is_comma() {
+                    self.advance();
+                }
+
+                match_arms.push((pattern, pattern_result));
+            } else if self.is_newline() {
+                self.advance();
+            } else {
+                return self.error(ErrorCode::ExpectedToken, "expected match arm in match");
+            }
+        }
+
+        self.create_node(AstNode::Match { target, match_arms }, span_start, span_end)
+    }
+
+    /// Pattern of a `match` arm with its alternatives and its guard, e.g., `1 | 2 if $y > 0`
+    fn match_pattern(&mut self) -> NodeId {
+        let _span = span!();
+        let span_start = self.position();
+
+        let mut alternatives = vec![self.pattern()];
+        while self.is_pipe() {
+            self.pipe();
+            alternatives.push(self.pattern());
+        }
+
+        let mut pattern = alternatives[0];
+        if alternatives.len() > 1 {
+            let span_end = self.get_span_end(alternatives[alternatives.len() - 1]);
+            let variables: Vec<_> = alternatives
+                .iter()
+                .flat_map(|alternative| self.compiler.pattern_variables(*alternative))
+                .collect();
+            pattern = self.create_node(AstNode::OrPattern(alternatives), span_start, span_end);
+            for variable in variables {
+                self.error_on_node(
+                    ErrorCode::UnsupportedSyntax,
+                    "variables cannot be bound by an or-pattern",
+                    variable,
+                );
+            }
+        }
+
+        if self.is_keyword(b"if") {
+            self.keyword(b"if");
+            let guard = self.condition();
+            let span_end = self.get_span_end(guard);
+            pattern = self.create_node(
+                AstNode::GuardedPattern { pattern, guard },
+                span_start,
+                span_end,
+            );
+        }
+
+        pattern
+    }
+
+    /// A single alternative of a `match` pattern
+    fn pattern(&mut self) -> NodeId {
+        let _span = span!();
+
+        if self.is_keyword(b"_") {
+            let span = self.tokens.peek_span();
+            self.advance_node(AstNode::Wildcard, span)
+        } else if self.is_lsquare() {
+            self.list_pattern()
+        } else if self.is_lcurly() {
+            self.record_pattern()
+        } else if self.is_simple_expression() {
+            self.simple_expression(BarewordContext::String)
+        } else {
+            self.error(ErrorCode::ExpectedToken, "expected pattern")
+        }
+    }
+
+    /// `[...]` pattern matching the items of a list
+    fn list_pattern(&mut self) -> NodeId {
+        let _span = span!();
+        let span_start = self.position();
+        let mut items = vec![];
+
+        let opened = self.lsquare();
+        loop {
+            if self.is_rsquare() {
+                break;
+            } else if self.is_comma() || self.is_newline() {
+                self.advance();
+            } else if self.is_eof() {
+                break;
+            } else {
+                items.push(self.pattern());
+            }
+        }
+        let span_end = if self.is_rsquare() {
+            self.tokens.peek_span().end
+        } else {
+            self.position()
+        };
+        self.rsquare(opened);
+
+        self.create_node(AstNode::List(items), span_start, span_end)
+    }
+
+    /// `{...}` pattern matching the fields of a record
+    fn record_pattern(&mut self) -> NodeId {
+        let _span = span!();
+        let span_start = self.position();
+        let mut entries = vec![];
+
+        let opened = self.lcurly();
+        loop {
+            self.skip_newlines();
+            if self.is_rcurly() || self.is_eof() {
+                break;
+            }
+            let key = self.simple_expression(BarewordContext::String);
+            self.skip_newlines();
+            self.colon();
+            self.skip_newlines();
+            let val = self.pattern();
+            entries.push(RecordEntry::Pair(key, val));
+
+            self.skip_newlines();
+            if self.is_comma() {
+                self.comma();
+            }
+        }
+        let span_end = self.position() + 1;
+        self.rcurly(opened);
+
+        self.create_node(AstNode::Record { entries }, span_start, span_end)
+    }
+
+    /// `try { ... }`, optionally followed by `catch { ... }` or `catch {|err| ... }`
+    pub fn try_expression(&mut self) -> NodeId {
+        let _span = span!();
+        let span_start = self.position();
+
+        self.keyword(b"try");
+        let block = self.block(BlockContext::Curlies);
+
+        let catch = if self.is_keyword(b"catch") {
+            self.advance();
+            Some(self.closure())
+        } else {
+            None
+        };
+        let span_end = self.get_span_end(catch.unwrap_or(block));
+
+        self.create_node(AstNode::Try { block, catch }, span_start, span_end)
     }
 
-    pub fn match_expression(&mut self) -> NodeId {
+    /// Closure where a block is expected, e.g., after `catch`, the parameters are optional
+    pub fn closure(&mut self) -> NodeId {
         let _span = span!();
         let span_start = self.position();
-        let span_end;
-
-        self.keyword(b"match");
-        let target = self.simple_expression(BarewordContext::String);
-
-        let mut match_arms = vec![];
 
         if !self.is_lcurly() {
-            return self.error("expected left curly brace '{'");
+            return self.error(ErrorCode::ExpectedToken, "expected closure");
         }
+        let opened = self.lcurly();
+        self.skip_newlines();
 
-        self.lcurly();
-
-        loop {
-            if self.is_rcurly() {
-                span_end = self.position() + 1;
-                self.rcurly();
-                break;
-            } else if self.is_simple_expression() {
-                let pattern = self.simple_expression(BarewordContext::String);
-
-                if !self.is_thick_arrow() {
-                    return self.error("expected thick arrow (=>) between match cases");
-                }
-                self.tokens.advance();
-
-                let pattern_result = self.simple_expression(BarewordContext::String);
-
-                if self.is_comma() {
-                    self.tokens.advance();
-                }
-
-                match_arms.push((pattern, pattern_result));
-            } else if self.is_newline() {
-                self.tokens.advance();
-            } else {
-                return self.error("expected match arm in match");
-            }
-        }
+        let params = (self.is_pipe() || self.tokens.peek_token() == Token::PipePipe)
+            .then(|| self.closure_params());
+        let block = self.block(BlockContext::Closure);
+        self.rcurly(opened);
+        let span_end = self.position();
 
-        self.create_node(AstNode::Match { target, match_arms }, span_start, span_end)
+        self.create_node(AstNode::Closure { params, block }, span_start, span_end)
     }
 
     pub fn if_expression(&mut self) -> NodeId {
@@ -821,14 +2452,14 @@ impl Parser {
 
         self.keyword(b"if");
 
-        let condition = self.expression();
+        let condition = self.condition();
         self.skip_newlines();
 
         let then_block = self.block(BlockContext::Curlies);
         self.skip_newlines();
 
         let else_block = if self.is_keyword(b"else") {
-            self.tokens.advance();
+            self.advance();
             self.skip_newlines();
 
             let block = if self.is_keyword(b"if") {
@@ -862,10 +2493,13 @@ impl Parser {
         let _span = span!();
         let span_start = self.position();
         let span_end;
+        let (_, opened) = self.tokens.peek();
         let param_list = {
             match params_context {
                 ParamsContext::Pipes => self.pipe(),
-                ParamsContext::Squares => self.lsquare(),
+                ParamsContext::Squares => {
+                    self.lsquare();
+                }
             }
 
             let mut output = vec![];
@@ -878,39 +2512,26 @@ impl Parser {
                         }
                     }
                     ParamsContext::Squares => {
-                        if self.is_rsquare() {
+                        // the `{` of the body or a closing delimiter of an enclosing expression
+                        // ends the parameters even without the `]`
+                        if self.is_rsquare()
+                            || self.is_lcurly()
+                            || self.is_rcurly()
+                            || self.is_rparen()
+                        {
                             break;
                         }
                     }
                 }
 
-                if self.is_comma() {
-                    self.tokens.advance();
+                if self.is_comma() || self.is_newline() {
+                    self.advance();
                     continue;
                 }
 
-                let name = self.name();
-
-                let ty = if self.is_colon() {
-                    // We have a type
-                    self.colon();
-
-                    Some(self.typename())
-                } else {
-                    None
-                };
-
-                let name_span = self.compiler.spans[name.0];
-                let param_span_end = if let Some(ty_id) = ty {
-                    self.compiler.spans[ty_id.0].end
-                } else {
-                    name_span.end
-                };
-
-                let param =
-                    self.create_node(AstNode::Param { name, ty }, name_span.start, param_span_end);
-
-                // output.push(self.name());
+                let param = self.param();
+                self.check_param_order(param, &output);
+                self.check_duplicate_param(param, &output);
                 output.push(param);
             }
 
@@ -918,7 +2539,7 @@ impl Parser {
 
             match params_context {
                 ParamsContext::Pipes => self.pipe(),
-                ParamsContext::Squares => self.rsquare(),
+                ParamsContext::Squares => self.rsquare(opened),
             }
 
             output
@@ -927,6 +2548,262 @@ impl Parser {
         self.create_node(AstNode::Params(param_list), span_start, span_end)
     }
 
+    /// Parameter of a signature, e.g., `x: int`, `y?`, `...rest: string` or
+    /// `--flag (-f): string = "a"`
+    fn param(&mut self) -> NodeId {
+        let span_start = self.position();
+
+        let (kind, name, short) = if self.is_dotdotdot() {
+            self.advance();
+            (ParamKind::Rest, self.name(), None)
+        } else if self.is_flag() {
+            let is_long = self.tokens.peek_next().map(|(token, _)| token) == Some(Token::Dash);
+            self.advance();
+            if is_long {
+                self.advance();
+            }
+            let name = self.flag_name();
+
+            let short = if is_long && self.is_lparen() {
+                let opened = self.lparen();
+                if self.is_dash() {
+                    self.advance();
+                }
+                let short = self.name();
+                if self.compiler.get_span_contents(short).len() != 1 {
+                    self.error_on_node(
+                        ErrorCode::InvalidSignature,
+                        "short flag must be a single character",
+                        short,
+                    );
+                }
+                self.rparen(opened);
+                Some(short)
+            } else {
+                None
+            };
+
+            if !is_long && self.compiler.get_span_contents(name).len() != 1 {
+                self.error_on_node(
+                    ErrorCode::InvalidSignature,
+                    "short flag must be a single character, use `--` for a long flag",
+                    name,
+                );
+            }
+            (ParamKind::Flag, name, short)
+        } else {
+            let name = self.name();
+            if self.is_question_mark() && !self.is_horizontal_space() {
+                self.advance();
+                (ParamKind::Optional, name, None)
+            } else {
+                (ParamKind::Required, name, None)
+            }
+        };
+
+        let ty = if self.is_colon() {
+            self.colon();
+            Some(self.typename())
+        } else {
+            None
+        };
+
+        let default = if self.is_equals() {
+            self.advance();
+            Some(self.simple_expression(BarewordContext::String))
+        } else {
+            None
+        };
+
+        let kind = match (kind, default) {
+            (ParamKind::Required, Some(_)) => ParamKind::Optional,
+            (ParamKind::Rest, Some(default)) => {
+                self.error_on_node(
+                    ErrorCode::InvalidSignature,
+                    "rest parameter cannot have a default value",
+                    default,
+                );
+                kind
+            }
+            _ => kind,
+        };
+
+        let span_end = [default, ty, short]
+            .into_iter()
+            .flatten()
+            .chain([name])
+            .map(|node_id| self.compiler.get_span(node_id).end)
+            .max()
+            .unwrap_or(span_start);
+
+        let variable_name = self.param_variable_name(name);
+        self.shadow_constant(variable_name);
+
+        self.create_node(
+            AstNode::Param {
+                name,
+                ty,
+                kind,
+                short,
+                default,
+            },
+            span_start,
+            span_end,
+        )
+    }
+
+    /// Report a positional parameter that cannot come after the parameters before it
+    fn check_param_order(&mut self, param: NodeId, previous: &[NodeId]) {
+        let kind_of = |node_id: &NodeId| match self.compiler.get_node(*node_id) {
+            AstNode::Param { kind, .. } => Some(*kind),
+            _ => None,
+        };
+        let Some(kind) = kind_of(&param) else {
+            return;
+        };
+        let previous: Vec<_> = previous.iter().filter_map(kind_of).collect();
+
+        let message = match kind {
+            ParamKind::Rest if previous.contains(&ParamKind::Rest) => {
+                "only one rest parameter is allowed"
+            }
+            ParamKind::Required | ParamKind::Optional if previous.contains(&ParamKind::Rest) => {
+                "positional parameter after the rest parameter"
+            }
+            ParamKind::Required if previous.contains(&ParamKind::Optional) => {
+                "required parameter after an optional one"
+            }
+            _ => return,
+        };
+        self.error_on_node(ErrorCode::InvalidSignature, message, param);
+    }
+
+    /// Name of the variable of a parameter, e.g., `--dry-run` is the variable `$dry_run`
+    fn param_variable_name(&self, name: NodeId) -> Vec<u8> {
+        let name = self.compiler.get_span_contents(name);
+        name.strip_prefix(b"--")
+            .unwrap_or(name)
+            .iter()
+            .map(|c| if *c == b'-' { b'_' } else { *c })
+            .collect()
+    }
+
+    /// Report a parameter with the same variable name or short flag as an earlier one
+    fn check_duplicate_param(&mut self, param: NodeId, previous: &[NodeId]) {
+        let names_of = |node_id: &NodeId| match self.compiler.get_node(*node_id) {
+            AstNode::Param { name, short, .. } => Some((*name, *short)),
+            _ => None,
+        };
+        let Some((name, short)) = names_of(&param) else {
+            return;
+        };
+        let previous: Vec<_> = previous.iter().filter_map(names_of).collect();
+
+        let variable_name = self.param_variable_name(name);
+        let duplicate = previous.iter().find_map(|(previous_name, previous_short)| {
+            if self.param_variable_name(*previous_name) == variable_name {
+                return Some((name, *previous_name, "parameter", ""));
+            }
+            let (short, previous_short) = (short?, (*previous_short)?);
+            let is_same = self.compiler.get_span_contents(short)
+                == self.compiler.get_span_contents(previous_short);
+            is_same.then_some((short, previous_short, "short flag", "-"))
+        });
+        let Some((duplicate, previous, what, prefix)) = duplicate else {
+            return;
+        };
+
+        let duplicate_name = String::from_utf8_lossy(self.compiler.get_span_contents(duplicate));
+        let message = format!("duplicate {what} `{prefix}{duplicate_name}`");
+        self.error_on_node(ErrorCode::InvalidSignature, message, duplicate);
+        let previous_span = self.compiler.get_span(previous);
+        if let Some(error) = self.compiler.errors.last_mut() {
+            error
+                .related
+                .push(RelatedSpan::new(previous_span, "first declared here"));
+        }
+    }
+
+    /// Name of a flag after its dashes, e.g., `keep-empty` of `--keep-empty`
+    ///
+    /// Dashes split barewords, so the name is made of all the tokens without whitespace between
+    /// them.
+    fn flag_name(&mut self) -> NodeId {
+        let (token, span) = self.tokens.peek();
+        if token != Token::Bareword {
+            return self.error(ErrorCode::ExpectedToken, "expected flag name");
+        }
+        let span_end = self.adjacent_tokens_end();
+        self.create_node(AstNode::Name, span.start, span_end)
+    }
+
+    /// Advance over the current token and the barewords, dashes and ints directly following it,
+    /// returning the end of the last one
+    fn adjacent_tokens_end(&mut self) -> usize {
+        let mut span_end = self.tokens.peek_span().end;
+        self.advance();
+        while matches!(
+            self.tokens.peek_token(),
+            Token::Bareword | Token::Dash | Token::Int
+        ) && self.tokens.peek_span().start == span_end
+        {
+            span_end = self.tokens.peek_span().end;
+            self.advance();
+        }
+        span_end
+    }
+
+    /// Flag argument of a call: `--long`, `-s` or a group of short flags, `-abc`
+    ///
+    /// A flag directly followed by `=` and its value, e.g., `--level=2`, is a `NamedValue`.
+    fn flag(&mut self) -> NodeId {
+        let span_start = self.position();
+        let is_long = self.tokens.peek_next().map(|(token, _)| token) == Some(Token::Dash);
+        let name_start = if is_long {
+            span_start + 2
+        } else {
+            span_start + 1
+        };
+
+        let span_end = self.adjacent_tokens_end();
+        let node = match span_end - name_start {
+            _ if is_long => AstNode::FlagLong,
+            1 => AstNode::FlagShort,
+            _ => AstNode::FlagShortGroup,
+        };
+        let name = self.create_node(node, span_start, span_end);
+        if !self.is_equals() || self.position() != span_end {
+            return name;
+        }
+
+        let (_, equals) = self.tokens.peek();
+        self.advance();
+        let value = if self.position() != equals.end || self.is_statement_end() || self.is_pipe() {
+            let value = self.create_node(AstNode::Garbage, equals.start, equals.end);
+            self.error_on_node(
+                ErrorCode::ExpectedToken,
+                "expected flag value after `=`",
+                value,
+            );
+            value
+        } else if self.is_bare_word() {
+            self.bare_word()
+        } else {
+            self.simple_expression(BarewordContext::String)
+        };
+        let value_end = self.get_span_end(value);
+
+        self.create_node(AstNode::NamedValue { name, value }, span_start, value_end)
+    }
+
+    /// Parameters of a closure between pipes, `||` is an empty list
+    fn closure_params(&mut self) -> NodeId {
+        if let (Token::PipePipe, span) = self.tokens.peek() {
+            return self.advance_node(AstNode::Params(vec![]), span);
+        }
+        self.signature_params(ParamsContext::Pipes)
+    }
+
     pub fn type_params(&mut self) -> NodeId {
         let _span = span!();
         let span_start = self.position();
@@ -942,7 +2819,7 @@ impl Parser {
                 }
 
                 if self.is_comma() {
-                    self.tokens.advance();
+                    self.advance();
                     continue;
                 }
 
@@ -970,12 +2847,13 @@ impl Parser {
 
             let optional = if self.is_question_mark() {
                 // We have an optional type
-                self.tokens.advance();
+                self.advance();
                 true
             } else {
                 false
             };
 
+            let span_end = params.map_or(span.end, |params| self.get_span_end(params));
             self.create_node(
                 AstNode::Type {
                     name,
@@ -983,10 +2861,10 @@ impl Parser {
                     optional,
                 },
                 span.start,
-                span.end,
+                span_end,
             )
         } else {
-            self.error("expect name")
+            self.error(ErrorCode::ExpectedToken, "expect name")
         }
     }
 
@@ -997,6 +2875,7 @@ impl Parser {
         let in_ty = self.typename();
         self.thin_arrow();
         let out_ty = self.typename();
+        let in_ty = Some(in_ty);
 
         let span_end = self.position();
         self.create_node(AstNode::InOutType(in_ty, out_ty), span_start, span_end)
@@ -1008,8 +2887,7 @@ impl Parser {
 
         if self.is_lsquare() {
             let span_start = self.position();
-
-            self.tokens.advance();
+            let opened = self.lsquare();
 
             let mut output = vec![];
             while self.has_tokens() {
@@ -1018,14 +2896,14 @@ impl Parser {
                 }
 
                 if self.is_comma() {
-                    self.tokens.advance();
+                    self.advance();
                     continue;
                 }
 
                 output.push(self.in_out_type());
             }
 
-            self.rsquare();
+            self.rsquare(opened);
             let span_end = self.position();
 
             self.create_node(AstNode::InOutTypes(output), span_start, span_end)
@@ -1036,23 +2914,44 @@ impl Parser {
         }
     }
 
-    pub fn def_statement(&mut self) -> NodeId {
-        let _span = span!();
+    /// `-> out`, the output type of a command taking any input
+    fn output_type(&mut self) -> NodeId {
         let span_start = self.position();
+        self.thin_arrow();
+        let out_ty = self.typename();
 
-        self.keyword(b"def");
+        let span_end = self.get_span_end(out_ty);
+        let in_out_type = self.create_node(AstNode::InOutType(None, out_ty), span_start, span_end);
+        self.create_node(AstNode::InOutTypes(vec![in_out_type]), span_start, span_end)
+    }
 
-        let name = match self.tokens.peek() {
+    /// Name of a `def` or `extern`, a bareword or a string
+    fn decl_name(&mut self, message: &'static str) -> NodeId {
+        match self.tokens.peek() {
             (Token::Bareword, span) => self.advance_node(AstNode::Name, span),
             (Token::DoubleQuotedString | Token::SingleQuotedString, span) => {
                 self.advance_node(AstNode::String, span)
             }
-            _ => return self.error("expected def name"),
-        };
+            _ => self.error(ErrorCode::ExpectedToken, message),
+        }
+    }
+
+    pub fn def_statement(&mut self) -> NodeId {
+        let _span = span!();
+        let span_start = self.position();
+
+        self.keyword(b"def");
+
+        let name = self.decl_name("expected def name");
+        if self.compiler.ast_nodes[name.0] == AstNode::Garbage {
+            return name;
+        }
 
         let params = self.signature_params(ParamsContext::Squares);
         let return_ty = if self.is_colon() {
             Some(self.in_out_types())
+        } else if self.is_thin_arrow() {
+            Some(self.output_type())
         } else {
             None
         };
@@ -1093,7 +2992,7 @@ impl Parser {
 
         self.equals();
 
-        let initializer = self.expression();
+        let initializer = self.pipeline(ExpressionContext::Value);
 
         let span_end = self.get_span_end(initializer);
 
@@ -1130,7 +3029,7 @@ impl Parser {
 
         self.equals();
 
-        let initializer = self.expression();
+        let initializer = self.pipeline(ExpressionContext::Value);
 
         let span_end = self.get_span_end(initializer);
 
@@ -1146,84 +3045,431 @@ impl Parser {
         )
     }
 
-    pub fn keyword(&mut self, keyword: &[u8]) {
+    /// `const` declaration, its value must be computed from literals and other constants
+    pub fn const_statement(&mut self) -> NodeId {
+        let _span = span!();
+        let span_start = self.position();
+
+        self.keyword(b"const");
+
+        let variable_name = self.variable_decl();
+
+        let ty = if self.is_colon() {
+            self.colon();
+            Some(self.typename())
+        } else {
+            None
+        };
+
+        self.equals();
+
+        let initializer = self.pipeline(ExpressionContext::Value);
+        let span_end = self.get_span_end(initializer);
+
+        if self.compiler.ast_nodes[variable_name.0] == AstNode::Variable {
+            match fold_constant(&self.compiler, initializer) {
+                Some(value) => {
+                    self.compiler.constants.insert(variable_name, value);
+                    let name = self.compiler.get_span_contents(variable_name);
+                    let name = name.strip_prefix(b"$").unwrap_or(name).to_vec();
+                    self.constants.push((name, Some(variable_name)));
+                }
+                None => {
+                    self.error_on_node(
+                        ErrorCode::NonConstantValue,
+                        "value of `const` is not known when parsing",
+                        initializer,
+                    );
+                    self.add_note("a constant is computed only from literals and other constants");
+                }
+            }
+        }
+
+        self.create_node(
+            AstNode::Const {
+                variable_name,
+                ty,
+                initializer,
+            },
+            span_start,
+            span_end,
+        )
+    }
+
+    pub fn keyword(&mut self, keyword: &[u8]) {
+        let _span = span!();
+        if self.is_keyword(keyword) {
+            self.advance();
+        } else {
+            self.error(
+                ErrorCode::ExpectedToken,
+                format!("expected keyword: {}", String::from_utf8_lossy(keyword)),
+            );
+        }
+    }
+
+    pub fn block(&mut self, context: BlockContext) -> NodeId {
+        let _span = span!();
+        let span_start = self.position();
+
+        let mut code_body = vec![];
+        let num_constants = self.constants.len();
+        if let BlockContext::Curlies = context {
+            self.lcurly();
+        }
+
+        while self.has_tokens() {
+            if self.is_rcurly() && context == BlockContext::Curlies {
+                self.advance();
+                break;
+            } else if self.is_rcurly() && context == BlockContext::Closure {
+                // not responsible for parsing it, yield back to the closure pass
+                break;
+            } else if self.is_semicolon() || self.is_newline() {
+                self.advance();
+                continue;
+            } else {
+                let num_errors = self.compiler.errors.len();
+                let statement = self.statement(ExpressionContext::Statement);
+                code_body.push(statement);
+                code_body.extend(self.recover_statement(statement, num_errors));
+
+                // nothing returns to the tokens of the previous top-level statements
+                if context == BlockContext::Bare && self.statements.is_some() {
+                    self.tokens.drop_consumed();
+                }
+            }
+        }
+
+        self.constants.truncate(num_constants);
+        self.compiler.blocks.push(Block::new(code_body));
+        let span_end = self.position();
+
+        self.create_node(
+            AstNode::Block(BlockId(self.compiler.blocks.len() - 1)),
+            span_start,
+            span_end,
+        )
+    }
+
+    /// Contents of parentheses, after the opening one
+    pub fn subexpression(&mut self, lparen: Span) -> NodeId {
+        let _span = span!();
+        let block_start = self.position();
+
+        // assignments are statements, but not in parentheses, e.g., `if ($x = 1)`
+        let mut code_body = vec![];
+        let mut statements_end = self.tokens.pos();
+        while self.has_tokens() && !self.is_rparen() && !self.is_rcurly() {
+            if self.is_semicolon() || self.is_newline() {
+                self.advance();
+            } else {
+                let num_errors = self.compiler.errors.len();
+                let statement = self.statement(ExpressionContext::Value);
+                code_body.push(statement);
+                code_body.extend(self.recover_statement(statement, num_errors));
+                statements_end = self.tokens.pos();
+            }
+        }
+
+        // the missing `)` is reported right after the last statement, not at the end of the file
+        if !self.is_rparen() {
+            self.tokens.set_pos(statements_end);
+        }
+        let block_end = self.position();
+        let span_end = if self.is_rparen() {
+            self.tokens.peek_span().end
+        } else {
+            block_end
+        };
+        self.rparen(lparen);
+
+        if let [expression] = code_body[..] {
+            if self.is_grouped_expression(expression) {
+                return expression;
+            }
+        }
+
+        self.compiler.blocks.push(Block::new(code_body));
+        let block = self.create_node(
+            AstNode::Block(BlockId(self.compiler.blocks.len() - 1)),
+            block_start,
+            block_end,
+        );
+        self.create_node(AstNode::Subexpression(block), lparen.start, span_end)
+    }
+
+    /// Skip the rest of a statement that has a syntax error, so that the next statement is parsed
+    /// from its start instead of the middle of the broken one
+    ///
+    /// The statement ends at a newline or a semicolon, or at a closing delimiter that does not
+    /// belong to it, e.g., the `}` of the enclosing block. Returns a garbage node covering the
+    /// skipped tokens, if there are any.
+    fn recover_statement(&mut self, statement: NodeId, num_errors: usize) -> Option<NodeId> {
+        let has_error = self.compiler.errors[num_errors..]
+            .iter()
+            .any(|error| error.severity == Severity::Error);
+        if !has_error {
+            return None;
+        }
+
+        // some statements look for a continuation on the next lines, e.g., `if` for `else`
+        let statement_end = self.get_span_end(statement);
+        let position = self.position();
+        if statement_end < position
+            && self.compiler.source[statement_end..position].contains(&b'\n')
+        {
+            return None;
+        }
+
+        let mut depth = 0usize;
+        let mut skipped: Option<Span> = None;
+        loop {
+            let (token, span) = self.tokens.peek();
+            match token {
+                Token::Eof => break,
+                Token::Newline | Token::Semicolon if depth == 0 => break,
+                Token::RParen | Token::RSquare | Token::RCurly | Token::StrInterpEnd
+                    if depth == 0 =>
+                {
+                    break
+                }
+                Token::LParen
+                | Token::LSquare
+                | Token::LCurly
+                | Token::DqStringInterpStart
+                | Token::SqStringInterpStart => depth += 1,
+                Token::RParen | Token::RSquare | Token::RCurly | Token::StrInterpEnd => depth -= 1,
+                _ => (),
+            }
+
+            self.advance();
+            skipped = Some(match skipped {
+                Some(skipped) => Span::new(skipped.start, span.end),
+                None => span,
+            });
+        }
+
+        skipped.map(|span| self.create_node(AstNode::Garbage, span.start, span.end))
+    }
+
+    /// Whether parentheses around the node only group it, i.e., it is neither a pipeline nor a
+    /// statement
+    fn is_grouped_expression(&self, node_id: NodeId) -> bool {
+        match self.compiler.get_node(node_id) {
+            AstNode::BinaryOp { op, .. } => {
+                self.compiler.get_node(*op).precedence() != ASSIGNMENT_PRECEDENCE
+            }
+            AstNode::Pipeline(_)
+            | AstNode::Statement(_)
+            | AstNode::Let { .. }
+            | AstNode::Const { .. }
+            | AstNode::While { .. }
+            | AstNode::For { .. }
+            | AstNode::Loop { .. }
+            | AstNode::Return(_)
+            | AstNode::Break
+            | AstNode::Continue
+            | AstNode::Def { .. }
+            | AstNode::Alias { .. }
+            | AstNode::Extern { .. }
+            | AstNode::Module { .. }
+            | AstNode::Use { .. }
+            | AstNode::Export(_)
+            | AstNode::ExportEnv(_)
+            | AstNode::OverlayUse { .. }
+            | AstNode::OverlayNew(_)
+            | AstNode::OverlayHide(_)
+            | AstNode::Hide(_) => false,
+            _ => true,
+        }
+    }
+
+    /// Statement of a block, or an expression whose value is the block's value if it is the last
+    /// one
+    ///
+    /// The context of the expression decides whether it can be an assignment.
+    fn statement(&mut self, context: ExpressionContext) -> NodeId {
+        let span_start = self.position();
+        let statement = if self.is_deprecated_command() {
+            self.call()
+        } else if self.is_keyword(b"def") {
+            self.def_statement()
+        } else if self.tokens.peek_token() == Token::At {
+            self.attributed_statement()
+        } else if self.is_keyword(b"let") {
+            self.let_statement()
+        } else if self.is_keyword(b"mut") {
+            self.mut_statement()
+        } else if self.is_keyword(b"const") {
+            self.const_statement()
+        } else if self.is_keyword(b"while") {
+            self.while_statement()
+        } else if self.is_keyword(b"for") {
+            self.for_statement()
+        } else if self.is_keyword(b"loop") {
+            self.loop_statement()
+        } else if self.is_keyword(b"return") {
+            self.return_statement()
+        } else if self.is_keyword(b"continue") {
+            self.continue_statement()
+        } else if self.is_keyword(b"break") {
+            self.break_statement()
+        } else if self.is_keyword(b"alias") {
+            self.alias_statement()
+        } else if self.is_keyword(b"extern") {
+            self.extern_statement()
+        } else if self.is_keyword(b"module") {
+            self.module_statement()
+        } else if self.is_keyword(b"use") {
+            self.use_statement()
+        } else if self.is_overlay_statement() {
+            self.overlay_statement()
+        } else if self.is_statement_name(b"hide") {
+            self.hide_statement()
+        } else if self.is_statement_name(b"export-env") {
+            self.export_env_statement()
+        } else if self.is_keyword(b"export") {
+            self.export_statement()
+        } else {
+            let exp_span_start = self.position();
+            let expression = self.pipeline(context);
+            let exp_span_end = self.get_span_end(expression);
+
+            if self.is_semicolon() {
+                // This is a statement, not an expression
+                self.advance();
+                self.create_node(AstNode::Statement(expression), exp_span_start, exp_span_end)
+            } else {
+                expression
+            }
+        };
+
+        self.doc_comment(statement, span_start);
+        statement
+    }
+
+    /// Declaration with its attributes, each of them on its own line
+    fn attributed_statement(&mut self) -> NodeId {
         let _span = span!();
-        if self.is_keyword(keyword) {
-            self.tokens.advance();
+        let mut attributes = vec![];
+        while self.tokens.peek_token() == Token::At {
+            attributes.push(self.attribute());
+            while self.is_newline() {
+                self.advance();
+            }
+        }
+
+        let decl = if self.is_keyword(b"def") {
+            self.def_statement()
+        } else if self.is_keyword(b"extern") {
+            self.extern_statement()
+        } else if self.is_keyword(b"export") {
+            self.export_statement()
         } else {
-            self.error(format!(
-                "expected keyword: {}",
-                String::from_utf8_lossy(keyword)
-            ));
+            // the attributes are the error, the next statement is parsed as usual
+            let last = *attributes
+                .last()
+                .expect("the statement starts with an attribute");
+            self.error_on_node(
+                ErrorCode::ExpectedToken,
+                "expected `def`, `extern` or `export` after the attribute",
+                last,
+            );
+            return last;
+        };
+
+        let definition = match self.compiler.get_node(decl) {
+            AstNode::Export(definition) => *definition,
+            _ => decl,
+        };
+        if matches!(
+            self.compiler.get_node(definition),
+            AstNode::Def { .. } | AstNode::Extern { .. }
+        ) {
+            self.compiler.attributes.insert(definition, attributes);
         }
+        decl
     }
 
-    pub fn block(&mut self, context: BlockContext) -> NodeId {
+    /// `@name` followed by the arguments of the attribute until the end of the line
+    fn attribute(&mut self) -> NodeId {
         let _span = span!();
-        let span_start = self.position();
+        let (_, at_span) = self.tokens.peek();
+        self.advance();
 
-        let mut code_body = vec![];
-        if let BlockContext::Curlies = context {
-            self.lcurly();
+        let (token, span) = self.tokens.peek();
+        if token != Token::Bareword || span.start != at_span.end {
+            return self.error(
+                ErrorCode::ExpectedToken,
+                "expected attribute name after `@`",
+            );
         }
+        let span = self.name_span();
+        let name = self.create_node(AstNode::Name, span.start, span.end);
 
-        while self.has_tokens() {
-            if self.is_rcurly() && context == BlockContext::Curlies {
-                self.rcurly();
-                break;
-            } else if self.is_rcurly() && context == BlockContext::Closure {
-                // not responsible for parsing it, yield back to the closure pass
-                break;
-            } else if self.is_semicolon() || self.is_newline() || self.is_comment() {
-                self.tokens.advance();
-                continue;
-            } else if self.is_keyword(b"def") {
-                code_body.push(self.def_statement());
-            } else if self.is_keyword(b"let") {
-                code_body.push(self.let_statement());
-            } else if self.is_keyword(b"mut") {
-                code_body.push(self.mut_statement());
-            } else if self.is_keyword(b"while") {
-                code_body.push(self.while_statement());
-            } else if self.is_keyword(b"for") {
-                code_body.push(self.for_statement());
-            } else if self.is_keyword(b"loop") {
-                code_body.push(self.loop_statement());
-            } else if self.is_keyword(b"return") {
-                code_body.push(self.return_statement());
-            } else if self.is_keyword(b"continue") {
-                code_body.push(self.continue_statement());
-            } else if self.is_keyword(b"break") {
-                code_body.push(self.break_statement());
-            } else if self.is_keyword(b"alias") {
-                code_body.push(self.alias_statement());
+        let mut args = vec![];
+        while !self.is_statement_end() {
+            args.push(if self.is_flag() {
+                self.flag()
             } else {
-                let exp_span_start = self.position();
-                let expression = self.expression_or_assignment();
-                let exp_span_end = self.get_span_end(expression);
-
-                if self.is_semicolon() {
-                    // This is a statement, not an expression
-                    self.tokens.advance();
-                    code_body.push(self.create_node(
-                        AstNode::Statement(expression),
-                        exp_span_start,
-                        exp_span_end,
-                    ))
-                } else {
-                    code_body.push(expression);
-                }
-            }
+                self.simple_expression(BarewordContext::String)
+            });
         }
+        let span_end = args.last().map_or(span.end, |arg| self.get_span_end(*arg));
 
-        self.compiler.blocks.push(Block::new(code_body));
-        let span_end = self.position();
+        self.create_node(AstNode::Attribute { name, args }, at_span.start, span_end)
+    }
 
-        self.create_node(
-            AstNode::Block(BlockId(self.compiler.blocks.len() - 1)),
-            span_start,
-            span_end,
-        )
+    /// Record the comment lines right above a `def`, `extern` or `const` as its doc comment
+    ///
+    /// The comments must be on their own lines, with no empty line between them and the
+    /// declaration. Lint comments are not part of the doc comment.
+    fn doc_comment(&mut self, statement: NodeId, span_start: usize) {
+        let decl = match self.compiler.get_node(statement) {
+            AstNode::Export(decl) => *decl,
+            _ => statement,
+        };
+        if !matches!(
+            self.compiler.get_node(decl),
+            AstNode::Def { .. } | AstNode::Extern { .. } | AstNode::Const { .. }
+        ) {
+            return;
+        }
+
+        let source = &self.compiler.source;
+        let num_before = self.comments.partition_point(|span| span.end <= span_start);
+        let mut lines = vec![];
+        let mut next_start = span_start;
+        for span in self.comments[..num_before].iter().rev() {
+            let file_start = self
+                .compiler
+                .file_of(*span)
+                .map_or(0, |(_, start, _)| *start);
+            let line_prefix = source[file_start..span.start]
+                .iter()
+                .rev()
+                .take_while(|c| **c != b'\n');
+            let between = &source[span.end..next_start];
+            let comment = &source[span.start..span.end];
+
+            let is_own_line = line_prefix.clone().all(|c| *c == b' ' || *c == b'\t');
+            let is_next_line = between.iter().all(u8::is_ascii_whitespace)
+                && between.iter().filter(|c| **c == b'\n').count() == 1;
+            if !is_own_line || !is_next_line || parse_lint_comment(comment).is_some() {
+                break;
+            }
+
+            let text = &comment[1..];
+            lines.push(text.strip_prefix(b" ").unwrap_or(text));
+            next_start = span.start;
+        }
+
+        if !lines.is_empty() {
+            lines.reverse();
+            let doc = String::from_utf8_lossy(&lines.join(&b'\n')).to_string();
+            self.compiler.doc_comments.insert(decl, doc);
+        }
     }
 
     pub fn while_statement(&mut self) -> NodeId {
@@ -1233,11 +3479,14 @@ impl Parser {
 
         if self.is_operator() {
             // TODO: flag parsing
-            self.error("WIP: Flags on while are not supported yet");
-            self.tokens.advance();
+            self.error(
+                ErrorCode::UnsupportedSyntax,
+                "WIP: Flags on while are not supported yet",
+            );
+            self.advance();
         }
 
-        let condition = self.expression();
+        let condition = self.condition();
         let block = self.block(BlockContext::Curlies);
         let span_end = self.get_span_end(block);
 
@@ -1324,13 +3573,243 @@ impl Parser {
             self.name()
         };
         self.equals();
-        let old_name = if self.is_string() {
+        let expansion = if self.is_name() {
+            self.call()
+        } else {
+            self.error(ErrorCode::ExpectedToken, "expected command to alias")
+        };
+        let span_end = self.get_span_end(expansion);
+        self.create_node(
+            AstNode::Alias {
+                new_name,
+                expansion,
+            },
+            span_start,
+            span_end,
+        )
+    }
+
+    pub fn extern_statement(&mut self) -> NodeId {
+        let _span = span!();
+        let span_start = self.position();
+        self.keyword(b"extern");
+
+        let name = self.decl_name("expected extern name");
+        let params = self.signature_params(ParamsContext::Squares);
+        let span_end = self.get_span_end(params);
+
+        self.create_node(AstNode::Extern { name, params }, span_start, span_end)
+    }
+
+    pub fn module_statement(&mut self) -> NodeId {
+        let _span = span!();
+        let span_start = self.position();
+        self.keyword(b"module");
+
+        let name = if self.is_string() {
             self.string()
         } else {
             self.name()
         };
-        let span_end = self.get_span_end(old_name);
-        self.create_node(AstNode::Alias { new_name, old_name }, span_start, span_end)
+        let block = self.block(BlockContext::Curlies);
+        let span_end = self.get_span_end(block);
+
+        self.create_node(AstNode::Module { name, block }, span_start, span_end)
+    }
+
+    pub fn use_statement(&mut self) -> NodeId {
+        let _span = span!();
+        let span_start = self.position();
+        self.keyword(b"use");
+
+        // a module defined in the source, a path or a module found by the engine, e.g., `std`
+        let module = self.simple_expression(BarewordContext::String);
+        let mut span_end = self.get_span_end(module);
+
+        let mut members = vec![];
+        while !self.is_statement_end() {
+            let member = match self.tokens.peek() {
+                (Token::Bareword, span) => self.advance_node(AstNode::Name, span),
+                (Token::DoubleQuotedString | Token::SingleQuotedString, _) => self.string(),
+                (Token::Asterisk, span) => self.advance_node(AstNode::ImportAll, span),
+                (Token::LSquare, _) => self.import_list(),
+                _ => self.error(ErrorCode::ExpectedToken, "expected import pattern"),
+            };
+            span_end = self.get_span_end(member);
+            members.push(member);
+        }
+
+        let use_statement =
+            self.create_node(AstNode::Use { module, members }, span_start, span_end);
+
+        // modules can be used by their name as well, e.g., `use std`
+        let path = self.compiler.parse_time_path(module);
+        if path.is_some_and(|path| path.ends_with(b".nu")) {
+            self.compiler.load_source(use_statement, module);
+        }
+        use_statement
+    }
+
+    /// `[a "b c"]` of `use module [a "b c"]`
+    fn import_list(&mut self) -> NodeId {
+        let span_start = self.position();
+        let opened = self.lsquare();
+
+        let mut items = vec![];
+        loop {
+            match self.tokens.peek() {
+                (Token::RSquare, _) => break,
+                (Token::Comma | Token::Newline, _) => self.advance(),
+                (Token::Bareword, span) => items.push(self.advance_node(AstNode::Name, span)),
+                (Token::DoubleQuotedString | Token::SingleQuotedString, _) => {
+                    items.push(self.string())
+                }
+                (Token::Eof, _) => break,
+                _ => items.push(self.error(ErrorCode::ExpectedToken, "expected member name")),
+            }
+        }
+
+        let span_end = self.position() + 1;
+        self.rsquare(opened);
+        self.create_node(AstNode::List(items), span_start, span_end)
+    }
+
+    pub fn export_statement(&mut self) -> NodeId {
+        let _span = span!();
+        let span_start = self.position();
+        self.keyword(b"export");
+
+        let definition = if self.is_keyword(b"def") {
+            self.def_statement()
+        } else if self.is_keyword(b"const") {
+            self.const_statement()
+        } else if self.is_keyword(b"alias") {
+            self.alias_statement()
+        } else if self.is_keyword(b"extern") {
+            self.extern_statement()
+        } else if self.is_keyword(b"use") {
+            self.use_statement()
+        } else if self.is_keyword(b"module") {
+            self.module_statement()
+        } else {
+            return self.error(
+                ErrorCode::ExpectedToken,
+                "expected `def`, `const`, `alias`, `extern`, `use` or `module` after `export`",
+            );
+        };
+        let span_end = self.get_span_end(definition);
+
+        self.create_node(AstNode::Export(definition), span_start, span_end)
+    }
+
+    pub fn export_env_statement(&mut self) -> NodeId {
+        let _span = span!();
+        let span_start = self.position();
+        // `export-env` is lexed as `export`, `-` and `env`
+        self.name_span();
+
+        let block = self.block(BlockContext::Curlies);
+        let span_end = self.get_span_end(block);
+
+        self.create_node(AstNode::ExportEnv(block), span_start, span_end)
+    }
+
+    /// `overlay use`, `overlay new` or `overlay hide`, other subcommands of `overlay` are calls
+    fn is_overlay_statement(&mut self) -> bool {
+        if !self.is_statement_name(b"overlay") {
+            return false;
+        }
+
+        let pos = self.tokens.pos();
+        self.advance();
+        let is_statement =
+            self.is_keyword(b"use") || self.is_keyword(b"new") || self.is_keyword(b"hide");
+        self.tokens.set_pos(pos);
+        is_statement
+    }
+
+    pub fn overlay_statement(&mut self) -> NodeId {
+        let _span = span!();
+        let span_start = self.position();
+        self.keyword(b"overlay");
+
+        if self.is_keyword(b"new") {
+            self.advance();
+            let name = self.overlay_name();
+            let span_end = self.get_span_end(name);
+            return self.create_node(AstNode::OverlayNew(name), span_start, span_end);
+        }
+
+        if self.is_keyword(b"hide") {
+            let mut span_end = self.position() + 4;
+            self.advance();
+            let name = (!self.is_statement_end()).then(|| self.overlay_name());
+            if let Some(name) = name {
+                span_end = self.get_span_end(name);
+            }
+            return self.create_node(AstNode::OverlayHide(name), span_start, span_end);
+        }
+
+        self.keyword(b"use");
+        let module = self.simple_expression(BarewordContext::String);
+        let mut span_end = self.get_span_end(module);
+
+        let name = if self.is_keyword(b"as") {
+            self.advance();
+            let name = self.overlay_name();
+            span_end = self.get_span_end(name);
+            Some(name)
+        } else {
+            None
+        };
+
+        let overlay_use =
+            self.create_node(AstNode::OverlayUse { module, name }, span_start, span_end);
+
+        let path = self.compiler.parse_time_path(module);
+        if path.is_some_and(|path| path.ends_with(b".nu")) {
+            self.compiler.load_source(overlay_use, module);
+        }
+        overlay_use
+    }
+
+    fn overlay_name(&mut self) -> NodeId {
+        match self.tokens.peek() {
+            (Token::Bareword, span) => self.advance_node(AstNode::Name, span),
+            (Token::DoubleQuotedString | Token::SingleQuotedString, _) => self.string(),
+            // the end of the statement is left to the enclosing block
+            (Token::Newline | Token::Semicolon | Token::RCurly | Token::Eof, span) => {
+                let name = self.create_node(AstNode::Garbage, span.start, span.start);
+                self.error_on_node(ErrorCode::ExpectedToken, "expected overlay name", name);
+                name
+            }
+            _ => self.error(ErrorCode::ExpectedToken, "expected overlay name"),
+        }
+    }
+
+    pub fn hide_statement(&mut self) -> NodeId {
+        let _span = span!();
+        let span_start = self.position();
+        let mut span_end = span_start + 4;
+        self.keyword(b"hide");
+
+        let mut parts = vec![];
+        while !self.is_statement_end() {
+            let part = match self.tokens.peek() {
+                (Token::Bareword, span) => self.advance_node(AstNode::Name, span),
+                (Token::DoubleQuotedString | Token::SingleQuotedString, _) => self.string(),
+                _ => self.error(ErrorCode::ExpectedToken, "expected name to hide"),
+            };
+            span_end = self.get_span_end(part);
+            parts.push(part);
+        }
+
+        let is_empty = parts.is_empty();
+        let hide = self.create_node(AstNode::Hide(parts), span_start, span_end);
+        if is_empty {
+            self.error_on_node(ErrorCode::ExpectedToken, "expected name to hide", hide);
+        }
+        hide
     }
 
     pub fn is_operator(&mut self) -> bool {
@@ -1360,12 +3839,27 @@ impl Parser {
             | Token::PlusPlusEquals => true,
             Token::Bareword => {
                 let op = self.compiler.get_span_contents_manual(span.start, span.end);
-                op == b"mod" || op == b"in" || op == b"and" || op == b"xor" || op == b"or"
+                op == b"mod"
+                    || op == b"in"
+                    || op == b"and"
+                    || op == b"xor"
+                    || op == b"or"
+                    || self.dashed_operator().is_some()
             }
             _ => false,
         }
     }
 
+    /// A name directly followed by `=`, e.g., `FOO=` of `FOO=bar cmd`
+    pub fn is_env_shorthand(&mut self) -> bool {
+        let (token, span) = self.tokens.peek();
+        token == Token::Bareword
+            && matches!(
+                self.tokens.peek_next(),
+                Some((Token::Equals, equals_span)) if equals_span.start == span.end
+            )
+    }
+
     pub fn is_equals(&mut self) -> bool {
         self.tokens.peek_token() == Token::Equals
     }
@@ -1410,12 +3904,17 @@ impl Parser {
         self.tokens.peek_token() == Token::Pipe
     }
 
-    pub fn is_dollar(&mut self) -> bool {
-        self.tokens.peek_token() == Token::Dollar
+    /// Pipe continuing the pipeline, possibly at the start of one of the next lines
+    pub fn is_pipe_after_newlines(&mut self) -> bool {
+        let pos = self.tokens.pos();
+        self.skip_newlines();
+        let is_pipe = self.is_pipe() || pipe_redirection(self.tokens.peek_token()).is_some();
+        self.tokens.set_pos(pos);
+        is_pipe
     }
 
-    pub fn is_comment(&mut self) -> bool {
-        self.tokens.peek_token() == Token::Comment
+    pub fn is_dollar(&mut self) -> bool {
+        self.tokens.peek_token() == Token::Dollar
     }
 
     pub fn is_question_mark(&mut self) -> bool {
@@ -1442,10 +3941,89 @@ impl Parser {
         self.tokens.peek_token() == Token::Semicolon
     }
 
+    /// Newline, semicolon, the end of the enclosing block or of the file
+    pub fn is_statement_end(&mut self) -> bool {
+        matches!(
+            self.tokens.peek_token(),
+            Token::Newline | Token::Semicolon | Token::RCurly | Token::Eof
+        )
+    }
+
+    pub fn is_dash(&mut self) -> bool {
+        self.tokens.peek_token() == Token::Dash
+    }
+
+    /// `-` or `--` directly followed by a name, e.g., `-a` or `--all`
+    pub fn is_flag(&mut self) -> bool {
+        let (token, span) = self.tokens.peek();
+        if token != Token::Dash {
+            return false;
+        }
+
+        let pos = self.tokens.pos();
+        self.advance();
+        let mut span_end = span.end;
+        if self.is_dash() && self.position() == span_end {
+            span_end = self.tokens.peek_span().end;
+            self.advance();
+        }
+        let is_flag = self.is_name() && self.position() == span_end;
+        self.tokens.set_pos(pos);
+        is_flag
+    }
+
+    /// Minus sign directly followed by an int, e.g., `-1`
+    pub fn is_negative_int(&mut self) -> bool {
+        let (token, span) = self.tokens.peek();
+        token == Token::Dash
+            && self
+                .tokens
+                .peek_next()
+                .is_some_and(|(next, next_span)| next == Token::Int && next_span.start == span.end)
+    }
+
+    /// Minus sign directly followed by a filesize or a duration, e.g., `-1day`
+    pub fn is_negative_unit_value(&mut self) -> bool {
+        let (token, span) = self.tokens.peek();
+        token == Token::Dash
+            && self.tokens.peek_next().is_some_and(|(next, next_span)| {
+                matches!(next, Token::Filesize | Token::Duration) && next_span.start == span.end
+            })
+    }
+
+    /// Minus sign directly followed by a variable or a subexpression, e.g., `-$x`
+    pub fn is_negation(&mut self) -> bool {
+        let (token, span) = self.tokens.peek();
+        token == Token::Dash
+            && self.tokens.peek_next().is_some_and(|(next, next_span)| {
+                matches!(next, Token::Dollar | Token::LParen) && next_span.start == span.end
+            })
+    }
+
+    pub fn is_operand(&mut self) -> bool {
+        self.is_simple_expression() || self.is_keyword(b"not") || self.is_negation()
+    }
+
     pub fn is_dot(&mut self) -> bool {
         self.tokens.peek_token() == Token::Dot
     }
 
+    /// `.0` without whitespace before it, lexed as a float
+    fn is_cell_path_index(&mut self) -> bool {
+        let (token, span) = self.tokens.peek();
+        if token != Token::Float || self.is_horizontal_space() {
+            return false;
+        }
+        match self.compiler.get_span_contents_manual(span.start, span.end) {
+            [b'.', digits @ ..] => !digits.is_empty() && digits.iter().all(u8::is_ascii_digit),
+            _ => false,
+        }
+    }
+
+    pub fn is_dotdotdot(&mut self) -> bool {
+        self.tokens.peek_token() == Token::DotDotDot
+    }
+
     pub fn is_dotdot(&mut self) -> bool {
         self.tokens.peek_token() == Token::DotDot
     }
@@ -1462,11 +4040,29 @@ impl Parser {
         self.tokens.peek_token() == Token::Float
     }
 
+    pub fn is_unit_value(&mut self) -> bool {
+        matches!(self.tokens.peek_token(), Token::Filesize | Token::Duration)
+    }
+
     pub fn is_string(&mut self) -> bool {
         self.tokens.peek_token() == Token::DoubleQuotedString
             || self.tokens.peek_token() == Token::SingleQuotedString
     }
 
+    pub fn is_raw_string(&mut self) -> bool {
+        matches!(
+            self.tokens.peek_token(),
+            Token::RawString | Token::BacktickBareword
+        )
+    }
+
+    pub fn is_string_interpolation(&mut self) -> bool {
+        matches!(
+            self.tokens.peek_token(),
+            Token::DqStringInterpStart | Token::SqStringInterpStart
+        )
+    }
+
     pub fn is_keyword(&mut self, keyword: &[u8]) -> bool {
         if let (Token::Bareword, span) = self.tokens.peek() {
             self.compiler.get_span_contents_manual(span.start, span.end) == keyword
@@ -1483,6 +4079,41 @@ impl Parser {
         self.tokens.peek_token() == Token::Eof
     }
 
+    /// Whether the next argument is a bare word, e.g., `*.rs`, `~/.config`, `..` or `foo.txt`
+    pub fn is_bare_word(&self) -> bool {
+        let (token, span) = self.tokens.peek();
+        let next = self
+            .tokens
+            .peek_next()
+            .filter(|(_, next)| next.start == span.end);
+        match token {
+            Token::Asterisk
+            | Token::AsteriskAsterisk
+            | Token::Tilde
+            | Token::Dot
+            | Token::ForwardSlash => true,
+            // `..` or `../src`, but not a range like `..5`
+            Token::DotDot => match next {
+                Some((next_token, _)) => [
+                    Token::ForwardSlash,
+                    Token::Eof,
+                    Token::Newline,
+                    Token::Pipe,
+                    Token::Semicolon,
+                    Token::RParen,
+                    Token::RCurly,
+                ]
+                .contains(&next_token),
+                None => true,
+            },
+            // a bareword followed by `..` is the start of a range
+            Token::Bareword => next.is_some_and(|(next_token, _)| {
+                BARE_WORD_TOKENS.contains(&next_token) && next_token != Token::DotDot
+            }),
+            _ => false,
+        }
+    }
+
     pub fn is_horizontal_space(&self) -> bool {
         let span_position = self.tokens.peek_span().start;
         let whitespace: &[u8] = b" \t";
@@ -1491,20 +4122,28 @@ impl Parser {
     }
 
     pub fn is_expression(&mut self) -> bool {
-        self.is_simple_expression()
+        self.is_operand()
             || self.is_keyword(b"if")
             || self.is_keyword(b"match")
+            || self.is_keyword(b"try")
             || self.is_keyword(b"where")
     }
 
     pub fn is_simple_expression(&mut self) -> bool {
         self.is_string()
+            || self.is_raw_string()
+            || self.is_string_interpolation()
             || self.is_int()
+            || self.is_negative_int()
             || self.is_float()
+            || self.is_unit_value()
+            || matches!(self.tokens.peek_token(), Token::Binary | Token::Datetime)
+            || self.is_negative_unit_value()
             || self.is_lcurly()
             || self.is_lsquare()
             || self.is_lparen()
             || self.is_dot()
+            || self.is_dotdot()
             || self.is_dollar()
             || self.is_keyword(b"true")
             || self.is_keyword(b"false")
@@ -1512,26 +4151,98 @@ impl Parser {
             || self.is_name()
     }
 
-    pub fn error_on_node(&mut self, message: impl Into<String>, node_id: NodeId) {
+    pub fn error_on_node(&mut self, code: ErrorCode, message: impl Into<Message>, node_id: NodeId) {
         self.compiler.errors.push(SourceError {
+            code,
             message: message.into(),
             node_id,
             severity: Severity::Error,
+            help: None,
+            note: None,
+            related: vec![],
+            fixes: vec![],
         });
     }
 
-    pub fn error(&mut self, message: impl Into<String>) -> NodeId {
+    /// Report an assignment operator where a value is expected
+    ///
+    /// A plain `=` in a condition is most likely meant to be a comparison, so it gets its own
+    /// error.
+    fn assignment_in_expression(&mut self, op: NodeId, context: ExpressionContext) {
+        let is_equals = matches!(self.compiler.get_node(op), AstNode::Assignment);
+
+        let is_condition = matches!(
+            context,
+            ExpressionContext::Condition | ExpressionContext::RowCondition
+        );
+        if is_equals && is_condition {
+            self.error_on_node(
+                ErrorCode::AssignmentInCondition,
+                "assignment used as a condition",
+                op,
+            );
+            if let Some(error) = self.compiler.errors.last_mut() {
+                error.help = Some("use `==` to compare the values".into());
+            }
+            self.add_note(CONDITION_ASSIGNMENT_NOTE);
+        } else {
+            self.error_on_node(
+                ErrorCode::AssignmentInExpression,
+                "assignment found in expression",
+                op,
+            );
+            self.add_note(ASSIGNMENT_NOTE);
+        }
+
+        if is_equals {
+            self.add_fix(Fix::new(
+                "compare with '==' instead",
+                vec![TextEdit::replace(self.compiler.get_span(op), "==")],
+            ));
+        }
+    }
+
+    /// Attach a suggested fix to the last reported error
+    fn add_fix(&mut self, fix: Fix) {
+        if let Some(error) = self.compiler.errors.last_mut() {
+            error.fixes.push(fix);
+        }
+    }
+
+    /// Attach a note to the last reported error
+    fn add_note(&mut self, note: &str) {
+        if let Some(error) = self.compiler.errors.last_mut() {
+            error.note = Some(note.into());
+        }
+    }
+
+    pub fn error(&mut self, code: ErrorCode, message: impl Into<Message>) -> NodeId {
+        self.error_with_related(code, message, vec![])
+    }
+
+    /// Same as error() but also points at other locations involved in the error
+    pub fn error_with_related(
+        &mut self,
+        code: ErrorCode,
+        message: impl Into<Message>,
+        related: Vec<RelatedSpan>,
+    ) -> NodeId {
         let (token, span) = self.tokens.peek();
 
         if token != Token::Eof {
-            self.tokens.advance();
+            self.advance();
         }
 
         let node_id = self.create_node(AstNode::Garbage, span.start, span.end);
         self.compiler.errors.push(SourceError {
+            code,
             message: message.into(),
             node_id,
             severity: Severity::Error,
+            help: None,
+            note: None,
+            related,
+            fixes: vec![],
         });
 
         node_id
@@ -1545,113 +4256,159 @@ impl Parser {
         self.compiler.push_node(ast_node)
     }
 
-    pub fn lparen(&mut self) {
+    /// Returns the span of the delimiter, for pointing at it when it is not closed
+    pub fn lparen(&mut self) -> Span {
+        let (_, span) = self.tokens.peek();
+
         if self.is_lparen() {
-            self.tokens.advance();
+            self.advance();
         } else {
-            self.error("expected: left paren '('");
+            self.error(ErrorCode::ExpectedToken, "expected: left paren '('");
         }
+
+        span
     }
 
-    pub fn rparen(&mut self) {
+    pub fn rparen(&mut self, opened: Span) {
         if self.is_rparen() {
-            self.tokens.advance();
+            self.advance();
         } else {
-            self.error("expected: right paren ')'");
+            self.unclosed_delimiter(opened, '(', ')');
         }
     }
 
-    pub fn lsquare(&mut self) {
+    /// Report the unclosed delimiter at its opening span, expecting the closing one before the
+    /// current token
+    fn unclosed_delimiter(&mut self, opened: Span, opening: char, closing: char) {
+        let close_at = self.tokens.peek_span().start;
+
+        // the current token is left for the enclosing expression, e.g., the `}` of the block
+        // containing the unclosed `(`
+        let node_id = self.create_node(AstNode::Garbage, opened.start, opened.end);
+        self.compiler.errors.push(SourceError::unclosed_delimiter(
+            node_id,
+            &opening.to_string(),
+            &closing.to_string(),
+            close_at,
+        ));
+    }
+
+    /// Returns the span of the delimiter, for pointing at it when it is not closed
+    pub fn lsquare(&mut self) -> Span {
+        let (_, span) = self.tokens.peek();
+
         if self.is_lsquare() {
-            self.tokens.advance();
+            self.advance();
         } else {
-            self.error("expected: left bracket '['");
+            self.error(ErrorCode::ExpectedToken, "expected: left bracket '['");
         }
+
+        span
     }
 
-    pub fn rsquare(&mut self) {
+    pub fn rsquare(&mut self, opened: Span) {
         if self.is_rsquare() {
-            self.tokens.advance();
+            self.advance();
         } else {
-            self.error("expected: right bracket ']'");
+            self.unclosed_delimiter(opened, '[', ']');
         }
     }
 
-    pub fn lcurly(&mut self) {
+    /// Returns the span of the delimiter, for pointing at it when it is not closed
+    pub fn lcurly(&mut self) -> Span {
+        let (_, span) = self.tokens.peek();
+
         if self.is_lcurly() {
-            self.tokens.advance();
+            self.advance();
+        } else {
+            self.error(ErrorCode::ExpectedToken, "expected: left bracket '{'");
+        }
+
+        span
+    }
+
+    /// End of the `}` about to be parsed by rcurly(), or the current position if it is missing
+    fn closing_curly_end(&mut self) -> usize {
+        if self.is_rcurly() {
+            self.tokens.peek_span().end
         } else {
-            self.error("expected: left bracket '{'");
+            self.position()
         }
     }
 
-    pub fn rcurly(&mut self) {
+    pub fn rcurly(&mut self, opened: Span) {
         if self.is_rcurly() {
-            self.tokens.advance();
+            self.advance();
         } else {
-            self.error("expected: right bracket '}'");
+            self.unclosed_delimiter(opened, '{', '}');
         }
     }
 
     pub fn pipe(&mut self) {
         if self.is_pipe() {
-            self.tokens.advance();
+            self.advance();
         } else {
-            self.error("expected: pipe symbol '|'");
+            self.error(ErrorCode::ExpectedToken, "expected: pipe symbol '|'");
         }
     }
 
     pub fn less_than(&mut self) {
         if self.is_less_than() {
-            self.tokens.advance();
+            self.advance();
         } else {
-            self.error("expected: less than/left angle bracket '<'");
+            self.error(
+                ErrorCode::ExpectedToken,
+                "expected: less than/left angle bracket '<'",
+            );
         }
     }
 
     pub fn greater_than(&mut self) {
         if self.is_greater_than() {
-            self.tokens.advance();
+            self.advance();
         } else {
-            self.error("expected: greater than/right angle bracket '>'");
+            self.error(
+                ErrorCode::ExpectedToken,
+                "expected: greater than/right angle bracket '>'",
+            );
         }
     }
 
     pub fn equals(&mut self) {
         if self.is_equals() {
-            self.tokens.advance();
+            self.advance();
         } else {
-            self.error("expected: equals '='");
+            self.error(ErrorCode::ExpectedToken, "expected: equals '='");
         }
     }
 
     pub fn thin_arrow(&mut self) {
         if self.is_thin_arrow() {
-            self.tokens.advance();
+            self.advance();
         } else {
-            self.error("expected: thin arrow '->'");
+            self.error(ErrorCode::ExpectedToken, "expected: thin arrow '->'");
         }
     }
 
     pub fn colon(&mut self) {
         if self.is_colon() {
-            self.tokens.advance();
+            self.advance();
         } else {
-            self.error("expected: colon ':'");
+            self.error(ErrorCode::ExpectedToken, "expected: colon ':'");
         }
     }
 
     pub fn comma(&mut self) {
         if self.is_comma() {
-            self.tokens.advance();
+            self.advance();
         } else {
-            self.error("expected: comma ','");
+            self.error(ErrorCode::ExpectedToken, "expected: comma ','");
         }
     }
 
     pub fn skip_newlines(&mut self) {
         while self.is_newline() {
-            self.tokens.advance();
+            self.advance();
         }
     }
 
@@ -1664,3 +4421,153 @@ impl Parser {
         self.tokens.set_pos(token_pos);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{
+        binary_literal_value, datetime_literal_value, float_literal_value, int_literal_value,
+        interpolation_chunk_value, raw_string_value, string_literal_value, unit_literal_value,
+        unquote, BlockContext, InvalidBinary, InvalidEscape, InvalidUnitValue, Parser,
+        DURATION_UNITS, FILESIZE_UNITS,
+    };
+    use crate::compiler::{Compiler, Span};
+
+    #[test]
+    fn float_literals() {
+        assert_eq!(float_literal_value(b"45_67.8_9"), Ok(4567.89));
+        assert_eq!(float_literal_value(b".3e3"), Ok(300.0));
+        assert_eq!(float_literal_value(b"2."), Ok(2.0));
+        assert!(float_literal_value(b"_._").is_err());
+    }
+
+    #[test]
+    fn radix_literals() {
+        assert_eq!(int_literal_value(b"0xFF"), Ok(255));
+        assert_eq!(int_literal_value(b"0o777"), Ok(511));
+        assert_eq!(int_literal_value(b"-0b1010"), Ok(-10));
+        assert_eq!(binary_literal_value(b"0x[FF 00]"), Ok(vec![0xff, 0x00]));
+        assert_eq!(binary_literal_value(b"0x[f]"), Ok(vec![0x0f]));
+        assert_eq!(binary_literal_value(b"0b[1\n00000001]"), Ok(vec![1, 1]));
+        assert_eq!(binary_literal_value(b"0o[377 007]"), Ok(vec![0xff, 7]));
+        // whitespace does not separate bytes, the missing digits are added at the front
+        assert_eq!(binary_literal_value(b"0x[FF 0]"), Ok(vec![0x0f, 0xf0]));
+        assert_eq!(binary_literal_value(b"0x[]"), Ok(vec![]));
+        assert_eq!(binary_literal_value(b"0o[777]"), Err(InvalidBinary));
+    }
+
+    #[test]
+    fn datetime_literals() {
+        let datetime = |contents: &[u8]| datetime_literal_value(contents).map(|d| d.to_rfc3339());
+        assert_eq!(
+            datetime(b"2024-01-15"),
+            Ok("2024-01-15T00:00:00+00:00".into())
+        );
+        assert_eq!(
+            datetime(b"2024-01-15T10:30:00.5"),
+            Ok("2024-01-15T10:30:00.500+00:00".into())
+        );
+        assert_eq!(
+            datetime(b"2024-01-15T10:30:00-02:00"),
+            Ok("2024-01-15T10:30:00-02:00".into())
+        );
+        assert_eq!(
+            datetime(b"2024-01-15Z"),
+            Ok("2024-01-15T00:00:00+00:00".into())
+        );
+        assert!(datetime(b"2024-02-30").is_err());
+        assert!(datetime(b"2024-01-15T25:00:00").is_err());
+    }
+
+    #[test]
+    fn unit_literals() {
+        assert_eq!(unit_literal_value(b"10kb", FILESIZE_UNITS), Ok(10_000));
+        assert_eq!(unit_literal_value(b"2KiB", FILESIZE_UNITS), Ok(2048));
+        assert_eq!(unit_literal_value(b"2.5mb", FILESIZE_UNITS), Ok(2_500_000));
+        assert_eq!(unit_literal_value(b"-1_000b", FILESIZE_UNITS), Ok(-1000));
+        assert_eq!(
+            unit_literal_value(b"500ms", DURATION_UNITS),
+            Ok(500_000_000)
+        );
+        assert_eq!(
+            unit_literal_value(b"1.5day", DURATION_UNITS),
+            Ok(36 * 3600 * 1_000_000_000)
+        );
+        assert_eq!(
+            unit_literal_value(b"100000eb", FILESIZE_UNITS),
+            Err(InvalidUnitValue)
+        );
+        assert_eq!(
+            unit_literal_value(b"10kb", DURATION_UNITS),
+            Err(InvalidUnitValue)
+        );
+    }
+
+    #[test]
+    fn string_literals() {
+        assert_eq!(string_literal_value(b"'a\\nb'"), Ok(b"a\\nb".to_vec()));
+        assert_eq!(string_literal_value(b"`a b`"), Ok(b"a b".to_vec()));
+        assert_eq!(
+            string_literal_value(br#""a\"b\\c\n\t\b""#),
+            Ok(b"a\"b\\c\n\t\x08".to_vec())
+        );
+        assert_eq!(
+            string_literal_value(br#""ab\q""#),
+            Err(InvalidEscape { offset: 3 })
+        );
+        assert_eq!(
+            string_literal_value(br#""ab\""#),
+            Err(InvalidEscape { offset: 3 })
+        );
+    }
+
+    #[test]
+    fn interpolation_chunks() {
+        assert_eq!(
+            interpolation_chunk_value(br#"a\(b\)\n"#, true),
+            Err(InvalidEscape { offset: 4 })
+        );
+        assert_eq!(
+            interpolation_chunk_value(br#"a\(b) \"c\"\t"#, true),
+            Ok(b"a(b) \"c\"\t".to_vec())
+        );
+        assert_eq!(
+            interpolation_chunk_value(br#"a\nb"#, false),
+            Ok(br#"a\nb"#.to_vec())
+        );
+        assert_eq!(
+            string_literal_value(br#""a\(""#),
+            Err(InvalidEscape { offset: 2 })
+        );
+    }
+
+    #[test]
+    fn unquoted() {
+        assert_eq!(unquote(b"\"x.nu\""), b"x.nu");
+        assert_eq!(unquote(b"'x.nu'"), b"x.nu");
+        assert_eq!(unquote(b"x.nu"), b"x.nu");
+        assert_eq!(unquote(b"\""), b"\"");
+    }
+
+    #[test]
+    fn raw_string_values() {
+        assert_eq!(raw_string_value(br"r#'C:\x'#"), br"C:\x");
+        assert_eq!(raw_string_value(b"r##'a'#b'##"), b"a'#b");
+        assert_eq!(raw_string_value(br"`a\n b`"), br"a\n b");
+        assert_eq!(raw_string_value(b"r#''#"), b"");
+    }
+
+    #[test]
+    fn statements_drop_parsed_tokens() {
+        let src = "let x = [1 2 3]\nls | get name\n".repeat(100);
+        let mut compiler = Compiler::new();
+        compiler.add_file("test.nu", src.as_bytes());
+
+        let mut parser = Parser::with_statements(compiler, Span::new(0, src.len()));
+        parser.lex_statements();
+        parser.block(BlockContext::Bare);
+
+        // only the last statement's newline and the EOF are left
+        assert_eq!(parser.tokens.iter().count(), 2);
+        assert!(parser.compiler.errors.is_empty());
+    }
+}
@@ -0,0 +1,332 @@
+//! Property-based tests checking invariants of the compiler stages on generated expressions
+use crate::compiler::Compiler;
+use crate::ir_generator::IrGenerator;
+use crate::lexer::lex;
+use crate::parser::{AstNode, NodeId, Parser, RecordEntry};
+use crate::resolver::Resolver;
+use crate::typechecker::Typechecker;
+
+use proptest::prelude::*;
+
+const OPERATORS: &[&str] = &[
+    "+", "-", "*", "/", "//", "mod", "**", "==", "!=", "<", "<=", ">", ">=", "and", "or", "++",
+];
+/// Indices into OPERATORS of operators that take and return numbers
+const NUMERIC_OPERATORS: std::ops::Range<usize> = 0..7;
+
+/// Generated expression, rendered to nushell source before parsing
+#[derive(Debug, Clone)]
+enum Expr {
+    Int(u32),
+    Float(u16, u16),
+    String(String),
+    Bool(bool),
+    Null,
+    /// Reference to a previously defined variable (index modulo the number of variables), or 0 if
+    /// there are no variables
+    Variable(usize),
+    BinaryOp(Box<Expr>, usize, Box<Expr>),
+    List(Vec<Expr>),
+    Record(Vec<(String, Expr)>),
+}
+
+fn expr_strategy() -> BoxedStrategy<Expr> {
+    let leaf = prop_oneof![
+        any::<u32>().prop_map(Expr::Int),
+        (any::<u16>(), any::<u16>()).prop_map(|(i, f)| Expr::Float(i, f)),
+        "[a-z]{0,8}".prop_map(Expr::String),
+        any::<bool>().prop_map(Expr::Bool),
+        Just(Expr::Null),
+        any::<usize>().prop_map(Expr::Variable),
+    ];
+
+    leaf.prop_recursive(4, 32, 4, |inner| {
+        prop_oneof![
+            (inner.clone(), 0..OPERATORS.len(), inner.clone())
+                .prop_map(|(lhs, op, rhs)| Expr::BinaryOp(Box::new(lhs), op, Box::new(rhs))),
+            prop::collection::vec(inner.clone(), 0..4).prop_map(Expr::List),
+            prop::collection::vec(("[a-z]{1,4}", inner), 0..4).prop_map(Expr::Record),
+        ]
+    })
+    .boxed()
+}
+
+/// Expression strategy producing only well-typed numeric expressions
+fn numeric_expr_strategy() -> BoxedStrategy<Expr> {
+    let leaf = prop_oneof![
+        any::<u32>().prop_map(Expr::Int),
+        (any::<u16>(), any::<u16>()).prop_map(|(i, f)| Expr::Float(i, f)),
+        any::<usize>().prop_map(Expr::Variable),
+    ];
+
+    leaf.prop_recursive(4, 32, 2, |inner| {
+        (inner.clone(), NUMERIC_OPERATORS, inner)
+            .prop_map(|(lhs, op, rhs)| Expr::BinaryOp(Box::new(lhs), op, Box::new(rhs)))
+    })
+    .boxed()
+}
+
+/// Program made of `let` statements followed by a final expression
+fn program_strategy() -> impl Strategy<Value = String> {
+    program_of(expr_strategy())
+}
+
+/// Same as `program_strategy()`, but the program is expected to pass typechecking
+fn typed_program_strategy() -> impl Strategy<Value = String> {
+    program_of(numeric_expr_strategy())
+}
+
+fn program_of(exprs: BoxedStrategy<Expr>) -> impl Strategy<Value = String> {
+    (prop::collection::vec(exprs.clone(), 0..4), exprs).prop_map(|(lets, last)| {
+        let mut src = String::new();
+        for (i, init) in lets.iter().enumerate() {
+            src.push_str(&format!("let x{i} = "));
+            render(init, i, &mut src);
+            src.push('\n');
+        }
+        render(&last, lets.len(), &mut src);
+        src.push('\n');
+        src
+    })
+}
+
+fn render(expr: &Expr, num_vars: usize, out: &mut String) {
+    match expr {
+        Expr::Int(val) => out.push_str(&val.to_string()),
+        Expr::Float(int, frac) => out.push_str(&format!("{int}.{frac}")),
+        Expr::String(val) => out.push_str(&format!("\"{val}\"")),
+        Expr::Bool(val) => out.push_str(&val.to_string()),
+        Expr::Null => out.push_str("null"),
+        Expr::Variable(idx) => {
+            if num_vars == 0 {
+                out.push('0');
+            } else {
+                out.push_str(&format!("$x{}", idx % num_vars));
+            }
+        }
+        Expr::BinaryOp(lhs, op, rhs) => {
+            render_operand(lhs, num_vars, out);
+            out.push_str(&format!(" {} ", OPERATORS[*op]));
+            render_operand(rhs, num_vars, out);
+        }
+        Expr::List(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(' ');
+                }
+                render_operand(item, num_vars, out);
+            }
+            out.push(']');
+        }
+        Expr::Record(pairs) => {
+            out.push('{');
+            for (i, (key, val)) in pairs.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                out.push_str(&format!("{key}: "));
+                render_operand(val, num_vars, out);
+            }
+            out.push('}');
+        }
+    }
+}
+
+fn render_operand(expr: &Expr, num_vars: usize, out: &mut String) {
+    if matches!(expr, Expr::BinaryOp(..)) {
+        out.push('(');
+        render(expr, num_vars, out);
+        out.push(')');
+    } else {
+        render(expr, num_vars, out);
+    }
+}
+
+fn parse(src: &str) -> Compiler {
+    let mut compiler = Compiler::new();
+    let span_offset = compiler.span_offset();
+    compiler.add_file("proptest.nu", src.as_bytes());
+
+    let (tokens, err) = lex(src.as_bytes(), span_offset);
+    assert!(err.is_ok(), "lexing failed: {err:?}");
+
+    Parser::new(compiler, tokens).parse()
+}
+
+fn root(compiler: &Compiler) -> NodeId {
+    NodeId(compiler.ast_nodes.len() - 1)
+}
+
+/// Direct children of the nodes that can be generated by `program_strategy()`
+fn children(compiler: &Compiler, node_id: NodeId) -> Vec<NodeId> {
+    match compiler.get_node(node_id) {
+        AstNode::Block(block_id) => compiler.blocks[block_id.0].nodes.clone(),
+        AstNode::Let {
+            variable_name,
+            initializer,
+            ..
+        } => vec![*variable_name, *initializer],
+        AstNode::BinaryOp { lhs, op, rhs } => vec![*lhs, *op, *rhs],
+        AstNode::List(items) => items.clone(),
+        AstNode::Record { entries } => entries.iter().flat_map(RecordEntry::nodes).collect(),
+        _ => vec![],
+    }
+}
+
+/// Print the AST back to source code, parenthesizing every nested binary operation
+fn unparse(compiler: &Compiler, node_id: NodeId, out: &mut String) {
+    match compiler.get_node(node_id) {
+        AstNode::Block(block_id) => {
+            for node in &compiler.blocks[block_id.0].nodes {
+                unparse(compiler, *node, out);
+                out.push('\n');
+            }
+        }
+        AstNode::Let {
+            variable_name,
+            initializer,
+            ..
+        } => {
+            out.push_str("let ");
+            unparse(compiler, *variable_name, out);
+            out.push_str(" = ");
+            unparse(compiler, *initializer, out);
+        }
+        AstNode::BinaryOp { lhs, op, rhs } => {
+            unparse_operand(compiler, *lhs, out);
+            out.push(' ');
+            unparse(compiler, *op, out);
+            out.push(' ');
+            unparse_operand(compiler, *rhs, out);
+        }
+        AstNode::List(items) => {
+            out.push('[');
+            for item in items {
+                unparse_operand(compiler, *item, out);
+                out.push(' ');
+            }
+            out.push(']');
+        }
+        AstNode::Record { entries } => {
+            out.push('{');
+            for entry in entries {
+                match entry {
+                    RecordEntry::Pair(key, val) => {
+                        unparse(compiler, *key, out);
+                        out.push_str(": ");
+                        unparse_operand(compiler, *val, out);
+                    }
+                    RecordEntry::Spread(spread) => unparse(compiler, *spread, out),
+                }
+                out.push_str(", ");
+            }
+            out.push('}');
+        }
+        _ => out.push_str(&String::from_utf8_lossy(
+            compiler.get_span_contents(node_id),
+        )),
+    }
+}
+
+fn unparse_operand(compiler: &Compiler, node_id: NodeId, out: &mut String) {
+    if matches!(compiler.get_node(node_id), AstNode::BinaryOp { .. }) {
+        out.push('(');
+        unparse(compiler, node_id, out);
+        out.push(')');
+    } else {
+        unparse(compiler, node_id, out);
+    }
+}
+
+/// Render the tree structure of the AST, independent of node order and spans
+fn structure(compiler: &Compiler, node_id: NodeId) -> String {
+    let node = compiler.get_node(node_id);
+    let name = format!("{node:?}");
+    let name = name.split([' ', '(']).next().unwrap_or_default();
+
+    let children: Vec<_> = children(compiler, node_id)
+        .into_iter()
+        .map(|child| structure(compiler, child))
+        .collect();
+
+    let is_container = matches!(
+        node,
+        AstNode::Block(_) | AstNode::List(_) | AstNode::Record { .. }
+    );
+
+    if children.is_empty() && !is_container {
+        format!(
+            "{name}:{}",
+            String::from_utf8_lossy(compiler.get_span_contents(node_id))
+        )
+    } else {
+        format!("({name} {})", children.join(" "))
+    }
+}
+
+proptest! {
+    #[test]
+    fn spans_cover_input(src in program_strategy()) {
+        let compiler = parse(&src);
+        prop_assert!(compiler.errors.is_empty(), "parse errors: {}", compiler.display_state());
+
+        let root_span = compiler.get_span(root(&compiler));
+        prop_assert_eq!(root_span.start, 0);
+        prop_assert_eq!(root_span.end, src.len());
+
+        for node_idx in 0..compiler.ast_nodes.len() {
+            let span = compiler.spans[node_idx];
+            prop_assert!(span.start <= span.end && span.end <= src.len());
+
+            for child in children(&compiler, NodeId(node_idx)) {
+                let child_span = compiler.get_span(child);
+                prop_assert!(
+                    span.start <= child_span.start && child_span.end <= span.end,
+                    "span of node {} not within its parent {}",
+                    child.0,
+                    node_idx
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn reparse_is_stable(src in program_strategy()) {
+        let compiler = parse(&src);
+        prop_assert!(compiler.errors.is_empty(), "parse errors: {}", compiler.display_state());
+
+        let mut printed = String::new();
+        unparse(&compiler, root(&compiler), &mut printed);
+
+        let reparsed = parse(&printed);
+        prop_assert!(reparsed.errors.is_empty(), "reparse errors: {}", reparsed.display_state());
+        prop_assert_eq!(
+            structure(&compiler, root(&compiler)),
+            structure(&reparsed, root(&reparsed))
+        );
+
+        let mut reprinted = String::new();
+        unparse(&reparsed, root(&reparsed), &mut reprinted);
+        prop_assert_eq!(printed, reprinted);
+    }
+
+    #[test]
+    fn ir_generation_does_not_panic(src in typed_program_strategy()) {
+        let mut compiler = parse(&src);
+        prop_assume!(compiler.errors.is_empty());
+
+        let mut resolver = Resolver::new(&compiler);
+        resolver.resolve();
+        compiler.merge_name_bindings(resolver.to_name_bindings());
+        prop_assume!(compiler.errors.is_empty());
+
+        let mut typechecker = Typechecker::new(&compiler);
+        typechecker.typecheck();
+        compiler.merge_types(typechecker.to_types());
+        prop_assume!(compiler.errors.is_empty());
+
+        let mut ir_generator = IrGenerator::new(&compiler);
+        ir_generator.generate();
+    }
+}
@@ -1,15 +1,78 @@
-pub trait Command: CommandClone {
+/// How the arguments of a command are matched to a parameter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ParamKind {
+    /// `x`, positional parameter that must be given
+    Required,
+    /// `x?` or `x = 1`, positional parameter that can be left out
+    Optional,
+    /// `...rest`, takes the remaining positional arguments
+    Rest,
+    /// `--flag` or `-f`, a flag without a type is a switch without a value
+    Flag,
+}
+
+/// Parameter of a command's signature
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Parameter {
+    /// Name of a positional parameter or long name of a flag, without the dashes
+    pub name: String,
+    pub short: Option<char>,
+    pub kind: ParamKind,
+    /// Name of the type as written, e.g., `list<int>`, None if the parameter is not annotated
+    ///
+    /// For rest parameters, this is the type of each argument.
+    pub ty: Option<String>,
+}
+
+pub trait Command: CommandClone + Send + Sync {
     fn name(&self) -> &str;
+
+    /// Names of the input and output types, if they are known before typechecking
+    fn input_output_types(&self) -> &[(String, String)] {
+        &[]
+    }
+
+    /// Parameters of the command, None if its signature is not known
+    fn params(&self) -> Option<&[Parameter]> {
+        None
+    }
+
+    /// Whether flags missing from the parameters are accepted, e.g., by the signature of an
+    /// external command given with `extern`
+    fn allows_unknown_flags(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Declaration {
     name: String,
+    params: Option<Vec<Parameter>>,
+    allows_unknown_flags: bool,
 }
 
 impl Declaration {
     pub fn new(name: String) -> Self {
-        Self { name }
+        Self {
+            name,
+            params: None,
+            allows_unknown_flags: false,
+        }
+    }
+
+    /// Declaration of a command defined with a signature, e.g., by `def`
+    pub fn with_params(mut self, params: Vec<Parameter>) -> Self {
+        self.params = Some(params);
+        self
+    }
+
+    /// Declaration of an external command, which may take more flags than it declares
+    pub fn with_unknown_flags(mut self) -> Self {
+        self.allows_unknown_flags = true;
+        self
     }
 }
 
@@ -17,6 +80,14 @@ impl Command for Declaration {
     fn name(&self) -> &str {
         &self.name
     }
+
+    fn params(&self) -> Option<&[Parameter]> {
+        self.params.as_deref()
+    }
+
+    fn allows_unknown_flags(&self) -> bool {
+        self.allows_unknown_flags
+    }
 }
 
 // Cloning implementation taken from Nushell
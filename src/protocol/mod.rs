@@ -1,5 +1,7 @@
 //! Some placeholder functionality from nu-protocol
 
 mod command;
+mod signature;
 
 pub use command::*;
+pub use signature::*;
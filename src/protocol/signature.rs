@@ -0,0 +1,253 @@
+use std::path::Path;
+
+use super::Command;
+
+/// Signature of a command defined outside of the compiled source, e.g., a builtin or a plugin
+/// command
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Signature {
+    pub name: String,
+    /// Pairs of input and output type names, e.g., `("string", "list<string>")`
+    pub input_output_types: Vec<(String, String)>,
+}
+
+impl Signature {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            input_output_types: vec![],
+        }
+    }
+
+    pub fn input_output_type(
+        mut self,
+        in_type: impl Into<String>,
+        out_type: impl Into<String>,
+    ) -> Self {
+        self.input_output_types
+            .push((in_type.into(), out_type.into()));
+        self
+    }
+}
+
+impl Command for Signature {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn input_output_types(&self) -> &[(String, String)] {
+        &self.input_output_types
+    }
+}
+
+#[derive(Debug)]
+pub enum SignatureError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    /// The JSON is valid, but does not describe signatures
+    Invalid(String),
+}
+
+impl std::fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignatureError::Io(err) => write!(f, "failed to read signatures: {err}"),
+            SignatureError::Json(err) => write!(f, "failed to parse signatures: {err}"),
+            SignatureError::Invalid(msg) => write!(f, "invalid signatures: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+/// Table of externally known commands, looked up by the resolver for names not defined in the
+/// source
+#[derive(Debug, Clone, Default)]
+pub struct Signatures {
+    signatures: Vec<Signature>,
+}
+
+impl Signatures {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a signature, replacing a previously added signature with the same name
+    pub fn add(&mut self, signature: Signature) {
+        match self
+            .signatures
+            .iter_mut()
+            .find(|sig| sig.name == signature.name)
+        {
+            Some(sig) => *sig = signature,
+            None => self.signatures.push(signature),
+        }
+    }
+
+    pub fn find(&self, name: &[u8]) -> Option<&Signature> {
+        self.signatures
+            .iter()
+            .find(|sig| sig.name.as_bytes() == name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.signatures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.signatures.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Signature> {
+        self.signatures.iter()
+    }
+
+    /// Format the signatures as JSON in the form read by `add_json()`
+    pub fn to_json(&self) -> serde_json::Value {
+        self.signatures
+            .iter()
+            .map(|signature| {
+                serde_json::json!({
+                    "name": signature.name,
+                    "input_output_types": signature.input_output_types,
+                })
+            })
+            .collect()
+    }
+
+    /// Add signatures from a JSON file, see `add_json()` for the format
+    pub fn add_json_file(&mut self, path: impl AsRef<Path>) -> Result<(), SignatureError> {
+        let contents = std::fs::read_to_string(path).map_err(SignatureError::Io)?;
+        self.add_json(&contents)
+    }
+
+    /// Add signatures from a JSON array of the form
+    ///
+    /// ```json
+    /// [{ "name": "str length", "input_output_types": [["string", "int"]] }]
+    /// ```
+    ///
+    /// `input_output_types` is optional.
+    pub fn add_json(&mut self, json: &str) -> Result<(), SignatureError> {
+        let value: serde_json::Value = serde_json::from_str(json).map_err(SignatureError::Json)?;
+
+        let serde_json::Value::Array(entries) = value else {
+            return Err(SignatureError::Invalid("expected an array".into()));
+        };
+
+        for (idx, entry) in entries.iter().enumerate() {
+            let Some(name) = entry.get("name").and_then(|name| name.as_str()) else {
+                return Err(SignatureError::Invalid(format!(
+                    "signature {idx} does not have a name"
+                )));
+            };
+
+            let mut signature = Signature::new(name);
+
+            if let Some(types) = entry.get("input_output_types") {
+                let Some(pairs) = parse_input_output_types(types) else {
+                    return Err(SignatureError::Invalid(format!(
+                        "input_output_types of `{name}` must be an array of [input, output] pairs"
+                    )));
+                };
+
+                signature.input_output_types = pairs;
+            }
+
+            self.add(signature);
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_input_output_types(types: &serde_json::Value) -> Option<Vec<(String, String)>> {
+    types
+        .as_array()?
+        .iter()
+        .map(|pair| match pair.as_array()?.as_slice() {
+            [serde_json::Value::String(in_type), serde_json::Value::String(out_type)] => {
+                Some((in_type.clone(), out_type.clone()))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Signature, SignatureError, Signatures};
+    use crate::compiler::Compiler;
+    use crate::lexer::lex;
+    use crate::parser::{AstNode, NodeId, Parser};
+    use crate::resolver::Resolver;
+    use crate::typechecker::{Type, Typechecker, INT_TYPE};
+
+    #[test]
+    fn parse_json() {
+        let mut signatures = Signatures::new();
+        signatures
+            .add_json(
+                r#"[
+                    { "name": "str length", "input_output_types": [["string", "int"]] },
+                    { "name": "ls" }
+                ]"#,
+            )
+            .expect("valid signatures");
+
+        assert_eq!(signatures.len(), 2);
+        assert_eq!(
+            signatures.find(b"str length"),
+            Some(&Signature::new("str length").input_output_type("string", "int"))
+        );
+        assert_eq!(signatures.find(b"ls"), Some(&Signature::new("ls")));
+
+        assert!(matches!(
+            signatures.add_json(r#"[{ "name": "ls", "input_output_types": ["any"] }]"#),
+            Err(SignatureError::Invalid(_))
+        ));
+        assert!(matches!(
+            signatures.add_json("{"),
+            Err(SignatureError::Json(_))
+        ));
+
+        let mut roundtrip = Signatures::new();
+        roundtrip
+            .add_json(&signatures.to_json().to_string())
+            .expect("formatted signatures are valid");
+        assert_eq!(
+            roundtrip.iter().collect::<Vec<_>>(),
+            signatures.iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn calls_use_signature_types() {
+        let mut signatures = Signatures::new();
+        signatures.add(Signature::new("str length").input_output_type("string", "list<int>"));
+
+        let src = b"str length";
+        let mut compiler = Compiler::new();
+        compiler.add_file("test.nu", src);
+
+        let (tokens, err) = lex(src, 0);
+        assert!(err.is_ok());
+        let mut compiler = Parser::new(compiler, tokens).parse();
+
+        let mut resolver = Resolver::new(&compiler).with_signatures(&signatures);
+        resolver.resolve();
+        let name_bindings = resolver.to_name_bindings();
+        compiler.merge_name_bindings(name_bindings);
+        assert!(compiler.errors.is_empty());
+
+        let mut typechecker = Typechecker::new(&compiler);
+        typechecker.typecheck();
+
+        let call = (0..compiler.ast_nodes.len())
+            .map(NodeId)
+            .find(|node_id| matches!(compiler.get_node(*node_id), AstNode::Call { .. }))
+            .expect("missing call");
+        assert_eq!(typechecker.type_of(call), Type::List(INT_TYPE));
+    }
+}
@@ -0,0 +1,87 @@
+//! Python bindings for analyzing nushell scripts from Python tooling
+//!
+//! Build and install the module with [`maturin`](https://github.com/PyO3/maturin): `maturin develop`
+//! (the features are configured in pyproject.toml). The functions mirror the WebAssembly bindings:
+//! each takes the source code and returns plain dicts and lists with the same shape as the JSON
+//! output of the CLI. All spans are byte offsets into the UTF-8 encoded source.
+//!
+//! ```python
+//! import new_nu_parser
+//! for diagnostic in new_nu_parser.diagnostics("1 + 'a'"):
+//!     print(diagnostic["message"])
+//! ```
+use pyo3::prelude::*;
+
+use crate::bindings::compile;
+
+const FILE_NAME: &str = "script.nu";
+
+fn to_py<'py>(py: Python<'py>, value: &serde_json::Value) -> PyResult<Bound<'py, PyAny>> {
+    // going through the json module is simpler than building the objects one by one
+    py.import_bound("json")?
+        .call_method1("loads", (value.to_string(),))
+}
+
+/// Parse the source and return the AST nodes and parser errors
+#[pyfunction]
+fn parse<'py>(py: Python<'py>, source: &str) -> PyResult<Bound<'py, PyAny>> {
+    let compiled = compile(FILE_NAME, source.as_bytes());
+    to_py(py, &compiled.compiler.to_json())
+}
+
+/// Run all stages up to typechecking and return the output of each stage that ran
+#[pyfunction]
+fn typecheck<'py>(py: Python<'py>, source: &str) -> PyResult<Bound<'py, PyAny>> {
+    let compiled = compile(FILE_NAME, source.as_bytes());
+    to_py(py, &compiled.stages_json())
+}
+
+/// Return the errors of all stages with the spans of the nodes they refer to
+#[pyfunction]
+fn diagnostics<'py>(py: Python<'py>, source: &str) -> PyResult<Bound<'py, PyAny>> {
+    let compiled = compile(FILE_NAME, source.as_bytes());
+    to_py(py, &compiled.diagnostics_json())
+}
+
+/// Return the spans of the source classified for syntax highlighting, sorted by position
+#[pyfunction]
+fn semantic_tokens<'py>(py: Python<'py>, source: &str) -> PyResult<Bound<'py, PyAny>> {
+    let compiled = compile(FILE_NAME, source.as_bytes());
+    to_py(py, &compiled.semantic_tokens_json())
+}
+
+#[pymodule]
+fn new_nu_parser(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(parse, m)?)?;
+    m.add_function(wrap_pyfunction!(typecheck, m)?)?;
+    m.add_function(wrap_pyfunction!(diagnostics, m)?)?;
+    m.add_function(wrap_pyfunction!(semantic_tokens, m)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use pyo3::prelude::*;
+
+    use super::{diagnostics, typecheck};
+
+    #[test]
+    fn returns_python_objects() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let stages = typecheck(py, "let x = 1\n$x").expect("typecheck failed");
+            assert!(stages.contains("types").expect("not a dict"));
+
+            let diagnostics = diagnostics(py, "1 + 'a'").expect("diagnostics failed");
+            let message: String = diagnostics
+                .get_item(0)
+                .and_then(|diagnostic| diagnostic.get_item("message"))
+                .and_then(|message| message.extract())
+                .expect("missing message");
+            assert!(
+                message.contains("addition"),
+                "unexpected message: {message}"
+            );
+        });
+    }
+}
@@ -0,0 +1,178 @@
+//! Rendering of diagnostics for terminals
+//!
+//! Each diagnostic is printed along with the lines of the source it refers to, similar to rustc:
+//!
+//! ```text
+//! error[nu::parser::unclosed_delimiter]: unclosed '('
+//!  --> test.nu:1:1
+//!   |
+//! 1 | (1 + (2 * 3)
+//!   | ^
+//!   |             - expected ')' to close the '('
+//!   = fix: insert the missing ')'
+//! ```
+use std::collections::BTreeMap;
+
+use crate::compiler::{Compiler, Span};
+use crate::errors::{Severity, SourceError};
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const CYAN: &str = "\x1b[1;36m";
+const BLUE: &str = "\x1b[1;34m";
+
+struct Style {
+    color: bool,
+}
+
+impl Style {
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color {
+            format!("{style}{text}{RESET}")
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+/// A span to underline in the source excerpt
+struct Label<'a> {
+    span: Span,
+    message: Option<&'a str>,
+    primary: bool,
+}
+
+/// Render all errors, separated by empty lines
+pub fn render_all(compiler: &Compiler, errors: &[SourceError], color: bool) -> String {
+    errors
+        .iter()
+        .map(|error| render(compiler, error, color))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Render the error with the excerpt of the file containing it, using ANSI colors if `color` is
+/// set
+///
+/// Related spans in other files than the error's one are left out.
+pub fn render(compiler: &Compiler, error: &SourceError, color: bool) -> String {
+    let style = Style { color };
+    let span = compiler.get_span(error.node_id);
+
+    let (severity, severity_style) = match error.severity {
+        Severity::Error => ("error", RED),
+        Severity::Warning => ("warning", YELLOW),
+        Severity::Note => ("note", CYAN),
+    };
+
+    let mut result = format!(
+        "{}{}\n",
+        style.paint(severity_style, &format!("{severity}[{}]", error.code)),
+        style.paint(BOLD, &format!(": {}", error.message(compiler))),
+    );
+
+    let mut labels = vec![Label {
+        span,
+        message: None,
+        primary: true,
+    }];
+
+    let file = compiler.file_of(span);
+    labels.extend(
+        error
+            .related
+            .iter()
+            .filter(|related| compiler.file_of(related.span) == file)
+            .map(|related| Label {
+                span: related.span,
+                message: Some(&related.message),
+                primary: false,
+            }),
+    );
+
+    // Line number -> labels starting on the line
+    let mut lines: BTreeMap<usize, Vec<Label>> = BTreeMap::new();
+    for label in labels {
+        let ((line, _), _) = compiler.span_line_columns(label.span);
+        lines.entry(line).or_default().push(label);
+    }
+
+    let width = lines.keys().last().map_or(1, |line| line.to_string().len());
+    let pad = " ".repeat(width);
+    let gutter = |text: &str| style.paint(BLUE, text);
+
+    if let Some((fname, file_start, file_end)) = file {
+        let ((line, column), _) = compiler.span_line_columns(span);
+        result.push_str(&format!("{pad}{} {fname}:{line}:{column}\n", gutter("-->")));
+        result.push_str(&format!("{pad} {}\n", gutter("|")));
+
+        let mut previous_line = None;
+        for (line, labels) in &lines {
+            if previous_line.is_some_and(|previous| line - previous > 1) {
+                result.push_str(&format!("{}\n", gutter("...")));
+            }
+            previous_line = Some(*line);
+
+            let offset = labels[0].span.start;
+            let line_start = compiler.source[*file_start..offset]
+                .iter()
+                .rposition(|c| *c == b'\n')
+                .map_or(*file_start, |pos| file_start + pos + 1);
+            let line_end = compiler.source[offset..*file_end]
+                .iter()
+                .position(|c| *c == b'\n')
+                .map_or(*file_end, |pos| offset + pos);
+            let text = String::from_utf8_lossy(&compiler.source[line_start..line_end]);
+            let text = text.trim_end_matches('\r');
+
+            result.push_str(&format!(
+                "{} {text}\n",
+                gutter(&format!("{line:>width$} |"))
+            ));
+
+            for label in labels {
+                // keep the tabs so that the marks line up with the source
+                let indent: String =
+                    String::from_utf8_lossy(&compiler.source[line_start..label.span.start])
+                        .chars()
+                        .map(|c| if c == '\t' { '\t' } else { ' ' })
+                        .collect();
+
+                let end = label.span.end.clamp(label.span.start, line_end);
+                let len = String::from_utf8_lossy(&compiler.source[label.span.start..end])
+                    .chars()
+                    .count()
+                    .max(1);
+
+                let marks = if label.primary {
+                    style.paint(severity_style, &"^".repeat(len))
+                } else {
+                    style.paint(BLUE, &"-".repeat(len))
+                };
+
+                let message = label
+                    .message
+                    .map(|message| format!(" {message}"))
+                    .unwrap_or_default();
+
+                result.push_str(&format!("{pad} {} {indent}{marks}{message}\n", gutter("|")));
+            }
+        }
+    }
+
+    if let Some(help) = &error.help {
+        result.push_str(&format!("{pad} {} {help}\n", gutter("= help:")));
+    }
+
+    if let Some(note) = &error.note {
+        result.push_str(&format!("{pad} {} {note}\n", gutter("= note:")));
+    }
+
+    for fix in &error.fixes {
+        result.push_str(&format!("{pad} {} {}\n", gutter("= fix:"), fix.message));
+    }
+
+    result
+}
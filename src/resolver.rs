@@ -1,15 +1,26 @@
-use crate::protocol::{Command, Declaration};
+use crate::engine::{EngineIds, EngineLookup};
+use crate::files::PARSE_TIME_COMMANDS;
+use crate::protocol::{Command, Declaration, ParamKind, Parameter, Signatures};
 use crate::{
-    compiler::Compiler,
-    errors::{Severity, SourceError},
-    parser::{AstNode, BlockId, NodeId},
+    compiler::{Compiler, Profile, Span},
+    errors::{did_you_mean, ErrorCode, Fix, Message, RelatedSpan, Severity, SourceError, TextEdit},
+    lint::Lint,
+    parser::{AstNode, BlockId, NodeId, RecordEntry},
 };
-use std::collections::HashMap;
+use nu_protocol::{ENV_VARIABLE_ID, NU_VARIABLE_ID};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// Variables defined by Nushell itself, without the leading `$`, and their ids in the engine
+const BUILTIN_VARIABLES: &[(&[u8], nu_protocol::VarId)] =
+    &[(b"env", ENV_VARIABLE_ID), (b"nu", NU_VARIABLE_ID)];
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ScopeId(pub usize);
 
 #[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FrameType {
     /// Default scope frame marking the scope of a block/closure
     Scope,
@@ -20,10 +31,14 @@ pub enum FrameType {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Frame {
     pub frame_type: FrameType,
     pub variables: HashMap<Vec<u8>, NodeId>,
     pub decls: HashMap<Vec<u8>, NodeId>,
+    pub modules: HashMap<Vec<u8>, ModuleId>,
+    /// Names hidden by `hide`, the declarations of the frames below are not visible under them
+    pub hidden: HashSet<Vec<u8>>,
     /// Node that defined the scope frame (e.g., a block or overlay)
     pub node_id: NodeId,
 }
@@ -34,22 +49,43 @@ impl Frame {
             frame_type: scope_type,
             variables: HashMap::new(),
             decls: HashMap::new(),
+            modules: HashMap::new(),
+            hidden: HashSet::new(),
             node_id,
         }
     }
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Variable {
     pub is_mutable: bool,
 }
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct VarId(pub usize);
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DeclId(pub usize);
 
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ModuleId(pub usize);
+
+/// Module defined by `module` or loaded from a file by `use`
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Module {
+    pub name: String,
+    /// Exported definitions -> node of the definition's name
+    ///
+    /// The definitions of exported submodules are included, prefixed by the submodule's name,
+    /// e.g., `sub foo`.
+    pub decls: HashMap<Vec<u8>, NodeId>,
+}
+
 /// Fields extracted from Resolver
 pub struct NameBindings {
     pub scope: Vec<Frame>,
@@ -58,6 +94,10 @@ pub struct NameBindings {
     pub var_resolution: HashMap<NodeId, VarId>,
     pub decls: Vec<Box<dyn Command>>,
     pub decl_resolution: HashMap<NodeId, DeclId>,
+    pub call_name_parts: HashMap<NodeId, usize>,
+    pub modules: Vec<Module>,
+    pub module_resolution: HashMap<NodeId, ModuleId>,
+    pub engine_ids: EngineIds,
     pub errors: Vec<SourceError>,
 }
 
@@ -70,6 +110,10 @@ impl NameBindings {
             var_resolution: HashMap::new(),
             decls: vec![],
             decl_resolution: HashMap::new(),
+            call_name_parts: HashMap::new(),
+            modules: vec![],
+            module_resolution: HashMap::new(),
+            engine_ids: EngineIds::default(),
             errors: vec![],
         }
     }
@@ -97,6 +141,23 @@ pub struct Resolver<'a> {
     pub decls: Vec<Box<dyn Command>>,
     /// Mapping of decl's name node -> Command
     pub decl_resolution: HashMap<NodeId, DeclId>,
+    /// Number of parts of the command name of resolved calls, e.g., 2 for `foo bar` calling `bar`
+    /// imported from the module `foo`
+    pub call_name_parts: HashMap<NodeId, usize>,
+    /// Modules, indexed by ModuleId
+    pub modules: Vec<Module>,
+    /// Mapping of module's name node (in `module` or `use`) -> Module
+    pub module_resolution: HashMap<NodeId, ModuleId>,
+    /// Externally known commands for looking up names not defined in the source
+    signatures: Option<&'a Signatures>,
+    /// Declarations created for the signatures found so far
+    signature_decls: HashMap<Vec<u8>, DeclId>,
+    /// Engine for looking up names not defined in the source nor in the signatures
+    engine: Option<&'a dyn EngineLookup>,
+    /// Engine's ids of the declarations and variables found in the engine
+    pub engine_ids: EngineIds,
+    /// Variables created for the builtin variables used so far, e.g., `$env` in config files
+    builtin_variables: HashMap<Vec<u8>, VarId>,
     /// Errors encountered during name binding
     pub errors: Vec<SourceError>,
 }
@@ -111,10 +172,55 @@ impl<'a> Resolver<'a> {
             var_resolution: HashMap::new(),
             decls: vec![],
             decl_resolution: HashMap::new(),
+            call_name_parts: HashMap::new(),
+            modules: vec![],
+            module_resolution: HashMap::new(),
+            signatures: None,
+            signature_decls: HashMap::new(),
+            engine: None,
+            engine_ids: EngineIds::default(),
+            builtin_variables: HashMap::new(),
             errors: vec![],
         }
     }
 
+    /// Create a resolver continuing from previously resolved name bindings
+    ///
+    /// The name bindings are typically taken out of the compiler with
+    /// `Compiler::take_name_bindings()` and merged back after resolving.
+    pub fn with_name_bindings(compiler: &'a Compiler, name_bindings: NameBindings) -> Self {
+        Self {
+            compiler,
+            scope: name_bindings.scope,
+            scope_stack: name_bindings.scope_stack,
+            variables: name_bindings.variables,
+            var_resolution: name_bindings.var_resolution,
+            decls: name_bindings.decls,
+            decl_resolution: name_bindings.decl_resolution,
+            call_name_parts: name_bindings.call_name_parts,
+            modules: name_bindings.modules,
+            module_resolution: name_bindings.module_resolution,
+            signatures: None,
+            signature_decls: HashMap::new(),
+            engine: None,
+            engine_ids: name_bindings.engine_ids,
+            builtin_variables: HashMap::new(),
+            errors: name_bindings.errors,
+        }
+    }
+
+    /// Look up the commands that are not defined in the source in the signatures
+    pub fn with_signatures(mut self, signatures: &'a Signatures) -> Self {
+        self.signatures = Some(signatures);
+        self
+    }
+
+    /// Look up the names that are not defined in the source in the engine
+    pub fn with_engine(mut self, engine: &'a dyn EngineLookup) -> Self {
+        self.engine = Some(engine);
+        self
+    }
+
     pub fn to_name_bindings(self) -> NameBindings {
         NameBindings {
             scope: self.scope,
@@ -123,6 +229,10 @@ impl<'a> Resolver<'a> {
             var_resolution: self.var_resolution,
             decls: self.decls,
             decl_resolution: self.decl_resolution,
+            call_name_parts: self.call_name_parts,
+            modules: self.modules,
+            module_resolution: self.module_resolution,
+            engine_ids: self.engine_ids,
             errors: self.errors,
         }
     }
@@ -155,7 +265,11 @@ impl<'a> Resolver<'a> {
                 .map(|(name, id)| format!("{0}: {id:?}", String::from_utf8_lossy(name)))
                 .collect();
 
-            if vars.is_empty() && decls.is_empty() {
+            if vars.is_empty()
+                && decls.is_empty()
+                && scope.modules.is_empty()
+                && scope.hidden.is_empty()
+            {
                 result.push_str(" (empty)\n");
                 continue;
             }
@@ -173,27 +287,118 @@ impl<'a> Resolver<'a> {
                 let line_decl = format!("      decls: [ {0} ]\n", decls.join(", "));
                 result.push_str(&line_decl);
             }
+
+            let mut modules: Vec<String> = scope
+                .modules
+                .iter()
+                .map(|(name, id)| format!("{0}: {id:?}", String::from_utf8_lossy(name)))
+                .collect();
+            if !modules.is_empty() {
+                modules.sort();
+                let line_module = format!("    modules: [ {0} ]\n", modules.join(", "));
+                result.push_str(&line_module);
+            }
+
+            let mut hidden: Vec<_> = scope
+                .hidden
+                .iter()
+                .map(|name| String::from_utf8_lossy(name))
+                .collect();
+            if !hidden.is_empty() {
+                hidden.sort();
+                let line_hidden = format!("     hidden: [ {0} ]\n", hidden.join(", "));
+                result.push_str(&line_hidden);
+            }
+        }
+
+        if !self.modules.is_empty() {
+            result.push_str("==== MODULES ====\n");
+            for (i, module) in self.modules.iter().enumerate() {
+                let mut decls: Vec<String> = module
+                    .decls
+                    .iter()
+                    .map(|(name, id)| format!("{0}: {id:?}", String::from_utf8_lossy(name)))
+                    .collect();
+                decls.sort();
+                result.push_str(&format!(
+                    "{i}: Module {0}, exports: [ {1} ]\n",
+                    module.name,
+                    decls.join(", ")
+                ));
+            }
         }
 
         if !self.errors.is_empty() {
             result.push_str("==== SCOPE ERRORS ====\n");
             for error in &self.errors {
-                result.push_str(&format!(
-                    "{:?} (NodeId {}): {}\n",
-                    error.severity, error.node_id.0, error.message
-                ));
+                result.push_str(&error.display(self.compiler));
             }
         }
 
         result
     }
 
+    /// Format the scope frames and errors as JSON for machine-readable output
+    pub fn to_json(&self) -> serde_json::Value {
+        let names_to_json = |names: &HashMap<Vec<u8>, NodeId>| {
+            names
+                .iter()
+                .map(|(name, id)| (String::from_utf8_lossy(name).to_string(), id.0.into()))
+                .collect::<serde_json::Map<_, _>>()
+        };
+
+        let scope: Vec<_> = self
+            .scope
+            .iter()
+            .map(|frame| {
+                serde_json::json!({
+                    "frame_type": format!("{:?}", frame.frame_type),
+                    "node_id": frame.node_id.0,
+                    "variables": names_to_json(&frame.variables),
+                    "decls": names_to_json(&frame.decls),
+                })
+            })
+            .collect();
+        let errors: Vec<_> = self
+            .errors
+            .iter()
+            .map(|error| error.to_json(self.compiler))
+            .collect();
+
+        serde_json::json!({ "scope": scope, "errors": errors })
+    }
+
     pub fn resolve(&mut self) {
         if !self.compiler.ast_nodes.is_empty() {
             let last = self.compiler.ast_nodes.len() - 1;
             let last_node_id = NodeId(last);
-            self.resolve_node(last_node_id)
+            self.resolve_node(last_node_id);
+            self.lint_unused_variables();
+        }
+    }
+
+    /// Same as resolve(), but keep the top-level definitions in scope for the next resolution
+    ///
+    /// The first call enters a root scope frame that is never exited. The last node is expected to
+    /// be the block of newly parsed code, which is resolved inside the root frame.
+    pub fn resolve_incremental(&mut self) {
+        if self.compiler.ast_nodes.is_empty() {
+            return;
+        }
+
+        let last_node_id = NodeId(self.compiler.ast_nodes.len() - 1);
+
+        let AstNode::Block(block_id) = self.compiler.ast_nodes[last_node_id.0] else {
+            self.resolve_node(last_node_id);
+            return;
+        };
+
+        if self.scope_stack.is_empty() {
+            self.enter_scope(last_node_id);
         }
+
+        let root_scope = self.scope_stack[0];
+        self.resolve_block(last_node_id, block_id, Some(root_scope));
     }
 
     pub fn resolve_node(&mut self, node_id: NodeId) {
@@ -225,7 +430,8 @@ impl<'a> Resolver<'a> {
                 block,
             } => {
                 // define the command before the block to enable recursive calls
-                self.define_decl(name);
+                let decl = Declaration::new(self.decl_name(name)).with_params(self.params(params));
+                self.add_decl(name, decl);
 
                 // making sure the def parameters and body end up in the same scope frame
                 self.enter_scope(block);
@@ -240,14 +446,77 @@ impl<'a> Resolver<'a> {
             }
             AstNode::Alias {
                 new_name,
-                old_name: _,
+                expansion,
             } => {
+                // the expansion cannot refer to the alias itself
+                self.resolve_node(expansion);
                 self.define_decl(new_name);
             }
+            AstNode::Extern { name, params } => {
+                let decl = Declaration::new(self.decl_name(name))
+                    .with_params(self.params(params))
+                    .with_unknown_flags();
+                self.add_decl(name, decl);
+
+                // the parameters are not visible anywhere, there is no body
+                self.enter_scope(params);
+                self.resolve_node(params);
+                self.exit_scope();
+            }
+            AstNode::EnvShorthand {
+                ref assignments,
+                block,
+            } => {
+                for (_, value) in assignments {
+                    self.resolve_node(*value);
+                }
+                // the block is called by `do`, which keeps the changes of the environment to it
+                if let Some(decl_id) = self.find_engine_decl(b"do") {
+                    self.decl_resolution.insert(node_id, decl_id);
+                }
+                self.resolve_node(block);
+            }
+            AstNode::Module { name, block } => {
+                let module_id = self.resolve_module(self.decl_name(name), block);
+                self.add_module(name, module_id);
+            }
+            AstNode::Use {
+                module,
+                ref members,
+            } => {
+                self.resolve_use(node_id, module, members);
+            }
+            AstNode::OverlayUse { module, .. } => self.resolve_overlay_use(node_id, module),
+            AstNode::OverlayNew(_) => self.enter_overlay(node_id, HashMap::new()),
+            AstNode::OverlayHide(name) => self.hide_overlay(node_id, name),
+            AstNode::Hide(ref parts) => self.hide_decl(node_id, parts),
+            // outside of a module, an exported definition is like any other
+            AstNode::Export(definition) => self.resolve_node(definition),
+            AstNode::ExportEnv(block) => self.resolve_node(block),
             AstNode::Params(ref params) => {
                 for param in params {
-                    if let AstNode::Param { name, .. } = self.compiler.ast_nodes[param.0] {
-                        self.define_variable(name, false);
+                    if let AstNode::Param {
+                        name,
+                        kind,
+                        default,
+                        ..
+                    } = self.compiler.ast_nodes[param.0]
+                    {
+                        if let Some(default) = default {
+                            self.resolve_node(default);
+                        }
+                        if kind == ParamKind::Flag {
+                            // `$keep_empty` for `--keep-empty`
+                            let var_name = self
+                                .compiler
+                                .get_span_contents(name)
+                                .iter()
+                                .map(|c| if *c == b'-' { b'_' } else { *c })
+                                .collect();
+                            self.define_variable_named(name, var_name, false);
+                        } else {
+                            self.define_variable(name, false);
+                        }
                     } else {
                         panic!("param is not a param");
                     }
@@ -262,6 +531,14 @@ impl<'a> Resolver<'a> {
                 self.resolve_node(initializer);
                 self.define_variable(variable_name, is_mutable)
             }
+            AstNode::Const {
+                variable_name,
+                initializer,
+                ..
+            } => {
+                self.resolve_node(initializer);
+                self.define_variable(variable_name, false)
+            }
             AstNode::While { condition, block } => {
                 self.resolve_node(condition);
                 self.resolve_node(block);
@@ -287,15 +564,23 @@ impl<'a> Resolver<'a> {
             AstNode::Loop { block } => {
                 self.resolve_node(block);
             }
-            AstNode::BinaryOp { lhs, op: _, rhs } => {
+            AstNode::UnaryOp { value, .. } => self.resolve_node(value),
+            AstNode::BinaryOp { lhs, op, rhs } => {
                 self.resolve_node(lhs);
                 self.resolve_node(rhs);
+
+                if self.compiler.ast_nodes[op.0].is_assignment() {
+                    self.check_mutable(lhs);
+                }
             }
-            AstNode::Range { lhs, rhs } => {
-                self.resolve_node(lhs);
-                self.resolve_node(rhs);
+            AstNode::Range { from, next, to, .. } => {
+                for bound in [from, next, to].into_iter().flatten() {
+                    self.resolve_node(bound);
+                }
             }
-            AstNode::List(ref nodes) => {
+            AstNode::List(ref nodes)
+            | AstNode::StringInterpolation(ref nodes)
+            | AstNode::Pipeline(ref nodes) => {
                 for node in nodes {
                     self.resolve_node(*node);
                 }
@@ -306,16 +591,13 @@ impl<'a> Resolver<'a> {
                     self.resolve_node(*row);
                 }
             }
-            AstNode::Record { ref pairs } => {
-                for (key, val) in pairs {
-                    self.resolve_node(*key);
-                    self.resolve_node(*val);
+            AstNode::Record { ref entries } => {
+                for node in entries.iter().flat_map(RecordEntry::nodes) {
+                    self.resolve_node(node);
                 }
             }
-            AstNode::MemberAccess { target, field } => {
-                self.resolve_node(target);
-                self.resolve_node(field);
-            }
+            AstNode::Spread(value) => self.resolve_node(value),
+            AstNode::CellPath { head, .. } => self.resolve_node(head),
             AstNode::If {
                 condition,
                 then_block,
@@ -327,27 +609,82 @@ impl<'a> Resolver<'a> {
                     self.resolve_node(block);
                 }
             }
+            AstNode::Try { block, catch } => {
+                self.resolve_node(block);
+                if let Some(catch) = catch {
+                    self.resolve_node(catch);
+                }
+            }
             AstNode::Match {
                 target,
                 ref match_arms,
             } => {
                 self.resolve_node(target);
                 for (arm_lhs, arm_rhs) in match_arms {
-                    self.resolve_node(*arm_lhs);
+                    let variables = self.compiler.pattern_variables(*arm_lhs);
+                    if variables.is_empty() {
+                        self.resolve_node(*arm_lhs);
+                        self.resolve_node(*arm_rhs);
+                        continue;
+                    }
+
+                    // the variables bound by the pattern are visible in its guard and arm only
+                    self.enter_scope(*arm_lhs);
+                    for variable in variables {
+                        self.define_variable(variable, false);
+                    }
+                    if let AstNode::GuardedPattern { guard, .. } = self.compiler.get_node(*arm_lhs)
+                    {
+                        self.resolve_node(*guard);
+                    }
                     self.resolve_node(*arm_rhs);
+                    self.exit_scope();
+                }
+            }
+            AstNode::OrPattern(ref alternatives) => {
+                for alternative in alternatives {
+                    self.resolve_node(*alternative);
                 }
             }
-            AstNode::Statement(node) => self.resolve_node(node),
+            AstNode::GuardedPattern { pattern, guard } => {
+                self.resolve_node(pattern);
+                self.resolve_node(guard);
+            }
+            AstNode::Statement(node) | AstNode::Subexpression(node) => self.resolve_node(node),
             AstNode::Param { .. } => (/* seems unused for now */),
             AstNode::Type { .. } => ( /* probably doesn't make sense to resolve? */ ),
-            AstNode::NamedValue { .. } => (/* seems unused for now */),
+            AstNode::NamedValue { value, .. } => self.resolve_node(value),
+            AstNode::Redirection {
+                target: Some(target),
+                ..
+            } => self.resolve_node(target),
             // All remaining matches do not contain NodeId => there is nothing to resolve
             _ => (),
         }
     }
 
+    /// Span of the variable's name without the `$`
+    fn var_name_span(&self, node_id: NodeId) -> Span {
+        let span = self.compiler.get_span(node_id);
+        let var_name = trim_var_name(self.compiler.get_span_contents(node_id));
+        Span::new(span.end - var_name.len(), span.end)
+    }
+
+    /// Name of the variable without the `$`
+    ///
+    /// The variables not written in the source, the implicit `$it` of a row condition and the
+    /// head of its shorthand cell paths, have an empty span.
+    fn variable_name(&self, node_id: NodeId) -> &'a [u8] {
+        let span = self.compiler.get_span(node_id);
+        if span.start == span.end {
+            b"it"
+        } else {
+            trim_var_name(self.compiler.get_span_contents(node_id))
+        }
+    }
+
     pub fn resolve_variable(&mut self, unbound_node_id: NodeId) {
-        let var_name = trim_var_name(self.compiler.get_span_contents(unbound_node_id));
+        let var_name = self.variable_name(unbound_node_id);
 
         if let Some(node_id) = self.find_variable(var_name) {
             let var_id = self
@@ -356,49 +693,524 @@ impl<'a> Resolver<'a> {
                 .expect("internal error: missing resolved variable");
 
             self.var_resolution.insert(unbound_node_id, *var_id);
+        } else if let Some(var_id) = self.find_engine_variable(var_name) {
+            self.var_resolution.insert(unbound_node_id, var_id);
+        } else if let Some(var_id) = self.find_builtin_variable(var_name) {
+            self.var_resolution.insert(unbound_node_id, var_id);
         } else {
+            let visible_variables = self
+                .scope_stack
+                .iter()
+                .flat_map(|scope_id| self.scope[scope_id.0].variables.keys())
+                .map(|name| name.as_slice());
+
+            let name_span = self.var_name_span(unbound_node_id);
             self.errors.push(SourceError {
-                message: format!("variable `{}` not found", String::from_utf8_lossy(var_name)),
+                code: ErrorCode::VariableNotFound,
+                message: Message::source("variable `", name_span, "` not found"),
                 node_id: unbound_node_id,
                 severity: Severity::Error,
+                help: did_you_mean(visible_variables, var_name)
+                    .map(|suggestion| format!("did you mean `${suggestion}`?")),
+                note: None,
+                related: vec![],
+                fixes: vec![],
             })
         }
     }
 
+    /// Report an assignment to a variable that was not declared with `mut`
+    fn check_mutable(&mut self, target: NodeId) {
+        // `$x.foo = 1` changes `$x`
+        let target = match self.compiler.ast_nodes[target.0] {
+            AstNode::CellPath { head, .. } => head,
+            _ => target,
+        };
+        if self.compiler.ast_nodes[target.0] != AstNode::Variable {
+            return;
+        }
+
+        let Some(var_id) = self.var_resolution.get(&target) else {
+            // already reported as not found
+            return;
+        };
+
+        if self.variables[var_id.0].is_mutable {
+            return;
+        }
+
+        let var_name = trim_var_name(self.compiler.get_span_contents(target));
+        let var_name = String::from_utf8_lossy(var_name);
+
+        let mut related = vec![];
+        let mut help = None;
+        let mut fixes = vec![];
+
+        if let Some(definition) = self.find_variable(var_name.as_bytes()) {
+            related.push(RelatedSpan::new(
+                self.compiler.get_span(definition),
+                "declared here",
+            ));
+
+            let let_statement = self.compiler.ast_nodes.iter().position(|ast_node| {
+                matches!(ast_node, AstNode::Let { variable_name, .. } if *variable_name == definition)
+            });
+            if let Some(let_statement) = let_statement {
+                let keyword_start = self.compiler.get_span(NodeId(let_statement)).start;
+
+                help = Some(format!("declare `{var_name}` with `mut` instead of `let`"));
+                fixes.push(Fix::new(
+                    "replace `let` with `mut`",
+                    vec![TextEdit::replace(
+                        Span::new(keyword_start, keyword_start + "let".len()),
+                        "mut",
+                    )],
+                ));
+            }
+        }
+
+        self.errors.push(SourceError {
+            code: ErrorCode::ImmutableAssignment,
+            message: Message::source(
+                "cannot assign to immutable variable `",
+                self.var_name_span(target),
+                "`",
+            ),
+            node_id: target,
+            severity: Severity::Error,
+            help,
+            note: Some(
+                "only variables declared with `mut` can be changed, unlike `let` variables, \
+                 parameters and loop variables"
+                    .into(),
+            ),
+            related,
+            fixes,
+        });
+    }
+
     pub fn resolve_call(&mut self, unbound_node_id: NodeId, parts: &[NodeId]) {
         // Find out the potentially longest command name
         let max_name_parts = parts
             .iter()
-            .position(|part| matches!(self.compiler.ast_nodes[part.0], AstNode::Name))
-            .expect("call does not have any name")
-            + 1;
+            .take_while(|part| matches!(self.compiler.ast_nodes[part.0], AstNode::Name))
+            .count();
+        assert!(max_name_parts > 0, "call does not have any name");
 
-        // Try to find the longest matching subcommand
+        // Try to find the longest matching subcommand, preferring the ones defined in the source
         let first_start = self.compiler.spans[parts[0].0].start;
 
-        for n in (0..max_name_parts).rev() {
-            let last_end = self.compiler.spans[parts[n].0].end;
-            let name = self
-                .compiler
-                .get_span_contents_manual(first_start, last_end);
-
-            if let Some(node_id) = self.find_decl(name) {
-                let decl_id = self
-                    .decl_resolution
-                    .get(&node_id)
-                    .expect("internal error: missing resolved decl");
+        let names: Vec<_> = (0..max_name_parts)
+            .rev()
+            .map(|n| {
+                let last_end = self.compiler.spans[parts[n].0].end;
+                self.compiler
+                    .get_span_contents_manual(first_start, last_end)
+            })
+            .collect();
 
-                self.decl_resolution.insert(unbound_node_id, *decl_id);
-                break;
+        let hidden: Vec<_> = names.iter().filter(|name| self.is_hidden(name)).collect();
+        let decl_id = names
+            .iter()
+            .enumerate()
+            .find_map(|(idx, name)| {
+                self.find_decl(name).map(|node_id| {
+                    let decl_id = self
+                        .decl_resolution
+                        .get(&node_id)
+                        .expect("internal error: missing resolved decl");
+                    (idx, *decl_id)
+                })
+            })
+            .or_else(|| {
+                names
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, name)| !hidden.contains(name))
+                    .find_map(|(idx, name)| Some((idx, self.find_signature_decl(name)?)))
+            })
+            .or_else(|| {
+                names
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, name)| !hidden.contains(name))
+                    .find_map(|(idx, name)| Some((idx, self.find_engine_decl(name)?)))
+            });
+
+        if let Some((idx, decl_id)) = decl_id {
+            self.decl_resolution.insert(unbound_node_id, decl_id);
+            self.call_name_parts
+                .insert(unbound_node_id, max_name_parts - idx);
+        } else {
+            // the call does not correspond to any existing decl, it is an external call
+            if let Some(engine) = self.engine {
+                self.engine_ids.run_external = engine.find_decl(b"run-external");
+            }
+            if self.compiler.options.profile == Profile::Config {
+                self.check_parse_time_path(&names, &parts[max_name_parts..]);
             }
         }
 
-        // TODO? If the call does not correspond to any existing decl, it is an external call
-
         // Resolve args
         for part in &parts[max_name_parts..] {
             self.resolve_node(*part);
         }
+
+        if let Some(root) = self.compiler.sources.get(&unbound_node_id) {
+            self.resolve_source(names[names.len() - 1], *root);
+        }
+    }
+
+    /// Resolve the file loaded by a `source` or `use` call
+    ///
+    /// The definitions of a sourced file are added to the current scope, like if the file's
+    /// contents were in place of the call.
+    fn resolve_source(&mut self, command: &[u8], root: NodeId) {
+        match self.compiler.ast_nodes[root.0] {
+            AstNode::Block(block_id) if command.starts_with(b"source") => {
+                for node_id in &self.compiler.blocks[block_id.0].nodes {
+                    self.resolve_node(*node_id);
+                }
+            }
+            // TODO: import the definitions of the used module
+            _ => self.resolve_node(root),
+        }
+    }
+
+    /// Resolve the block of a module in its own scope frame, collecting the exported definitions
+    fn resolve_module(&mut self, name: String, block: NodeId) -> ModuleId {
+        let AstNode::Block(block_id) = self.compiler.ast_nodes[block.0] else {
+            panic!("internal error: module's body is not a block");
+        };
+
+        let mut decls = HashMap::new();
+        self.enter_scope(block);
+        for node_id in &self.compiler.blocks[block_id.0].nodes {
+            let AstNode::Export(definition) = self.compiler.ast_nodes[node_id.0] else {
+                self.resolve_node(*node_id);
+                continue;
+            };
+
+            match self.compiler.ast_nodes[definition.0] {
+                AstNode::Def { name, .. }
+                | AstNode::Alias { new_name: name, .. }
+                | AstNode::Extern { name, .. } => {
+                    self.resolve_node(definition);
+                    decls.insert(self.decl_name(name).into_bytes(), name);
+                }
+                AstNode::Use {
+                    module,
+                    ref members,
+                } => decls.extend(self.resolve_use(definition, module, members)),
+                AstNode::Module { name, .. } => {
+                    self.resolve_node(definition);
+                    let submodule = &self.modules[self.module_resolution[&name].0];
+                    for (decl_name, decl) in &submodule.decls {
+                        let mut prefixed = submodule.name.as_bytes().to_vec();
+                        prefixed.push(b' ');
+                        prefixed.extend(decl_name);
+                        decls.insert(prefixed, *decl);
+                    }
+                }
+                _ => self.resolve_node(definition),
+            }
+        }
+        self.exit_scope();
+
+        self.modules.push(Module { name, decls });
+        ModuleId(self.modules.len() - 1)
+    }
+
+    /// Import the definitions selected by the import pattern of `use` into the current scope
+    ///
+    /// Returns the imported definitions by their new names, `export use` exports them from the
+    /// enclosing module. Nothing is imported from a module that is not defined in the source nor
+    /// loaded from a file, e.g., a module of the standard library.
+    fn resolve_use(
+        &mut self,
+        use_node: NodeId,
+        module: NodeId,
+        members: &[NodeId],
+    ) -> Vec<(Vec<u8>, NodeId)> {
+        let Some(module_id) = self.used_module(use_node, module, "use") else {
+            return vec![];
+        };
+
+        // `use foo sub bar` imports `bar` of the submodule `sub`
+        let module = &self.modules[module_id.0];
+        let (selected, submodules) = match members.split_last() {
+            Some((last, submodules)) => (Some(*last), submodules),
+            None => (None, members),
+        };
+        let mut prefix = vec![];
+        for submodule in submodules {
+            prefix.extend(self.member_name(*submodule));
+            prefix.push(b' ');
+        }
+        let head = match submodules.last() {
+            Some(submodule) => self.member_name(*submodule),
+            None => module.name.as_bytes(),
+        };
+
+        // the members selected by their names, the whole module is imported without a member and
+        // all its members with `*`
+        let named = match selected.map(|member| self.compiler.get_node(member)) {
+            None | Some(AstNode::ImportAll) => None,
+            Some(AstNode::List(items)) => Some(items.clone()),
+            Some(_) => Some(selected.into_iter().collect()),
+        };
+        let decls = module.decls.iter().filter_map(|(decl_name, decl)| {
+            Some((decl_name.strip_prefix(prefix.as_slice())?, *decl))
+        });
+
+        let mut imported = vec![];
+        let mut errors = vec![];
+        let Some(named) = named else {
+            for (decl_name, decl) in decls {
+                let new_name = if selected.is_none() {
+                    [head, b" ", decl_name].concat()
+                } else {
+                    decl_name.to_vec()
+                };
+                imported.push((main_command_name(new_name, head), decl));
+            }
+            return self.import(imported);
+        };
+
+        for member in named {
+            // a definition, or a submodule with all its definitions
+            let name = self.member_name(member);
+            let imported_before = imported.len();
+            for (decl_name, decl) in decls.clone() {
+                if decl_name == name
+                    || (decl_name.starts_with(name) && decl_name.get(name.len()) == Some(&b' '))
+                {
+                    imported.push((main_command_name(decl_name.to_vec(), head), decl));
+                }
+            }
+
+            if imported.len() == imported_before {
+                errors.push(SourceError {
+                    code: ErrorCode::ModuleMemberNotFound,
+                    message: format!(
+                        "module `{}` does not export `{}`",
+                        module.name,
+                        String::from_utf8_lossy(name)
+                    )
+                    .into(),
+                    node_id: member,
+                    severity: Severity::Error,
+                    help: did_you_mean(module.decls.keys().map(|name| name.as_slice()), name)
+                        .map(|suggestion| format!("did you mean `{suggestion}`?")),
+                    note: None,
+                    related: vec![],
+                    fixes: vec![],
+                });
+            }
+        }
+        self.errors.extend(errors);
+        self.import(imported)
+    }
+
+    /// The module used by `use` or `overlay use`, loaded from a file or defined in the source
+    fn used_module(&mut self, use_node: NodeId, module: NodeId, command: &str) -> Option<ModuleId> {
+        let module_id = if let Some(root) = self.compiler.sources.get(&use_node) {
+            Some(self.resolve_module(self.used_module_name(module), *root))
+        } else if matches!(
+            self.compiler.ast_nodes[module.0],
+            AstNode::String | AstNode::Name
+        ) {
+            self.find_module(self.member_name(module))
+        } else {
+            self.resolve_node(module);
+            if self.compiler.options.profile == Profile::Config {
+                self.check_constant_path(command, module);
+            }
+            None
+        }?;
+
+        self.module_resolution.insert(module, module_id);
+        Some(module_id)
+    }
+
+    /// Name of a used module, the file name without the extension for a module loaded from a file
+    fn used_module_name(&self, module: NodeId) -> String {
+        let path = self
+            .compiler
+            .parse_time_path(module)
+            .unwrap_or_else(|| self.member_name(module).to_vec());
+        let path = String::from_utf8_lossy(&path).to_string();
+        Path::new(&path)
+            .file_stem()
+            .map_or(path.clone(), |stem| stem.to_string_lossy().to_string())
+    }
+
+    /// Activate the exported definitions of the module as an overlay
+    ///
+    /// A module that is neither defined in the source nor loaded from a file is activated as an
+    /// empty overlay, so that it can be hidden again.
+    fn resolve_overlay_use(&mut self, node_id: NodeId, module: NodeId) {
+        let decls = match self.used_module(node_id, module, "overlay use") {
+            Some(module_id) => {
+                let module = &self.modules[module_id.0];
+                module
+                    .decls
+                    .iter()
+                    .map(|(name, decl)| {
+                        (
+                            main_command_name(name.clone(), module.name.as_bytes()),
+                            *decl,
+                        )
+                    })
+                    .collect()
+            }
+            None => HashMap::new(),
+        };
+        self.enter_overlay(node_id, decls);
+    }
+
+    /// Push the frame of an overlay and a light frame for the definitions made while it is active
+    fn enter_overlay(&mut self, node_id: NodeId, decls: HashMap<Vec<u8>, NodeId>) {
+        let mut overlay = Frame::new(FrameType::Overlay, node_id);
+        overlay.decls = decls;
+        self.scope.push(overlay);
+        self.scope_stack.push(ScopeId(self.scope.len() - 1));
+
+        self.scope.push(Frame::new(FrameType::Light, node_id));
+        self.scope_stack.push(ScopeId(self.scope.len() - 1));
+    }
+
+    /// Deactivate the overlay of the given name, or the last activated one, with the definitions
+    /// made while it was active
+    ///
+    /// Only the overlays activated in the current block can be hidden.
+    fn hide_overlay(&mut self, node_id: NodeId, name: Option<NodeId>) {
+        let name = name.map(|name| self.member_name(name));
+        let scope_start = self
+            .scope_stack
+            .iter()
+            .rposition(|scope_id| self.scope[scope_id.0].frame_type == FrameType::Scope)
+            .unwrap_or_default();
+        let overlay = self.scope_stack[scope_start..]
+            .iter()
+            .rposition(|scope_id| {
+                let frame = &self.scope[scope_id.0];
+                frame.frame_type == FrameType::Overlay
+                    && name.map_or(true, |name| self.overlay_name(frame.node_id) == name)
+            })
+            .map(|pos| scope_start + pos);
+
+        let Some(pos) = overlay else {
+            let message = match name {
+                Some(name) => format!("overlay `{}` is not active", String::from_utf8_lossy(name)),
+                None => "no overlay is active".to_string(),
+            };
+            self.errors.push(SourceError {
+                code: ErrorCode::OverlayNotFound,
+                message: message.into(),
+                node_id,
+                severity: Severity::Error,
+                help: None,
+                note: None,
+                related: vec![],
+                fixes: vec![],
+            });
+            return;
+        };
+
+        // the light frame of the overlay is right above it
+        let overlay_node = self.scope[self.scope_stack[pos].0].node_id;
+        let end = self.scope_stack[pos + 1..]
+            .iter()
+            .position(|scope_id| {
+                let frame = &self.scope[scope_id.0];
+                frame.frame_type != FrameType::Light || frame.node_id != overlay_node
+            })
+            .map_or(self.scope_stack.len(), |light_frames| {
+                pos + 1 + light_frames
+            });
+        self.scope_stack.drain(pos..end);
+    }
+
+    /// Name of an active overlay: the name given by `as`, or the name of the used module
+    fn overlay_name(&self, node_id: NodeId) -> &'a [u8] {
+        match self.compiler.ast_nodes[node_id.0] {
+            AstNode::OverlayUse {
+                name: Some(name), ..
+            }
+            | AstNode::OverlayNew(name) => self.member_name(name),
+            AstNode::OverlayUse { module, .. } => {
+                let name = self.member_name(module);
+                let stem = Path::new(std::str::from_utf8(name).unwrap_or_default())
+                    .file_stem()
+                    .map(|stem| stem.as_encoded_bytes());
+                stem.unwrap_or(name)
+            }
+            _ => panic!("internal error: frame of an overlay not defined by an overlay"),
+        }
+    }
+
+    /// Hide the declaration from the rest of the current scope frame
+    fn hide_decl(&mut self, node_id: NodeId, parts: &[NodeId]) {
+        if parts.is_empty() {
+            return;
+        }
+        let name = parts
+            .iter()
+            .map(|part| self.member_name(*part))
+            .collect::<Vec<_>>()
+            .join(&b' ');
+
+        // without the engine's declarations, the name can be one of them
+        let is_defined = self.find_decl(&name).is_some()
+            || self.find_signature_decl(&name).is_some()
+            || self.find_engine_decl(&name).is_some()
+            || (self.engine.is_none() && self.signatures.is_none());
+        if !is_defined {
+            self.errors.push(SourceError {
+                code: ErrorCode::DeclNotFound,
+                message: format!(
+                    "`{}` is not defined, so it cannot be hidden",
+                    String::from_utf8_lossy(&name)
+                )
+                .into(),
+                node_id,
+                severity: Severity::Error,
+                help: None,
+                note: None,
+                related: vec![],
+                fixes: vec![],
+            });
+            return;
+        }
+
+        let current_scope_id = self
+            .scope_stack
+            .last()
+            .expect("internal error: missing scope frame id");
+        let frame = &mut self.scope[current_scope_id.0];
+        frame.decls.remove(&name);
+        frame.hidden.insert(name);
+    }
+
+    /// Add the imported definitions to the current scope frame under their new names
+    fn import(&mut self, imported: Vec<(Vec<u8>, NodeId)>) -> Vec<(Vec<u8>, NodeId)> {
+        let current_scope_id = self
+            .scope_stack
+            .last()
+            .expect("internal error: missing scope frame id");
+        for (name, decl) in &imported {
+            let frame = &mut self.scope[current_scope_id.0];
+            frame.hidden.remove(name);
+            frame.decls.insert(name.clone(), *decl);
+        }
+
+        imported
+    }
+
+    /// Name of a member of an import pattern, or of the used module
+    fn member_name(&self, node_id: NodeId) -> &'a [u8] {
+        trim_decl_name(self.compiler.get_span_contents(node_id))
     }
 
     pub fn resolve_block(
@@ -456,8 +1268,26 @@ impl<'a> Resolver<'a> {
     }
 
     pub fn define_variable(&mut self, var_name_id: NodeId, is_mutable: bool) {
-        let var_name = self.compiler.get_span_contents(var_name_id);
-        let var_name = trim_var_name(var_name).to_vec();
+        let var_name = self.variable_name(var_name_id).to_vec();
+        self.define_variable_named(var_name_id, var_name, is_mutable);
+    }
+
+    /// Define a variable whose name differs from the source of its node, e.g., of a flag
+    fn define_variable_named(&mut self, var_name_id: NodeId, var_name: Vec<u8>, is_mutable: bool) {
+        if let Some(shadowed_id) = self.find_variable(&var_name) {
+            let message = Message::source(
+                "variable `",
+                self.var_name_span(var_name_id),
+                "` shadows an earlier variable",
+            );
+            if let Some(mut error) = self.compiler.lint(Lint::Shadowing, message, var_name_id) {
+                error.related.push(RelatedSpan::new(
+                    self.compiler.get_span(shadowed_id),
+                    "previously defined here",
+                ));
+                self.errors.push(error);
+            }
+        }
 
         let current_scope_id = self
             .scope_stack
@@ -476,20 +1306,107 @@ impl<'a> Resolver<'a> {
         self.var_resolution.insert(var_name_id, var_id);
     }
 
+    /// Report `let`/`mut` and `for` variables that are not used anywhere
+    ///
+    /// Not done by resolve_incremental() since the next resolution may still use the variables.
+    /// Variables starting with an underscore are exempt.
+    fn lint_unused_variables(&mut self) {
+        let mut uses: HashMap<VarId, usize> = HashMap::new();
+        for var_id in self.var_resolution.values() {
+            *uses.entry(*var_id).or_default() += 1;
+        }
+
+        for ast_node in &self.compiler.ast_nodes {
+            let var_name_id = match ast_node {
+                AstNode::Let { variable_name, .. } | AstNode::Const { variable_name, .. } => {
+                    *variable_name
+                }
+                AstNode::For { variable, .. } => *variable,
+                _ => continue,
+            };
+
+            let Some(var_id) = self.var_resolution.get(&var_name_id) else {
+                continue;
+            };
+
+            let var_name = trim_var_name(self.compiler.get_span_contents(var_name_id));
+
+            // the definition counts as a use as well
+            if uses[var_id] > 1 || var_name.starts_with(b"_") {
+                continue;
+            }
+
+            let message = Message::source(
+                "variable `",
+                self.var_name_span(var_name_id),
+                "` is never used",
+            );
+            if let Some(error) = self
+                .compiler
+                .lint(Lint::UnusedVariable, message, var_name_id)
+            {
+                self.errors.push(error);
+            }
+        }
+    }
+
     pub fn define_decl(&mut self, decl_name_id: NodeId) {
-        // TODO: Deduplicate code with define_variable()
+        let decl = Declaration::new(self.decl_name(decl_name_id));
+        self.add_decl(decl_name_id, decl);
+    }
+
+    fn decl_name(&self, decl_name_id: NodeId) -> String {
         let decl_name = self.compiler.get_span_contents(decl_name_id);
-        let decl_name = trim_decl_name(decl_name).to_vec();
-        let decl = Declaration::new(String::from_utf8_lossy(&decl_name).to_string());
+        String::from_utf8_lossy(trim_decl_name(decl_name)).to_string()
+    }
+
+    /// Parameters of a signature for checking the calls of its command
+    fn params(&self, params: NodeId) -> Vec<Parameter> {
+        let AstNode::Params(ref params) = self.compiler.ast_nodes[params.0] else {
+            return vec![];
+        };
+
+        let name_of = |node_id: NodeId| {
+            String::from_utf8_lossy(self.compiler.get_span_contents(node_id)).to_string()
+        };
+        params
+            .iter()
+            .filter_map(|param| match self.compiler.ast_nodes[param.0] {
+                AstNode::Param {
+                    name,
+                    ty,
+                    kind,
+                    short,
+                    ..
+                } => {
+                    // a flag with only a short name, e.g., `-f`
+                    let short = short.or((kind == ParamKind::Flag
+                        && self.compiler.get_span_contents(name).len() == 1)
+                        .then_some(name));
+                    Some(Parameter {
+                        name: name_of(name),
+                        short: short.and_then(|short| name_of(short).chars().next()),
+                        kind,
+                        ty: ty.map(name_of),
+                    })
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn add_decl(&mut self, decl_name_id: NodeId, decl: Declaration) {
+        // TODO: Deduplicate code with define_variable()
+        let decl_name = decl.name().as_bytes().to_vec();
 
         let current_scope_id = self
             .scope_stack
             .last()
             .expect("internal error: missing scope frame id");
 
-        self.scope[current_scope_id.0]
-            .decls
-            .insert(decl_name, decl_name_id);
+        let frame = &mut self.scope[current_scope_id.0];
+        frame.hidden.remove(&decl_name);
+        frame.decls.insert(decl_name, decl_name_id);
 
         self.decls.push(Box::new(decl));
         let decl_id = DeclId(self.decls.len() - 1);
@@ -498,6 +1415,29 @@ impl<'a> Resolver<'a> {
         self.decl_resolution.insert(decl_name_id, decl_id);
     }
 
+    fn add_module(&mut self, module_name_id: NodeId, module_id: ModuleId) {
+        let module_name = self.decl_name(module_name_id).into_bytes();
+        let current_scope_id = self
+            .scope_stack
+            .last()
+            .expect("internal error: missing scope frame id");
+
+        self.scope[current_scope_id.0]
+            .modules
+            .insert(module_name, module_id);
+        self.module_resolution.insert(module_name_id, module_id);
+    }
+
+    pub fn find_module(&self, module_name: &[u8]) -> Option<ModuleId> {
+        for scope_id in self.scope_stack.iter().rev() {
+            if let Some(id) = self.scope[scope_id.0].modules.get(module_name) {
+                return Some(*id);
+            }
+        }
+
+        None
+    }
+
     pub fn find_variable(&self, var_name: &[u8]) -> Option<NodeId> {
         for scope_id in self.scope_stack.iter().rev() {
             if let Some(id) = self.scope[scope_id.0].variables.get(var_name) {
@@ -511,13 +1451,149 @@ impl<'a> Resolver<'a> {
     pub fn find_decl(&self, var_name: &[u8]) -> Option<NodeId> {
         // TODO: Deduplicate code with find_variable()
         for scope_id in self.scope_stack.iter().rev() {
-            if let Some(id) = self.scope[scope_id.0].decls.get(var_name) {
+            let frame = &self.scope[scope_id.0];
+            if let Some(id) = frame.decls.get(var_name) {
                 return Some(*id);
             }
+            if frame.hidden.contains(var_name) {
+                return None;
+            }
         }
 
         None
     }
+
+    /// Whether the name was hidden by `hide` after its last definition
+    fn is_hidden(&self, decl_name: &[u8]) -> bool {
+        for scope_id in self.scope_stack.iter().rev() {
+            let frame = &self.scope[scope_id.0];
+            if frame.decls.contains_key(decl_name) {
+                return false;
+            }
+            if frame.hidden.contains(decl_name) {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Find a declaration in the signatures, reusing the DeclId if it was already found before
+    fn find_signature_decl(&mut self, decl_name: &[u8]) -> Option<DeclId> {
+        let signature = self.signatures?.find(decl_name)?;
+
+        if let Some(decl_id) = self.signature_decls.get(decl_name) {
+            return Some(*decl_id);
+        }
+
+        self.decls.push(Box::new(signature.clone()));
+        let decl_id = DeclId(self.decls.len() - 1);
+        self.signature_decls.insert(decl_name.to_vec(), decl_id);
+
+        Some(decl_id)
+    }
+
+    /// Find a variable in the engine, reusing the VarId if it was already found before
+    fn find_engine_variable(&mut self, var_name: &[u8]) -> Option<VarId> {
+        let engine_var_id = self.engine?.find_variable(var_name)?;
+
+        if let Some(var_id) = self.engine_ids.find_variable(engine_var_id) {
+            return Some(var_id);
+        }
+
+        // mutability is checked by the engine
+        self.variables.push(Variable { is_mutable: false });
+        let var_id = VarId(self.variables.len() - 1);
+        self.engine_ids.variables.insert(var_id, engine_var_id);
+
+        Some(var_id)
+    }
+
+    /// Find a variable defined by Nushell itself, creating it on its first use
+    ///
+    /// The variables have the same ids in every engine, they are mapped to them even without one.
+    fn find_builtin_variable(&mut self, var_name: &[u8]) -> Option<VarId> {
+        let (_, engine_var_id) = BUILTIN_VARIABLES
+            .iter()
+            .find(|(name, _)| *name == var_name)?;
+
+        if let Some(var_id) = self.builtin_variables.get(var_name) {
+            return Some(*var_id);
+        }
+
+        // only the environment can be assigned to, e.g., `$env.FOO = 1`
+        self.variables.push(Variable {
+            is_mutable: var_name == b"env",
+        });
+        let var_id = VarId(self.variables.len() - 1);
+        self.builtin_variables.insert(var_name.to_vec(), var_id);
+        self.engine_ids.variables.insert(var_id, *engine_var_id);
+
+        Some(var_id)
+    }
+
+    /// Report a `source`, `use` or `overlay use` call whose path is not known at parse time
+    ///
+    /// `names` are the possible names of the call from the longest, `args` the parts after the
+    /// longest name.
+    fn check_parse_time_path(&mut self, names: &[&[u8]], args: &[NodeId]) {
+        let Some((longer_names, command)) = names.iter().enumerate().find_map(|(idx, name)| {
+            PARSE_TIME_COMMANDS
+                .iter()
+                .find(|command| command.as_bytes() == *name)
+                .map(|command| (idx, command))
+        }) else {
+            return;
+        };
+
+        // the parts of the longer names are the command's arguments, e.g., the path in `use std`
+        if longer_names > 0 {
+            return;
+        }
+
+        if let Some(path) = args.first() {
+            self.check_constant_path(command, *path);
+        }
+    }
+
+    fn check_constant_path(&mut self, command: &str, path: NodeId) {
+        if matches!(
+            self.compiler.ast_nodes[path.0],
+            AstNode::String | AstNode::Name
+        ) || self.compiler.constants.contains_key(&path)
+        {
+            return;
+        }
+
+        self.errors.push(SourceError {
+            code: ErrorCode::ConfigNonConstantPath,
+            message: format!("`{command}` needs a path known at parse time").into(),
+            node_id: path,
+            severity: Severity::Error,
+            help: Some("use a string literal instead".into()),
+            note: Some(
+                "the file is read when the configuration is parsed, before any code runs".into(),
+            ),
+            related: vec![],
+            fixes: vec![],
+        });
+    }
+
+    /// Find a declaration in the engine, reusing the DeclId if it was already found before
+    fn find_engine_decl(&mut self, decl_name: &[u8]) -> Option<DeclId> {
+        let engine_decl_id = self.engine?.find_decl(decl_name)?;
+
+        if let Some(decl_id) = self.engine_ids.find_decl(engine_decl_id) {
+            return Some(decl_id);
+        }
+
+        let decl = Declaration::new(String::from_utf8_lossy(decl_name).to_string());
+        self.decls.push(Box::new(decl));
+        let decl_id = DeclId(self.decls.len() - 1);
+        self.engine_ids.decls.insert(decl_id, engine_decl_id);
+
+        Some(decl_id)
+    }
 }
 
 fn trim_var_name(name: &[u8]) -> &[u8] {
@@ -527,6 +1603,18 @@ fn trim_var_name(name: &[u8]) -> &[u8] {
         name
     }
 }
+/// Name of an imported `main` command, which is the name of its module, e.g., `foo` for
+/// `foo main`
+fn main_command_name(name: Vec<u8>, module_name: &[u8]) -> Vec<u8> {
+    if name == b"main" {
+        module_name.to_vec()
+    } else if let Some(name) = name.strip_suffix(b" main") {
+        name.to_vec()
+    } else {
+        name
+    }
+}
+
 fn trim_decl_name(name: &[u8]) -> &[u8] {
     if (name.starts_with(b"'") && name.ends_with(b"'"))
         || (name.starts_with(b"\"") && name.ends_with(b"\""))
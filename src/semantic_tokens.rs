@@ -0,0 +1,105 @@
+//! Classification of the parsed source for syntax highlighting
+use crate::compiler::{Compiler, Span};
+use crate::parser::{AstNode, NodeId};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SemanticTokenKind {
+    Number,
+    String,
+    Variable,
+    /// Keyword-like literals such as `true` or `null`
+    Keyword,
+    /// Declared parameters as well as flags passed to a call
+    Parameter,
+    Type,
+    /// Calls and names of definitions
+    Function,
+    Operator,
+}
+
+impl SemanticTokenKind {
+    /// All kinds, a new variant must be added here as well
+    pub const ALL: &'static [SemanticTokenKind] = &[
+        SemanticTokenKind::Number,
+        SemanticTokenKind::String,
+        SemanticTokenKind::Variable,
+        SemanticTokenKind::Keyword,
+        SemanticTokenKind::Parameter,
+        SemanticTokenKind::Type,
+        SemanticTokenKind::Function,
+        SemanticTokenKind::Operator,
+    ];
+
+    /// Name of the LSP semantic token type of the kind
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SemanticTokenKind::Number => "number",
+            SemanticTokenKind::String => "string",
+            SemanticTokenKind::Variable => "variable",
+            SemanticTokenKind::Keyword => "keyword",
+            SemanticTokenKind::Parameter => "parameter",
+            SemanticTokenKind::Type => "type",
+            SemanticTokenKind::Function => "function",
+            SemanticTokenKind::Operator => "operator",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SemanticToken {
+    pub span: Span,
+    pub kind: SemanticTokenKind,
+}
+
+/// Spans of the parsed source classified for syntax highlighting, sorted by position
+///
+/// Only parsing is needed, so this also works for sources with errors in the later stages. A span
+/// is classified only once, even if multiple nodes share it.
+pub fn semantic_tokens(compiler: &Compiler) -> Vec<SemanticToken> {
+    let mut tokens = vec![];
+    let mut push = |node_id: NodeId, kind| {
+        let span = compiler.get_span(node_id);
+        // not in the source, e.g., the implicit `$it` of a row condition
+        if span.start == span.end {
+            return;
+        }
+        tokens.push(SemanticToken { span, kind })
+    };
+
+    for (idx, ast_node) in compiler.ast_nodes.iter().enumerate() {
+        let node_id = NodeId(idx);
+        match ast_node {
+            AstNode::Int | AstNode::Float | AstNode::Filesize | AstNode::Duration => {
+                push(node_id, SemanticTokenKind::Number)
+            }
+            AstNode::String | AstNode::RawString | AstNode::GlobPattern => {
+                push(node_id, SemanticTokenKind::String)
+            }
+            AstNode::Variable => push(node_id, SemanticTokenKind::Variable),
+            AstNode::True | AstNode::False | AstNode::Null => {
+                push(node_id, SemanticTokenKind::Keyword)
+            }
+            AstNode::FlagLong | AstNode::FlagShort | AstNode::FlagShortGroup => {
+                push(node_id, SemanticTokenKind::Parameter)
+            }
+            AstNode::Type { name, .. } => push(*name, SemanticTokenKind::Type),
+            AstNode::Param { name, .. } => push(*name, SemanticTokenKind::Parameter),
+            AstNode::Def { name, .. } => push(*name, SemanticTokenKind::Function),
+            AstNode::Alias { new_name, .. } => push(*new_name, SemanticTokenKind::Function),
+            AstNode::Extern { name, .. } => push(*name, SemanticTokenKind::Function),
+            AstNode::Call { parts } => {
+                if let Some(first) = parts.first() {
+                    push(*first, SemanticTokenKind::Function);
+                }
+            }
+            AstNode::Not => push(node_id, SemanticTokenKind::Operator),
+            _ if ast_node.precedence() > 0 => push(node_id, SemanticTokenKind::Operator),
+            _ => (),
+        }
+    }
+
+    tokens.sort_by_key(|token| (token.span.start, token.span.end));
+    tokens.dedup_by_key(|token| token.span);
+
+    tokens
+}
@@ -1,12 +1,48 @@
+use crate::ast_converter::AstConverter;
+use crate::ast_diff::AstDiff;
+use crate::compiler::{CompilerOptions, Profile, Span};
+use crate::engine::EngineLookup;
+use crate::format::{format_source, FormatOptions};
+use crate::ir_generator::IrGenerator;
+use crate::ir_validator::assert_valid;
 use crate::lexer::lex;
+use crate::render::render_all;
 use crate::resolver::Resolver;
 use crate::typechecker::Typechecker;
 use crate::{compiler::Compiler, parser::Parser};
 
+use nu_protocol::engine::EngineState;
 use std::path::Path;
+use std::sync::OnceLock;
 
 fn evaluate_example(fname: &Path) -> String {
+    let (_, result) = compile_example(fname);
+    result
+}
+
+/// Run all stages up to typechecking and return the compiler along with the stages' output
+fn compile_example(fname: &Path) -> (Compiler, String) {
+    compile_example_with_options(fname, CompilerOptions::default())
+}
+
+fn compile_example_with_options(fname: &Path, options: CompilerOptions) -> (Compiler, String) {
+    compile_example_with_engine(fname, options, None)
+}
+
+/// Engine with nushell's core commands (`error make`, `describe`, ...)
+fn engine_state() -> &'static EngineState {
+    static ENGINE_STATE: OnceLock<EngineState> = OnceLock::new();
+    ENGINE_STATE.get_or_init(nu_cmd_lang::create_default_context)
+}
+
+/// Same as compile_example_with_options() but also resolving names defined by the engine
+fn compile_example_with_engine(
+    fname: &Path,
+    options: CompilerOptions,
+    engine: Option<&dyn EngineLookup>,
+) -> (Compiler, String) {
     let mut compiler = Compiler::new();
+    compiler.options = options;
     let contents = std::fs::read(fname).expect("We only run tests found by glob");
 
     let span_offset = compiler.span_offset();
@@ -24,18 +60,21 @@ fn evaluate_example(fname: &Path) -> String {
 
     let mut result = compiler.display_state();
 
-    if !compiler.errors.is_empty() {
-        return result;
+    if compiler.has_fatal_errors(&compiler.errors) {
+        return (compiler, result);
     }
 
     let mut resolver = Resolver::new(&compiler);
+    if let Some(engine) = engine {
+        resolver = resolver.with_engine(engine);
+    }
     resolver.resolve();
     result.push_str(&resolver.display_state());
 
     compiler.merge_name_bindings(resolver.to_name_bindings());
 
-    if !compiler.errors.is_empty() {
-        return result;
+    if compiler.has_fatal_errors(&compiler.errors) {
+        return (compiler, result);
     }
 
     let mut typechecker = Typechecker::new(&compiler);
@@ -44,9 +83,177 @@ fn evaluate_example(fname: &Path) -> String {
 
     compiler.merge_types(typechecker.to_types());
 
+    (compiler, result)
+}
+
+/// The commands are resolved from the engine's core commands, like in nushell
+fn evaluate_ir(fname: &Path) -> String {
+    let (mut compiler, mut result) =
+        compile_example_with_engine(fname, CompilerOptions::default(), Some(engine_state()));
+
+    if compiler.has_fatal_errors(&compiler.errors) {
+        return result;
+    }
+
+    let mut ir_generator = IrGenerator::new(&compiler);
+    ir_generator.generate();
+    let unoptimized = ir_generator.display_state();
+    result.push_str(&unoptimized);
+    assert_blocks_valid(ir_generator);
+
+    // The optimized IR is shown only where the optimization changes something
+    compiler.options.optimize_ir = true;
+    let mut ir_generator = IrGenerator::new(&compiler);
+    ir_generator.generate();
+    let optimized = ir_generator.display_state();
+    assert_blocks_valid(ir_generator);
+    if optimized != unoptimized {
+        result.push_str("==== OPTIMIZED ====\n");
+        result.push_str(&optimized);
+    }
+
+    result
+}
+
+/// Check the generated blocks with `IrValidator`, also in release builds
+fn assert_blocks_valid(ir_generator: IrGenerator) {
+    if !ir_generator.errors().is_empty() {
+        return;
+    }
+
+    let (block, closures) = ir_generator.blocks();
+    assert_valid(&block);
+    for closure in closures {
+        assert_valid(&closure.block);
+    }
+}
+
+fn evaluate_convert(fname: &Path) -> String {
+    let (compiler, mut result) = compile_example(fname);
+
+    if compiler.has_fatal_errors(&compiler.errors) {
+        return result;
+    }
+
+    let engine_state = nu_protocol::engine::EngineState::new();
+    let mut working_set = nu_protocol::engine::StateWorkingSet::new(&engine_state);
+    let mut converter = AstConverter::new(&compiler, &mut working_set);
+    converter.convert();
+    result.push_str(&converter.display_state());
+
+    result
+}
+
+/// Compile the example as a configuration file
+fn evaluate_config(fname: &Path) -> String {
+    let options = CompilerOptions {
+        profile: Profile::Config,
+        ..Default::default()
+    };
+    let (_, result) = compile_example_with_options(fname, options);
     result
 }
 
+/// Render the errors of all stages up to typechecking the way the CLI prints them
+fn evaluate_render(fname: &Path) -> String {
+    let (compiler, _) = compile_example(fname);
+
+    // the file names are absolute paths
+    render_all(&compiler, &compiler.errors, false)
+        .replace(concat!(env!("CARGO_MANIFEST_DIR"), "/"), "")
+}
+
+/// Compile each line separately, keeping the name bindings and types of the previous lines without
+/// errors
+fn evaluate_incremental(fname: &Path) -> String {
+    let mut compiler = Compiler::new();
+    let mut types = None;
+    let contents = std::fs::read(fname).expect("We only run tests found by glob");
+
+    let mut result = String::new();
+
+    for line in contents
+        .split(|c| *c == b'\n')
+        .filter(|line| !line.is_empty())
+    {
+        result.push_str(&format!(
+            "==== LINE '{}' ====\n",
+            String::from_utf8_lossy(line)
+        ));
+
+        let checkpoint = compiler.checkpoint();
+
+        let span_offset = compiler.span_offset();
+        compiler.add_file(&fname.to_string_lossy(), line);
+
+        let (tokens, err) = lex(line, span_offset);
+        assert!(err.is_ok(), "lexing error: {err:?}");
+
+        compiler = Parser::new(compiler, tokens).parse();
+        if compiler.has_fatal_errors(&compiler.errors) {
+            result.push_str(&compiler.display_state());
+            compiler.rollback(checkpoint);
+            continue;
+        }
+
+        let name_bindings = compiler.take_name_bindings();
+        let mut resolver = Resolver::with_name_bindings(&compiler, name_bindings);
+        resolver.resolve_incremental();
+        result.push_str(&resolver.display_state());
+        compiler.merge_name_bindings(resolver.to_name_bindings());
+
+        if compiler.has_fatal_errors(&compiler.errors) {
+            compiler.rollback(checkpoint);
+            continue;
+        }
+
+        let mut typechecker = match types.take() {
+            Some(types) => Typechecker::with_types(&compiler, types),
+            None => Typechecker::new(&compiler),
+        };
+        typechecker.typecheck();
+        result.push_str(&typechecker.display_state());
+        let mut line_types = typechecker.to_types();
+
+        if compiler.has_fatal_errors(&line_types.errors) {
+            compiler.rollback(checkpoint);
+            line_types.truncate(&compiler);
+        }
+        types = Some(line_types);
+    }
+
+    result
+}
+
+/// Format the source, checking that formatting the result again does not change it and that the
+/// AST stays the same
+fn evaluate_format(fname: &Path) -> String {
+    let contents = std::fs::read(fname).expect("We only run tests found by glob");
+    let options = FormatOptions::default();
+
+    let formatted = format_source(&contents, &options).expect("source can be lexed");
+    let reformatted = format_source(&formatted, &options).expect("formatted source can be lexed");
+    assert_eq!(
+        String::from_utf8_lossy(&reformatted),
+        String::from_utf8_lossy(&formatted),
+        "formatting is not idempotent"
+    );
+
+    let mut original = Compiler::new();
+    if original.check(&fname.to_string_lossy(), &contents) {
+        let mut reparsed = Compiler::new();
+        assert!(reparsed.check(&fname.to_string_lossy(), &formatted));
+        let diff = AstDiff::new(&original, &reparsed);
+        assert!(
+            diff.is_empty(),
+            "formatting changed the AST:\n{}",
+            diff.display(&original, &reparsed)
+        );
+    }
+
+    String::from_utf8_lossy(&formatted).into_owned()
+}
+
 fn evaluate_lexer(fname: &Path) -> String {
     let contents = std::fs::read(fname);
 
@@ -64,6 +271,110 @@ fn evaluate_lexer(fname: &Path) -> String {
     res
 }
 
+/// Stage of the compiler whose output is snapshotted separately for the sources in tests/stages
+#[derive(Debug, Clone, Copy)]
+enum Stage {
+    Tokens,
+    Ast,
+    Names,
+    Types,
+    Ir,
+}
+
+/// Output of a single stage, running all the previous stages first
+///
+/// If a previous stage has fatal errors, the compilation stops there and the output only says so,
+/// its errors are part of that stage's snapshot.
+fn evaluate_stage(fname: &Path, stage: Stage) -> String {
+    let not_reached = |previous: &str| format!("==== NOT REACHED ====\nerrors in {previous}\n");
+
+    let mut compiler = Compiler::new();
+    let contents = std::fs::read(fname).expect("We only run tests found by glob");
+
+    let span_offset = compiler.span_offset();
+    compiler.add_file(&fname.to_string_lossy(), &contents);
+
+    let (tokens, err) = lex(&contents, span_offset);
+    if let Stage::Tokens = stage {
+        let mut result = tokens.display(&contents);
+        if let Err(e) = err {
+            result.push_str(&format!("Lexing error. Error: {:?}", e));
+        }
+        return result;
+    }
+    if err.is_err() {
+        return not_reached("tokens");
+    }
+
+    compiler = Parser::new(compiler, tokens).parse();
+    if let Stage::Ast = stage {
+        return compiler.display_state();
+    }
+    if compiler.has_fatal_errors(&compiler.errors) {
+        return not_reached("ast");
+    }
+
+    let mut resolver = Resolver::new(&compiler);
+    resolver.resolve();
+    if let Stage::Names = stage {
+        return resolver.display_state();
+    }
+    compiler.merge_name_bindings(resolver.to_name_bindings());
+    if compiler.has_fatal_errors(&compiler.errors) {
+        return not_reached("names");
+    }
+
+    let mut typechecker = Typechecker::new(&compiler);
+    typechecker.typecheck();
+    if let Stage::Types = stage {
+        return typechecker.display_state();
+    }
+    compiler.merge_types(typechecker.to_types());
+    if compiler.has_fatal_errors(&compiler.errors) {
+        return not_reached("types");
+    }
+
+    let mut ir_generator = IrGenerator::new(&compiler);
+    ir_generator.generate();
+    ir_generator.display_state()
+}
+
+/// Snapshot the output of the stage for each source in tests/stages
+///
+/// Each stage has its own test, so that a single stage's snapshots can be regenerated with, e.g.,
+/// `cargo insta test --accept -- test_stage_types`.
+fn assert_stage_snapshots(stage: Stage) {
+    let name = format!("stage_{stage:?}").to_lowercase();
+    insta::glob!("../tests/stages", "*.nu", |path| {
+        insta::assert_snapshot!(name.as_str(), evaluate_stage(path, stage));
+    });
+}
+
+#[test]
+fn test_stage_tokens() {
+    assert_stage_snapshots(Stage::Tokens);
+}
+
+#[test]
+fn test_stage_ast() {
+    assert_stage_snapshots(Stage::Ast);
+}
+
+#[test]
+fn test_stage_names() {
+    assert_stage_snapshots(Stage::Names);
+}
+
+#[test]
+fn test_stage_types() {
+    assert_stage_snapshots(Stage::Types);
+}
+
+#[test]
+fn test_stage_ir() {
+    assert_stage_snapshots(Stage::Ir);
+}
+
 #[test]
 fn test_node_output() {
     insta::glob!("../tests", "*.nu", |path| {
@@ -71,9 +382,120 @@ fn test_node_output() {
     });
 }
 
+#[test]
+fn test_ir() {
+    insta::glob!("../tests/ir", "*.nu", |path| {
+        insta::assert_snapshot!(evaluate_ir(path));
+    });
+}
+
+#[test]
+fn test_convert() {
+    insta::glob!("../tests/convert", "*.nu", |path| {
+        insta::assert_snapshot!(evaluate_convert(path));
+    });
+}
+
+#[test]
+fn test_config() {
+    insta::glob!("../tests/config", "*.nu", |path| {
+        insta::assert_snapshot!(evaluate_config(path));
+    });
+}
+
+#[test]
+fn test_render() {
+    insta::glob!("../tests/render", "*.nu", |path| {
+        insta::assert_snapshot!(evaluate_render(path));
+    });
+}
+
+#[test]
+fn test_incremental() {
+    insta::glob!("../tests/incremental", "*.nu", |path| {
+        insta::assert_snapshot!(evaluate_incremental(path));
+    });
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_roundtrip() {
+    insta::glob!("../tests", "*.nu", |path| {
+        let (compiler, _) = compile_example(path);
+
+        let ast_nodes = serde_json::to_string(&compiler.ast_nodes).expect("AST is serializable");
+        let spans = serde_json::to_string(&compiler.spans).expect("spans are serializable");
+        let errors = serde_json::to_string(&compiler.errors).expect("errors are serializable");
+
+        let parsed_nodes: Vec<crate::parser::AstNode> =
+            serde_json::from_str(&ast_nodes).expect("AST is deserializable");
+        let parsed_spans: Vec<crate::compiler::Span> =
+            serde_json::from_str(&spans).expect("spans are deserializable");
+        let parsed_errors: Vec<crate::errors::SourceError> =
+            serde_json::from_str(&errors).expect("errors are deserializable");
+
+        assert_eq!(parsed_nodes, compiler.ast_nodes);
+        assert_eq!(parsed_spans, compiler.spans);
+        assert_eq!(parsed_errors.len(), compiler.errors.len());
+    });
+}
+
+#[test]
+fn test_format() {
+    insta::glob!("../tests/format", "*.nu", |path| {
+        insta::assert_snapshot!(evaluate_format(path));
+    });
+}
+
 #[test]
 fn test_lexer() {
     insta::glob!("../tests/lex", "*.nu", |path| {
         insta::assert_snapshot!(evaluate_lexer(path));
     });
 }
+
+#[test]
+fn test_check() {
+    insta::glob!("../tests", "*.nu", |path| {
+        let contents = std::fs::read(path).expect("We only run tests found by glob");
+        let fname = path.to_string_lossy();
+
+        let (tokens, _) = lex(&contents, 0);
+        let mut parsed = Compiler::new();
+        parsed.add_file(&fname, &contents);
+        parsed = Parser::new(parsed, tokens).parse();
+
+        let mut checked = Compiler::new();
+        let valid = checked.check(&fname, &contents);
+
+        assert_eq!(valid, !parsed.has_fatal_errors(&parsed.errors));
+        let summary = |compiler: &Compiler| {
+            compiler
+                .errors
+                .iter()
+                .map(|error| (error.code, error.message.clone(), error.node_id))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(summary(&checked), summary(&parsed));
+        assert!(checked.scope.is_empty(), "check must not resolve names");
+    });
+
+    let mut compiler = Compiler::new();
+    assert!(!compiler.check("lex_error.nu", br#"$"foo("baz")bar""#));
+    assert_eq!(
+        compiler.errors.last().map(|error| error.code),
+        Some(crate::errors::ErrorCode::LexError)
+    );
+
+    // reported at the opening quote, expecting the closing one at the end of the file
+    let mut compiler = Compiler::new();
+    assert!(!compiler.check("unclosed_string.nu", b"let x = \"unclosed\n"));
+    let error = compiler.errors.last().expect("missing error");
+    assert_eq!(error.code, crate::errors::ErrorCode::UnclosedDelimiter);
+    assert_eq!(compiler.get_span(error.node_id), Span::new(8, 9));
+    assert_eq!(error.related[0].span, Span::new(17, 17));
+    assert_eq!(
+        error.fixes[0].apply(b"let x = \"unclosed\n", 0),
+        Ok(b"let x = \"unclosed\"\n".to_vec())
+    );
+}
@@ -1,23 +1,32 @@
-use crate::compiler::Compiler;
-use crate::errors::{Severity, SourceError};
-use crate::parser::{AstNode, NodeId};
+use crate::compiler::{Compiler, Profile};
+use crate::config::{config_path, ConfigValue, CONFIG_SCHEMA};
+use crate::errors::{
+    did_you_mean, ErrorCode, Fix, Message, RelatedSpan, Severity, SourceError, TextEdit,
+};
+use crate::lint::Lint;
+use crate::parser::{unquote, AstNode, NodeId, RecordEntry};
+use crate::protocol::{Command, ParamKind, Parameter};
 use std::cmp::Ordering;
 use std::collections::HashSet;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TypeId(pub usize);
 
 /// Input/output type pair of a closure/command
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct InOutType {
     pub in_type: TypeId,
     pub out_type: TypeId,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OneOfId(pub usize);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Type {
     /// Any node that hasn't been touched by the typechecker will have this type
     Unknown,
@@ -34,20 +43,98 @@ pub enum Type {
     Float,
     Bool,
     String,
+    /// Pattern of file paths, e.g., `*.rs`
+    Glob,
     Binary,
     Closure,
+    Range,
+    Filesize,
+    Duration,
+    Datetime,
     List(TypeId),
     Stream(TypeId),
     OneOf(OneOfId),
     Error,
 }
 
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Types {
     pub types: Vec<Type>,
     pub node_types: Vec<TypeId>,
+    pub oneof_types: Vec<HashSet<TypeId>>,
+    pub variable_types: Vec<TypeId>,
+    pub decl_types: Vec<Vec<InOutType>>,
     pub errors: Vec<SourceError>,
 }
 
+impl Types {
+    /// Drop the types of nodes, variables and declarations that are no longer in the compiler,
+    /// e.g., after `Compiler::rollback()`
+    pub fn truncate(&mut self, compiler: &Compiler) {
+        self.node_types.truncate(compiler.ast_nodes.len());
+        self.variable_types.truncate(compiler.variables.len());
+        self.decl_types.truncate(compiler.decls.len());
+        self.errors.clear();
+    }
+
+    pub fn type_to_string(&self, type_id: TypeId) -> String {
+        type_to_string(&self.types, &self.oneof_types, type_id)
+    }
+}
+
+/// Format the type the way it is written in the source, e.g., `list<int>`
+fn type_to_string(types: &[Type], oneof_types: &[HashSet<TypeId>], type_id: TypeId) -> String {
+    let ty = &types[type_id.0];
+
+    match ty {
+        Type::Unknown => "unknown".to_string(),
+        Type::Forbidden => "forbidden".to_string(),
+        Type::None => "()".to_string(),
+        Type::Any => "any".to_string(),
+        Type::Number => "number".to_string(),
+        Type::Nothing => "nothing".to_string(),
+        Type::Int => "int".to_string(),
+        Type::Float => "float".to_string(),
+        Type::Bool => "bool".to_string(),
+        Type::Binary => "binary".to_string(),
+        Type::String => "string".to_string(),
+        Type::Glob => "glob".to_string(),
+        Type::Closure => "closure".to_string(),
+        Type::Range => "range".to_string(),
+        Type::Filesize => "filesize".to_string(),
+        Type::Duration => "duration".to_string(),
+        Type::Datetime => "datetime".to_string(),
+        Type::List(subtype_id) => {
+            format!("list<{}>", type_to_string(types, oneof_types, *subtype_id))
+        }
+        Type::Stream(subtype_id) => {
+            format!(
+                "stream<{}>",
+                type_to_string(types, oneof_types, *subtype_id)
+            )
+        }
+        Type::OneOf(id) => {
+            let mut fmt = "oneof<".to_string();
+            let mut names: Vec<_> = oneof_types[id.0]
+                .iter()
+                .map(|ty| type_to_string(types, oneof_types, *ty) + ", ")
+                .collect();
+            names.sort();
+            for name in &names {
+                fmt += name;
+            }
+            if !names.is_empty() {
+                fmt.pop();
+                fmt.pop();
+            }
+            fmt.push('>');
+            fmt
+        }
+        Type::Error => "error".to_string(),
+    }
+}
+
 // The below are predefined simple types hardcoded into the Typechecker to avoid re-adding them all
 // the time:
 
@@ -69,6 +156,16 @@ pub const CLOSURE_TYPE: TypeId = TypeId(11);
 pub const LIST_ANY_TYPE: TypeId = TypeId(12);
 pub const BYTE_STREAM_TYPE: TypeId = TypeId(13);
 pub const ERROR_TYPE: TypeId = TypeId(14);
+pub const RANGE_TYPE: TypeId = TypeId(15);
+pub const FILESIZE_TYPE: TypeId = TypeId(16);
+pub const DURATION_TYPE: TypeId = TypeId(17);
+pub const DATETIME_TYPE: TypeId = TypeId(18);
+pub const GLOB_TYPE: TypeId = TypeId(19);
+
+const NON_BOOL_CONDITION_NOTE: &str =
+    "values are not converted to booleans implicitly, compare them explicitly, e.g., `$x != 0`";
+const LOOP_BLOCK_VALUE_NOTE: &str =
+    "loops are statements, use a command like `each` to collect values into a list";
 
 pub struct Typechecker<'a> {
     /// Immutable reference to a compiler after the name binding pass
@@ -91,7 +188,7 @@ pub struct Typechecker<'a> {
 
 impl<'a> Typechecker<'a> {
     pub fn new(compiler: &'a Compiler) -> Self {
-        Self {
+        let mut typechecker = Self {
             compiler,
             types: vec![
                 // The order must be the same as with the xxx_TYPE constants above
@@ -110,18 +207,73 @@ impl<'a> Typechecker<'a> {
                 Type::List(ANY_TYPE),
                 Type::Stream(BINARY_TYPE),
                 Type::Error,
+                Type::Range,
+                Type::Filesize,
+                Type::Duration,
+                Type::Datetime,
+                Type::Glob,
             ],
             node_types: vec![UNKNOWN_TYPE; compiler.ast_nodes.len()],
             oneof_types: Vec::new(),
             variable_types: vec![UNKNOWN_TYPE; compiler.variables.len()],
-            decl_types: vec![
+            decl_types: vec![],
+            errors: vec![],
+        };
+
+        typechecker.add_decl_types();
+        typechecker
+    }
+
+    /// Create a typechecker continuing from the output of a previous typechecker
+    ///
+    /// Types of variables and declarations from the previous run are kept, so that new nodes
+    /// added to the compiler since then (e.g., a new REPL line) can refer to them. Previous errors
+    /// are dropped.
+    pub fn with_types(compiler: &'a Compiler, types: Types) -> Self {
+        let mut typechecker = Self {
+            compiler,
+            types: types.types,
+            node_types: types.node_types,
+            oneof_types: types.oneof_types,
+            variable_types: types.variable_types,
+            decl_types: types.decl_types,
+            errors: vec![],
+        };
+
+        typechecker
+            .node_types
+            .resize(compiler.ast_nodes.len(), UNKNOWN_TYPE);
+        typechecker
+            .variable_types
+            .resize(compiler.variables.len(), UNKNOWN_TYPE);
+        typechecker.add_decl_types();
+
+        typechecker
+    }
+
+    /// Add input/output types of the declarations that don't have them yet
+    ///
+    /// Declarations with types known before typechecking (e.g., from signatures) get these types,
+    /// the other ones start as `any -> any`.
+    fn add_decl_types(&mut self) {
+        let compiler = self.compiler;
+        for decl in &compiler.decls[self.decl_types.len()..] {
+            let in_out_types = if decl.input_output_types().is_empty() {
                 vec![InOutType {
                     in_type: ANY_TYPE,
                     out_type: ANY_TYPE,
-                }];
-                compiler.decls.len()
-            ],
-            errors: vec![],
+                }]
+            } else {
+                decl.input_output_types()
+                    .iter()
+                    .map(|(in_type, out_type)| InOutType {
+                        in_type: self.type_id_from_name(in_type.as_bytes()),
+                        out_type: self.type_id_from_name(out_type.as_bytes()),
+                    })
+                    .collect()
+            };
+
+            self.decl_types.push(in_out_types);
         }
     }
 
@@ -129,6 +281,9 @@ impl<'a> Typechecker<'a> {
         Types {
             types: self.types,
             node_types: self.node_types,
+            oneof_types: self.oneof_types,
+            variable_types: self.variable_types,
+            decl_types: self.decl_types,
             errors: self.errors,
         }
     }
@@ -154,16 +309,29 @@ impl<'a> Typechecker<'a> {
         if !self.errors.is_empty() {
             result.push_str("==== TYPE ERRORS ====\n");
             for error in &self.errors {
-                result.push_str(&format!(
-                    "{:?} (NodeId {}): {}\n",
-                    error.severity, error.node_id.0, error.message
-                ));
+                result.push_str(&error.display(self.compiler));
             }
         }
 
         result
     }
 
+    /// Format the types of nodes and errors as JSON for machine-readable output
+    pub fn to_json(&self) -> serde_json::Value {
+        let types: Vec<_> = self
+            .node_types
+            .iter()
+            .map(|type_id| self.type_to_string(*type_id))
+            .collect();
+        let errors: Vec<_> = self
+            .errors
+            .iter()
+            .map(|error| error.to_json(self.compiler))
+            .collect();
+
+        serde_json::json!({ "types": types, "errors": errors })
+    }
+
     /// Typecheck AST nodes, starting from the last node
     pub fn typecheck(&mut self) {
         if !self.compiler.ast_nodes.is_empty() {
@@ -184,8 +352,16 @@ impl<'a> Typechecker<'a> {
         self.types[type_id.0]
     }
 
+    /// Whether the node's type is already an error, i.e., the node has been reported and further
+    /// diagnostics about it would only repeat the same problem
+    fn is_error(&self, node_id: NodeId) -> bool {
+        self.type_id_of(node_id) == ERROR_TYPE
+    }
+
     fn typecheck_node(&mut self, node_id: NodeId) {
         match self.compiler.ast_nodes[node_id.0] {
+            // Already reported by the parser
+            AstNode::Garbage => self.set_node_type_id(node_id, ERROR_TYPE),
             AstNode::Null => {
                 self.set_node_type_id(node_id, NOTHING_TYPE);
             }
@@ -195,10 +371,51 @@ impl<'a> Typechecker<'a> {
             AstNode::Float => {
                 self.set_node_type_id(node_id, FLOAT_TYPE);
             }
+            AstNode::Filesize => {
+                self.set_node_type_id(node_id, FILESIZE_TYPE);
+            }
+            AstNode::Duration => {
+                self.set_node_type_id(node_id, DURATION_TYPE);
+            }
+            AstNode::Binary => {
+                self.set_node_type_id(node_id, BINARY_TYPE);
+            }
+            AstNode::Datetime => {
+                self.set_node_type_id(node_id, DATETIME_TYPE);
+            }
             AstNode::True | AstNode::False => {
                 self.set_node_type_id(node_id, BOOL_TYPE);
             }
-            AstNode::String => {
+            AstNode::String | AstNode::RawString => {
+                self.set_node_type_id(node_id, STRING_TYPE);
+            }
+            AstNode::GlobPattern => {
+                self.set_node_type_id(node_id, GLOB_TYPE);
+            }
+            AstNode::Pipeline(ref elements) => {
+                for element in elements {
+                    self.typecheck_node(*element);
+                }
+                // the value of the pipeline is the output of its last element
+                let last = elements[elements.len() - 1];
+                self.set_node_type_id(node_id, self.type_id_of(last));
+            }
+            AstNode::EnvShorthand {
+                ref assignments,
+                block,
+            } => {
+                for (name, value) in assignments {
+                    self.set_node_type_id(*name, FORBIDDEN_TYPE);
+                    self.typecheck_node(*value);
+                }
+                self.typecheck_node(block);
+                self.set_node_type_id(node_id, self.type_id_of(block));
+            }
+            AstNode::StringInterpolation(ref parts) => {
+                // any value can be interpolated, it is converted to a string
+                for part in parts {
+                    self.typecheck_node(*part);
+                }
                 self.set_node_type_id(node_id, STRING_TYPE);
             }
             AstNode::Params(ref params) => {
@@ -208,21 +425,13 @@ impl<'a> Typechecker<'a> {
                 // Params are not supposed to be evaluated
                 self.set_node_type_id(node_id, FORBIDDEN_TYPE);
             }
-            AstNode::Param { name, ty } => {
-                if let Some(ty) = ty {
-                    self.typecheck_node(ty);
-
-                    let var_id = self
-                        .compiler
-                        .var_resolution
-                        .get(&name)
-                        .expect("missing resolved variable");
-                    self.variable_types[var_id.0] = self.type_id_of(ty);
-                    self.set_node_type_id(node_id, self.type_id_of(ty));
-                } else {
-                    self.set_node_type_id(node_id, ANY_TYPE);
-                }
-            }
+            AstNode::Param {
+                name,
+                ty,
+                kind,
+                default,
+                ..
+            } => self.typecheck_param(name, ty, kind, default, node_id),
             AstNode::Type {
                 name,
                 params,
@@ -233,15 +442,15 @@ impl<'a> Typechecker<'a> {
             }
             AstNode::List(ref items) => {
                 if let Some(first_id) = items.first() {
-                    self.typecheck_node(*first_id);
-                    let first_type = self.type_of(*first_id);
+                    let first_type_id = self.typecheck_list_item(*first_id);
+                    let first_type = self.types[first_type_id.0];
 
                     let mut all_numbers = is_type_compatible(first_type, Type::Number);
                     let mut all_same = true;
 
                     for item_id in items.iter().skip(1) {
-                        self.typecheck_node(*item_id);
-                        let item_type = self.type_of(*item_id);
+                        let item_type_id = self.typecheck_list_item(*item_id);
+                        let item_type = self.types[item_type_id.0];
 
                         if all_numbers && !is_type_compatible(item_type, Type::Number) {
                             all_numbers = false;
@@ -253,7 +462,7 @@ impl<'a> Typechecker<'a> {
                     }
 
                     if all_same {
-                        self.set_node_type(node_id, Type::List(self.type_id_of(*first_id)));
+                        self.set_node_type(node_id, Type::List(first_type_id));
                     } else if all_numbers {
                         self.set_node_type(node_id, Type::List(NUMBER_TYPE));
                     } else {
@@ -279,6 +488,14 @@ impl<'a> Typechecker<'a> {
 
                 self.set_node_type_id(node_id, block_type);
             }
+            AstNode::Statement(inner) => {
+                self.typecheck_node(inner);
+                self.set_node_type_id(node_id, NONE_TYPE);
+            }
+            AstNode::Subexpression(block) => {
+                self.typecheck_node(block);
+                self.set_node_type_id(node_id, self.type_id_of(block));
+            }
             AstNode::Closure { params, block } => {
                 // TODO: input/output types
                 if let Some(params_node_id) = params {
@@ -288,12 +505,64 @@ impl<'a> Typechecker<'a> {
                 self.typecheck_node(block);
                 self.set_node_type_id(node_id, CLOSURE_TYPE);
             }
+            AstNode::Record { ref entries } => {
+                for entry in entries {
+                    for node in entry.nodes() {
+                        self.typecheck_node(node);
+                    }
+                    if let RecordEntry::Spread(spread) = entry {
+                        self.check_spread(*spread, true);
+                    }
+                }
+                // TODO: record types
+                self.set_node_type_id(node_id, ANY_TYPE);
+            }
+            AstNode::Table { header, ref rows } => {
+                self.typecheck_node(header);
+                for row in rows {
+                    self.typecheck_node(*row);
+                }
+                // TODO: record types
+                self.set_node_type_id(node_id, LIST_ANY_TYPE);
+            }
+            AstNode::Range { from, next, to, .. } => {
+                for bound in [from, next, to].into_iter().flatten() {
+                    self.typecheck_node(bound);
+                    if !self.is_error(bound)
+                        && !is_type_compatible(self.type_of(bound), Type::Number)
+                    {
+                        self.error(
+                            ErrorCode::TypeMismatch,
+                            format!(
+                                "range bounds must be numbers, found {}",
+                                self.type_to_string(self.type_id_of(bound))
+                            ),
+                            bound,
+                        );
+                    }
+                }
+                self.set_node_type_id(node_id, RANGE_TYPE);
+            }
+            AstNode::Spread(value) => {
+                self.typecheck_node(value);
+                self.set_node_type_id(node_id, self.type_id_of(value));
+            }
+            AstNode::CellPath { head, ref members } => {
+                let members: Vec<_> = members.iter().map(|member| member.member).collect();
+                self.typecheck_cell_path(head, &members, node_id)
+            }
             AstNode::BinaryOp { lhs, op, rhs } => self.typecheck_binary_op(lhs, op, rhs, node_id),
+            AstNode::UnaryOp { op, value } => self.typecheck_unary_op(op, value, node_id),
             AstNode::Let {
                 variable_name,
                 ty,
                 initializer,
                 is_mutable: _,
+            }
+            | AstNode::Const {
+                variable_name,
+                ty,
+                initializer,
             } => self.typecheck_let(variable_name, ty, initializer, node_id),
             AstNode::Variable => {
                 let var_id = self
@@ -332,8 +601,15 @@ impl<'a> Typechecker<'a> {
                 }
 
                 // the condition should always evaluate to a boolean
-                if self.type_of(condition) != Type::Bool {
-                    self.error("The condition for if branch is not a boolean", condition);
+                if self.is_error(condition) {
+                    self.set_node_type_id(node_id, ERROR_TYPE);
+                } else if self.type_of(condition) != Type::Bool {
+                    self.error(
+                        ErrorCode::NonBoolCondition,
+                        "The condition for if branch is not a boolean",
+                        condition,
+                    );
+                    self.add_note(NON_BOOL_CONDITION_NOTE);
                     self.set_node_type_id(node_id, ERROR_TYPE);
                 } else if types.len() > 1 {
                     self.oneof_types.push(types);
@@ -342,16 +618,63 @@ impl<'a> Typechecker<'a> {
                     self.set_node_type_id(node_id, *types.iter().next().expect("Can't be empty"));
                 }
             }
+            AstNode::Try { block, catch } => {
+                self.typecheck_node(block);
+
+                // without `catch`, the value is nothing if the block fails
+                let mut types = HashSet::new();
+                self.add_resolved_types(&mut types, &self.type_id_of(block));
+                match catch {
+                    Some(catch) => {
+                        self.typecheck_node(catch);
+                        if let AstNode::Closure { block, .. } = self.compiler.ast_nodes[catch.0] {
+                            self.add_resolved_types(&mut types, &self.type_id_of(block));
+                        }
+                    }
+                    None => {
+                        types.insert(NOTHING_TYPE);
+                    }
+                }
+
+                if types.len() > 1 {
+                    self.oneof_types.push(types);
+                    self.set_node_type(node_id, Type::OneOf(OneOfId(self.oneof_types.len() - 1)));
+                } else {
+                    self.set_node_type_id(node_id, *types.iter().next().expect("Can't be empty"));
+                }
+            }
             AstNode::Def {
                 name,
                 params,
                 return_ty,
                 block,
             } => self.typecheck_def(name, params, return_ty, block, node_id),
-            AstNode::Alias { new_name, old_name } => {
-                self.typecheck_alias(new_name, old_name, node_id)
+            AstNode::Alias {
+                new_name,
+                expansion,
+            } => self.typecheck_alias(new_name, expansion, node_id),
+            AstNode::Extern { name, params } => self.typecheck_extern(name, params, node_id),
+            AstNode::Module { block, .. } | AstNode::ExportEnv(block) => {
+                self.typecheck_node(block);
+                self.set_node_type_id(node_id, NONE_TYPE);
+            }
+            AstNode::Use { .. } | AstNode::OverlayUse { .. } => {
+                if let Some(root) = self.compiler.sources.get(&node_id) {
+                    self.typecheck_node(*root);
+                }
+                self.set_node_type_id(node_id, NONE_TYPE);
+            }
+            AstNode::OverlayNew(_) | AstNode::OverlayHide(_) | AstNode::Hide(_) => {
+                self.set_node_type_id(node_id, NONE_TYPE)
+            }
+            AstNode::Export(definition) => {
+                self.typecheck_node(definition);
+                self.set_node_type_id(node_id, self.type_id_of(definition));
+            }
+            AstNode::Call { ref parts } => {
+                self.typecheck_call(parts, node_id, true);
+                self.check_error_make(parts);
             }
-            AstNode::Call { ref parts } => self.typecheck_call(parts, node_id),
             AstNode::For {
                 variable,
                 range,
@@ -368,32 +691,47 @@ impl<'a> Typechecker<'a> {
                 if let Type::List(type_id) = self.type_of(range) {
                     self.variable_types[var_id.0] = type_id;
                     self.set_node_type_id(variable, type_id);
+                } else if self.type_of(range) == Type::Range {
+                    self.variable_types[var_id.0] = NUMBER_TYPE;
+                    self.set_node_type_id(variable, NUMBER_TYPE);
                 } else {
                     self.variable_types[var_id.0] = ANY_TYPE;
                     self.set_node_type_id(variable, ERROR_TYPE);
-                    self.error("For loop range is not a list", range);
+                    if !self.is_error(range) {
+                        self.error(
+                            ErrorCode::NonListIteration,
+                            "For loop range is not a list",
+                            range,
+                        );
+                    }
                 }
 
-                self.typecheck_node(block);
-                if self.type_id_of(block) != NONE_TYPE {
-                    self.error("Blocks in looping constructs cannot return values", block);
-                }
+                self.typecheck_loop_block(block);
 
                 if self.type_id_of(node_id) != ERROR_TYPE {
                     self.set_node_type_id(node_id, NONE_TYPE);
                 }
             }
+            AstNode::Loop { block } => {
+                self.typecheck_loop_block(block);
+                self.set_node_type_id(node_id, NONE_TYPE);
+            }
+            AstNode::Break | AstNode::Continue => self.set_node_type_id(node_id, NONE_TYPE),
             AstNode::While { condition, block } => {
-                self.typecheck_node(block);
-                if self.type_id_of(block) != NONE_TYPE {
-                    self.error("Blocks in looping constructs cannot return values", block);
-                }
+                self.typecheck_loop_block(block);
 
                 self.typecheck_node(condition);
 
                 // the condition should always evaluate to a boolean
-                if self.type_of(condition) != Type::Bool {
-                    self.error("The condition for while loop is not a boolean", condition);
+                if self.is_error(condition) {
+                    self.set_node_type_id(node_id, ERROR_TYPE);
+                } else if self.type_of(condition) != Type::Bool {
+                    self.error(
+                        ErrorCode::NonBoolCondition,
+                        "The condition for while loop is not a boolean",
+                        condition,
+                    );
+                    self.add_note(NON_BOOL_CONDITION_NOTE);
                     self.set_node_type_id(node_id, ERROR_TYPE);
                 } else {
                     self.set_node_type_id(node_id, self.type_id_of(block));
@@ -405,29 +743,37 @@ impl<'a> Typechecker<'a> {
             } => {
                 // Check all the output types of match
                 let output_types = self.typecheck_match(target, match_arms);
-                match output_types.len().cmp(&1) {
-                    Ordering::Greater => {
-                        self.oneof_types.push(output_types);
-                        self.set_node_type(
-                            node_id,
-                            Type::OneOf(OneOfId(self.oneof_types.len() - 1)),
-                        );
-                    }
-                    Ordering::Equal => {
-                        self.set_node_type_id(
-                            node_id,
-                            *output_types
-                                .iter()
-                                .next()
-                                .expect("Will contain one element"),
-                        );
-                    }
-                    Ordering::Less => {
-                        self.set_node_type_id(node_id, NOTHING_TYPE);
-                    }
+                self.set_oneof_node_type(node_id, output_types);
+            }
+            AstNode::Wildcard => self.set_node_type_id(node_id, ANY_TYPE),
+            AstNode::OrPattern(ref alternatives) => {
+                let mut types = HashSet::new();
+                for alternative in alternatives {
+                    self.typecheck_node(*alternative);
+                    self.add_resolved_types(&mut types, &self.type_id_of(*alternative));
+                }
+
+                if types.contains(&ANY_TYPE) {
+                    self.set_node_type_id(node_id, ANY_TYPE);
+                } else {
+                    self.set_oneof_node_type(node_id, types);
+                }
+            }
+            AstNode::GuardedPattern { pattern, guard } => {
+                self.typecheck_node(pattern);
+                self.typecheck_node(guard);
+
+                if !self.is_error(guard) && self.type_of(guard) != Type::Bool {
+                    self.error(
+                        ErrorCode::NonBoolCondition,
+                        "The guard of a match arm is not a boolean",
+                        guard,
+                    );
                 }
+                self.set_node_type_id(node_id, self.type_id_of(pattern));
             }
             _ => self.error(
+                ErrorCode::TypecheckUnsupportedNode,
                 format!(
                     "unsupported ast node '{:?}' in typechecker",
                     self.compiler.ast_nodes[node_id.0]
@@ -437,6 +783,38 @@ impl<'a> Typechecker<'a> {
         }
     }
 
+    /// One of the types, or nothing if there are none
+    fn set_oneof_node_type(&mut self, node_id: NodeId, types: HashSet<TypeId>) {
+        match types.len().cmp(&1) {
+            Ordering::Greater => {
+                self.oneof_types.push(types);
+                self.set_node_type(node_id, Type::OneOf(OneOfId(self.oneof_types.len() - 1)));
+            }
+            Ordering::Equal => {
+                self.set_node_type_id(
+                    node_id,
+                    *types.iter().next().expect("Will contain one element"),
+                );
+            }
+            Ordering::Less => {
+                self.set_node_type_id(node_id, NOTHING_TYPE);
+            }
+        }
+    }
+
+    /// The body of `for`, `while` and `loop`, which must not produce a value
+    fn typecheck_loop_block(&mut self, block: NodeId) {
+        self.typecheck_node(block);
+        if self.type_id_of(block) != NONE_TYPE && !self.is_error(block) {
+            self.error(
+                ErrorCode::LoopBlockValue,
+                "Blocks in looping constructs cannot return values",
+                block,
+            );
+            self.add_note(LOOP_BLOCK_VALUE_NOTE);
+        }
+    }
+
     fn typecheck_match(
         &mut self,
         target: &NodeId,
@@ -448,50 +826,334 @@ impl<'a> Typechecker<'a> {
         // typecheck each node
         let target_id = self.type_id_of(*target);
         for (match_node, result_node) in match_arms {
+            let pattern = match self.compiler.get_node(*match_node) {
+                AstNode::GuardedPattern { pattern, .. } => *pattern,
+                _ => *match_node,
+            };
+
+            // a variable on its own binds the whole target, the others bind any part of it
+            let variables = self.compiler.pattern_variables(*match_node);
+            let has_variables = !variables.is_empty();
+            for variable in variables {
+                let var_id = self
+                    .compiler
+                    .var_resolution
+                    .get(&variable)
+                    .expect("missing resolved variable");
+                self.variable_types[var_id.0] = if variable == pattern {
+                    target_id
+                } else {
+                    ANY_TYPE
+                };
+            }
             self.typecheck_node(*match_node);
             self.typecheck_node(*result_node);
 
-            let match_id = self.type_id_of(*match_node);
-            match (self.type_of(*target), self.type_of(*match_node)) {
-                // First is of type Any which will always match
-                (Type::Any, _) => {
-                    self.add_resolved_types(&mut output_types, &self.type_id_of(*result_node));
-                }
-                // Same as above but for second
-                (_, Type::Any) => {
-                    self.add_resolved_types(&mut output_types, &self.type_id_of(*result_node));
-                }
-                // the second is one of the possible types of the first
-                (Type::OneOf(id), _) if self.oneof_types[id.0].contains(&match_id) => {
-                    self.add_resolved_types(&mut output_types, &self.type_id_of(*result_node));
-                }
-                // the first is one of the possible types of the second
-                (_, Type::OneOf(id)) if self.oneof_types[id.0].contains(&target_id) => {
-                    self.add_resolved_types(&mut output_types, &self.type_id_of(*result_node));
-                }
-                // the both the target and the one matched against are
-                // oneof<many types> then we need to check if they have any type in common
-                (Type::OneOf(id1), Type::OneOf(id2)) => {
-                    if self.oneof_types[id1.0]
-                        .intersection(&self.oneof_types[id2.0])
-                        .count()
-                        != 0
-                    {
-                        self.add_resolved_types(&mut output_types, &self.type_id_of(*result_node));
+            // `_` and the patterns binding variables are not checked against the target
+            let is_wildcard = matches!(self.compiler.get_node(pattern), AstNode::Wildcard);
+            if is_wildcard || has_variables || self.can_match(*target, pattern) {
+                self.add_resolved_types(&mut output_types, &self.type_id_of(*result_node));
+            }
+        }
+        output_types
+    }
+
+    /// Whether a value of the target's type can match the pattern, reports an error if not
+    fn can_match(&mut self, target: NodeId, pattern: NodeId) -> bool {
+        if let AstNode::OrPattern(alternatives) = self.compiler.get_node(pattern) {
+            // each alternative is checked on its own, so that all the mismatches are reported
+            let mut can_match = false;
+            for alternative in alternatives {
+                can_match |= self.can_match(target, *alternative);
+            }
+            return can_match;
+        }
+
+        let target_id = self.type_id_of(target);
+        let match_id = self.type_id_of(pattern);
+        match (self.type_of(target), self.type_of(pattern)) {
+            // First is of type Any which will always match
+            (Type::Any, _) => true,
+            // Same as above but for second
+            (_, Type::Any) => true,
+            // the second is one of the possible types of the first
+            (Type::OneOf(id), _) if self.oneof_types[id.0].contains(&match_id) => true,
+            // the first is one of the possible types of the second
+            (_, Type::OneOf(id)) if self.oneof_types[id.0].contains(&target_id) => true,
+            // the both the target and the one matched against are
+            // oneof<many types> then we need to check if they have any type in common
+            (Type::OneOf(id1), Type::OneOf(id2)) => {
+                if self.oneof_types[id1.0]
+                    .intersection(&self.oneof_types[id2.0])
+                    .count()
+                    != 0
+                {
+                    true
+                } else {
+                    self.error(ErrorCode::TypeMismatch, "The target to be matched against and the possible types of the matched arm are completely disjoint", pattern);
+                    false
+                }
+            }
+            // lists match by their items, e.g., `[]` is a list<any> matching any list
+            (Type::List(target_item), Type::List(match_item))
+                if is_type_compatible(self.types[target_item.0], self.types[match_item.0]) =>
+            {
+                true
+            }
+            // Check if the two types can be matched
+            (target_id, match_id) if is_type_compatible(target_id, match_id) => true,
+            _ => {
+                self.error(ErrorCode::TypeMismatch, "The types do not match", pattern);
+                false
+            }
+        }
+    }
+
+    fn typecheck_cell_path(&mut self, head: NodeId, members: &[NodeId], node_id: NodeId) {
+        self.typecheck_node(head);
+        for member in members {
+            self.set_node_type_id(*member, FORBIDDEN_TYPE);
+        }
+
+        if self.is_error(head) {
+            self.set_node_type_id(node_id, ERROR_TYPE);
+            return;
+        }
+
+        let path = if self.compiler.options.profile == Profile::Config {
+            config_path(self.compiler, node_id)
+        } else {
+            None
+        };
+        let Some(path) = path else {
+            // TODO: record types
+            self.set_node_type_id(node_id, ANY_TYPE);
+            return;
+        };
+
+        let mut value = &CONFIG_SCHEMA;
+        for (idx, field) in path.iter().enumerate() {
+            let name = self.compiler.get_span_contents(*field);
+            let Some(field_value) = value.field(name) else {
+                self.error(
+                    ErrorCode::ConfigUnknownField,
+                    format!(
+                        "unknown config field `{}`",
+                        self.path_to_string(&path[..=idx])
+                    ),
+                    *field,
+                );
+                self.add_help(
+                    did_you_mean(value.field_names(), name)
+                        .map(|suggestion| format!("did you mean `{suggestion}`?")),
+                );
+                self.set_node_type_id(node_id, ERROR_TYPE);
+                return;
+            };
+            value = field_value;
+        }
+
+        self.set_node_type_id(node_id, config_value_type(value));
+    }
+
+    /// Check the value assigned to `$env.config` or one of its fields in a config file
+    fn check_config_assignment(&mut self, lhs: NodeId, rhs: NodeId) {
+        if self.compiler.options.profile != Profile::Config || self.is_error(lhs) {
+            return;
+        }
+        let Some(path) = config_path(self.compiler, lhs) else {
+            return;
+        };
+
+        if path.is_empty() {
+            let message = "assignment replaces the whole configuration";
+            if let Some(mut error) = self.compiler.lint(Lint::ConfigOverwrite, message, lhs) {
+                error.help = Some(
+                    "set the fields one by one, e.g., `$env.config.show_banner = false`".into(),
+                );
+                error.note =
+                    Some("the settings made before that are not in the record are lost".into());
+                self.errors.push(error);
+            }
+        }
+
+        let expected = config_field(self.compiler, &path);
+        let path = self.path_to_string(&path);
+        self.check_config_value(expected, rhs, &path);
+    }
+
+    fn check_config_value(&mut self, expected: &ConfigValue, value: NodeId, path: &str) {
+        if self.is_error(value) {
+            return;
+        }
+
+        let value_type = self.type_of(value);
+        let contents = self.compiler.get_span_contents(value);
+
+        let valid = match (expected, &self.compiler.ast_nodes[value.0]) {
+            (ConfigValue::Any, _) => true,
+            (ConfigValue::Record(_), AstNode::Record { entries }) => {
+                // the fields of spread values are not known until they are evaluated
+                for (key, field_value) in entries.iter().filter_map(|entry| match entry {
+                    RecordEntry::Pair(key, val) => Some((key, val)),
+                    RecordEntry::Spread(_) => None,
+                }) {
+                    let name = unquote(self.compiler.get_span_contents(*key));
+                    let field_path = if path.is_empty() {
+                        String::from_utf8_lossy(name).into_owned()
                     } else {
-                        self.error("The target to be matched against and the possible types of the matched arm are completely disjoint", *match_node);
+                        format!("{path}.{}", String::from_utf8_lossy(name))
+                    };
+
+                    match expected.field(name) {
+                        Some(field) => self.check_config_value(field, *field_value, &field_path),
+                        None => {
+                            self.error(
+                                ErrorCode::ConfigUnknownField,
+                                format!("unknown config field `{field_path}`"),
+                                *key,
+                            );
+                            self.add_help(
+                                did_you_mean(expected.field_names(), name)
+                                    .map(|suggestion| format!("did you mean `{suggestion}`?")),
+                            );
+                        }
                     }
                 }
-                // Check if the two types can be matched
-                (target_id, match_id) if is_type_compatible(target_id, match_id) => {
-                    self.add_resolved_types(&mut output_types, &self.type_id_of(*result_node));
+                true
+            }
+            (ConfigValue::Choice(choices), AstNode::String) => {
+                let choice = unquote(contents);
+                if !choices.iter().any(|c| c.as_bytes() == choice) {
+                    self.error(
+                        ErrorCode::ConfigInvalidValue,
+                        format!(
+                            "invalid value `{}` for config field `{path}`",
+                            String::from_utf8_lossy(choice)
+                        ),
+                        value,
+                    );
+                    let help = did_you_mean(choices.iter().map(|c| c.as_bytes()), choice)
+                        .map(|suggestion| format!("did you mean `{suggestion}`?"))
+                        .unwrap_or_else(|| format!("expected one of {}", choices.join(", ")));
+                    self.add_help(Some(help));
                 }
-                _ => {
-                    self.error("The types do not match", *match_node);
+                true
+            }
+            (ConfigValue::Record(_), _) => value_type == Type::Any,
+            (ConfigValue::List, _) => matches!(value_type, Type::List(_) | Type::Any),
+            (ConfigValue::Choice(_) | ConfigValue::String, _) => {
+                is_type_compatible(value_type, Type::String)
+            }
+            (ConfigValue::Bool, _) => is_type_compatible(value_type, Type::Bool),
+            (ConfigValue::Int, _) => is_type_compatible(value_type, Type::Int),
+            (ConfigValue::Closure, _) => is_type_compatible(value_type, Type::Closure),
+        };
+
+        if valid {
+            return;
+        }
+
+        self.error(
+            ErrorCode::ConfigInvalidValue,
+            format!(
+                "invalid value for config field `{path}`: expected {}, found {}",
+                expected.description(),
+                self.type_to_string(self.type_id_of(value))
+            ),
+            value,
+        );
+
+        // a common mistake is quoting the value, e.g., `"false"`
+        let unquoted = unquote(contents);
+        let quoted = matches!(
+            (expected, unquoted),
+            (ConfigValue::Bool, b"true" | b"false")
+        ) || (*expected == ConfigValue::Int
+            && !unquoted.is_empty()
+            && unquoted.iter().all(u8::is_ascii_digit));
+
+        if self.compiler.ast_nodes[value.0] == AstNode::String && quoted {
+            let unquoted = String::from_utf8_lossy(unquoted).into_owned();
+            self.add_help(Some(format!("use `{unquoted}` without quotes")));
+            if let Some(error) = self.errors.last_mut() {
+                error.fixes.push(Fix::new(
+                    "remove the quotes",
+                    vec![TextEdit::replace(self.compiler.get_span(value), unquoted)],
+                ));
+            }
+        }
+    }
+
+    /// Check the record that `error make` raises the error from, e.g., `error make {msg: "foo"}`
+    ///
+    /// The command is recognized by its name, it is checked even if its signature is not known.
+    fn check_error_make(&mut self, parts: &[NodeId]) {
+        let [error, make, record, ..] = *parts else {
+            return;
+        };
+        if self.compiler.get_span_contents(error) != b"error"
+            || self.compiler.get_span_contents(make) != b"make"
+            || self.is_error(record)
+        {
+            return;
+        }
+
+        let AstNode::Record { ref entries } = self.compiler.ast_nodes[record.0] else {
+            // records are typed as any
+            if self.type_of(record) != Type::Any {
+                let record_type = self.type_to_string(self.type_id_of(record));
+                self.error(
+                    ErrorCode::InvalidErrorRecord,
+                    format!("`error make` expects a record, found {record_type}"),
+                    record,
+                );
+            }
+            return;
+        };
+
+        let msg = entries.iter().find_map(|entry| match entry {
+            RecordEntry::Pair(key, value)
+                if unquote(self.compiler.get_span_contents(*key)) == b"msg" =>
+            {
+                Some(*value)
+            }
+            _ => None,
+        });
+        match msg {
+            Some(msg) => {
+                if !self.is_error(msg) && !is_type_compatible(self.type_of(msg), Type::String) {
+                    let msg_type = self.type_to_string(self.type_id_of(msg));
+                    self.error(
+                        ErrorCode::InvalidErrorRecord,
+                        format!("the `msg` of `error make` must be a string, found {msg_type}"),
+                        msg,
+                    );
                 }
             }
+            // the spread record may have the message
+            None if entries
+                .iter()
+                .any(|entry| matches!(entry, RecordEntry::Spread(_))) => {}
+            None => {
+                self.error(
+                    ErrorCode::InvalidErrorRecord,
+                    "`error make` needs a record with a `msg` field",
+                    record,
+                );
+                self.add_help(Some(
+                    "add the error message, e.g., `{msg: \"failed\"}`".into(),
+                ));
+            }
         }
-        output_types
+    }
+
+    /// Format the fields of a config path, e.g., `table.mode`
+    fn path_to_string(&self, path: &[NodeId]) -> String {
+        path.iter()
+            .map(|field| String::from_utf8_lossy(self.compiler.get_span_contents(*field)))
+            .collect::<Vec<_>>()
+            .join(".")
     }
 
     fn typecheck_binary_op(&mut self, lhs: NodeId, op: NodeId, rhs: NodeId, node_id: NodeId) {
@@ -499,10 +1161,104 @@ impl<'a> Typechecker<'a> {
         self.typecheck_node(rhs);
         self.set_node_type_id(op, FORBIDDEN_TYPE);
 
+        let out_type = match self.compiler.ast_nodes[op.0] {
+            AstNode::Assignment => {
+                self.check_config_assignment(lhs, rhs);
+                self.check_assigned_type(lhs, self.type_id_of(rhs), rhs);
+                Some(Type::None)
+            }
+            AstNode::AddAssignment => {
+                self.typecheck_compound_assignment(lhs, &AstNode::Plus, op, rhs)
+            }
+            AstNode::SubtractAssignment => {
+                self.typecheck_compound_assignment(lhs, &AstNode::Minus, op, rhs)
+            }
+            AstNode::MultiplyAssignment => {
+                self.typecheck_compound_assignment(lhs, &AstNode::Multiply, op, rhs)
+            }
+            AstNode::DivideAssignment => {
+                self.typecheck_compound_assignment(lhs, &AstNode::Divide, op, rhs)
+            }
+            AstNode::AppendAssignment => {
+                self.typecheck_compound_assignment(lhs, &AstNode::Append, op, rhs)
+            }
+            ref op_node => self.binary_op_type(lhs, op_node, op, rhs),
+        };
+
+        if let Some(ty) = out_type {
+            self.set_node_type(node_id, ty);
+        } else {
+            self.set_node_type_id(node_id, ERROR_TYPE);
+        }
+    }
+
+    /// Check `$x += 1` and the like as `$x = $x + 1`, the operation must be valid for the operands
+    /// and its result must fit the target
+    fn typecheck_compound_assignment(
+        &mut self,
+        lhs: NodeId,
+        op_node: &AstNode,
+        op: NodeId,
+        rhs: NodeId,
+    ) -> Option<Type> {
+        let ty = self.binary_op_type(lhs, op_node, op, rhs)?;
+        let type_id = self.push_type(ty);
+        self.check_assigned_type(lhs, type_id, rhs);
+        Some(Type::None)
+    }
+
+    /// Report a value assigned to a variable of a different type, e.g., `mut x = 1; $x = "a"`
+    ///
+    /// Only whole variables are checked, the fields of records are not typed yet.
+    fn check_assigned_type(&mut self, target: NodeId, value_type: TypeId, value: NodeId) {
+        if self.compiler.ast_nodes[target.0] != AstNode::Variable
+            || self.is_error(target)
+            || self.is_error(value)
+            || self.is_assignable(self.type_id_of(target), value_type)
+        {
+            return;
+        }
+
+        let target_type = self.type_to_string(self.type_id_of(target));
+        self.error_with_related(
+            ErrorCode::TypeMismatch,
+            format!(
+                "cannot assign {} to a variable of type {target_type}",
+                self.type_to_string(value_type)
+            ),
+            value,
+            vec![RelatedSpan::new(
+                self.compiler.get_span(target),
+                format!("this is {target_type}"),
+            )],
+        );
+    }
+
+    /// Whether a value of one type can be stored in a variable of the other
+    fn is_assignable(&self, target: TypeId, value: TypeId) -> bool {
+        match (self.types[target.0], self.types[value.0]) {
+            (Type::List(target), Type::List(value)) => self.is_assignable(target, value),
+            // the alternatives are not compared yet
+            (Type::OneOf(_), _) | (_, Type::OneOf(_)) => true,
+            (target, value) => is_type_compatible(target, value),
+        }
+    }
+
+    /// Type of a binary operation, None if it is not valid for the operands
+    fn binary_op_type(
+        &mut self,
+        lhs: NodeId,
+        op_node: &AstNode,
+        op: NodeId,
+        rhs: NodeId,
+    ) -> Option<Type> {
         let lhs_type = self.type_of(lhs);
         let rhs_type = self.type_of(rhs);
 
-        let out_type = match self.compiler.ast_nodes[op.0] {
+        let unit_type = check_datetime_op(op_node, lhs_type, rhs_type)
+            .or_else(|| check_unit_op(op_node, lhs_type, rhs_type));
+        match op_node {
+            _ if unit_type.is_some() => unit_type,
             AstNode::Equal | AstNode::NotEqual => Some(Type::Bool),
             AstNode::LessThan
             | AstNode::GreaterThan
@@ -530,14 +1286,17 @@ impl<'a> Typechecker<'a> {
                     Some(type_id)
                 }
             }
-            AstNode::RegexMatch | AstNode::NotRegexMatch => match (lhs_type, rhs_type) {
+            AstNode::RegexMatch
+            | AstNode::NotRegexMatch
+            | AstNode::StartsWith
+            | AstNode::EndsWith => match (lhs_type, rhs_type) {
                 (Type::String | Type::Any, Type::String | Type::Any) => Some(Type::Bool),
                 _ => {
                     self.binary_op_err("string operation", lhs, op, rhs);
                     None
                 }
             },
-            AstNode::In => match rhs_type {
+            AstNode::In | AstNode::NotIn => match rhs_type {
                 Type::String => match lhs_type {
                     Type::String | Type::Any => Some(Type::Bool),
                     _ => {
@@ -559,6 +1318,17 @@ impl<'a> Typechecker<'a> {
                     None
                 }
             },
+            AstNode::BitOr
+            | AstNode::BitXor
+            | AstNode::BitAnd
+            | AstNode::ShiftLeft
+            | AstNode::ShiftRight => match (lhs_type, rhs_type) {
+                (Type::Int | Type::Any, Type::Int | Type::Any) => Some(Type::Int),
+                _ => {
+                    self.binary_op_err("bit operation", lhs, op, rhs);
+                    None
+                }
+            },
             AstNode::And | AstNode::Xor | AstNode::Or => match (lhs_type, rhs_type) {
                 (Type::Bool, Type::Bool) => Some(Type::Bool),
                 _ => {
@@ -600,25 +1370,60 @@ impl<'a> Typechecker<'a> {
                         let common_type_id = self.push_type(common_type);
                         Some(Type::List(common_type_id))
                     }
+                    (Type::String, Type::String) => Some(Type::String),
+                    (Type::Binary, Type::Binary) => Some(Type::Binary),
+                    (Type::Any, _) | (_, Type::Any) => Some(Type::Any),
                     _ => {
                         self.binary_op_err("append", lhs, op, rhs);
                         None
                     }
                 }
             }
-            AstNode::Assignment
-            | AstNode::AddAssignment
-            | AstNode::SubtractAssignment
-            | AstNode::MultiplyAssignment
-            | AstNode::DivideAssignment
-            | AstNode::AppendAssignment => Some(Type::None),
             _ => panic!("internal error: unsupported node passed as binary op: {op:?}"),
+        }
+    }
+
+    fn typecheck_unary_op(&mut self, op: NodeId, value: NodeId, node_id: NodeId) {
+        self.typecheck_node(value);
+        self.set_node_type_id(op, FORBIDDEN_TYPE);
+
+        let value_type = self.type_of(value);
+        let (op_msg, out_type) = match self.compiler.ast_nodes[op.0] {
+            AstNode::Not => (
+                "logical operation",
+                matches!(value_type, Type::Bool | Type::Any).then_some(Type::Bool),
+            ),
+            AstNode::Minus => (
+                "negation",
+                match value_type {
+                    Type::Int | Type::Float | Type::Number | Type::Filesize | Type::Duration => {
+                        Some(value_type)
+                    }
+                    Type::Any => Some(Type::Number),
+                    _ => None,
+                },
+            ),
+            _ => panic!("internal error: unsupported node passed as unary op: {op:?}"),
         };
 
         if let Some(ty) = out_type {
             self.set_node_type(node_id, ty);
-        } else {
-            self.set_node_type_id(node_id, ERROR_TYPE);
+            return;
+        }
+
+        self.set_node_type_id(op, ERROR_TYPE);
+        self.set_node_type_id(node_id, ERROR_TYPE);
+        if !self.is_error(value) {
+            let value_type = self.type_to_string(self.type_id_of(value));
+            self.error_with_related(
+                ErrorCode::UnsupportedOperation,
+                format!("type mismatch: unsupported {op_msg} of {value_type}"),
+                op,
+                vec![RelatedSpan::new(
+                    self.compiler.get_span(value),
+                    format!("this is {value_type}"),
+                )],
+            );
         }
     }
 
@@ -641,13 +1446,19 @@ impl<'a> Typechecker<'a> {
                         let AstNode::InOutType(in_ty, out_ty) = self.compiler.get_node(*ty) else {
                             panic!("internal error: return type is not a return type");
                         };
-                        let AstNode::Type {
-                            name: in_name,
-                            params: in_params,
-                            optional: in_optional,
-                        } = *self.compiler.get_node(*in_ty)
-                        else {
-                            panic!("internal error: type is not a type");
+                        let in_type = match in_ty {
+                            Some(in_ty) => {
+                                let AstNode::Type {
+                                    name: in_name,
+                                    params: in_params,
+                                    optional: in_optional,
+                                } = *self.compiler.get_node(*in_ty)
+                                else {
+                                    panic!("internal error: type is not a type");
+                                };
+                                self.typecheck_type(in_name, in_params, in_optional)
+                            }
+                            None => ANY_TYPE,
                         };
                         let AstNode::Type {
                             name: out_name,
@@ -658,7 +1469,7 @@ impl<'a> Typechecker<'a> {
                             panic!("internal error: type is not a type");
                         };
                         InOutType {
-                            in_type: self.typecheck_type(in_name, in_params, in_optional),
+                            in_type,
                             out_type: self.typecheck_type(out_name, out_params, out_optional),
                         }
                     })
@@ -688,8 +1499,81 @@ impl<'a> Typechecker<'a> {
         }
     }
 
-    fn typecheck_alias(&mut self, new_name: NodeId, old_name: NodeId, node_id: NodeId) {
+    fn typecheck_param(
+        &mut self,
+        name: NodeId,
+        ty: Option<NodeId>,
+        kind: ParamKind,
+        default: Option<NodeId>,
+        node_id: NodeId,
+    ) {
+        let var_id = *self
+            .compiler
+            .var_resolution
+            .get(&name)
+            .expect("missing resolved variable");
+
+        let declared_type = if let Some(ty) = ty {
+            self.typecheck_node(ty);
+            self.type_id_of(ty)
+        } else if kind == ParamKind::Flag {
+            // a flag without a type is a switch
+            BOOL_TYPE
+        } else {
+            let name_end = self.compiler.get_span(name).end;
+            let annotation_at = match self.compiler.source.get(name_end) {
+                Some(b'?') => name_end + 1,
+                _ => name_end,
+            };
+            let message = Message::source(
+                "parameter `",
+                self.compiler.get_span(name),
+                "` has no type annotation and is implicitly `any`",
+            );
+            if let Some(mut error) = self.compiler.lint(Lint::ImplicitAny, message, name) {
+                error.fixes.push(Fix::new(
+                    "add a type annotation",
+                    vec![TextEdit::insert(annotation_at, ": any")],
+                ));
+                self.errors.push(error);
+            }
+            ANY_TYPE
+        };
+
+        if let Some(default) = default {
+            self.typecheck_node(default);
+            if let Some(ty) = ty {
+                if !self.is_error(default)
+                    && !is_type_compatible(self.type_of(ty), self.type_of(default))
+                {
+                    self.error_with_related(
+                        ErrorCode::TypeMismatch,
+                        "default value does not match declared type",
+                        default,
+                        vec![RelatedSpan::new(
+                            self.compiler.get_span(ty),
+                            format!("declared as {} here", self.type_to_string(declared_type)),
+                        )],
+                    );
+                }
+            }
+        }
+
+        // the rest parameter collects the arguments into a list
+        let var_type = match kind {
+            ParamKind::Rest => self.push_type(Type::List(declared_type)),
+            _ => declared_type,
+        };
+        self.variable_types[var_id.0] = var_type;
+        self.set_node_type_id(node_id, var_type);
+    }
+
+    fn typecheck_alias(&mut self, new_name: NodeId, expansion: NodeId, node_id: NodeId) {
         self.set_node_type_id(node_id, NONE_TYPE);
+        if let AstNode::Call { ref parts } = self.compiler.ast_nodes[expansion.0] {
+            // the arguments of the alias's calls are appended, so the expansion may lack some
+            self.typecheck_call(parts, expansion, false);
+        }
 
         // set input/output types for the command
         let decl_id_new = self
@@ -698,7 +1582,7 @@ impl<'a> Typechecker<'a> {
             .get(&new_name)
             .expect("missing declared new name for alias");
 
-        let decl_id_old = self.compiler.decl_resolution.get(&old_name);
+        let decl_id_old = self.compiler.decl_resolution.get(&expansion);
 
         self.decl_types[decl_id_new.0] = decl_id_old.map_or(
             vec![InOutType {
@@ -709,12 +1593,32 @@ impl<'a> Typechecker<'a> {
         );
     }
 
-    fn typecheck_call(&mut self, parts: &[NodeId], node_id: NodeId) {
+    fn typecheck_extern(&mut self, name: NodeId, params: NodeId, node_id: NodeId) {
+        self.typecheck_node(params);
+        self.set_node_type_id(node_id, NONE_TYPE);
+
+        let decl_id = self
+            .compiler
+            .decl_resolution
+            .get(&name)
+            .expect("missing declared extern");
+        self.decl_types[decl_id.0] = vec![InOutType {
+            in_type: ANY_TYPE,
+            out_type: ANY_TYPE,
+        }];
+    }
+
+    /// Typecheck the arguments of a call, `is_complete` is false if more arguments are appended
+    /// later, as to the expansion of an alias
+    fn typecheck_call(&mut self, parts: &[NodeId], node_id: NodeId, is_complete: bool) {
         let num_name_parts = if let Some(decl_id) = self.compiler.decl_resolution.get(&node_id) {
             // TODO: The type should be `oneof<all_possible_output_types>`
-            self.set_node_type_id(node_id, ANY_TYPE);
+            match self.decl_types[decl_id.0].as_slice() {
+                [in_out_type] => self.set_node_type_id(node_id, in_out_type.out_type),
+                _ => self.set_node_type_id(node_id, ANY_TYPE),
+            }
 
-            self.compiler.decls[decl_id.0].name().split(' ').count()
+            self.compiler.call_name_parts[&node_id]
         } else {
             // external call
             self.node_types[node_id.0] = BYTE_STREAM_TYPE;
@@ -722,12 +1626,310 @@ impl<'a> Typechecker<'a> {
         };
 
         for part in &parts[num_name_parts..] {
-            if matches!(self.compiler.ast_nodes[part.0], AstNode::Name) {
-                self.set_node_type_id(*part, STRING_TYPE);
-            } else {
-                self.typecheck_node(*part);
+            match self.compiler.ast_nodes[part.0] {
+                AstNode::Name => self.set_node_type_id(*part, STRING_TYPE),
+                AstNode::FlagLong | AstNode::FlagShort | AstNode::FlagShortGroup => {
+                    self.set_node_type_id(*part, FORBIDDEN_TYPE)
+                }
+                AstNode::NamedValue { name, value } => {
+                    self.set_node_type_id(*part, FORBIDDEN_TYPE);
+                    self.set_node_type_id(name, FORBIDDEN_TYPE);
+                    self.typecheck_node(value);
+                }
+                AstNode::Spread(_) => {
+                    self.typecheck_node(*part);
+                    self.check_spread(*part, false);
+                }
+                AstNode::Redirection { target, .. } => {
+                    self.set_node_type_id(*part, FORBIDDEN_TYPE);
+                    if let Some(target) = target {
+                        self.typecheck_redirection_target(target);
+                    }
+                }
+                _ => self.typecheck_node(*part),
+            }
+        }
+
+        let compiler = self.compiler;
+        if let Some(decl_id) = compiler.decl_resolution.get(&node_id) {
+            let decl = &compiler.decls[decl_id.0];
+            if let Some(params) = decl.params() {
+                self.typecheck_args(decl.as_ref(), params, parts, num_name_parts, is_complete);
             }
         }
+
+        if let Some(root) = self.compiler.sources.get(&node_id) {
+            self.typecheck_node(*root);
+        }
+    }
+
+    /// Check the arguments of a call against the parameters of the called command
+    ///
+    /// The value of a flag with a type is the argument after it.
+    fn typecheck_args(
+        &mut self,
+        decl: &dyn Command,
+        params: &[Parameter],
+        parts: &[NodeId],
+        num_name_parts: usize,
+        is_complete: bool,
+    ) {
+        let command = decl.name();
+        let mut positionals = params
+            .iter()
+            .filter(|param| param.kind != ParamKind::Flag)
+            .peekable();
+
+        let mut args = parts[num_name_parts..].iter().copied().peekable();
+        while let Some(mut arg) = args.next() {
+            // the value of `--flag=value` is checked like the argument after the flag
+            let mut named_value = None;
+            if let AstNode::NamedValue { name, value } = self.compiler.get_node(arg) {
+                arg = *name;
+                named_value = Some(*value);
+            }
+
+            let contents = self.compiler.get_span_contents(arg);
+            let flags: Vec<_> = match self.compiler.get_node(arg) {
+                // not an argument of the command
+                AstNode::Redirection { .. } => continue,
+                AstNode::Spread(_) => {
+                    // the spread items are passed as the rest arguments, after the positionals
+                    match params.iter().find(|param| param.kind == ParamKind::Rest) {
+                        Some(rest) => self.typecheck_spread_arg(rest, arg),
+                        None => self.error(
+                            ErrorCode::ExtraArgument,
+                            format!("`{command}` has no rest parameter to spread the list into"),
+                            arg,
+                        ),
+                    }
+                    continue;
+                }
+                AstNode::FlagLong => vec![params.iter().find(|param| {
+                    param.kind == ParamKind::Flag && param.name.as_bytes() == &contents[2..]
+                })],
+                AstNode::FlagShort | AstNode::FlagShortGroup => contents[1..]
+                    .iter()
+                    .map(|short| {
+                        params.iter().find(|param| {
+                            param.kind == ParamKind::Flag && param.short == Some(*short as char)
+                        })
+                    })
+                    .collect(),
+                _ => {
+                    match positionals.peek() {
+                        Some(param) => {
+                            self.typecheck_arg(param, arg);
+                            if param.kind != ParamKind::Rest {
+                                positionals.next();
+                            }
+                        }
+                        None => self.error(
+                            ErrorCode::ExtraArgument,
+                            format!("extra argument for `{command}`"),
+                            arg,
+                        ),
+                    }
+                    continue;
+                }
+            };
+
+            let Some(flags) = flags.into_iter().collect::<Option<Vec<_>>>() else {
+                if decl.allows_unknown_flags() {
+                    continue;
+                }
+                self.error(
+                    ErrorCode::UnknownFlag,
+                    format!(
+                        "unknown flag `{}` for `{command}`",
+                        String::from_utf8_lossy(contents)
+                    ),
+                    arg,
+                );
+                let declared_flags: Vec<_> = params
+                    .iter()
+                    .filter(|param| param.kind == ParamKind::Flag)
+                    .flat_map(|param| {
+                        let short = param.short.map(|short| format!("-{short}"));
+                        std::iter::once(format!("--{}", param.name)).chain(short)
+                    })
+                    .collect();
+                self.add_help(
+                    did_you_mean(declared_flags.iter().map(|flag| flag.as_bytes()), contents)
+                        .map(|suggestion| format!("did you mean `{suggestion}`?")),
+                );
+                continue;
+            };
+
+            // only the last flag of a group can take a value
+            let flag = flags.last().filter(|flag| flag.ty.is_some());
+            if let Some(value) = named_value {
+                match flag {
+                    Some(flag) => self.typecheck_arg(flag, value),
+                    None => self.error(
+                        ErrorCode::ExtraArgument,
+                        format!(
+                            "flag `{}` of `{command}` does not take a value",
+                            String::from_utf8_lossy(contents)
+                        ),
+                        value,
+                    ),
+                }
+                continue;
+            }
+            let Some(flag) = flag else {
+                continue;
+            };
+            let value = args.next_if(|value| {
+                !matches!(
+                    self.compiler.get_node(*value),
+                    AstNode::FlagLong
+                        | AstNode::FlagShort
+                        | AstNode::FlagShortGroup
+                        | AstNode::NamedValue { .. }
+                )
+            });
+            match value {
+                Some(value) => self.typecheck_arg(flag, value),
+                None => self.error(
+                    ErrorCode::MissingArgument,
+                    format!("flag `--{}` expects a value", flag.name),
+                    arg,
+                ),
+            }
+        }
+
+        for param in positionals {
+            if is_complete && param.kind == ParamKind::Required {
+                self.error(
+                    ErrorCode::MissingArgument,
+                    format!("missing argument `{}` for `{command}`", param.name),
+                    parts[0],
+                );
+            }
+        }
+    }
+
+    /// Check that the argument matches the type of the parameter
+    fn typecheck_arg(&mut self, param: &Parameter, arg: NodeId) {
+        let Some(ty) = &param.ty else {
+            return;
+        };
+        let param_type = self.type_id_from_name(ty.as_bytes());
+        if param_type == UNKNOWN_TYPE || self.is_error(arg) {
+            return;
+        }
+
+        // a bare word is a string or a glob depending on the parameter, `*.rs` is just a string
+        // for a string parameter
+        let contents = self.compiler.get_span_contents(arg);
+        match (self.types[param_type.0], self.compiler.get_node(arg)) {
+            (Type::String, AstNode::GlobPattern) => self.set_node_type_id(arg, STRING_TYPE),
+            (Type::Glob, AstNode::String | AstNode::Name) if unquote(contents) == contents => {
+                self.set_node_type_id(arg, GLOB_TYPE)
+            }
+            _ => (),
+        }
+
+        if !is_type_compatible(self.types[param_type.0], self.type_of(arg)) {
+            self.error(
+                ErrorCode::TypeMismatch,
+                format!(
+                    "argument of `{}` must be {}, found {}",
+                    param.name,
+                    self.type_to_string(param_type),
+                    self.type_to_string(self.type_id_of(arg))
+                ),
+                arg,
+            );
+        }
+    }
+
+    /// The file of a redirection is given by its path
+    fn typecheck_redirection_target(&mut self, target: NodeId) {
+        self.typecheck_node(target);
+        if !self.is_error(target) && !is_type_compatible(self.type_of(target), Type::String) {
+            self.error(
+                ErrorCode::TypeMismatch,
+                format!(
+                    "redirection target must be a file path, found {}",
+                    self.type_to_string(self.type_id_of(target))
+                ),
+                target,
+            );
+        }
+    }
+
+    /// Check that the items of the spread list match the type of the rest parameter
+    fn typecheck_spread_arg(&mut self, rest: &Parameter, spread: NodeId) {
+        let Some(ty) = &rest.ty else {
+            return;
+        };
+        let param_type = self.type_id_from_name(ty.as_bytes());
+        if param_type == UNKNOWN_TYPE || self.is_error(spread) {
+            return;
+        }
+
+        let item_type = self.spread_item_type(spread);
+        if !is_type_compatible(self.types[param_type.0], self.types[item_type.0]) {
+            self.error(
+                ErrorCode::TypeMismatch,
+                format!(
+                    "items of `{}` must be {}, found {}",
+                    rest.name,
+                    self.type_to_string(param_type),
+                    self.type_to_string(item_type)
+                ),
+                spread,
+            );
+        }
+    }
+
+    /// Typecheck an item of a list literal, returning the type of the items it adds to the list
+    fn typecheck_list_item(&mut self, item: NodeId) -> TypeId {
+        self.typecheck_node(item);
+        if matches!(self.compiler.get_node(item), AstNode::Spread(_)) {
+            self.check_spread(item, false);
+            self.spread_item_type(item)
+        } else {
+            self.type_id_of(item)
+        }
+    }
+
+    /// Check that the spread value is a record inside of a record, and a list otherwise
+    fn check_spread(&mut self, spread: NodeId, in_record: bool) {
+        let AstNode::Spread(value) = *self.compiler.get_node(spread) else {
+            panic!("internal error: spread is not a Spread node");
+        };
+        if self.is_error(value) {
+            return;
+        }
+
+        // records are not typed yet, any value of a known type is not a record
+        let valid = match self.type_of(value) {
+            Type::Any | Type::Unknown | Type::OneOf(_) => true,
+            Type::List(_) | Type::Stream(_) => !in_record,
+            _ => false,
+        };
+        if !valid {
+            let expected = if in_record { "a record" } else { "a list" };
+            self.error(
+                ErrorCode::TypeMismatch,
+                format!(
+                    "spread value must be {expected}, found {}",
+                    self.type_to_string(self.type_id_of(value))
+                ),
+                value,
+            );
+        }
+    }
+
+    /// Type of the items added by spreading a list
+    fn spread_item_type(&self, spread: NodeId) -> TypeId {
+        match self.type_of(spread) {
+            Type::List(item_type) | Type::Stream(item_type) => item_type,
+            _ => ANY_TYPE,
+        }
     }
 
     fn typecheck_let(
@@ -742,8 +1944,34 @@ impl<'a> Typechecker<'a> {
         if let Some(ty) = ty {
             self.typecheck_node(ty);
 
-            if !is_type_compatible(self.type_of(ty), self.type_of(initializer)) {
-                self.error("initializer does not match declared type", initializer)
+            if !self.is_error(initializer)
+                && !is_type_compatible(self.type_of(ty), self.type_of(initializer))
+            {
+                self.error_with_related(
+                    ErrorCode::TypeMismatch,
+                    "initializer does not match declared type",
+                    initializer,
+                    vec![RelatedSpan::new(
+                        self.compiler.get_span(ty),
+                        format!(
+                            "declared as {} here",
+                            self.type_to_string(self.type_id_of(ty))
+                        ),
+                    )],
+                );
+
+                if self.is_annotatable(self.type_id_of(initializer)) {
+                    let initializer_type = self.type_to_string(self.type_id_of(initializer));
+                    if let Some(error) = self.errors.last_mut() {
+                        error.fixes.push(Fix::new(
+                            format!("change the type annotation to {initializer_type}"),
+                            vec![TextEdit::replace(
+                                self.compiler.get_span(ty),
+                                initializer_type,
+                            )],
+                        ));
+                    }
+                }
             }
         }
 
@@ -772,11 +2000,7 @@ impl<'a> Typechecker<'a> {
     ) -> TypeId {
         let name = self.compiler.get_span_contents(name_id);
 
-        // taken from parse_shape_name() in Nushell:
         match name {
-            b"any" => ANY_TYPE,
-            // b"binary" => SyntaxShape::Binary,
-            // b"block" => // not possible to pass blocks
             b"list" => {
                 if let Some(params_id) = params_id {
                     self.typecheck_node(params_id);
@@ -785,10 +2009,14 @@ impl<'a> Typechecker<'a> {
                         if params.len() > 1 {
                             let types =
                                 String::from_utf8_lossy(self.compiler.get_span_contents(params_id));
-                            self.error(format!("list must have only one type parameter (to allow selection of types, use oneof{} -- WIP)", types), params_id);
+                            self.error(ErrorCode::InvalidListType, format!("list must have only one type parameter (to allow selection of types, use oneof{} -- WIP)", types), params_id);
                             self.push_type(Type::List(UNKNOWN_TYPE))
                         } else if params.is_empty() {
-                            self.error("list must have one type parameter", params_id);
+                            self.error(
+                                ErrorCode::InvalidListType,
+                                "list must have one type parameter",
+                                params_id,
+                            );
                             self.push_type(Type::List(UNKNOWN_TYPE))
                         } else {
                             let params_ty_id = self.type_id_of(params[0]);
@@ -801,22 +2029,41 @@ impl<'a> Typechecker<'a> {
                     LIST_ANY_TYPE
                 }
             }
+            _ => self.type_id_from_name(name),
+        }
+    }
+
+    /// Type of a type name that is not parsed into an AST, e.g., `list<int>` from a signature
+    fn type_id_from_name(&mut self, name: &[u8]) -> TypeId {
+        if let Some(params) = name
+            .strip_prefix(b"list<")
+            .and_then(|name| name.strip_suffix(b">"))
+        {
+            let params_ty_id = self.type_id_from_name(params);
+            return self.push_type(Type::List(params_ty_id));
+        }
+
+        // taken from parse_shape_name() in Nushell:
+        match name {
+            b"any" => ANY_TYPE,
+            b"binary" => BINARY_TYPE,
+            // b"block" => // not possible to pass blocks
+            b"list" => LIST_ANY_TYPE,
             b"bool" => BOOL_TYPE,
             // b"cell-path" => SyntaxShape::CellPath,
             b"closure" => CLOSURE_TYPE, //FIXME: Closures should have known output types
-            // b"datetime" => SyntaxShape::DateTime,
-            // b"directory" => SyntaxShape::Directory,
-            // b"duration" => SyntaxShape::Duration,
+            b"datetime" => DATETIME_TYPE,
+            b"directory" => STRING_TYPE,
+            b"duration" => DURATION_TYPE,
             // b"error" => SyntaxShape::Error,
             b"float" => FLOAT_TYPE,
-            // b"filesize" => SyntaxShape::Filesize,
-            // b"glob" => SyntaxShape::GlobPattern,
+            b"filesize" => FILESIZE_TYPE,
+            b"glob" => GLOB_TYPE,
             b"int" => INT_TYPE,
-            // _ if bytes.starts_with(b"list") => parse_list_shape(working_set, bytes, span, use_loc),
             b"nothing" => NOTHING_TYPE,
             b"number" => NUMBER_TYPE,
-            // b"path" => SyntaxShape::Filepath,
-            // b"range" => SyntaxShape::Range,
+            b"path" => STRING_TYPE,
+            b"range" => RANGE_TYPE,
             // _ if bytes.starts_with(b"record") => {
             //     parse_collection_shape(working_set, bytes, span, use_loc)
             // }
@@ -848,7 +2095,12 @@ impl<'a> Typechecker<'a> {
             Type::Float => FLOAT_TYPE,
             Type::Bool => BOOL_TYPE,
             Type::String => STRING_TYPE,
+            Type::Glob => GLOB_TYPE,
             Type::Closure => CLOSURE_TYPE,
+            Type::Range => RANGE_TYPE,
+            Type::Filesize => FILESIZE_TYPE,
+            Type::Duration => DURATION_TYPE,
+            Type::Datetime => DATETIME_TYPE,
             Type::List(ANY_TYPE) => LIST_ANY_TYPE,
             _ => {
                 self.types.push(ty);
@@ -889,67 +2141,84 @@ impl<'a> Typechecker<'a> {
     }
 
     fn type_to_string(&self, type_id: TypeId) -> String {
-        let ty = &self.types[type_id.0];
+        type_to_string(&self.types, &self.oneof_types, type_id)
+    }
 
-        match ty {
-            Type::Unknown => "unknown".to_string(),
-            Type::Forbidden => "forbidden".to_string(),
-            Type::None => "()".to_string(),
-            Type::Any => "any".to_string(),
-            Type::Number => "number".to_string(),
-            Type::Nothing => "nothing".to_string(),
-            Type::Int => "int".to_string(),
-            Type::Float => "float".to_string(),
-            Type::Bool => "bool".to_string(),
-            Type::Binary => "binary".to_string(),
-            Type::String => "string".to_string(),
-            Type::Closure => "closure".to_string(),
-            Type::List(subtype_id) => {
-                format!("list<{}>", self.type_to_string(*subtype_id))
-            }
-            Type::Stream(subtype_id) => {
-                format!("stream<{}>", self.type_to_string(*subtype_id))
-            }
-            Type::OneOf(id) => {
-                let mut fmt = "oneof<".to_string();
-                let mut types: Vec<_> = self.oneof_types[id.0]
-                    .iter()
-                    .map(|ty| self.type_to_string(*ty) + ", ")
-                    .collect();
-                types.sort();
-                for ty in &types {
-                    fmt += ty;
-                }
-                if !types.is_empty() {
-                    fmt.pop();
-                    fmt.pop();
-                }
-                fmt.push('>');
-                fmt
-            }
-            Type::Error => "error".to_string(),
+    /// Attach a help message to the last reported error
+    fn add_help(&mut self, help: Option<String>) {
+        if let Some(error) = self.errors.last_mut() {
+            error.help = help;
         }
     }
 
-    fn error(&mut self, msg: impl Into<String>, node_id: NodeId) {
+    /// Attach a note to the last reported error
+    fn add_note(&mut self, note: &str) {
+        if let Some(error) = self.errors.last_mut() {
+            error.note = Some(note.into());
+        }
+    }
+
+    fn error(&mut self, code: ErrorCode, msg: impl Into<Message>, node_id: NodeId) {
+        self.error_with_related(code, msg, node_id, vec![]);
+    }
+
+    /// Same as error() but also points at other nodes or tokens involved in the error
+    fn error_with_related(
+        &mut self,
+        code: ErrorCode,
+        msg: impl Into<Message>,
+        node_id: NodeId,
+        related: Vec<RelatedSpan>,
+    ) {
         self.errors.push(SourceError {
+            code,
             message: msg.into(),
             node_id,
             severity: Severity::Error,
+            help: None,
+            note: None,
+            related,
+            fixes: vec![],
         })
     }
 
+    /// Whether a type can be written as a type annotation, e.g., for suggesting it in a fix
+    fn is_annotatable(&self, type_id: TypeId) -> bool {
+        match self.types[type_id.0] {
+            Type::Unknown
+            | Type::Forbidden
+            | Type::None
+            | Type::Stream(_)
+            | Type::OneOf(_)
+            | Type::Error => false,
+            Type::List(item_id) => self.is_annotatable(item_id),
+            _ => true,
+        }
+    }
+
     fn binary_op_err(&mut self, op_msg: &str, lhs: NodeId, op: NodeId, rhs: NodeId) {
-        self.error(
+        self.set_node_type_id(op, ERROR_TYPE);
+
+        // One of the operands has been reported already
+        if self.is_error(lhs) || self.is_error(rhs) {
+            return;
+        }
+
+        let lhs_type = self.type_to_string(self.type_id_of(lhs));
+        let rhs_type = self.type_to_string(self.type_id_of(rhs));
+
+        self.error_with_related(
+            ErrorCode::UnsupportedOperation,
             format!(
                 "type mismatch: unsupported {} between {} and {}",
-                op_msg,
-                self.type_to_string(self.type_id_of(lhs)),
-                self.type_to_string(self.type_id_of(rhs)),
+                op_msg, lhs_type, rhs_type,
             ),
             op,
+            vec![
+                RelatedSpan::new(self.compiler.get_span(lhs), format!("this is {lhs_type}")),
+                RelatedSpan::new(self.compiler.get_span(rhs), format!("this is {rhs_type}")),
+            ],
         );
-        self.set_node_type_id(op, ERROR_TYPE);
     }
 
     fn add_resolved_types(&mut self, types: &mut HashSet<TypeId>, ty: &TypeId) {
@@ -962,6 +2231,30 @@ impl<'a> Typechecker<'a> {
 }
 
 /// Check if one type can be cast to another type
+/// Schema of the config field at the path
+///
+/// The path must have been checked to exist by typechecking the cell path.
+fn config_field(compiler: &Compiler, path: &[NodeId]) -> &'static ConfigValue {
+    let mut value = &CONFIG_SCHEMA;
+    for field in path {
+        value = value
+            .field(compiler.get_span_contents(*field))
+            .expect("internal error: unknown config field");
+    }
+    value
+}
+
+fn config_value_type(value: &ConfigValue) -> TypeId {
+    match value {
+        ConfigValue::Any | ConfigValue::Record(_) => ANY_TYPE,
+        ConfigValue::Bool => BOOL_TYPE,
+        ConfigValue::Int => INT_TYPE,
+        ConfigValue::String | ConfigValue::Choice(_) => STRING_TYPE,
+        ConfigValue::Closure => CLOSURE_TYPE,
+        ConfigValue::List => LIST_ANY_TYPE,
+    }
+}
+
 fn is_type_compatible(lhs: Type, rhs: Type) -> bool {
     match (lhs, rhs) {
         (Type::Int, Type::Number) => true,
@@ -970,6 +2263,8 @@ fn is_type_compatible(lhs: Type, rhs: Type) -> bool {
         (Type::Number, Type::Float) => true,
         (Type::Any, _) => true,
         (_, Type::Any) => true,
+        // a quoted string is a glob that is not expanded
+        (Type::Glob, Type::String) => true,
         _ => lhs == rhs,
     }
 }
@@ -993,6 +2288,58 @@ fn check_numeric_op(lhs: Type, rhs: Type) -> Type {
     }
 }
 
+/// Type of an operation on filesizes or durations, None if neither operand has a unit
+///
+/// Values with the same unit can be added, subtracted, compared and divided, and they can be
+/// multiplied or divided by a number.
+fn check_unit_op(op: &AstNode, lhs: Type, rhs: Type) -> Option<Type> {
+    let is_number = |ty| matches!(ty, Type::Int | Type::Float | Type::Number | Type::Any);
+    let unit = match (lhs, rhs) {
+        (Type::Filesize | Type::Duration, _) => lhs,
+        (_, Type::Filesize | Type::Duration) => rhs,
+        _ => return None,
+    };
+    let same_unit = lhs == rhs || lhs == Type::Any || rhs == Type::Any;
+
+    match op {
+        AstNode::Plus | AstNode::Minus if same_unit => Some(unit),
+        AstNode::Equal | AstNode::NotEqual => Some(Type::Bool),
+        AstNode::LessThan
+        | AstNode::GreaterThan
+        | AstNode::LessThanOrEqual
+        | AstNode::GreaterThanOrEqual
+            if same_unit =>
+        {
+            Some(Type::Bool)
+        }
+        AstNode::Divide if lhs == rhs => Some(Type::Float),
+        AstNode::Divide if lhs == unit && is_number(rhs) => Some(unit),
+        AstNode::Multiply if is_number(lhs) || is_number(rhs) => Some(unit),
+        _ => None,
+    }
+}
+
+/// Type of an operation on datetimes, None if it is not one
+///
+/// Subtracting two datetimes gives the duration between them, a duration can be added to or
+/// subtracted from a datetime.
+fn check_datetime_op(op: &AstNode, lhs: Type, rhs: Type) -> Option<Type> {
+    match (lhs, op, rhs) {
+        (Type::Datetime, AstNode::Minus, Type::Datetime) => Some(Type::Duration),
+        (Type::Datetime, AstNode::Plus | AstNode::Minus, Type::Duration)
+        | (Type::Duration, AstNode::Plus, Type::Datetime) => Some(Type::Datetime),
+        (
+            Type::Datetime,
+            AstNode::LessThan
+            | AstNode::GreaterThan
+            | AstNode::LessThanOrEqual
+            | AstNode::GreaterThanOrEqual,
+            Type::Datetime,
+        ) => Some(Type::Bool),
+        _ => None,
+    }
+}
+
 /// Check whether two types can perform addition
 fn check_plus_op(lhs: Type, rhs: Type) -> Type {
     match (rhs, lhs) {
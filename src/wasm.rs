@@ -0,0 +1,49 @@
+//! JavaScript bindings for running the parser in WebAssembly, e.g., in a web playground or an
+//! editor extension
+//!
+//! Build with `wasm-pack build --features wasm`. Each function takes the source code and returns
+//! plain JS objects with the same shape as the JSON output of the CLI. All spans are byte offsets
+//! into the UTF-8 encoded source.
+use crate::bindings::compile;
+
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+const FILE_NAME: &str = "playground.nu";
+
+fn to_js(value: &serde_json::Value) -> Result<JsValue, JsError> {
+    // JSON-compatible serializer produces plain objects instead of ES2015 Maps
+    value
+        .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .map_err(|err| JsError::new(&err.to_string()))
+}
+
+/// Parse the source and return the AST nodes and parser errors
+#[wasm_bindgen]
+pub fn parse(source: &str) -> Result<JsValue, JsError> {
+    let compiled = compile(FILE_NAME, source.as_bytes());
+    to_js(&compiled.compiler.to_json())
+}
+
+/// Run all stages up to typechecking and return the output of each stage that ran
+#[wasm_bindgen]
+pub fn typecheck(source: &str) -> Result<JsValue, JsError> {
+    let compiled = compile(FILE_NAME, source.as_bytes());
+    to_js(&compiled.stages_json())
+}
+
+/// Return the errors of all stages with the spans of the nodes they refer to
+#[wasm_bindgen]
+pub fn diagnostics(source: &str) -> Result<JsValue, JsError> {
+    let compiled = compile(FILE_NAME, source.as_bytes());
+    to_js(&compiled.diagnostics_json())
+}
+
+/// Return the spans of the source classified for syntax highlighting, sorted by position
+///
+/// The kinds are named after LSP semantic token types, e.g., "variable", "function" or "number".
+#[wasm_bindgen]
+pub fn semantic_tokens(source: &str) -> Result<JsValue, JsError> {
+    let compiled = compile(FILE_NAME, source.as_bytes());
+    to_js(&compiled.semantic_tokens_json())
+}
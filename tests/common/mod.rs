@@ -0,0 +1,20 @@
+//! Helpers shared by the integration tests
+use std::path::{Path, PathBuf};
+
+/// Collect the *.nu files in the directory, including its subdirectories if `recursive` is set
+pub fn collect_nu_files(dir: &Path, recursive: bool, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        panic!("cannot read directory {}", dir.display());
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            if recursive {
+                collect_nu_files(&path, recursive, files);
+            }
+        } else if path.extension().is_some_and(|ext| ext == "nu") {
+            files.push(path);
+        }
+    }
+}
@@ -0,0 +1,233 @@
+//! Compatibility report over a corpus of nushell scripts
+//!
+//! Runs every *.nu file found recursively in the directory given by the `NU_CORPUS` environment
+//! variable (e.g., nushell's standard library or a collection of user scripts) through all the
+//! stages up to typechecking and reports how far each file got. Without `NU_CORPUS`, the snapshot
+//! test sources in tests/ are used.
+//!
+//! Run with: `cargo test --features corpus --test corpus -- --nocapture`
+//!
+//! To track the compatibility over time, `NU_CORPUS_BASELINE` may point to a file with the status
+//! of each file from a previous run. Files whose status got worse or better are reported, and the
+//! test fails if any file regressed. Set `NU_CORPUS_UPDATE_BASELINE=1` to write the current status
+//! to the baseline file instead.
+#![cfg(feature = "corpus")]
+
+mod common;
+
+use std::collections::BTreeMap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+
+use common::collect_nu_files;
+use new_nu_parser::compiler::Compiler;
+use new_nu_parser::lexer::lex;
+use new_nu_parser::parser::Parser;
+use new_nu_parser::resolver::Resolver;
+use new_nu_parser::typechecker::Typechecker;
+
+/// How far a file got, ordered from the worst to the best
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Status {
+    Crashed,
+    LexError,
+    ParseError,
+    ResolveError,
+    TypeError,
+    Ok,
+}
+
+impl Status {
+    const ALL: [Status; 6] = [
+        Status::Crashed,
+        Status::LexError,
+        Status::ParseError,
+        Status::ResolveError,
+        Status::TypeError,
+        Status::Ok,
+    ];
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Status::Crashed => "crashed",
+            Status::LexError => "lex-error",
+            Status::ParseError => "parse-error",
+            Status::ResolveError => "resolve-error",
+            Status::TypeError => "type-error",
+            Status::Ok => "ok",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<Self> {
+        Status::ALL.into_iter().find(|status| status.as_str() == s)
+    }
+}
+
+/// Status of the file along with the first fatal error message, if any
+fn compile(fname: &Path, contents: &[u8]) -> (Status, Option<String>) {
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        let mut compiler = Compiler::new();
+        let span_offset = compiler.span_offset();
+        compiler.add_file(&fname.to_string_lossy(), contents);
+
+        let first_error = |compiler: &Compiler| {
+            compiler
+                .errors
+                .iter()
+                .find(|error| error.is_fatal(compiler.options.warnings_as_errors))
+                .map(|error| error.message(compiler).into_owned())
+        };
+
+        let (tokens, err) = lex(contents, span_offset);
+        if let Err(e) = err {
+            return (Status::LexError, Some(format!("{:?}", e.item)));
+        }
+
+        compiler = Parser::new(compiler, tokens).parse();
+        if compiler.has_fatal_errors(&compiler.errors) {
+            return (Status::ParseError, first_error(&compiler));
+        }
+
+        let mut resolver = Resolver::new(&compiler);
+        resolver.resolve();
+        compiler.merge_name_bindings(resolver.to_name_bindings());
+        if compiler.has_fatal_errors(&compiler.errors) {
+            return (Status::ResolveError, first_error(&compiler));
+        }
+
+        let mut typechecker = Typechecker::new(&compiler);
+        typechecker.typecheck();
+        compiler.merge_types(typechecker.to_types());
+        if compiler.has_fatal_errors(&compiler.errors) {
+            return (Status::TypeError, first_error(&compiler));
+        }
+
+        (Status::Ok, None)
+    }));
+
+    res.unwrap_or((Status::Crashed, None))
+}
+
+/// Baseline file with a line `<status> <path>` for each file, the paths relative to the corpus
+fn read_baseline(path: &Path) -> BTreeMap<String, Status> {
+    let Ok(contents) = std::fs::read_to_string(path) else {
+        panic!("cannot read baseline {}", path.display());
+    };
+
+    contents
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (status, fname) = line
+                .split_once(' ')
+                .unwrap_or_else(|| panic!("invalid baseline line '{line}'"));
+            let status = Status::from_str(status)
+                .unwrap_or_else(|| panic!("invalid status in baseline line '{line}'"));
+            (fname.to_string(), status)
+        })
+        .collect()
+}
+
+fn write_baseline(path: &Path, statuses: &BTreeMap<String, Status>) {
+    let mut contents = String::new();
+    for (fname, status) in statuses {
+        contents.push_str(&format!("{} {fname}\n", status.as_str()));
+    }
+
+    if let Err(err) = std::fs::write(path, contents) {
+        panic!("cannot write baseline {}: {err}", path.display());
+    }
+}
+
+#[test]
+fn corpus() {
+    let corpus = std::env::var("NU_CORPUS").unwrap_or_else(|_| "tests".into());
+    let corpus = Path::new(&corpus);
+
+    let mut files = vec![];
+    collect_nu_files(corpus, true, &mut files);
+    files.sort();
+
+    let mut statuses = BTreeMap::new();
+
+    println!("==== CORPUS ====");
+
+    for fname in &files {
+        let contents = std::fs::read(fname).expect("file was found by directory walk");
+        let (status, error) = compile(fname, &contents);
+
+        let relative = fname.strip_prefix(corpus).unwrap_or(fname);
+        match error {
+            Some(error) => println!("{} {}: {error}", status.as_str(), relative.display()),
+            None => println!("{} {}", status.as_str(), relative.display()),
+        }
+
+        statuses.insert(relative.to_string_lossy().into_owned(), status);
+    }
+
+    let percentage = |count: usize| {
+        if files.is_empty() {
+            100.0
+        } else {
+            100.0 * count as f64 / files.len() as f64
+        }
+    };
+
+    println!("Files: {}", files.len());
+    for status in Status::ALL.into_iter().rev() {
+        let count = statuses.values().filter(|s| **s == status).count();
+        println!(
+            "  {:<13} {count:>6} ({:.1} %)",
+            status.as_str(),
+            percentage(count)
+        );
+    }
+    let parsed = statuses
+        .values()
+        .filter(|status| **status > Status::ParseError)
+        .count();
+    println!("Parsed: {parsed} ({:.1} %)", percentage(parsed));
+
+    let Ok(baseline_path) = std::env::var("NU_CORPUS_BASELINE") else {
+        return;
+    };
+    let baseline_path = Path::new(&baseline_path);
+
+    if std::env::var("NU_CORPUS_UPDATE_BASELINE").is_ok_and(|update| update == "1") {
+        write_baseline(baseline_path, &statuses);
+        println!("Baseline written to {}", baseline_path.display());
+        return;
+    }
+
+    let baseline = read_baseline(baseline_path);
+    let mut regressions = vec![];
+
+    println!("==== CHANGES SINCE BASELINE ====");
+
+    for (fname, status) in &statuses {
+        match baseline.get(fname) {
+            Some(old) if old > status => {
+                println!("regressed {fname}: {} -> {}", old.as_str(), status.as_str());
+                regressions.push(fname.as_str());
+            }
+            Some(old) if old < status => {
+                println!("improved {fname}: {} -> {}", old.as_str(), status.as_str())
+            }
+            Some(_) => (),
+            None => println!("new {fname}: {}", status.as_str()),
+        }
+    }
+    for fname in baseline
+        .keys()
+        .filter(|fname| !statuses.contains_key(*fname))
+    {
+        println!("removed {fname}");
+    }
+
+    assert!(
+        regressions.is_empty(),
+        "{} files regressed since the baseline: {}",
+        regressions.len(),
+        regressions.join(", ")
+    );
+}
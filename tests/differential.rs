@@ -0,0 +1,171 @@
+//! Differential testing against the old parser (nu-parser)
+//!
+//! Runs a corpus of nushell scripts through both parsers and compares whether they accept or
+//! reject each file. The corpus consists of the snapshot test sources in tests/ and, if set, all
+//! *.nu files found recursively in the directory given by the `NU_DIFFERENTIAL_CORPUS`
+//! environment variable (e.g., nushell's standard library).
+//!
+//! Run with: `cargo test --features differential --test differential -- --nocapture`
+//!
+//! The test only prints a report by default. To make it fail when the compatibility drops below
+//! some level, set `NU_DIFFERENTIAL_MIN_AGREEMENT` to the minimum percentage of matching verdicts.
+#![cfg(feature = "differential")]
+
+mod common;
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+
+use common::collect_nu_files;
+
+use new_nu_parser::compiler::Compiler;
+use new_nu_parser::lexer::lex;
+use new_nu_parser::parser::Parser;
+use new_nu_parser::resolver::Resolver;
+use new_nu_parser::typechecker::Typechecker;
+use nu_protocol::engine::{EngineState, StateWorkingSet};
+
+/// Outcome of running a parser on a single file
+enum Verdict {
+    Accepted,
+    /// Rejected with the first diagnostic message
+    Rejected(String),
+    /// The parser panicked
+    Crashed,
+}
+
+impl std::fmt::Display for Verdict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Verdict::Accepted => write!(f, "accepted"),
+            Verdict::Rejected(msg) => write!(f, "rejected: {msg}"),
+            Verdict::Crashed => write!(f, "crashed"),
+        }
+    }
+}
+
+impl Verdict {
+    fn is_accepted(&self) -> bool {
+        matches!(self, Verdict::Accepted)
+    }
+}
+
+fn verdict_new(fname: &Path, contents: &[u8]) -> Verdict {
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        let mut compiler = Compiler::new();
+        let span_offset = compiler.span_offset();
+        compiler.add_file(&fname.to_string_lossy(), contents);
+
+        let (tokens, err) = lex(contents, span_offset);
+        if let Err(e) = err {
+            return Err(format!("lexing error: {:?}", e.item));
+        }
+
+        compiler = Parser::new(compiler, tokens).parse();
+
+        if compiler.errors.is_empty() {
+            let mut resolver = Resolver::new(&compiler);
+            resolver.resolve();
+            compiler.merge_name_bindings(resolver.to_name_bindings());
+        }
+
+        if compiler.errors.is_empty() {
+            let mut typechecker = Typechecker::new(&compiler);
+            typechecker.typecheck();
+            compiler.merge_types(typechecker.to_types());
+        }
+
+        match compiler.errors.first() {
+            Some(error) => Err(error.message(&compiler).into_owned()),
+            None => Ok(()),
+        }
+    }));
+
+    match res {
+        Ok(Ok(())) => Verdict::Accepted,
+        Ok(Err(msg)) => Verdict::Rejected(msg),
+        Err(_) => Verdict::Crashed,
+    }
+}
+
+fn verdict_old(engine_state: &EngineState, fname: &Path, contents: &[u8]) -> Verdict {
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        let mut working_set = StateWorkingSet::new(engine_state);
+        let _ = nu_parser::parse(
+            &mut working_set,
+            Some(&fname.to_string_lossy()),
+            contents,
+            false,
+        );
+
+        match working_set.parse_errors.first() {
+            Some(error) => Err(error.to_string()),
+            None => Ok(()),
+        }
+    }));
+
+    match res {
+        Ok(Ok(())) => Verdict::Accepted,
+        Ok(Err(msg)) => Verdict::Rejected(msg),
+        Err(_) => Verdict::Crashed,
+    }
+}
+
+#[test]
+fn differential() {
+    let mut files = vec![];
+    collect_nu_files(Path::new("tests"), false, &mut files);
+
+    if let Ok(corpus) = std::env::var("NU_DIFFERENTIAL_CORPUS") {
+        collect_nu_files(Path::new(&corpus), true, &mut files);
+    }
+
+    files.sort();
+
+    let engine_state = nu_cmd_lang::create_default_context();
+
+    let mut num_agree = 0;
+    let mut num_crashes = 0;
+
+    println!("==== DIFFERENTIAL ====");
+
+    for fname in &files {
+        let contents = std::fs::read(fname).expect("file was found by directory walk");
+
+        let new = verdict_new(fname, &contents);
+        let old = verdict_old(&engine_state, fname, &contents);
+
+        if matches!(new, Verdict::Crashed) {
+            num_crashes += 1;
+        }
+
+        if new.is_accepted() == old.is_accepted() {
+            num_agree += 1;
+        } else {
+            println!("{}:", fname.display());
+            println!("  new: {new}");
+            println!("  old: {old}");
+        }
+    }
+
+    let agreement = if files.is_empty() {
+        100.0
+    } else {
+        100.0 * num_agree as f64 / files.len() as f64
+    };
+
+    println!(
+        "Files: {}, same verdict: {num_agree} ({agreement:.1} %), new parser crashes: {num_crashes}",
+        files.len()
+    );
+
+    if let Ok(min_agreement) = std::env::var("NU_DIFFERENTIAL_MIN_AGREEMENT") {
+        let min_agreement: f64 = min_agreement
+            .parse()
+            .expect("NU_DIFFERENTIAL_MIN_AGREEMENT must be a number");
+        assert!(
+            agreement >= min_agreement,
+            "agreement {agreement:.1} % is below the required {min_agreement:.1} %"
+        );
+    }
+}
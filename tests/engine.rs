@@ -0,0 +1,544 @@
+//! Evaluation of the generated IR in the nushell engine (nu-engine)
+//!
+//! Checks that the IR blocks produced by `IrGenerator` are executable and evaluate to the expected
+//! values. Besides the cases below, every IR snapshot source in tests/ir that compiles without
+//! errors must evaluate without errors.
+//!
+//! Run with: `cargo test --features engine-tests --test engine`
+#![cfg(feature = "engine-tests")]
+
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+use chrono::DateTime;
+use new_nu_parser::compiler::{Compiler, CompilerOptions};
+use new_nu_parser::ir_emitter::NuProtocolEmitter;
+use new_nu_parser::ir_generator::{IrClosure, IrGenerator};
+use new_nu_parser::lexer::lex;
+use new_nu_parser::parser::Parser;
+use new_nu_parser::resolver::Resolver;
+use new_nu_parser::typechecker::Typechecker;
+use nu_protocol::ast::Block;
+use nu_protocol::debugger::WithoutDebug;
+use nu_protocol::engine::{EngineState, Stack, StateWorkingSet};
+use nu_protocol::ir::IrBlock;
+use nu_protocol::{BlockId, PipelineData, PositionalArg, Signature, Span, SyntaxShape, Value};
+
+/// Engine with nushell's core commands (`echo`, `describe`, ...)
+fn engine_state() -> &'static EngineState {
+    static ENGINE_STATE: OnceLock<EngineState> = OnceLock::new();
+    ENGINE_STATE.get_or_init(nu_cmd_lang::create_default_context)
+}
+
+/// The generated root block and the blocks of its closures
+struct Compiled {
+    ir_block: IrBlock,
+    closures: Vec<IrClosure<IrBlock>>,
+}
+
+/// Run all the stages of the compiler on the source, returning the first error message if any
+///
+/// The commands are resolved from `engine_state()`.
+fn compile(fname: &str, contents: &[u8]) -> Result<Compiled, String> {
+    compile_with_options(fname, contents, CompilerOptions::default())
+}
+
+fn compile_with_options(
+    fname: &str,
+    contents: &[u8],
+    options: CompilerOptions,
+) -> Result<Compiled, String> {
+    let mut compiler = Compiler::new();
+    compiler.options = options;
+    let span_offset = compiler.span_offset();
+    compiler.add_file(fname, contents);
+
+    let (tokens, err) = lex(contents, span_offset);
+    if let Err(e) = err {
+        return Err(format!("lexing error: {:?}", e.item));
+    }
+
+    compiler = Parser::new(compiler, tokens).parse();
+
+    if !compiler.has_fatal_errors(&compiler.errors) {
+        let mut resolver = Resolver::new(&compiler).with_engine(engine_state());
+        resolver.resolve();
+        compiler.merge_name_bindings(resolver.to_name_bindings());
+    }
+
+    if !compiler.has_fatal_errors(&compiler.errors) {
+        let mut typechecker = Typechecker::new(&compiler);
+        typechecker.typecheck();
+        compiler.merge_types(typechecker.to_types());
+    }
+
+    let warnings_as_errors = compiler.options.warnings_as_errors;
+    if let Some(error) = compiler
+        .errors
+        .iter()
+        .find(|error| error.is_fatal(warnings_as_errors))
+    {
+        return Err(error.message(&compiler).into_owned());
+    }
+
+    // the closures are added to the engine after its blocks by eval()
+    let first_block_id = BlockId::new(engine_state().num_blocks());
+    let emitter = NuProtocolEmitter::with_first_block_id(first_block_id);
+    let mut ir_generator = IrGenerator::with_emitter(&compiler, emitter);
+    ir_generator.generate();
+
+    if let Some(error) = ir_generator.errors().first() {
+        return Err(error.message(&compiler).into_owned());
+    }
+    let (ir_block, closures) = ir_generator.blocks();
+    Ok(Compiled { ir_block, closures })
+}
+
+/// Evaluate the IR block in `engine_state()` with the closures added
+fn eval(compiled: Compiled) -> Result<Value, String> {
+    let mut engine_state = engine_state().clone();
+    let mut working_set = StateWorkingSet::new(&engine_state);
+    for closure in compiled.closures {
+        let mut signature = Signature::new("closure");
+        for param in closure.params {
+            signature.required_positional.push(PositionalArg {
+                name: format!("{param:?}"),
+                desc: String::new(),
+                shape: SyntaxShape::Any,
+                var_id: Some(NuProtocolEmitter::var_id(param)),
+                default_value: None,
+            });
+        }
+
+        working_set.add_block(Arc::new(Block {
+            signature: Box::new(signature),
+            captures: closure
+                .captures
+                .into_iter()
+                .map(NuProtocolEmitter::var_id)
+                .collect(),
+            ir_block: Some(closure.block),
+            ..Block::new()
+        }));
+    }
+    let delta = working_set.render();
+    engine_state
+        .merge_delta(delta)
+        .map_err(|err| format!("{err:?}"))?;
+
+    let mut stack = Stack::new();
+    let block = Block {
+        ir_block: Some(compiled.ir_block),
+        ..Block::new()
+    };
+
+    nu_engine::eval_block::<WithoutDebug>(&engine_state, &mut stack, &block, PipelineData::empty())
+        .and_then(|data| data.into_value(Span::unknown()))
+        .map_err(|err| format!("{err:?}"))
+}
+
+/// Checks the value of the source compiled both with and without optimizing the IR
+#[track_caller]
+fn assert_evaluates_to(source: &str, expected: Value) {
+    for optimize_ir in [false, true] {
+        let options = CompilerOptions {
+            optimize_ir,
+            ..Default::default()
+        };
+        let compiled = compile_with_options("test.nu", source.as_bytes(), options)
+            .unwrap_or_else(|err| panic!("failed to compile `{source}`: {err}"));
+        let value =
+            eval(compiled).unwrap_or_else(|err| panic!("failed to evaluate `{source}`: {err}"));
+
+        assert_eq!(
+            value, expected,
+            "unexpected value of `{source}` (optimize_ir: {optimize_ir})"
+        );
+    }
+}
+
+#[test]
+fn int_literals() {
+    assert_evaluates_to("1", Value::test_int(1));
+    assert_evaluates_to("1_000", Value::test_int(1000));
+    assert_evaluates_to("0x10", Value::test_int(16));
+    assert_evaluates_to("-5", Value::test_int(-5));
+}
+
+#[test]
+fn string_literals() {
+    assert_evaluates_to("'a\\b'", Value::test_string("a\\b"));
+    assert_evaluates_to(r#""a\t\"b\"""#, Value::test_string("a\t\"b\""));
+    assert_evaluates_to(r#""a" + 'b'"#, Value::test_string("ab"));
+}
+
+#[test]
+fn math() {
+    assert_evaluates_to("1 + 2", Value::test_int(3));
+    assert_evaluates_to("1 + 2 * 3", Value::test_int(7));
+    assert_evaluates_to("(1 + 2) * 3", Value::test_int(9));
+    assert_evaluates_to(
+        "-9223372036854775808 + 1_000 * 0x10",
+        Value::test_int(i64::MIN + 16_000),
+    );
+    assert_evaluates_to("2 * 3 + 0.5", Value::test_float(6.5));
+    assert_evaluates_to("1\n'a' + 'b'\n2 + 2", Value::test_int(4));
+    assert_evaluates_to("7 - 2 // 4 * 3 mod 2 ** 2", Value::test_int(7));
+    assert_evaluates_to("1 / 4", Value::test_float(0.25));
+    assert_evaluates_to("1 bit-shl 3 bit-or 1 bit-xor 3", Value::test_int(10));
+    assert_evaluates_to("[1] ++ [2] == [1, 2]", Value::test_bool(true));
+}
+
+#[test]
+fn comparisons_and_logic() {
+    assert_evaluates_to("1 < 2 and 2 >= 2", Value::test_bool(true));
+    assert_evaluates_to("1 > 2 or 1 != 1", Value::test_bool(false));
+    assert_evaluates_to("'abc' =~ 'b' xor 'abc' !~ 'c'", Value::test_bool(true));
+    assert_evaluates_to("1 in [1, 2] and 3 not-in [1, 2]", Value::test_bool(true));
+    // the right-hand side is not evaluated, so it does not fail
+    assert_evaluates_to(
+        "false and (9223372036854775807 + 1 == 0)",
+        Value::test_bool(false),
+    );
+    assert_evaluates_to(
+        "true or (9223372036854775807 + 1 == 0)",
+        Value::test_bool(true),
+    );
+}
+
+#[test]
+fn variables() {
+    assert_evaluates_to("let x = 3\n$x + 1", Value::test_int(4));
+    assert_evaluates_to(
+        "let a = 1\nlet b = 2\nlet c = 3\n$a + $b * $c",
+        Value::test_int(7),
+    );
+    assert_evaluates_to("let x = 1", Value::test_nothing());
+}
+
+#[test]
+fn if_else() {
+    assert_evaluates_to("if true { 1 } else { 2 }", Value::test_int(1));
+    assert_evaluates_to("if false { 1 } else { 2 }", Value::test_int(2));
+    assert_evaluates_to("if false { 1 }", Value::test_nothing());
+    assert_evaluates_to(
+        "let x = if false { 1 } else if true { 2 + 3 } else { 4 }\n$x * 2",
+        Value::test_int(10),
+    );
+    assert_evaluates_to("if true { let x = 1\n$x + 1 } else { }", Value::test_int(2));
+}
+
+#[test]
+fn loops() {
+    assert_evaluates_to("loop { break }", Value::test_nothing());
+    assert_evaluates_to("while false { }\n1", Value::test_int(1));
+    assert_evaluates_to(
+        "while true { loop { break }\nbreak }\n2",
+        Value::test_int(2),
+    );
+    assert_evaluates_to(
+        "let x = 1\nloop { if true { break } else { continue } }\n$x",
+        Value::test_int(1),
+    );
+}
+
+#[test]
+fn lists_and_records() {
+    assert_evaluates_to(
+        "[1 (2 + 3) []]",
+        Value::test_list(vec![
+            Value::test_int(1),
+            Value::test_int(5),
+            Value::test_list(vec![]),
+        ]),
+    );
+    assert_evaluates_to(
+        "{a: 1, \"b c\": [2.5]}",
+        Value::test_record(nu_protocol::record! {
+            "a" => Value::test_int(1),
+            "b c" => Value::test_list(vec![Value::test_float(2.5)]),
+        }),
+    );
+    assert_evaluates_to(
+        "let r = {a: 1, b: 2}\n{...$r, c: 3}",
+        Value::test_record(nu_protocol::record! {
+            "a" => Value::test_int(1),
+            "b" => Value::test_int(2),
+            "c" => Value::test_int(3),
+        }),
+    );
+    assert_evaluates_to(
+        "[[a b]; [1 2]]",
+        Value::test_list(vec![Value::test_record(nu_protocol::record! {
+            "a" => Value::test_int(1),
+            "b" => Value::test_int(2),
+        })]),
+    );
+}
+
+#[test]
+fn for_loops() {
+    assert_evaluates_to("for x in [1 2 3] { let y = $x }", Value::test_nothing());
+    assert_evaluates_to(
+        "let xs = [[1 2] [3]]\nfor x in $xs { for y in $x { if true { continue } } }\n1",
+        Value::test_int(1),
+    );
+    assert_evaluates_to("for x in [1 2] { break }\n2", Value::test_int(2));
+    let sum = |range: &str| format!("mut s = 0\nfor i in {range} {{ $s = $s + $i }}\n$s");
+    assert_evaluates_to(&sum("1..4"), Value::test_int(10));
+    assert_evaluates_to(&sum("1..<4"), Value::test_int(6));
+    assert_evaluates_to(&sum("0..2..6"), Value::test_int(12));
+    assert_evaluates_to("..5 | describe", Value::test_string("range"));
+}
+
+#[test]
+fn cell_paths_and_assignments() {
+    assert_evaluates_to("let x = {a: {b: 1}}\n$x.a.b", Value::test_int(1));
+    assert_evaluates_to("let x = {a: [1 2]}\n$x.a.1", Value::test_int(2));
+    assert_evaluates_to("let x = {'a b': 1}\n$x.\"a b\"", Value::test_int(1));
+    assert_evaluates_to("let x = {a: 1}\n$x.b?", Value::test_nothing());
+    assert_evaluates_to("({a: [3]}).a.0", Value::test_int(3));
+    assert_evaluates_to(
+        "mut x = {a: {b: 1}}\n$x.a.b = 5\n$x.a = {b: ($x.a.b * 2), c: 3}\n$x.a.b + $x.a.c",
+        Value::test_int(13),
+    );
+    assert_evaluates_to(
+        "mut sum = 0\nfor x in [1 2 3] { $sum = $sum + $x }\n$sum",
+        Value::test_int(6),
+    );
+}
+
+#[test]
+fn compound_assignments() {
+    assert_evaluates_to(
+        "mut x = 1\n$x += 2\n$x *= 4\n$x -= 2\n$x",
+        Value::test_int(10),
+    );
+    assert_evaluates_to("mut x = 3.0\n$x /= 2\n$x", Value::test_float(1.5));
+    assert_evaluates_to(
+        "mut x = [1]\n$x ++= [2]\n$x",
+        Value::test_list(vec![Value::test_int(1), Value::test_int(2)]),
+    );
+    assert_evaluates_to(
+        "mut x = {a: {b: 1}}\n$x.a.b += 4\n$x.a.b",
+        Value::test_int(5),
+    );
+}
+
+#[test]
+fn environment() {
+    assert_evaluates_to("$env.FOO = 'bar'\n$env.FOO", Value::test_string("bar"));
+    assert_evaluates_to(
+        "$env.FOO = {a: 1}\n$env.FOO.a += 2\n$env.FOO.a",
+        Value::test_int(3),
+    );
+    assert_evaluates_to("$env.MISSING?", Value::test_nothing());
+    assert_evaluates_to(
+        "let x = 'baz'\nFOO=bar BAR=$x echo $env.FOO $env.BAR",
+        Value::test_list(vec![Value::test_string("bar"), Value::test_string("baz")]),
+    );
+    // the variables are set only for the command
+    assert_evaluates_to("FOO=bar echo 1\n$env.FOO?", Value::test_nothing());
+}
+
+#[test]
+fn spread() {
+    assert_evaluates_to(
+        "let args = [2 3]\n[1 ...$args 4]",
+        Value::test_list(vec![
+            Value::test_int(1),
+            Value::test_int(2),
+            Value::test_int(3),
+            Value::test_int(4),
+        ]),
+    );
+    assert_evaluates_to(
+        "let args = [2 3]\necho 1 ...$args",
+        Value::test_list(vec![
+            Value::test_int(1),
+            Value::test_int(2),
+            Value::test_int(3),
+        ]),
+    );
+}
+
+#[test]
+fn calls() {
+    assert_evaluates_to("echo 1", Value::test_int(1));
+    assert_evaluates_to(
+        "echo 1 abc (2 + 3)",
+        Value::test_list(vec![
+            Value::test_int(1),
+            Value::test_string("abc"),
+            Value::test_int(5),
+        ]),
+    );
+    assert_evaluates_to("let x = echo 2\n$x * 3", Value::test_int(6));
+    assert_evaluates_to("do --ignore-errors { 1 / 0 }", Value::test_nothing());
+}
+
+#[test]
+fn closures() {
+    assert_evaluates_to("do { 1 }", Value::test_int(1));
+    assert_evaluates_to("do {|x: int| $x * 2 } 3", Value::test_int(6));
+    assert_evaluates_to("do {|x, y| $x + $y } 1 2", Value::test_int(3));
+    assert_evaluates_to("do {|| 4 }", Value::test_int(4));
+    assert_evaluates_to(
+        "let x = 1\nlet f = {|y: int| let z = $y * 10\ndo { $x + $z }\n}\ndo $f 2",
+        Value::test_int(21),
+    );
+}
+
+#[test]
+fn match_expressions() {
+    // the parameter is `any`, so all the patterns are possible
+    let source = |value: &str| {
+        format!(
+            "do {{|v: any|\nmatch $v {{\n1 => 'one',\n'a' => {{\nlet x = 2\n$x * 3\n}},\n[1, $y] => $y,\n{{a: $z}} => $z,\n_ => null\n}}\n}} {value}"
+        )
+    };
+    assert_evaluates_to(&source("1"), Value::test_string("one"));
+    assert_evaluates_to(&source("'a'"), Value::test_int(6));
+    assert_evaluates_to(&source("[1, 5]"), Value::test_int(5));
+    assert_evaluates_to(&source("[1, 5, 6]"), Value::test_nothing());
+    assert_evaluates_to(&source("{a: true, b: 1}"), Value::test_bool(true));
+    assert_evaluates_to("match 3 {\n1 => 2\n}", Value::test_nothing());
+    assert_evaluates_to("match 3 {\n$x => ($x + 1)\n}", Value::test_int(4));
+    assert_evaluates_to(
+        "match 2 {\n1 | 2 => 'small'\n}",
+        Value::test_string("small"),
+    );
+    assert_evaluates_to(
+        "match [1, 2] {\n[$a, $b] if $a > $b => $a,\n[$a, $b] if $a < $b => $b\n}",
+        Value::test_int(2),
+    );
+}
+
+#[test]
+fn unary_operators() {
+    assert_evaluates_to("let x = true\nnot $x", Value::test_bool(false));
+    assert_evaluates_to("let x = 2\n-$x * 3", Value::test_int(-6));
+    assert_evaluates_to("let x = 0.5\n-(1 + $x)", Value::test_float(-1.5));
+    assert_evaluates_to("not (not false)", Value::test_bool(false));
+}
+
+#[test]
+fn pipelines() {
+    assert_evaluates_to("[1, 2] | describe", Value::test_string("list<int>"));
+    assert_evaluates_to("let x = 'a' | describe\n$x", Value::test_string("string"));
+    assert_evaluates_to(
+        "do {\n1\n} | describe\n| describe",
+        Value::test_string("string"),
+    );
+}
+
+#[test]
+fn radix_and_binary_literals() {
+    assert_evaluates_to("0xff + 0o10 + 0b11", Value::test_int(266));
+    assert_evaluates_to("0x[DE ad]", Value::test_binary(vec![0xde, 0xad]));
+    assert_evaluates_to("0b[1 00000001]", Value::test_binary(vec![1, 1]));
+    assert_evaluates_to("0x[] | describe", Value::test_string("binary"));
+}
+
+#[test]
+fn unit_values() {
+    assert_evaluates_to("10kb + 1KiB", Value::test_filesize(11024));
+    assert_evaluates_to("2.5mb", Value::test_filesize(2_500_000));
+    assert_evaluates_to("1sec - 500ms", Value::test_duration(500_000_000));
+    assert_evaluates_to("-1day * 2", Value::test_duration(-2 * 86_400_000_000_000));
+    assert_evaluates_to("1hr / 1min", Value::test_float(60.0));
+}
+
+#[test]
+fn datetime_literals() {
+    let date = |rfc3339| Value::test_date(DateTime::parse_from_rfc3339(rfc3339).unwrap());
+    assert_evaluates_to("2024-01-15", date("2024-01-15T00:00:00+00:00"));
+    assert_evaluates_to(
+        "2024-01-15T10:30:00+02:00 + 30min",
+        date("2024-01-15T11:00:00+02:00"),
+    );
+    assert_evaluates_to(
+        "2024-01-16 - 2024-01-15T12:00:00Z",
+        Value::test_duration(12 * 3_600_000_000_000),
+    );
+    assert_evaluates_to("2024-01-15 < 2024-01-15T00:00:01", Value::test_bool(true));
+}
+
+#[test]
+fn string_interpolation() {
+    assert_evaluates_to(
+        "let name = 'nu'\n$\"hello ($name), you are (2 + 2)\"",
+        Value::test_string("hello nu, you are 4"),
+    );
+    assert_evaluates_to("$'(1.5)(true)'", Value::test_string("1.5true"));
+    assert_evaluates_to(r#"$"a\t\(b) (1)\"""#, Value::test_string("a\t(b) 1\""));
+    assert_evaluates_to(r"$'a\n(1)'", Value::test_string("a\\n1"));
+}
+
+#[test]
+fn subexpression() {
+    assert_evaluates_to("(echo 1; echo 2) + 1", Value::test_int(3));
+    assert_evaluates_to("(let x = 2; $x * $x) * (1 + 2)", Value::test_int(12));
+    assert_evaluates_to("let x = (\n  echo 1\n  echo 2\n)\n$x", Value::test_int(2));
+}
+
+#[test]
+fn try_catch() {
+    assert_evaluates_to("try { 1 } catch { 2 }", Value::test_int(1));
+    assert_evaluates_to(
+        "try { 9223372036854775807 + 1 } catch { 2 }",
+        Value::test_int(2),
+    );
+    assert_evaluates_to(
+        "try {\nerror make {msg: 'boom'}\n} catch {|err| $err.msg }",
+        Value::test_string("boom"),
+    );
+    assert_evaluates_to("try {\nerror make {msg: 'boom'}\n}\n3", Value::test_int(3));
+    assert_evaluates_to("try {\nerror make {msg: 'boom'}\n}", Value::test_nothing());
+    assert_evaluates_to(
+        "let x = try {\nerror make {msg: 'boom'}\n} catch {\n'caught'\n}\n$x",
+        Value::test_string("caught"),
+    );
+}
+
+#[test]
+fn runtime_errors_point_at_the_source() {
+    let source = "1 + (9223372036854775807 * 2)";
+    let compiled = compile("test.nu", source.as_bytes()).expect("valid source");
+    assert!(compiled
+        .ir_block
+        .spans
+        .iter()
+        .all(|span| *span != Span::unknown()));
+
+    let err = eval(compiled).expect_err("the multiplication overflows");
+    let span = Span::new(5, 28);
+    assert_eq!(&source[span.start..span.end], "9223372036854775807 * 2");
+    assert!(err.contains(&format!("{span:?}")), "unexpected error {err}");
+}
+
+#[test]
+fn ir_snapshot_sources() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/ir");
+    let mut evaluated = 0;
+
+    for entry in std::fs::read_dir(dir).expect("missing tests/ir") {
+        let path = entry.expect("failed to read tests/ir").path();
+        if path.extension().map_or(true, |ext| ext != "nu") {
+            continue;
+        }
+
+        let contents = std::fs::read(&path).expect("failed to read the source");
+        // sources exercising unsupported nodes are covered by the snapshots only
+        let Ok(compiled) = compile(&path.to_string_lossy(), &contents) else {
+            continue;
+        };
+
+        if let Err(err) = eval(compiled) {
+            panic!("failed to evaluate {}: {err}", path.display());
+        }
+        evaluated += 1;
+    }
+
+    assert!(evaluated > 0, "no source in tests/ir was evaluated");
+}
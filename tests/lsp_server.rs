@@ -0,0 +1,155 @@
+//! Runs the example language server (src/bin/lsp.rs) through a session of an LSP client
+//!
+//! Run with: `cargo test --features lsp-server --test lsp_server`
+#![cfg(feature = "lsp-server")]
+
+use std::io::{BufReader, Write};
+use std::process::{ChildStdin, ChildStdout, Command, Stdio};
+
+use lsp_server::{Message, Notification, Request, RequestId, Response};
+use serde_json::{json, Value};
+
+struct Client {
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    next_id: i32,
+}
+
+impl Client {
+    fn send(&mut self, message: Message) {
+        message.write(&mut self.stdin).expect("failed to send");
+        self.stdin.flush().expect("failed to send");
+    }
+
+    fn receive(&mut self) -> Message {
+        Message::read(&mut self.stdout)
+            .expect("failed to receive")
+            .expect("server closed the connection")
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(Message::Notification(Notification::new(
+            method.into(),
+            params,
+        )));
+    }
+
+    /// Send the request and wait for its result, skipping the notifications sent in the meantime
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        let id = RequestId::from(self.next_id);
+        self.next_id += 1;
+        self.send(Message::Request(Request::new(
+            id.clone(),
+            method.into(),
+            params,
+        )));
+
+        loop {
+            if let Message::Response(Response {
+                id: response_id,
+                result,
+                error,
+            }) = self.receive()
+            {
+                assert_eq!(response_id, id);
+                assert!(error.is_none(), "{method} failed: {error:?}");
+                return result.unwrap_or_default();
+            }
+        }
+    }
+
+    fn wait_for_notification(&mut self, method: &str) -> Value {
+        loop {
+            if let Message::Notification(notification) = self.receive() {
+                if notification.method == method {
+                    return notification.params;
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn session() {
+    let mut server = Command::new(env!("CARGO_BIN_EXE_lsp"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to start the server");
+
+    let mut client = Client {
+        stdin: server.stdin.take().expect("missing stdin"),
+        stdout: BufReader::new(server.stdout.take().expect("missing stdout")),
+        next_id: 1,
+    };
+
+    let initialized = client.request("initialize", json!({ "capabilities": {} }));
+    assert_eq!(
+        initialized["capabilities"]["definitionProvider"],
+        json!(true)
+    );
+    client.notify("initialized", json!({}));
+
+    let uri = "file:///test.nu";
+    let document = json!({ "uri": uri });
+    client.notify(
+        "textDocument/didOpen",
+        json!({
+            "textDocument": {
+                "uri": uri,
+                "languageId": "nushell",
+                "version": 1,
+                "text": "let x = 1\n$x + 'a'\n",
+            },
+        }),
+    );
+    let diagnostics = client.wait_for_notification("textDocument/publishDiagnostics");
+    assert_eq!(diagnostics["uri"], uri);
+    assert_eq!(
+        diagnostics["diagnostics"][0]["code"],
+        "nu::typechecker::unsupported_operation"
+    );
+
+    let position = json!({ "textDocument": document, "position": { "line": 1, "character": 1 } });
+
+    let definition = client.request("textDocument/definition", position.clone());
+    assert_eq!(
+        definition["range"],
+        json!({ "start": { "line": 0, "character": 4 }, "end": { "line": 0, "character": 5 } })
+    );
+
+    let hover = client.request("textDocument/hover", position.clone());
+    assert_eq!(hover["contents"]["value"], "```nu\n$x: int\n```");
+
+    let tokens = client.request(
+        "textDocument/semanticTokens/full",
+        json!({ "textDocument": document }),
+    );
+    assert!(!tokens["data"]
+        .as_array()
+        .expect("missing tokens")
+        .is_empty());
+
+    // the completion still works while the document is not valid
+    client.notify(
+        "textDocument/didChange",
+        json!({
+            "textDocument": { "uri": uri, "version": 2 },
+            "contentChanges": [{ "text": "let x = 1\n$" }],
+        }),
+    );
+    let diagnostics = client.wait_for_notification("textDocument/publishDiagnostics");
+    assert_eq!(diagnostics["diagnostics"][0]["severity"], 1);
+
+    let completion = client.request(
+        "textDocument/completion",
+        json!({ "textDocument": document, "position": { "line": 1, "character": 1 } }),
+    );
+    assert_eq!(completion[0]["label"], "$x");
+
+    client.request("shutdown", Value::Null);
+    client.notify("exit", Value::Null);
+
+    let status = server.wait().expect("server did not exit");
+    assert!(status.success());
+}